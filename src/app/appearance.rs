@@ -0,0 +1,184 @@
+use std::borrow::Cow;
+
+use ratatui::buffer::Buffer;
+use ratatui::style::{Color, Modifier};
+
+/// 画面に出す記号。ASCIIモードでは対応するASCII表記に置き換える
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Glyph {
+    Directory,
+    File,
+    Ai,
+    Ok,
+    Error,
+    Warning,
+    Waiting,
+    Tool,
+    Passed,
+    Failed,
+    /// このセッションで変更したファイル
+    Touched,
+    /// 選択中の行
+    Pointer,
+    Collapsed,
+    Expanded,
+}
+
+/// 記号の表（絵文字, ASCII表記）。Glyphの順に並べる
+const GLYPH_TABLE: &[(&str, &str)] = &[
+    ("📁", "[DIR]"),
+    ("📄", "[FILE]"),
+    ("🤖", "[AI]"),
+    ("✅", "[OK]"),
+    ("❌", "[ERR]"),
+    ("⚠", "[WARN]"),
+    ("⏳", "[WAIT]"),
+    ("🛠", "[TOOL]"),
+    ("✓", "[OK]"),
+    ("✗", "[FAIL]"),
+    ("✎", "*"),
+    ("➤", ">"),
+    ("▸", ">"),
+    ("▾", "v"),
+];
+
+/// 通知やメッセージの本文に書かれている、Glyph以外の絵文字のASCII表記
+const TEXT_GLYPHS: &[(&str, &str)] = &[
+    ("📋", "[COPY]"),
+    ("✂", "[CUT]"),
+    ("🧹", "[CLEAR]"),
+    ("📂", "[DIR]"),
+    ("👀", "[WATCH]"),
+    ("📥", "[IMPORT]"),
+    ("🔢", "[TOKENS]"),
+    ("🗜", "[COMPACT]"),
+    ("📎", "[ATTACH]"),
+    ("📦", "[ARCHIVE]"),
+    ("📊", "[STATS]"),
+    ("💰", "[COST]"),
+    ("🩺", "[DOCTOR]"),
+    ("🔖", "[BOOKMARK]"),
+    ("🔔", "[NOTE]"),
+    ("🗄", "[ARCHIVE]"),
+    ("🍴", "[FORK]"),
+    ("🔒", "[LOCKED]"),
+    ("🚫", "[DENIED]"),
+    ("📝", "[NOTE]"),
+    ("🗑", "[DEL]"),
+    ("🪝", "[HOOK]"),
+    ("↩", "<-"),
+    ("💾", "[SAVED]"),
+    ("🔁", "[REPLAY]"),
+];
+
+/// 応答待ちのタブに出すスピナーのコマ（絵文字モード, ASCIIモード）
+const SPINNER_FRAMES: (&[&str], &[&str]) = (&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"], &["|", "/", "-", "\\"]);
+/// スピナーの1コマの長さ（画面は100msごとに描き直す）
+const SPINNER_FRAME_MILLIS: u128 = 100;
+
+/// 絵文字の表示形式を指定する異体字セレクタ（ASCIIモードでは取り除く）
+const VARIATION_SELECTOR: char = '\u{FE0F}';
+
+impl Glyph {
+    fn entry(self) -> (&'static str, &'static str) {
+        GLYPH_TABLE[self as usize]
+    }
+}
+
+/// 表示の設定（ASCII_MODE / HIGH_CONTRAST、/appearance で切り替える）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Appearance {
+    /// 絵文字・記号をASCII表記にする
+    pub ascii_mode: bool,
+    /// 明るい色と太字だけで描画する（薄い文字を使わない）
+    pub high_contrast: bool,
+}
+
+impl Appearance {
+    pub fn glyph(&self, glyph: Glyph) -> &'static str {
+        let (emoji, ascii) = glyph.entry();
+        if self.ascii_mode { ascii } else { emoji }
+    }
+
+    /// 現在時刻に応じたスピナーのコマ
+    pub fn spinner(&self) -> &'static str {
+        let frames = if self.ascii_mode { SPINNER_FRAMES.1 } else { SPINNER_FRAMES.0 };
+        let elapsed = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        frames[(elapsed.as_millis() / SPINNER_FRAME_MILLIS) as usize % frames.len()]
+    }
+
+    /// ASCIIモードなら本文中の絵文字・記号を記号の表に従って置き換える
+    pub fn text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if !self.ascii_mode || text.is_ascii() {
+            return Cow::Borrowed(text);
+        }
+        let mut text: String = text.chars().filter(|&c| c != VARIATION_SELECTOR).collect();
+        for (emoji, ascii) in GLYPH_TABLE.iter().chain(TEXT_GLYPHS) {
+            if text.contains(emoji) {
+                text = text.replace(emoji, ascii);
+            }
+        }
+        Cow::Owned(text)
+    }
+
+    /// ハイコントラストなら描画し終えた画面の色を明るい色に、薄い文字を太字にする
+    pub fn apply_high_contrast(&self, buffer: &mut Buffer) {
+        if !self.high_contrast {
+            return;
+        }
+        for cell in buffer.content.iter_mut() {
+            // 選択行などの灰色の背景は白黒反転にする
+            if matches!(cell.bg, Color::DarkGray | Color::Gray) {
+                cell.bg = Color::White;
+                cell.fg = Color::Black;
+            } else {
+                cell.fg = bright_color(cell.fg);
+            }
+            cell.modifier.remove(Modifier::DIM);
+            cell.modifier.insert(Modifier::BOLD);
+        }
+    }
+}
+
+/// 暗い色を同系統の明るい色にする（青は黒の背景で読みにくいので水色にする）
+fn bright_color(color: Color) -> Color {
+    match color {
+        Color::DarkGray | Color::Gray => Color::White,
+        Color::Red => Color::LightRed,
+        Color::Green => Color::LightGreen,
+        Color::Yellow => Color::LightYellow,
+        Color::Blue | Color::LightBlue | Color::Cyan => Color::LightCyan,
+        Color::Magenta => Color::LightMagenta,
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::layout::Rect;
+    use ratatui::style::Style;
+
+    #[test]
+    fn test_ascii_glyphs_and_high_contrast() {
+        let emoji = Appearance::default();
+        let ascii = Appearance { ascii_mode: true, high_contrast: false };
+        assert_eq!(emoji.glyph(Glyph::Directory), "📁");
+        assert_eq!(ascii.glyph(Glyph::Directory), "[DIR]");
+        assert_eq!(ascii.glyph(Glyph::Expanded), "v");
+        assert_eq!(GLYPH_TABLE.len(), Glyph::Expanded as usize + 1);
+        assert_eq!(
+            ascii.text("⚠️ Stopped. ✅ done, 🛠 execute_command ✗ 📋 copied"),
+            "[WARN] Stopped. [OK] done, [TOOL] execute_command [FAIL] [COPY] copied"
+        );
+        assert!(matches!(ascii.text("plain"), Cow::Borrowed("plain")));
+        assert_eq!(emoji.text("✅ done"), "✅ done");
+
+        let mut buffer = Buffer::empty(Rect::new(0, 0, 2, 1));
+        buffer[(0, 0)].set_style(Style::default().fg(Color::Blue).add_modifier(Modifier::DIM));
+        buffer[(1, 0)].set_style(Style::default().fg(Color::Green).bg(Color::DarkGray));
+        Appearance { ascii_mode: false, high_contrast: true }.apply_high_contrast(&mut buffer);
+        assert_eq!((buffer[(0, 0)].fg, buffer[(0, 0)].modifier), (Color::LightCyan, Modifier::BOLD));
+        assert_eq!((buffer[(1, 0)].fg, buffer[(1, 0)].bg), (Color::Black, Color::White));
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::app::{ChatApp, ChatEvent};
+use crate::debug_log;
+use crate::file_access::FileReference;
+
+/// 添付したファイル（選択中のファイルと@file参照）の変更の監視
+/// 保存時に置き換え（一時ファイルからのrename）をするエディタもあるので、親ディレクトリを監視して添付したパスだけを通知する
+pub struct AttachmentWatcher {
+    watcher: RecommendedWatcher,
+    /// 監視中のファイル（正規化したパス）
+    watched: Arc<Mutex<HashSet<PathBuf>>>,
+    /// 監視中のディレクトリ
+    directories: HashSet<PathBuf>,
+}
+
+/// 添付したファイルの内容が変わった可能性のあるイベントか（読み取りやメタデータだけの変更は除く）
+fn is_content_change(kind: &EventKind) -> bool {
+    matches!(kind, EventKind::Create(_) | EventKind::Remove(_))
+        || matches!(kind, EventKind::Modify(modify) if !matches!(modify, notify::event::ModifyKind::Metadata(_)))
+}
+
+impl AttachmentWatcher {
+    pub fn new(sender: mpsc::UnboundedSender<ChatEvent>) -> notify::Result<Self> {
+        let watched: Arc<Mutex<HashSet<PathBuf>>> = Arc::default();
+        let filter = watched.clone();
+        let watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| match result {
+            Ok(event) if is_content_change(&event.kind) => {
+                let Ok(watched) = filter.lock() else {
+                    return;
+                };
+                for path in event.paths.iter().filter(|path| watched.contains(*path)) {
+                    let _ = sender.send(ChatEvent::AttachmentChanged(path.clone()));
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                debug_log!("[AttachmentWatcher] {:?}", e);
+            }
+        })?;
+        Ok(Self { watcher, watched, directories: HashSet::new() })
+    }
+
+    /// pathの監視を始める（監視済みなら何もしない）
+    pub fn watch(&mut self, path: &Path) -> notify::Result<()> {
+        let path = path.canonicalize()?;
+        if let Some(directory) = path.parent().filter(|directory| !self.directories.contains(*directory)) {
+            self.watcher.watch(directory, RecursiveMode::NonRecursive)?;
+            self.directories.insert(directory.to_path_buf());
+        }
+        if let Ok(mut watched) = self.watched.lock() {
+            watched.insert(path);
+        }
+        Ok(())
+    }
+
+    pub fn is_watching(&self, path: &Path) -> bool {
+        path.canonicalize().is_ok_and(|path| self.watched.lock().is_ok_and(|watched| watched.contains(&path)))
+    }
+}
+
+impl ChatApp {
+    /// 送信するメッセージに添付したファイルの監視を始める（監視を始められなくても送信は続ける）
+    pub fn watch_attachments(&mut self, references: &[FileReference]) {
+        if references.is_empty() {
+            return;
+        }
+        if self.attachment_watcher.is_none() {
+            match AttachmentWatcher::new(self.event_sender.clone()) {
+                Ok(watcher) => self.attachment_watcher = Some(watcher),
+                Err(e) => {
+                    debug_log!("[watch_attachments] watcher error: {:?}", e);
+                    return;
+                }
+            }
+        }
+        let Some(watcher) = self.attachment_watcher.as_mut() else {
+            return;
+        };
+        for reference in references {
+            if let Err(e) = watcher.watch(Path::new(&reference.path)) {
+                debug_log!("[watch_attachments] {}: {:?}", reference.path, e);
+            }
+        }
+    }
+
+    /// 添付したファイルが変わったことを通知する（contuiのedit_fileによる変更と、通知済みのファイルは無視する）
+    pub fn on_attachment_changed(&mut self, path: PathBuf) {
+        if self.changed_attachments.contains(&path) || self.gemini_client.is_own_write(&path) {
+            return;
+        }
+        let name = path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+        self.notify(format!("✎ {} changed since last attach (/refresh or R to re-attach)", name));
+        self.changed_attachments.insert(path);
+    }
+
+    /// 変更された添付ファイルかどうか（ファイルブラウザの表示用）
+    pub fn is_attachment_changed(&self, path: &Path) -> bool {
+        !self.changed_attachments.is_empty()
+            && path.canonicalize().is_ok_and(|path| self.changed_attachments.contains(&path))
+    }
+
+    /// /refresh, R: 変更された添付ファイルを次のメッセージに添付し直す（削除されたファイルは外す）
+    pub fn refresh_changed_attachments(&mut self) {
+        if self.changed_attachments.is_empty() {
+            self.notify("No attached files have changed".to_string());
+            return;
+        }
+        let mut attached = Vec::new();
+        let mut removed = Vec::new();
+        for path in std::mem::take(&mut self.changed_attachments) {
+            let display = path.display().to_string();
+            if !path.is_file() {
+                removed.push(display);
+                continue;
+            }
+            if !self.ui.selected_files.iter().any(|file| Path::new(file).canonicalize().is_ok_and(|file| file == path)) {
+                self.ui.selected_files.push(display.clone());
+            }
+            attached.push(display);
+        }
+        let mut lines = Vec::new();
+        if !attached.is_empty() {
+            lines.push(format!("📎 Re-attached for the next message: {}", attached.join(", ")));
+        }
+        if !removed.is_empty() {
+            lines.push(format!("🗑 Deleted since last attach: {}", removed.join(", ")));
+        }
+        self.push_system_message(lines.join("\n"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attachment_changes_skip_own_writes() {
+        let (dir, mut app) = crate::app::file_operations::tests::test_app("attach", false);
+        std::fs::write(dir.join("main.rs"), "fn main() {}\n").unwrap();
+        let path = dir.join("main.rs").canonicalize().unwrap();
+        app.watch_attachments(&[FileReference::whole(path.display().to_string())]);
+        assert!(app.attachment_watcher.as_ref().unwrap().is_watching(&path));
+
+        // contuiが編集した直後の通知は無視する
+        let edit = format!("```edit_file:{}:1:1\nfn main() {{ run(); }}\n```", path.display());
+        assert_eq!(app.gemini_client.process_edit_file_response(&edit).unwrap().len(), 1);
+        app.on_attachment_changed(path.clone());
+        assert!(!app.is_attachment_changed(&path));
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(&path, "fn main() { other(); }\n").unwrap();
+        app.on_attachment_changed(path.clone());
+        assert!(app.is_attachment_changed(&path));
+
+        app.refresh_changed_attachments();
+        assert!(!app.is_attachment_changed(&path));
+        assert_eq!(app.ui.selected_files, vec![path.display().to_string()]);
+    }
+}
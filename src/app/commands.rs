@@ -0,0 +1,1872 @@
+use uuid::Uuid;
+use chrono::{Local, Utc};
+
+use crate::app::{ChatApp, ChatEvent, InputMode, RenderMode, CONTEXT_MESSAGE_LIMIT, CONTEXT_TOKEN_BUDGET};
+use crate::bookmarks::{default_bookmarks_path, save_bookmarks};
+use crate::app::file_operations::{format_file_reference, strip_attachment_summary};
+use crate::app::task_queue::UserTask;
+use crate::templates::{default_templates_dir, list_templates, load_template, save_template};
+use crate::config::format_cost;
+use crate::app::session_diff::{git_diff_lines, unified_diff_lines, DiffLine, DiffLineKind};
+use crate::git::{self, GitStatus, RepoStatus};
+use crate::import::parse_import;
+use crate::hooks::HOOK_SCHEMAS;
+use crate::file_access::{resolve_user_path, DirectoryEntry, PathAccess, DIRECTORY_SUFFIX};
+use unicode_width::UnicodeWidthStr;
+use std::path::PathBuf;
+use crate::history::{session_csv, session_transcript, user_prompts, CleanupTarget, HistoryStats, SessionUsage};
+use crate::gemini::{format_model_list, group_digits, Content, EffectiveSettings, Part, MAX_TOKENS_RANGE, TEMPERATURE_RANGE};
+
+/// コマンドハンドラのエラー
+#[derive(Debug)]
+pub enum CommandError {
+    /// 引数が不正（usageを表示する）
+    Usage,
+    /// 実行時のエラー
+    Failed(String),
+}
+
+pub type CommandHandler = fn(&mut ChatApp, &[String]) -> Result<(), CommandError>;
+
+/// スラッシュコマンドの定義
+pub struct Command {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub usage: &'static str,
+    pub description: &'static str,
+    pub handler: CommandHandler,
+}
+
+/// スラッシュコマンドのレジストリ
+pub struct CommandRegistry {
+    commands: Vec<Command>,
+}
+
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommandRegistry {
+    /// 組み込みコマンドを登録したレジストリを作成
+    pub fn new() -> Self {
+        let mut registry = Self { commands: Vec::new() };
+        registry.register(Command {
+            name: "help",
+            aliases: &["h", "?"],
+            usage: "/help [command]",
+            description: "Show available commands",
+            handler: cmd_help,
+        });
+        registry.register(Command {
+            name: "clearlog",
+            aliases: &[],
+            usage: "/clearlog",
+            description: "Delete all messages in the current session",
+            handler: cmd_clearlog,
+        });
+        registry.register(Command {
+            name: "clear",
+            aliases: &[],
+            usage: "/clear",
+            description: "Clear the visible messages (the session history is kept)",
+            handler: cmd_clear,
+        });
+        registry.register(Command {
+            name: "cd",
+            aliases: &[],
+            usage: "/cd <path>",
+            description: "Change the working directory (used by the file browser and execute_command)",
+            handler: cmd_cd,
+        });
+        registry.register(Command {
+            name: "ls",
+            aliases: &[],
+            usage: "/ls [path]",
+            description: "List a directory (name, size, modified) without opening the file browser",
+            handler: cmd_ls,
+        });
+        registry.register(Command {
+            name: "watch",
+            aliases: &[],
+            usage: "/watch <file_path>",
+            description: "Send lines appended to a file to the AI (checked every 2 seconds)",
+            handler: cmd_watch,
+        });
+        registry.register(Command {
+            name: "unwatch",
+            aliases: &[],
+            usage: "/unwatch",
+            description: "Stop watching the file started with /watch",
+            handler: cmd_unwatch,
+        });
+        registry.register(Command {
+            name: "refresh",
+            aliases: &[],
+            usage: "/refresh",
+            description: "Re-attach files that changed on disk since they were attached",
+            handler: cmd_refresh,
+        });
+        registry.register(Command {
+            name: "echo",
+            aliases: &[],
+            usage: "/echo <text>",
+            description: "Insert a note into the conversation without sending it to the AI",
+            handler: cmd_echo,
+        });
+        registry.register(Command {
+            name: "redact",
+            aliases: &[],
+            usage: "/redact [on|off]",
+            description: "Show or toggle redaction of secrets before this session's messages are sent to the LLM",
+            handler: cmd_redact,
+        });
+        registry.register(Command {
+            name: "hooks",
+            aliases: &[],
+            usage: "/hooks",
+            description: "Show the configured hooks and the JSON they receive on stdin",
+            handler: cmd_hooks,
+        });
+        registry.register(Command {
+            name: "share",
+            aliases: &[],
+            usage: "/share",
+            description: "Copy a summary of the current session to the clipboard",
+            handler: cmd_share,
+        });
+        registry.register(Command {
+            name: "trash",
+            aliases: &[],
+            usage: "/trash [restore <n>|purge <n|all>]",
+            description: "List deleted sessions, restore one, or delete them permanently",
+            handler: cmd_trash,
+        });
+        registry.register(Command {
+            name: "export",
+            aliases: &[],
+            usage: "/export csv",
+            description: "Export the current session's messages to contui_export_<timestamp>.csv",
+            handler: cmd_export,
+        });
+        registry.register(Command {
+            name: "session",
+            aliases: &[],
+            usage: "/session [export-all [--with-steps]]",
+            description: "Show the current session, or export all sessions to a JSON archive (--with-steps keeps agent steps)",
+            handler: cmd_session,
+        });
+        registry.register(Command {
+            name: "import",
+            aliases: &[],
+            usage: "/import <path>",
+            description: "Import a conversation (contui export, [{role, content}] array or ChatGPT conversations.json) as a new session",
+            handler: cmd_import,
+        });
+        registry.register(Command {
+            name: "changes",
+            aliases: &[],
+            usage: "/changes",
+            description: "List files the AI has changed in this session",
+            handler: cmd_changes,
+        });
+        registry.register(Command {
+            name: "tokens",
+            aliases: &[],
+            usage: "/tokens",
+            description: "Count the conversation context's tokens exactly with the countTokens API",
+            handler: cmd_tokens,
+        });
+        registry.register(Command {
+            name: "context",
+            aliases: &["ctx"],
+            usage: "/context show [N]",
+            description: "Show the conversation context sent to the LLM (or the last N messages)",
+            handler: cmd_context,
+        });
+        registry.register(Command {
+            name: "truncate",
+            aliases: &[],
+            usage: "/truncate <N>",
+            description: "Remove all messages in the current session except the last N",
+            handler: cmd_truncate,
+        });
+        registry.register(Command {
+            name: "compact",
+            aliases: &[],
+            usage: "/compact [keep]",
+            description: "Summarize older messages, keeping the last [keep] verbatim",
+            handler: cmd_compact,
+        });
+        registry.register(Command {
+            name: "compare",
+            aliases: &[],
+            usage: "/compare <model_a> <model_b> | /compare off",
+            description: "Send each message to two models and show the answers side by side",
+            handler: cmd_compare,
+        });
+        registry.register(Command {
+            name: "temp",
+            aliases: &[],
+            usage: "/temp [0.0-2.0 | off]",
+            description: "Override temperature for this session (no args: show effective settings)",
+            handler: cmd_temp,
+        });
+        registry.register(Command {
+            name: "maxtokens",
+            aliases: &[],
+            usage: "/maxtokens [n | off]",
+            description: "Override max output tokens for this session",
+            handler: cmd_maxtokens,
+        });
+        registry.register(Command {
+            name: "cleanup",
+            aliases: &[],
+            usage: "/cleanup archived | empty | older <days> | confirm | cancel",
+            description: "Bulk-delete sessions (asks for /cleanup confirm first)",
+            handler: cmd_cleanup,
+        });
+        registry.register(Command {
+            name: "stats",
+            aliases: &[],
+            usage: "/stats",
+            description: "Show conversation statistics across all sessions",
+            handler: cmd_stats,
+        });
+        registry.register(Command {
+            name: "usage",
+            aliases: &["cost"],
+            usage: "/usage",
+            description: "Show token usage and estimated cost for this session",
+            handler: cmd_usage,
+        });
+        registry.register(Command {
+            name: "format",
+            aliases: &[],
+            usage: "/format [raw|markdown|json]",
+            description: "Show or change how chat messages are rendered",
+            handler: cmd_format,
+        });
+        registry.register(Command {
+            name: "appearance",
+            aliases: &[],
+            usage: "/appearance [ascii|contrast] [on|off]",
+            description: "Show or toggle ASCII mode (no emoji) and high-contrast colors",
+            handler: cmd_appearance,
+        });
+        registry.register(Command {
+            name: "tab",
+            aliases: &[],
+            usage: "/tab [new|close|<number>]",
+            description: "List tabs, open a new tab, close the current tab or switch to a tab",
+            handler: cmd_tab,
+        });
+        registry.register(Command {
+            name: "doctor",
+            aliases: &[],
+            usage: "/doctor",
+            description: "Check config, API key, network, file permissions and terminal support",
+            handler: cmd_doctor,
+        });
+        registry.register(Command {
+            name: "bookmark",
+            aliases: &["bm"],
+            usage: "/bookmark <label> | /bookmark list",
+            description: "Bookmark the selected message, or list bookmarks",
+            handler: cmd_bookmark,
+        });
+        registry.register(Command {
+            name: "goto",
+            aliases: &[],
+            usage: "/goto <label>",
+            description: "Jump to a bookmarked message",
+            handler: cmd_goto,
+        });
+        registry.register(Command {
+            name: "diff",
+            aliases: &[],
+            usage: "/diff [<path> | <session_a> <session_b>]",
+            description: "Show git diff for a path (default: files the AI changed this session), or a diff between two sessions (numbered from the most recently updated)",
+            handler: cmd_diff,
+        });
+        registry.register(Command {
+            name: "replay",
+            aliases: &[],
+            usage: "/replay <session>",
+            description: "Resend the user messages of a session (numbered from the most recently updated) one by one in a new session with the current model and settings",
+            handler: cmd_replay,
+        });
+        registry.register(Command {
+            name: "format-code",
+            aliases: &[],
+            usage: "/format-code",
+            description: "Format the first ```lang code block typed after the command with rustfmt/prettier/black/gofmt and put it back in the input",
+            handler: cmd_format_code,
+        });
+        registry.register(Command {
+            name: "notifications",
+            aliases: &["notes"],
+            usage: "/notifications",
+            description: "Show the last 20 notifications with timestamps",
+            handler: cmd_notifications,
+        });
+        registry.register(Command {
+            name: "template",
+            aliases: &["tpl"],
+            usage: "/template save <name> (template on the following lines) | load <name> | list",
+            description: "Save, load or list reusable prompt templates",
+            handler: cmd_template,
+        });
+        registry.register(Command {
+            name: "flush",
+            aliases: &[],
+            usage: "/flush",
+            description: "Retry saving messages that failed to reach the history file",
+            handler: cmd_flush,
+        });
+        registry.register(Command {
+            name: "permissions",
+            aliases: &["perms"],
+            usage: "/permissions",
+            description: "View and edit the directories the AI is allowed to access",
+            handler: cmd_permissions,
+        });
+        registry.register(Command {
+            name: "gemini-version",
+            aliases: &["models"],
+            usage: "/gemini-version",
+            description: "List the models available to the API key and their supported methods",
+            handler: cmd_gemini_version,
+        });
+        registry.register(Command {
+            name: "version",
+            aliases: &["v"],
+            usage: "/version",
+            description: "Show the contui version",
+            handler: cmd_version,
+        });
+        registry
+    }
+
+    pub fn register(&mut self, command: Command) {
+        self.commands.push(command);
+    }
+
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    /// 名前またはエイリアスでコマンドを検索
+    pub fn find(&self, name: &str) -> Option<&Command> {
+        self.commands
+            .iter()
+            .find(|c| c.name == name || c.aliases.contains(&name))
+    }
+
+    /// 未知のコマンド名に近いコマンド名を近い順に返す
+    pub fn suggest(&self, name: &str) -> Vec<&'static str> {
+        let mut candidates: Vec<(usize, &'static str)> = self
+            .commands
+            .iter()
+            .filter_map(|c| {
+                let distance = std::iter::once(c.name)
+                    .chain(c.aliases.iter().copied())
+                    .map(|n| edit_distance(name, n))
+                    .min()?;
+                if distance <= 2 || (!name.is_empty() && c.name.starts_with(name)) {
+                    Some((distance, c.name))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        candidates.sort();
+        candidates.into_iter().map(|(_, n)| n).collect()
+    }
+
+    /// 未知のコマンドに対するエラーメッセージ
+    pub fn unknown_command_message(&self, name: &str) -> String {
+        let mut msg = format!("❌ Unknown command: /{}", name);
+        let suggestions = self.suggest(name);
+        if !suggestions.is_empty() {
+            let names: Vec<String> = suggestions.iter().map(|s| format!("/{}", s)).collect();
+            msg.push_str(&format!("\nDid you mean: {}?", names.join(", ")));
+        }
+        msg.push_str("\nType /help to list available commands.");
+        msg
+    }
+
+    /// コマンド一覧を整形したテキスト
+    pub fn help_text(&self) -> String {
+        let width = self.commands.iter().map(|c| c.usage.len()).max().unwrap_or(0);
+        let mut lines = vec!["Available commands:".to_string()];
+        for command in &self.commands {
+            let mut line = format!("  {:<width$}  - {}", command.usage, command.description, width = width);
+            if !command.aliases.is_empty() {
+                let aliases: Vec<String> = command.aliases.iter().map(|a| format!("/{}", a)).collect();
+                line.push_str(&format!(" (aliases: {})", aliases.join(", ")));
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+}
+
+/// コマンド名の後ろの引数を、空白や引用符を残したまま返す（名前との区切りの空白1文字だけ除く）
+pub fn raw_command_args(input: &str) -> &str {
+    let body = input.trim_start().strip_prefix('/').unwrap_or(input);
+    let rest = body.trim_start_matches(|c: char| !c.is_whitespace());
+    let mut chars = rest.chars();
+    chars.next();
+    chars.as_str()
+}
+
+/// 入力行をコマンド名と引数に分解する（"/"で始まらない場合はNone）
+/// ダブルクォートで囲まれた引数は空白を含めて1つの引数として扱う
+pub fn parse_command_line(input: &str) -> Option<(String, Vec<String>)> {
+    let body = input.trim().strip_prefix('/')?;
+
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_token = false;
+    for ch in body.chars() {
+        match ch {
+            '"' => {
+                in_quotes = !in_quotes;
+                has_token = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if has_token {
+                    tokens.push(std::mem::take(&mut current));
+                    has_token = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_token = true;
+            }
+        }
+    }
+    if has_token {
+        tokens.push(current);
+    }
+
+    let mut tokens = tokens.into_iter();
+    let name = tokens.next().unwrap_or_default();
+    Some((name, tokens.collect()))
+}
+
+/// 2つの文字列のレーベンシュタイン距離
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b_chars.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b_chars.len() + 1];
+        for (j, cb) in b_chars.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+    prev[b_chars.len()]
+}
+
+impl ChatApp {
+    /// "/"で始まる入力をコマンドとして実行する
+    pub fn execute_slash_command(&mut self, input: &str) {
+        let Some((name, args)) = parse_command_line(input) else {
+            return;
+        };
+        let Some(command) = self.command_registry.find(&name) else {
+            let msg = self.command_registry.unknown_command_message(&name);
+            self.push_system_message(msg);
+            return;
+        };
+        let (handler, usage) = (command.handler, command.usage);
+        self.command_raw_args = raw_command_args(input).to_string();
+        match handler(self, &args) {
+            Ok(()) => {}
+            Err(CommandError::Usage) => {
+                self.push_system_message(format!("❌ Usage: {}", usage));
+            }
+            Err(CommandError::Failed(e)) => {
+                self.push_system_message(format!("❌ /{}: {}", name, e));
+            }
+        }
+    }
+
+    /// 履歴に保存しない表示専用のシステムメッセージを追加
+    pub fn push_system_message(&mut self, text: String) {
+        self.messages.push(crate::history::ChatMessage {
+            id: Uuid::new_v4(),
+            parts: vec![Part::Text { text }],
+            is_user: false,
+            timestamp: Utc::now(),
+            comparison: false,
+            steps: Vec::new(),
+            is_system: false,
+            excluded: false,
+        });
+        self.auto_scroll_if_at_bottom();
+    }
+
+    /// /echo: 現在のセッションにメモを追加する（履歴に保存するが、AIには送らない）
+    pub fn push_note(&mut self, text: String) -> anyhow::Result<()> {
+        let message = crate::history::ChatMessage {
+            id: Uuid::new_v4(),
+            parts: vec![Part::Text { text }],
+            is_user: false,
+            timestamp: Utc::now(),
+            comparison: false,
+            steps: Vec::new(),
+            is_system: true,
+            excluded: false,
+        };
+        {
+            let mut history_guard = self.history_manager.lock().unwrap();
+            let session_id = history_guard.ensure_active_session();
+            history_guard.get_history_mut().add_chat_message_to(session_id, message.clone())?;
+        }
+        self.messages.push(message);
+        self.autosave_history("push_note");
+        self.auto_scroll_if_at_bottom();
+        Ok(())
+    }
+}
+
+fn cmd_help(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let text = match args {
+        [] => app.command_registry.help_text(),
+        [name] => {
+            let name = name.trim_start_matches('/');
+            match app.command_registry.find(name) {
+                Some(command) => format!("{}\n  {}", command.usage, command.description),
+                None => app.command_registry.unknown_command_message(name),
+            }
+        }
+        _ => return Err(CommandError::Usage),
+    };
+    app.push_system_message(text);
+    Ok(())
+}
+
+/// 画面のメッセージだけを消す（履歴には残るので、セッションを切り替えて戻ると再び表示される）
+fn cmd_clear(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    app.messages.clear();
+    app.ui.scroll_offset = 0;
+    app.ui.selected_message_index = None;
+    app.notify("🧹 Cleared the view (history is kept; reopen the session to show it again)");
+    Ok(())
+}
+
+fn cmd_cd(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let [path] = args else {
+        return Err(CommandError::Usage);
+    };
+    let base = std::env::current_dir().unwrap_or_else(|_| PathBuf::from(&app.ui.current_directory));
+    let target = resolve_user_path(path, &base, dirs::home_dir().as_deref());
+    let target = target.canonicalize().map_err(|e| CommandError::Failed(format!("{}: {}", target.display(), e)))?;
+    if !target.is_dir() {
+        return Err(CommandError::Failed(format!("Not a directory: {}", target.display())));
+    }
+    // 許可ディレクトリの外なら、許可されてから移動する
+    match app.gemini_client.check_path_access(&target) {
+        Ok(PathAccess::Allowed) => app.change_working_directory(&target).map_err(CommandError::Failed),
+        Ok(PathAccess::Outside) if app.ui.directory_prompt.is_none() => {
+            app.request_directory_change(&target);
+            Ok(())
+        }
+        Ok(PathAccess::Outside) => Err(CommandError::Failed("Answer the pending directory access prompt first".to_string())),
+        Ok(PathAccess::Sensitive) => Err(CommandError::Failed(format!(
+            "{} is in a sensitive directory; add it to the allowed_directories file to allow it",
+            target.display()
+        ))),
+        Err(e) => Err(CommandError::Failed(format!("{}: {}", target.display(), e))),
+    }
+}
+
+fn cmd_ls(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let base = PathBuf::from(&app.ui.current_directory);
+    let target = match args {
+        [] => base,
+        [path] => resolve_user_path(path, &base, dirs::home_dir().as_deref()),
+        _ => return Err(CommandError::Usage),
+    };
+    let entries = app
+        .gemini_client
+        .list_directory_with_metadata(&target)
+        .map_err(|e| CommandError::Failed(format!("{}: {}", target.display(), e)))?;
+    app.push_system_message(format_directory_listing(&target.to_string_lossy(), &entries));
+    Ok(())
+}
+
+/// バイト数をB/KB/MB/GBで表す（1024単位、B以外は小数1桁）
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 { format!("{} B", bytes) } else { format!("{:.1} {}", size, UNITS[unit]) }
+}
+
+/// /ls の表（名前・サイズ・更新時刻の列を揃える。ディレクトリはサイズを表示しない）
+pub fn format_directory_listing(directory: &str, entries: &[DirectoryEntry]) -> String {
+    let rows: Vec<(String, String, String)> = entries
+        .iter()
+        .map(|entry| {
+            let name = if entry.is_dir { format!("{}{}", entry.name, DIRECTORY_SUFFIX) } else { entry.name.clone() };
+            let size = if entry.is_dir { "-".to_string() } else { format_size(entry.size) };
+            let modified = entry
+                .modified
+                .map(|time| chrono::DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            (name, size, modified)
+        })
+        .collect();
+    let name_width = rows.iter().map(|(name, _, _)| name.width()).chain(["Name".len()]).max().unwrap_or(0);
+    let size_width = rows.iter().map(|(_, size, _)| size.len()).chain(["Size".len()]).max().unwrap_or(0);
+    let files = entries.iter().filter(|entry| !entry.is_dir).count();
+    let mut lines = vec![
+        format!("{} ({} directories, {} files)", directory, entries.len() - files, files),
+        format!("{}{}  {:>size_width$}  Modified", "Name", " ".repeat(name_width - "Name".len()), "Size"),
+    ];
+    for (name, size, modified) in rows {
+        let padding = " ".repeat(name_width - name.width());
+        lines.push(format!("{}{}  {:>size_width$}  {}", name, padding, size, modified).trim_end().to_string());
+    }
+    lines.join("\n")
+}
+
+fn cmd_watch(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let [path] = args else {
+        return Err(CommandError::Usage);
+    };
+    let previous = app.file_watch.as_ref().map(|watch| watch.path.clone());
+    app.start_watch(path).map_err(CommandError::Failed)?;
+    match previous {
+        Some(previous) if previous != *path => app.notify(format!("👀 Watching {} (stopped watching {})", path, previous)),
+        _ => app.notify(format!("👀 Watching {} — appended lines are sent to the AI (/unwatch to stop)", path)),
+    }
+    Ok(())
+}
+
+fn cmd_unwatch(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let path = app.stop_watch().ok_or_else(|| CommandError::Failed("No file is being watched".to_string()))?;
+    app.notify(format!("Stopped watching {}", path));
+    Ok(())
+}
+
+fn cmd_refresh(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    app.refresh_changed_attachments();
+    Ok(())
+}
+
+fn cmd_echo(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    // 空白の並びや引用符もそのまま残す
+    let text = app.command_raw_args.clone();
+    app.push_note(text).map_err(|e| CommandError::Failed(e.to_string()))
+}
+
+/// 切り替えは表示中のセッションだけに効く（ほかのセッションは REDACT_SECRETS のまま）
+fn cmd_redact(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let session_id = app.current_session_id().ok_or_else(|| CommandError::Failed("No active session".to_string()))?;
+    match args {
+        [] => {
+            let status = if app.redaction_enabled(session_id) { "on" } else { "off" };
+            app.push_system_message(format!("🔒 Secret redaction is {} for this session (/redact on|off)", status));
+        }
+        [arg] if arg == "on" => {
+            app.redaction_overrides.insert(session_id, true);
+            app.notify("🔒 Secret redaction is on for this session".to_string());
+        }
+        [arg] if arg == "off" => {
+            app.redaction_overrides.insert(session_id, false);
+            app.notify("⚠ Secret redaction is off for this session until contui exits: API keys and tokens are sent to the LLM as-is".to_string());
+        }
+        _ => return Err(CommandError::Usage),
+    }
+    Ok(())
+}
+
+fn cmd_hooks(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let hooks = app.gemini_client.hooks();
+    let mut lines = vec!["🪝 Hooks (hooks.toml [hooks], HOOKS_FILE):".to_string()];
+    for (event, hook) in hooks.entries() {
+        match hook {
+            Some(hook) => {
+                let dir = hook.working_dir.as_ref().map_or_else(|| ".".to_string(), |dir| dir.display().to_string());
+                lines.push(format!("  {}: {} (dir: {}, timeout: {}s)", event, hook.command, dir, hook.timeout_secs));
+            }
+            None => lines.push(format!("  {}: (not set)", event)),
+        }
+    }
+    lines.push("JSON on stdin:".to_string());
+    lines.extend(HOOK_SCHEMAS.iter().map(|(event, schema)| format!("  {}: {}", event, schema)));
+    lines.push("A non-zero exit from pre_tool refuses the tool call; its output is returned to the model.".to_string());
+    app.push_system_message(lines.join("\n"));
+    Ok(())
+}
+
+fn cmd_clearlog(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let result = (*app.history_manager.lock().unwrap()).clear_messages();
+    match result {
+        Ok(_) => {
+            app.messages.clear();
+            app.push_system_message("✅ ログを全て削除しました.".to_string());
+            Ok(())
+        }
+        Err(e) => Err(CommandError::Failed(format!("ログ削除に失敗しました: {}", e))),
+    }
+}
+
+fn cmd_share(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    app.share_session();
+    Ok(())
+}
+
+fn cmd_session(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    match args {
+        [] => {}
+        [sub] if sub == "export-all" => return export_all_sessions(app, false),
+        [sub, flag] if sub == "export-all" && flag == "--with-steps" => return export_all_sessions(app, true),
+        _ => return Err(CommandError::Usage),
+    }
+    let text = {
+        let history_guard = app.history_manager.lock().unwrap();
+        match history_guard.get_history().get_current_session() {
+            Some(session) => format!(
+                "Session: {}\nID: {}\nMessages: {}",
+                session.title,
+                session.id,
+                session.messages.len()
+            ),
+            None => return Err(CommandError::Failed("No active session".to_string())),
+        }
+    };
+    app.push_system_message(text);
+    Ok(())
+}
+
+/// /trash の番号（1始まり）のセッション
+fn trashed_session_id(app: &ChatApp, number: &str) -> Result<Uuid, CommandError> {
+    let index = number.parse::<usize>().ok().and_then(|n| n.checked_sub(1)).ok_or(CommandError::Usage)?;
+    let history_guard = app.history_manager.lock().unwrap();
+    let trashed = history_guard.get_history().trashed_sessions();
+    trashed.get(index).map(|session| session.id).ok_or_else(|| CommandError::Failed(format!("No trashed session #{} (see /trash)", number)))
+}
+
+fn cmd_trash(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    match args {
+        [] => {
+            let text = {
+                let history_guard = app.history_manager.lock().unwrap();
+                let trashed = history_guard.get_history().trashed_sessions();
+                if trashed.is_empty() {
+                    "🗑 The trash is empty".to_string()
+                } else {
+                    let mut lines = vec![format!("🗑 Trash ({} sessions, purged after {} days):", trashed.len(), app.config.trash_retention_days)];
+                    for (i, session) in trashed.iter().enumerate() {
+                        let deleted_at = session.deleted_at.map(|at| at.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default();
+                        lines.push(format!("  {}. {} — deleted {} ({} messages)", i + 1, session.title, deleted_at, session.messages.len()));
+                    }
+                    lines.push("/trash restore <n> to restore, /trash purge <n|all> to delete permanently".to_string());
+                    lines.join("\n")
+                }
+            };
+            app.push_system_message(text);
+        }
+        [sub, number] if sub == "restore" => {
+            let session_id = trashed_session_id(app, number)?;
+            app.restore_trashed_session(session_id).map_err(|e| CommandError::Failed(e.to_string()))?;
+        }
+        [sub, target] if sub == "purge" => {
+            let session_ids: Vec<Uuid> = if target == "all" {
+                app.history_manager.lock().unwrap().get_history().trashed_sessions().iter().map(|session| session.id).collect()
+            } else {
+                vec![trashed_session_id(app, target)?]
+            };
+            let mut history_guard = app.history_manager.lock().unwrap();
+            for session_id in &session_ids {
+                let _ = history_guard.get_history_mut().delete_session(*session_id);
+            }
+            if let Err(e) = history_guard.save() {
+                return Err(CommandError::Failed(format!("履歴の保存に失敗しました: {}", e)));
+            }
+            drop(history_guard);
+            app.notify(format!("🗑 Permanently deleted {} session(s)", session_ids.len()));
+        }
+        _ => return Err(CommandError::Usage),
+    }
+    Ok(())
+}
+
+/// 現在のセッションをカレントディレクトリの contui_export_<timestamp>.csv に書き出す
+fn cmd_export(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !matches!(args, [format] if format == "csv") {
+        return Err(CommandError::Usage);
+    }
+    let (csv, count) = {
+        let history_guard = app.history_manager.lock().unwrap();
+        match history_guard.get_history().get_current_session() {
+            Some(session) => (session_csv(session), session.messages.len()),
+            None => return Err(CommandError::Failed("No active session".to_string())),
+        }
+    };
+    let file_name = format!("contui_export_{}.csv", Utc::now().format("%Y%m%d_%H%M%S"));
+    std::fs::write(&file_name, csv).map_err(|e| CommandError::Failed(format!("エクスポートに失敗しました: {}", e)))?;
+    app.notify(format!("📄 Exported {} messages to {}", count, file_name));
+    Ok(())
+}
+
+/// 全セッションをカレントディレクトリの contui_archive_<timestamp>.json に書き出す
+/// --with-steps を付けるとエージェントループの途中のステップも含める
+fn export_all_sessions(app: &mut ChatApp, with_steps: bool) -> Result<(), CommandError> {
+    let file_name = format!("contui_archive_{}.json", Utc::now().format("%Y%m%d_%H%M%S"));
+    let result = app
+        .history_manager
+        .lock()
+        .unwrap()
+        .export_all(std::path::Path::new(&file_name), with_steps);
+    let (session_count, message_count) =
+        result.map_err(|e| CommandError::Failed(format!("エクスポートに失敗しました: {}", e)))?;
+    app.push_system_message(format!(
+        "📦 Exported {} sessions ({} messages) to {}",
+        session_count, message_count, file_name
+    ));
+    Ok(())
+}
+
+/// 読み込みと変換はUIを止めないようブロッキングタスクで行い、結果はChatEventで受け取る
+fn cmd_import(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let [path] = args else {
+        return Err(CommandError::Usage);
+    };
+    let path = path.clone();
+    let gemini_client = app.gemini_client.clone();
+    let sender = app.event_sender.clone();
+    app.push_system_message(format!("📥 Importing {}...", path));
+    tokio::task::spawn_blocking(move || {
+        let result = gemini_client
+            .read_file(&path)
+            .and_then(|content| parse_import(&content))
+            .map_err(|e| e.to_string());
+        let _ = sender.send(ChatEvent::SessionImported { path, result });
+    });
+    Ok(())
+}
+
+fn cmd_changes(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let text = app.format_file_changes();
+    app.push_system_message(text);
+    Ok(())
+}
+
+/// コンテキストの各エントリをロール・文字数・プレビューで整形
+pub fn format_context(context: &[Content]) -> String {
+    let total_chars: usize = context.iter().map(content_char_count).sum();
+    let mut lines = vec![format!(
+        "Context sent to the LLM ({} entries, {} chars):",
+        context.len(),
+        total_chars
+    )];
+    for (i, content) in context.iter().enumerate() {
+        let preview_text = content
+            .parts
+            .iter()
+            .map(part_text)
+            .collect::<Vec<_>>()
+            .join(" ")
+            .replace('\n', " ");
+        lines.push(format!(
+            "[{}] {} ({} chars): {}",
+            i + 1,
+            content.role,
+            content_char_count(content),
+            ChatApp::truncate_string_safe(&preview_text, 60)
+        ));
+    }
+    lines.join("\n")
+}
+
+fn part_text(part: &Part) -> String {
+    match part {
+        Part::Text { text } => text.clone(),
+        Part::FunctionCall { function_call } => format!(
+            "FunctionCall: {}({})",
+            function_call.name,
+            serde_json::to_string(&function_call.args).unwrap_or_default()
+        ),
+        Part::FunctionResponse { function_response } => format!(
+            "FunctionResponse: {}: {}",
+            function_response.name,
+            serde_json::to_string(&function_response.response).unwrap_or_default()
+        ),
+        Part::ToolOutcome { tool_outcome } => tool_outcome.chip(),
+    }
+}
+
+fn content_char_count(content: &Content) -> usize {
+    content.parts.iter().map(|p| part_text(p).chars().count()).sum()
+}
+
+fn cmd_context(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    // 件数を省略したら送信時と同じくトークン数の上限で切り詰めたものを表示する
+    let context = match args {
+        [sub] if sub == "show" => (*app.history_manager.lock().unwrap()).get_conversation_context_with_budget(CONTEXT_TOKEN_BUDGET),
+        [sub, n] if sub == "show" => {
+            let max_messages = n.parse().map_err(|_| CommandError::Usage)?;
+            (*app.history_manager.lock().unwrap()).get_conversation_context(max_messages)
+        }
+        _ => return Err(CommandError::Usage),
+    };
+    app.push_system_message(format_context(&context));
+    Ok(())
+}
+
+fn cmd_tokens(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let context = (*app.history_manager.lock().unwrap()).get_conversation_context_with_budget(CONTEXT_TOKEN_BUDGET);
+    if context.is_empty() {
+        return Err(CommandError::Failed("The conversation context is empty".to_string()));
+    }
+    let gemini_client = match app.current_session_id() {
+        Some(session_id) => app.client_for_session(session_id),
+        None => app.gemini_client.clone(),
+    };
+    let sender = app.event_sender.clone();
+    app.notify("🔢 Counting tokens...");
+    tokio::spawn(async move {
+        let text = match gemini_client.count_tokens(&context).await {
+            Ok(tokens) => {
+                let window = gemini_client
+                    .context_window()
+                    .map(|limit| format!(", {:.1}% of the {} token window", tokens as f64 * 100.0 / limit as f64, group_digits(limit)))
+                    .unwrap_or_default();
+                format!(
+                    "🔢 Context: {} tokens ({} messages, {}{})",
+                    group_digits(tokens as u64),
+                    context.len(),
+                    gemini_client.model(),
+                    window
+                )
+            }
+            Err(e) => format!("❌ Token count failed: {}", e),
+        };
+        let _ = sender.send(ChatEvent::SystemMessage(text));
+    });
+    Ok(())
+}
+
+fn cmd_format_code(app: &mut ChatApp, _args: &[String]) -> Result<(), CommandError> {
+    app.format_code_in_input("/format-code").map_err(CommandError::Failed)
+}
+
+fn cmd_truncate(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let [keep] = args else {
+        return Err(CommandError::Usage);
+    };
+    let keep: usize = keep.parse().map_err(|_| CommandError::Usage)?;
+    // 要約中に消すと、要約が終わったときに記録する範囲が別のメッセージを指してしまう
+    if app.is_compacting() {
+        return Err(CommandError::Failed("要約の実行中はメッセージを削除できません".to_string()));
+    }
+    let removed = app
+        .history_manager
+        .lock()
+        .unwrap()
+        .get_history_mut()
+        .truncate_before_last(keep)
+        .map_err(|e| CommandError::Failed(e.to_string()))?;
+    if removed > 0 {
+        app.restore_session_messages();
+        app.autosave_history("cmd_truncate");
+    }
+    app.push_system_message(format!("Removed {} messages.", removed));
+    Ok(())
+}
+
+fn cmd_compact(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let keep_recent = match args {
+        [] => CONTEXT_MESSAGE_LIMIT,
+        [n] => n.parse().map_err(|_| CommandError::Usage)?,
+        _ => return Err(CommandError::Usage),
+    };
+    if app.is_compacting() {
+        return Err(CommandError::Failed("要約は既に実行中です".to_string()));
+    }
+    if app.start_compaction(keep_recent) {
+        app.push_system_message("🗜️ 古いメッセージの要約を開始しました".to_string());
+    } else {
+        app.push_system_message("Nothing to compact.".to_string());
+    }
+    Ok(())
+}
+
+fn cmd_compare(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let text = match args {
+        [] => match &app.compare_models {
+            Some((model_a, model_b)) => format!("Comparison mode: {} vs {}", model_a, model_b),
+            None => "Comparison mode is off.".to_string(),
+        },
+        [off] if off == "off" => {
+            app.compare_models = None;
+            "Comparison mode disabled.".to_string()
+        }
+        [model_a, model_b] => {
+            app.compare_models = Some((model_a.clone(), model_b.clone()));
+            format!("Comparison mode enabled: {} vs {}", model_a, model_b)
+        }
+        _ => return Err(CommandError::Usage),
+    };
+    app.push_system_message(text);
+    Ok(())
+}
+
+/// 現在有効な生成パラメータとその出所
+fn format_effective_settings(settings: &EffectiveSettings) -> String {
+    format!(
+        "temperature: {} ({})\nmax_tokens: {} ({})",
+        settings.temperature,
+        settings.temperature_source.label(),
+        settings.max_tokens,
+        settings.max_tokens_source.label()
+    )
+}
+
+fn cmd_temp(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let temperature = match args {
+        [] => {
+            let text = format_effective_settings(&app.gemini_client.effective_settings());
+            app.push_system_message(text);
+            return Ok(());
+        }
+        [off] if off == "off" => None,
+        [value] => {
+            let value: f32 = value.parse().map_err(|_| CommandError::Usage)?;
+            if !TEMPERATURE_RANGE.contains(&value) {
+                return Err(CommandError::Failed(format!(
+                    "temperature must be between {} and {}",
+                    TEMPERATURE_RANGE.start(),
+                    TEMPERATURE_RANGE.end()
+                )));
+            }
+            Some(value)
+        }
+        _ => return Err(CommandError::Usage),
+    };
+    app.update_generation_overrides(|overrides| overrides.temperature = temperature)
+        .map_err(|e| CommandError::Failed(e.to_string()))?;
+    let text = format_effective_settings(&app.gemini_client.effective_settings());
+    app.push_system_message(text);
+    Ok(())
+}
+
+fn cmd_maxtokens(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let max_tokens = match args {
+        [] => {
+            let text = format_effective_settings(&app.gemini_client.effective_settings());
+            app.push_system_message(text);
+            return Ok(());
+        }
+        [off] if off == "off" => None,
+        [value] => {
+            let value: u32 = value.parse().map_err(|_| CommandError::Usage)?;
+            if !MAX_TOKENS_RANGE.contains(&value) {
+                return Err(CommandError::Failed(format!(
+                    "max_tokens must be between {} and {}",
+                    MAX_TOKENS_RANGE.start(),
+                    MAX_TOKENS_RANGE.end()
+                )));
+            }
+            Some(value)
+        }
+        _ => return Err(CommandError::Usage),
+    };
+    app.update_generation_overrides(|overrides| overrides.max_tokens = max_tokens)
+        .map_err(|e| CommandError::Failed(e.to_string()))?;
+    let text = format_effective_settings(&app.gemini_client.effective_settings());
+    app.push_system_message(text);
+    Ok(())
+}
+
+fn cmd_cleanup(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let target = match args {
+        [] => {
+            let text = {
+                let history_guard = app.history_manager.lock().unwrap();
+                let history = history_guard.get_history();
+                let now = Utc::now();
+                format!(
+                    "Archived: {}, empty: {}, older than 30 days: {}\nUsage: /cleanup archived | empty | older <days>",
+                    history.cleanup_candidates(CleanupTarget::Archived, now).len(),
+                    history.cleanup_candidates(CleanupTarget::Empty, now).len(),
+                    history.cleanup_candidates(CleanupTarget::OlderThan(30), now).len()
+                )
+            };
+            app.push_system_message(text);
+            return Ok(());
+        }
+        [sub] if sub == "confirm" => {
+            let (target, session_ids) = app
+                .pending_cleanup
+                .take()
+                .ok_or_else(|| CommandError::Failed("確認待ちの削除はありません".to_string()))?;
+            let deleted = app.delete_sessions_bulk(&session_ids);
+            app.push_system_message(format!("🧹 Deleted {} {}", deleted, target.description()));
+            return Ok(());
+        }
+        [sub] if sub == "cancel" => {
+            app.pending_cleanup = None;
+            app.push_system_message("Cleanup cancelled.".to_string());
+            return Ok(());
+        }
+        [sub] if sub == "archived" => CleanupTarget::Archived,
+        [sub] if sub == "empty" => CleanupTarget::Empty,
+        [sub, days] if sub == "older" => {
+            let days: i64 = days.parse().map_err(|_| CommandError::Usage)?;
+            if days < 1 {
+                return Err(CommandError::Failed("days must be at least 1".to_string()));
+            }
+            CleanupTarget::OlderThan(days)
+        }
+        _ => return Err(CommandError::Usage),
+    };
+
+    let session_ids = app
+        .history_manager
+        .lock()
+        .unwrap()
+        .get_history()
+        .cleanup_candidates(target, Utc::now());
+    if session_ids.is_empty() {
+        app.pending_cleanup = None;
+        app.push_system_message(format!("No {} to delete.", target.description()));
+        return Ok(());
+    }
+    app.push_system_message(format!(
+        "⚠️ {} {} will be deleted (the current session is kept). Type /cleanup confirm to proceed or /cleanup cancel.",
+        session_ids.len(),
+        target.description()
+    ));
+    app.pending_cleanup = Some((target, session_ids));
+    Ok(())
+}
+
+/// 会話の統計を表示用に整形
+fn format_stats(stats: &HistoryStats) -> String {
+    let average_response_time = match stats.average_response_time {
+        Some(duration) => format!("{:.1}s", duration.num_milliseconds() as f64 / 1000.0),
+        None => "-".to_string(),
+    };
+    let longest_message = match &stats.longest_message {
+        Some(longest) => format!(
+            "{} chars ({} in \"{}\"): {}",
+            longest.chars,
+            if longest.is_user { "You" } else { "AI" },
+            longest.session_title,
+            longest.preview
+        ),
+        None => "-".to_string(),
+    };
+    [
+        "📊 Conversation stats".to_string(),
+        format!("Sessions:              {}", stats.sessions),
+        format!("Messages:              {}", stats.messages),
+        format!("Sent:                  {} chars (~{} tokens)", stats.chars_sent, stats.tokens_sent),
+        format!("Received:              {} chars (~{} tokens)", stats.chars_received, stats.tokens_received),
+        format!("Average response time: {}", average_response_time),
+        format!("Longest message:       {}", longest_message),
+    ]
+    .join("\n")
+}
+
+fn cmd_stats(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let stats = app.history_manager.lock().unwrap().get_history().stats();
+    app.push_system_message(format_stats(&stats));
+    Ok(())
+}
+
+/// セッションのモデル別使用量と料金の内訳
+fn format_usage(usage: &SessionUsage) -> String {
+    if usage.is_empty() {
+        return "No API usage recorded in this session.".to_string();
+    }
+    let mut lines = vec!["💰 Usage this session".to_string()];
+    for (model, model_usage) in &usage.models {
+        let estimated = if model_usage.estimated_requests > 0 {
+            format!(" ({} estimated from characters)", model_usage.estimated_requests)
+        } else {
+            String::new()
+        };
+        lines.push(format!(
+            "{}: {} requests, {} in / {} out tokens, {}{}",
+            model,
+            model_usage.requests,
+            model_usage.input_tokens,
+            model_usage.output_tokens,
+            format_cost(model_usage.cost_usd),
+            estimated
+        ));
+    }
+    let unpriced = if usage.total_cost().is_some() && usage.has_unpriced() {
+        " (excluding models without pricing)"
+    } else {
+        ""
+    };
+    lines.push(format!("Total: {}{}", format_cost(usage.total_cost()), unpriced));
+    lines.join("\n")
+}
+
+fn cmd_usage(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let text = {
+        let history_guard = app.history_manager.lock().unwrap();
+        match history_guard.get_history().get_current_session() {
+            Some(session) => match session.messages.iter().filter(|message| message.excluded).count() {
+                0 => format_usage(&session.usage),
+                excluded => format!("{}\n🚫 {} message(s) excluded from the context", format_usage(&session.usage), excluded),
+            },
+            None => return Err(CommandError::Failed("No active session".to_string())),
+        }
+    };
+    app.push_system_message(text);
+    Ok(())
+}
+
+fn cmd_format(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let text = match args {
+        [] => format!("Render mode: {}", app.ui.render_mode.label()),
+        [mode] => {
+            app.ui.render_mode = RenderMode::parse(mode).ok_or(CommandError::Usage)?;
+            format!("Render mode set to {}", app.ui.render_mode.label())
+        }
+        _ => return Err(CommandError::Usage),
+    };
+    app.push_system_message(text);
+    Ok(())
+}
+
+fn cmd_tab(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    match args {
+        [] => {
+            let lines: Vec<String> = app
+                .tab_labels()
+                .iter()
+                .enumerate()
+                .map(|(index, tab)| {
+                    let marker = if tab.active { "*" } else { " " };
+                    let busy = if tab.busy { " (waiting for a response)" } else { "" };
+                    format!("{} {}: {}{}", marker, index + 1, tab.title, busy)
+                })
+                .collect();
+            app.push_system_message(format!("Tabs:\n{}", lines.join("\n")));
+        }
+        [action] if action == "new" => app.open_tab(None),
+        [action] if action == "close" => app.request_close_tab(),
+        [number] => {
+            let index = number.parse::<usize>().ok().filter(|n| (1..=app.tabs.len()).contains(n)).ok_or_else(|| {
+                CommandError::Failed(format!("No tab {} (open tabs: {})", number, app.tabs.len()))
+            })?;
+            app.activate_tab(index - 1);
+        }
+        _ => return Err(CommandError::Usage),
+    }
+    Ok(())
+}
+
+fn cmd_appearance(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let (option, value) = match args {
+        [] => (None, None),
+        [option] => (Some(option.as_str()), None),
+        [option, value] => (Some(option.as_str()), Some(value.as_str())),
+        _ => return Err(CommandError::Usage),
+    };
+    let setting = match option {
+        None => None,
+        Some("ascii") => Some(&mut app.appearance.ascii_mode),
+        Some("contrast") => Some(&mut app.appearance.high_contrast),
+        Some(_) => return Err(CommandError::Usage),
+    };
+    if let Some(setting) = setting {
+        // on/offを省略したら切り替える
+        *setting = match value {
+            None => !*setting,
+            Some("on") => true,
+            Some("off") => false,
+            Some(_) => return Err(CommandError::Usage),
+        };
+    }
+    let label = |enabled: bool| if enabled { "on" } else { "off" };
+    let text = format!(
+        "ASCII mode: {}, high contrast: {}",
+        label(app.appearance.ascii_mode),
+        label(app.appearance.high_contrast)
+    );
+    app.push_system_message(text);
+    Ok(())
+}
+
+fn cmd_doctor(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let keyboard_enhancement = crossterm::terminal::supports_keyboard_enhancement().unwrap_or(false);
+    let sender = app.event_sender.clone();
+    tokio::spawn(async move {
+        let report = crate::diagnostics::run_diagnostics(keyboard_enhancement).await;
+        let _ = sender.send(ChatEvent::SystemMessage(report.to_string()));
+    });
+    app.push_system_message("🩺 Running diagnostics...".to_string());
+    Ok(())
+}
+
+fn cmd_bookmark(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let [label] = args else {
+        return Err(CommandError::Usage);
+    };
+    if label == "list" {
+        let text = format_bookmarks(app);
+        app.push_system_message(text);
+        return Ok(());
+    }
+    let message_id = app
+        .selected_message_index()
+        .and_then(|i| app.messages.get(i))
+        .map(|m| m.id)
+        .ok_or_else(|| CommandError::Failed("No message selected".to_string()))?;
+    // 履歴に保存されていない表示専用のメッセージはブックマークできない
+    let session_id = {
+        let history_guard = app.history_manager.lock().unwrap();
+        history_guard
+            .get_history()
+            .get_current_session()
+            .filter(|session| session.messages.iter().any(|m| m.id == message_id))
+            .map(|session| session.id)
+    }
+    .ok_or_else(|| CommandError::Failed("Only saved messages can be bookmarked".to_string()))?;
+
+    app.ui.bookmarks.insert(label.clone(), (session_id, message_id));
+    if let Some(path) = default_bookmarks_path() {
+        save_bookmarks(&path, &app.ui.bookmarks).map_err(|e| CommandError::Failed(e.to_string()))?;
+    }
+    app.push_system_message(format!("🔖 Bookmarked as '{}'", label));
+    Ok(())
+}
+
+/// ブックマーク一覧（ラベル順、セッション名とメッセージの冒頭を添える）
+fn format_bookmarks(app: &ChatApp) -> String {
+    if app.ui.bookmarks.is_empty() {
+        return "No bookmarks. Select a message and use /bookmark <label>.".to_string();
+    }
+    let mut labels: Vec<&String> = app.ui.bookmarks.keys().collect();
+    labels.sort();
+    let history_guard = app.history_manager.lock().unwrap();
+    let history = history_guard.get_history();
+    let mut lines = vec![format!("Bookmarks ({}):", labels.len())];
+    for label in labels {
+        let (session_id, message_id) = app.ui.bookmarks[label];
+        let session = history.sessions.get(&session_id);
+        let message = session.and_then(|s| s.messages.iter().find(|m| m.id == message_id));
+        let line = match (session, message) {
+            (Some(session), Some(message)) => {
+                let text: String = message.parts.iter().map(part_text).collect::<Vec<_>>().join(" ");
+                let preview = ChatApp::truncate_string_safe(&text.replace('\n', " "), 40);
+                format!("  {} — {}: {}", label, session.title, preview)
+            }
+            _ => format!("  {} — (message no longer exists)", label),
+        };
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+fn cmd_goto(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let [label] = args else {
+        return Err(CommandError::Usage);
+    };
+    let &(session_id, message_id) = app
+        .ui
+        .bookmarks
+        .get(label)
+        .ok_or_else(|| CommandError::Failed(format!("No bookmark named '{}'", label)))?;
+    if !app.goto_message(session_id, message_id) {
+        return Err(CommandError::Failed(format!("Bookmarked message for '{}' no longer exists", label)));
+    }
+    Ok(())
+}
+
+/// get_session_list() の順（更新が新しい順、1始まり）のセッションのユーザーメッセージを、新しいセッションで1件ずつ送り直す
+/// 送信キューに積むので、前の応答が終わってから次を送る
+fn cmd_replay(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let [index] = args else {
+        return Err(CommandError::Usage);
+    };
+    let index = index.parse::<usize>().map_err(|_| CommandError::Usage)?;
+    if app.task_queue.is_processing() || app.task_queue.pending() > 0 {
+        return Err(CommandError::Failed("Wait for the current request to finish before /replay".to_string()));
+    }
+    let (title, prompts) = {
+        let history_guard = app.history_manager.lock().unwrap();
+        let sessions = history_guard.get_history().get_session_list();
+        let session = index
+            .checked_sub(1)
+            .and_then(|i| sessions.get(i))
+            .ok_or_else(|| CommandError::Failed(format!("No session #{} (there are {})", index, sessions.len())))?;
+        (session.title.clone(), user_prompts(session))
+    };
+    if prompts.is_empty() {
+        return Err(CommandError::Failed(format!("Session #{} has no user messages to replay", index)));
+    }
+
+    app.create_new_session();
+    let Some(session_id) = app.history_manager.lock().unwrap().get_history().current_session_id else {
+        return Err(CommandError::Failed("Could not create a session to replay into".to_string()));
+    };
+    app.push_system_message(format!("🔁 Replaying {} message(s) from #{} {}", prompts.len(), index, title));
+    // 添付ファイルは送り直さないので、元のメッセージに付けた添付の要約も外す。途中でセッションを切り替えても作ったセッションに送る
+    for prompt in prompts {
+        let message = strip_attachment_summary(&prompt).to_string();
+        app.task_queue.enqueue(UserTask { message, files: Vec::new(), session_id });
+    }
+    app.process_next_task();
+    Ok(())
+}
+
+/// get_session_list() の順（更新が新しい順、1始まり）でセッションを比較する
+fn cmd_diff(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let [old_index, new_index] = args else {
+        return match args {
+            [] => show_git_diff(app, app.touched_paths()),
+            [path] => show_git_diff(app, vec![path.clone()]),
+            _ => Err(CommandError::Usage),
+        };
+    };
+    let (Ok(old_index), Ok(new_index)) = (old_index.parse::<usize>(), new_index.parse::<usize>()) else {
+        return Err(CommandError::Usage);
+    };
+    let lines = {
+        let history_guard = app.history_manager.lock().unwrap();
+        let sessions = history_guard.get_history().get_session_list();
+        let session = |index: usize| {
+            index
+                .checked_sub(1)
+                .and_then(|i| sessions.get(i))
+                .ok_or_else(|| CommandError::Failed(format!("No session #{} (there are {})", index, sessions.len())))
+        };
+        let (old, new) = (session(old_index)?, session(new_index)?);
+        unified_diff_lines(
+            &format!("#{} {}", old_index, old.title),
+            &session_transcript(old),
+            &format!("#{} {}", new_index, new.title),
+            &session_transcript(new),
+        )
+    };
+    app.ui.diff_lines = lines;
+    app.ui.diff_scroll = 0;
+    app.ui.input_mode = InputMode::DiffView;
+    Ok(())
+}
+
+/// 指定したパス（空ならこのセッションでAIが変更したファイル）の `git diff` をDiffViewで表示する
+pub(crate) fn show_git_diff(app: &mut ChatApp, paths: Vec<String>) -> Result<(), CommandError> {
+    if paths.is_empty() {
+        return Err(CommandError::Failed("No files have been changed by the AI in this session. Use /diff <path>.".to_string()));
+    }
+    let dir = std::path::Path::new(".");
+    let repo = RepoStatus::load(app.config.shell, dir)
+        .ok_or_else(|| CommandError::Failed("git diff is unavailable (not a git repository or git is not installed)".to_string()))?;
+    let output = git::diff(app.config.shell, dir, &paths)
+        .ok_or_else(|| CommandError::Failed("git diff failed".to_string()))?;
+    let mut lines = git_diff_lines(&output);
+    // 追跡されていないファイルはgit diffに出ないので、その旨だけ示す
+    for path in &paths {
+        if repo.status_of(std::path::Path::new(path)) == Some(GitStatus::Untracked) {
+            lines.push(DiffLine { kind: DiffLineKind::Header, text: format!("?? {} (untracked, not shown by git diff)", path) });
+        }
+    }
+    if lines.is_empty() {
+        app.push_system_message(format!("No git changes in {}", paths.join(", ")));
+        return Ok(());
+    }
+    app.ui.diff_lines = lines;
+    app.ui.diff_scroll = 0;
+    app.ui.input_mode = InputMode::DiffView;
+    Ok(())
+}
+
+fn cmd_template(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    let dir = default_templates_dir()
+        .ok_or_else(|| CommandError::Failed("Cannot find config directory".to_string()))?;
+    match args {
+        [sub] if sub == "list" => {
+            let names = list_templates(&dir).map_err(|e| CommandError::Failed(e.to_string()))?;
+            let text = if names.is_empty() {
+                "No templates. Use /template save <name> with the prompt on the following lines.".to_string()
+            } else {
+                format!("Templates ({}):\n{}", names.len(), names.iter().map(|n| format!("  {}", n)).collect::<Vec<_>>().join("\n"))
+            };
+            app.push_system_message(text);
+        }
+        // コマンド行の次の行以降をテンプレートの本文として保存する
+        [sub, name, ..] if sub == "save" => {
+            let (command_line, body) = app.ui.input.trim_start().split_once('\n').unwrap_or((app.ui.input.trim(), ""));
+            if parse_command_line(command_line).map(|(_, args)| args.len()) != Some(2) {
+                return Err(CommandError::Usage);
+            }
+            let mut text = body.trim().to_string();
+            // ファイルブラウザで選択中のファイルも @file 参照として含める
+            for path in &app.ui.selected_files {
+                let reference = format_file_reference(path);
+                if !text.contains(&reference) {
+                    if !text.is_empty() {
+                        text.push('\n');
+                    }
+                    text.push_str(&reference);
+                }
+            }
+            if text.is_empty() {
+                return Err(CommandError::Failed(
+                    "Nothing to save: write the prompt on the lines after /template save <name>".to_string(),
+                ));
+            }
+            let path = save_template(&dir, name, &text).map_err(|e| CommandError::Failed(e.to_string()))?;
+            app.push_system_message(format!("📄 Saved template '{}' to {}", name, path.display()));
+        }
+        [sub, name] if sub == "load" => {
+            let text = load_template(&dir, name).map_err(|e| CommandError::Failed(e.to_string()))?;
+            app.set_input(text);
+            app.ui.selected_files.clear();
+            app.ui.input_mode = InputMode::Insert;
+            app.push_system_message(format!("📄 Loaded template '{}'", name));
+        }
+        _ => return Err(CommandError::Usage),
+    }
+    Ok(())
+}
+
+/// /notifications で表示する通知の件数
+const NOTIFICATIONS_SHOWN: usize = 20;
+
+fn cmd_notifications(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let history = &app.ui.notification_history;
+    if history.is_empty() {
+        return Err(CommandError::Failed("No notifications yet".to_string()));
+    }
+    let shown = &history[history.len().saturating_sub(NOTIFICATIONS_SHOWN)..];
+    let mut text = format!("🔔 Notifications (last {} of {}):", shown.len(), history.len());
+    for (timestamp, message) in shown {
+        text.push_str(&format!("\n[{}] {}", timestamp.with_timezone(&Local).format("%H:%M:%S"), message));
+    }
+    app.ui.unread_notifications = 0;
+    app.push_system_message(text);
+    Ok(())
+}
+
+fn cmd_version(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let text = format!(
+        "contui v{} (model: {})",
+        env!("CARGO_PKG_VERSION"),
+        app.gemini_client.model()
+    );
+    app.push_system_message(text);
+    Ok(())
+}
+
+fn cmd_flush(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let mut history_guard = app.history_manager.lock().unwrap();
+    let pending = history_guard.pending_writes().len();
+    let result = history_guard.flush();
+    app.ui.save_warning = history_guard.pending_write_warning();
+    drop(history_guard);
+    match result {
+        Ok(()) => {
+            app.ui.unsaved = false;
+            app.notify(match pending {
+                0 => "💾 History saved".to_string(),
+                pending => format!("💾 History saved ({} pending message(s) written)", pending),
+            });
+            Ok(())
+        }
+        Err(e) => Err(CommandError::Failed(format!("History save failed: {:#}", e))),
+    }
+}
+
+fn cmd_permissions(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    app.open_permissions();
+    Ok(())
+}
+
+fn cmd_gemini_version(app: &mut ChatApp, args: &[String]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let gemini_client = app.gemini_client.clone();
+    let sender = app.event_sender.clone();
+    app.notify("📋 Fetching the model list...");
+    tokio::spawn(async move {
+        let text = match gemini_client.list_models().await {
+            Ok(models) => format_model_list(&models, gemini_client.model()),
+            Err(e) => format!("❌ Failed to fetch the model list: {}", e),
+        };
+        let _ = sender.send(ChatEvent::SystemMessage(text));
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command_line() {
+        assert_eq!(parse_command_line("hello"), None);
+        assert_eq!(parse_command_line("/help"), Some(("help".to_string(), vec![])));
+        assert_eq!(
+            parse_command_line("  /help   clearlog  "),
+            Some(("help".to_string(), vec!["clearlog".to_string()]))
+        );
+        assert_eq!(
+            parse_command_line(r#"/bookmark "my label" x"#),
+            Some(("bookmark".to_string(), vec!["my label".to_string(), "x".to_string()]))
+        );
+        assert_eq!(
+            parse_command_line(r#"/echo """#),
+            Some(("echo".to_string(), vec![String::new()]))
+        );
+        assert_eq!(parse_command_line("/"), Some((String::new(), vec![])));
+    }
+
+    #[test]
+    fn test_find_by_name_and_alias() {
+        let registry = CommandRegistry::new();
+        assert_eq!(registry.find("help").map(|c| c.name), Some("help"));
+        assert_eq!(registry.find("?").map(|c| c.name), Some("help"));
+        assert!(registry.find("nonexistent").is_none());
+    }
+
+    #[test]
+    fn test_unknown_command_suggestions() {
+        let registry = CommandRegistry::new();
+        assert_eq!(registry.suggest("hlep").first(), Some(&"help"));
+        assert_eq!(registry.suggest("clear"), vec!["clear", "clearlog"]);
+        assert!(registry.suggest("zzzzzzzz").is_empty());
+
+        let msg = registry.unknown_command_message("sesion");
+        assert!(msg.contains("Unknown command: /sesion"));
+        assert!(msg.contains("Did you mean: /session"));
+
+        let msg = registry.unknown_command_message("zzzzzzzz");
+        assert!(!msg.contains("Did you mean"));
+        assert!(msg.contains("/help"));
+    }
+
+    #[test]
+    fn test_format_context() {
+        let context = vec![
+            Content { role: "user".to_string(), parts: vec![Part::Text { text: "hello\nworld".to_string() }] },
+            Content { role: "model".to_string(), parts: vec![Part::Text { text: "x".repeat(100) }] },
+        ];
+        let text = format_context(&context);
+        assert!(text.starts_with("Context sent to the LLM (2 entries, 111 chars):"));
+        assert!(text.contains("[1] user (11 chars): hello world"));
+        assert!(text.contains(&format!("[2] model (100 chars): {}...", "x".repeat(60))));
+    }
+
+    #[test]
+    fn test_format_directory_listing() {
+        use chrono::TimeZone;
+        let modified = chrono::Local.with_ymd_and_hms(2024, 5, 1, 9, 30, 0).unwrap();
+        let entries = vec![
+            DirectoryEntry { name: "src".to_string(), is_dir: true, size: 4096, modified: Some(modified.into()) },
+            DirectoryEntry { name: "README.md".to_string(), is_dir: false, size: 512, modified: Some(modified.into()) },
+            DirectoryEntry { name: "データ.bin".to_string(), is_dir: false, size: 3 * 1024 * 1024 / 2, modified: None },
+        ];
+        let listing = format_directory_listing("/work", &entries);
+        let dir = format!("src{}", DIRECTORY_SUFFIX);
+        assert_eq!(
+            listing.lines().collect::<Vec<_>>(),
+            vec![
+                "/work (1 directories, 2 files)".to_string(),
+                "Name          Size  Modified".to_string(),
+                format!("{}             -  2024-05-01 09:30", dir),
+                "README.md    512 B  2024-05-01 09:30".to_string(),
+                "データ.bin  1.5 MB".to_string(),
+            ]
+        );
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+    }
+
+    #[test]
+    fn test_help_text_lists_all_commands() {
+        let registry = CommandRegistry::new();
+        let help = registry.help_text();
+        for command in registry.commands() {
+            assert!(help.contains(command.usage));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_sends_prompts_one_by_one_into_new_session() {
+        let (_dir, mut app) = crate::app::file_operations::tests::test_app("replay", false);
+        let source = app.history_manager.lock().unwrap().ensure_active_session();
+        {
+            let mut history_guard = app.history_manager.lock().unwrap();
+            let history = history_guard.get_history_mut();
+            history.add_message(vec![Part::Text { text: "first\n📎 1 file, 10 B total: a.rs\n⚠ Skipped b.rs: not found".to_string() }], true).unwrap();
+            history.add_message(vec![Part::Text { text: "answer".to_string() }], false).unwrap();
+            history.add_message(vec![Part::Text { text: "second".to_string() }], true).unwrap();
+        }
+        app.execute_slash_command("/replay 1");
+
+        let replay = app.history_manager.lock().unwrap().get_history().current_session_id.unwrap();
+        assert_ne!(replay, source);
+        let messages = |app: &ChatApp, session_id: Uuid| -> Vec<String> {
+            let history_guard = app.history_manager.lock().unwrap();
+            let session = &history_guard.get_history().sessions[&session_id];
+            session.messages.iter().map(|message| match &message.parts[..] {
+                [Part::Text { text }] => text.clone(),
+                _ => String::new(),
+            }).collect()
+        };
+        // 1件目だけ送り、添付の要約は外す
+        assert_eq!(messages(&app, replay), vec!["first"]);
+        assert!(app.task_queue.is_processing());
+        assert_eq!(app.task_queue.pending(), 1);
+
+        // 応答待ちの間に元のセッションへ切り替えても、次のメッセージは作ったセッションに送る
+        app.history_manager.lock().unwrap().get_history_mut().switch_session(source).unwrap();
+        app.restore_session_messages();
+        app.handle_chat_event(ChatEvent::for_session(replay, ChatEvent::TaskFinished));
+        assert_eq!(messages(&app, replay), vec!["first", "second"]);
+        assert_eq!(messages(&app, source).len(), 3);
+        assert_eq!(app.messages.len(), 3);
+        assert_eq!(app.task_queue.pending(), 0);
+    }
+
+    #[test]
+    fn test_echo_inserts_note_not_sent_to_ai() {
+        let (_dir, mut app) = crate::app::file_operations::tests::test_app("echo", false);
+        app.execute_slash_command("/echo ---- Hello,   world! ----");
+
+        let note = app.messages.last().unwrap();
+        assert!(note.is_system && !note.is_user);
+        assert!(matches!(&note.parts[..], [Part::Text { text }] if text == "---- Hello,   world! ----"));
+        let history_guard = app.history_manager.lock().unwrap();
+        let session = history_guard.get_history().get_current_session().unwrap();
+        assert_eq!(session.messages.last().unwrap().id, note.id);
+        // メモはAIに送るコンテキストに含めない
+        assert!(history_guard.get_conversation_context_with_budget(CONTEXT_TOKEN_BUDGET).is_empty());
+        drop(history_guard);
+
+        app.execute_slash_command("/echo say \"hi  there\"\n  indented");
+        assert!(matches!(&app.messages.last().unwrap().parts[..], [Part::Text { text }] if text == "say \"hi  there\"\n  indented"));
+
+        app.execute_slash_command("/echo");
+        assert!(matches!(&app.messages.last().unwrap().parts[..], [Part::Text { text }] if text.starts_with("❌ Usage: /echo")));
+    }
+
+    #[test]
+    fn test_redact_off_applies_only_to_current_session() {
+        let (_dir, mut app) = crate::app::file_operations::tests::test_app("redact", false);
+        app.create_new_session();
+        let first = app.current_session_id().unwrap();
+        app.execute_slash_command("/redact off");
+        assert!(!app.redaction_enabled(first));
+        assert!(!app.client_for_session(first).redactor().is_enabled());
+
+        app.create_new_session();
+        let second = app.current_session_id().unwrap();
+        assert!(app.redaction_enabled(second));
+        assert!(app.client_for_session(second).redactor().is_enabled());
+        assert!(app.gemini_client.redactor().is_enabled());
+
+        app.execute_slash_command("/redact");
+        assert!(matches!(&app.messages.last().unwrap().parts[..], [Part::Text { text }] if text.contains("is on for this session")));
+    }
+
+    #[test]
+    fn test_cd_outside_allowed_directories_waits_for_access() {
+        let (_dir, mut app) = crate::app::file_operations::tests::test_app("cd", false);
+        let outside = crate::test_common::TempDir::new("cd_outside");
+        let before = std::env::current_dir().unwrap();
+        let current_directory = app.ui.current_directory.clone();
+        app.execute_slash_command(&format!("/cd {}", outside.display()));
+
+        // 確認中は移動しない
+        let prompt = app.ui.directory_prompt.clone().unwrap();
+        assert_eq!(prompt.change_directory, Some(outside.canonicalize().unwrap()));
+        assert_eq!(std::env::current_dir().unwrap(), before);
+        assert_eq!(app.ui.current_directory, current_directory);
+
+        // 拒否したら移動しない
+        assert!(!app.answer_directory_prompt(crate::app::file_operations::DirectoryGrant::Deny));
+        assert_eq!(std::env::current_dir().unwrap(), before);
+        assert_eq!(app.ui.current_directory, current_directory);
+    }
+
+    #[tokio::test]
+    async fn test_truncate_is_refused_while_compacting() {
+        let (_dir, mut app) = crate::app::file_operations::tests::test_app("truncate_compacting", false);
+        app.history_manager.lock().unwrap().ensure_active_session();
+        for text in ["one", "two", "three"] {
+            app.history_manager.lock().unwrap().get_history_mut().add_message(vec![Part::Text { text: text.to_string() }], true).unwrap();
+        }
+        app.compaction_handle = Some(tokio::spawn(std::future::pending::<()>()));
+        app.execute_slash_command("/truncate 1");
+        assert!(matches!(&app.messages.last().unwrap().parts[..], [Part::Text { text }] if text.starts_with("❌")));
+        assert_eq!(app.history_manager.lock().unwrap().get_history().get_current_session().unwrap().messages.len(), 3);
+
+        app.compaction_handle.take().unwrap().abort();
+        app.execute_slash_command("/truncate 1");
+        assert_eq!(app.history_manager.lock().unwrap().get_history().get_current_session().unwrap().messages.len(), 1);
+    }
+}
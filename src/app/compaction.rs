@@ -0,0 +1,87 @@
+use crate::app::{ChatApp, ChatEvent, CONTEXT_MESSAGE_LIMIT};
+use crate::debug_log;
+use uuid::Uuid;
+
+impl ChatApp {
+    /// 自動要約が有効なら、コンテキストから外れるメッセージを要約する
+    pub fn maybe_compact_context(&mut self) {
+        if !self.config.auto_compact {
+            return;
+        }
+        self.start_compaction(CONTEXT_MESSAGE_LIMIT);
+    }
+
+    /// セッションがMAX_SESSION_MESSAGESを超えたら古いメッセージを削除する（保存は呼び出し側で行う）
+    /// 要約中は要約する範囲のインデックスがずれるので、次のメッセージまで待つ
+    pub fn trim_session_to_limit(&mut self, session_id: Uuid) {
+        let Some(max_messages) = self.config.max_session_messages else {
+            return;
+        };
+        if self.is_compacting() {
+            return;
+        }
+        let removed = self.history_manager.lock().unwrap().get_history_mut().trim_session(session_id, max_messages);
+        match removed {
+            Ok(0) => {}
+            Ok(_) => {
+                if self.is_visible_session(session_id) {
+                    self.restore_session_messages();
+                }
+                self.notify(format!("Auto-trimmed session to {} messages.", max_messages));
+            }
+            Err(e) => {
+                debug_log!("[trim_session_to_limit] {}: {:?}", session_id, e);
+            }
+        }
+    }
+
+    pub fn is_compacting(&self) -> bool {
+        self.compaction_handle.as_ref().is_some_and(|h| !h.is_finished())
+    }
+
+    /// 直近keep_recent件より前の未要約メッセージをバックグラウンドで要約する
+    /// 要約対象がない・既に実行中の場合はfalseを返す
+    pub fn start_compaction(&mut self, keep_recent: usize) -> bool {
+        if self.is_compacting() {
+            return false;
+        }
+        let request = self.history_manager.lock().unwrap().get_history().compaction_request(keep_recent);
+        let Some(request) = request else {
+            return false;
+        };
+
+        let gemini_client = match self.current_session_id() {
+            Some(session_id) => self.client_for_session(session_id),
+            None => self.gemini_client.clone(),
+        };
+        let sender = self.event_sender.clone();
+        let history_manager = self.history_manager.clone();
+        let handle = tokio::spawn(async move {
+            debug_log!("[compaction] summarizing {} messages (last_index={})", request.messages.len(), request.last_index);
+            let result = gemini_client
+                .summarize_conversation(request.previous_summary.as_deref(), &request.messages)
+                .await;
+            match result {
+                Ok(summary) => {
+                    let mut history_guard = history_manager.lock().unwrap();
+                    if let Err(e) = history_guard.get_history_mut().set_summary(request.session_id, summary, request.last_index) {
+                        debug_log!("[compaction] set_summary error: {:?}", e);
+                        return;
+                    }
+                    if let Err(e) = history_guard.save() {
+                        debug_log!("[compaction] save_history error: {:?}", e);
+                    }
+                    let _ = sender.send(ChatEvent::Notification(format!(
+                        "🗜️ {}件の古いメッセージを要約しました",
+                        request.messages.len()
+                    )));
+                }
+                Err(e) => {
+                    let _ = sender.send(ChatEvent::Error(format!("❌ 会話の要約に失敗しました: {}", e)));
+                }
+            }
+        });
+        self.compaction_handle = Some(handle);
+        true
+    }
+}
@@ -0,0 +1,154 @@
+use unicode_width::UnicodeWidthStr;
+
+use crate::app::appearance::Appearance;
+use crate::app::{ChatApp, ChatEvent, CONTEXT_TOKEN_BUDGET};
+use crate::debug_log;
+use crate::gemini::Part;
+use crate::history::ChatMessage;
+use crate::markdown::wrap_text;
+
+/// 比較表示の左右の区切り
+const COLUMN_SEPARATOR: &str = " │ ";
+
+impl ChatApp {
+    /// 同じメッセージを2つのモデルに並列で送信する
+    pub fn start_comparison(&mut self, message: String, model_a: String, model_b: String) {
+        let sender = self.event_sender.clone();
+        let (session_id, context) = {
+            let history_guard = self.history_manager.lock().unwrap();
+            let Some(session_id) = history_guard.get_history().current_session_id else {
+                drop(history_guard);
+                self.notify("❌ No active session to compare in".to_string());
+                return;
+            };
+            (session_id, history_guard.get_conversation_context_with_budget(CONTEXT_TOKEN_BUDGET))
+        };
+        let client = self.client_for_session(session_id);
+        let client_a = client.with_model(&model_a);
+        let client_b = client.with_model(&model_b);
+
+        if let Some(handle) = self.llm_task_handle.take() {
+            handle.abort();
+        }
+        let handle = tokio::spawn(async move {
+            debug_log!("[start_comparison] {} vs {}: {} bytes", model_a, model_b, message.len());
+            let (result_a, result_b) = tokio::join!(
+                client_a.chat_text_only(&message, Some(&context)),
+                client_b.chat_text_only(&message, Some(&context)),
+            );
+            let format_result = |result: anyhow::Result<String>| match result {
+                Ok(text) => text,
+                Err(e) => format!("❌ {}", e),
+            };
+            let comparison = ChatEvent::Comparison {
+                session_id,
+                model_a,
+                response_a: format_result(result_a),
+                model_b,
+                response_b: format_result(result_b),
+            };
+            let _ = sender.send(ChatEvent::for_session(session_id, comparison));
+            let _ = sender.send(ChatEvent::for_session(session_id, ChatEvent::TaskFinished));
+        });
+        self.llm_task_handle = Some(handle);
+        self.task_session = Some(session_id);
+        self.start_request_timer(session_id);
+    }
+
+    /// 比較結果を1つのメッセージとして比較を始めたセッションに保存し、表示中ならそのまま表示する
+    pub fn handle_comparison_result(&mut self, session_id: uuid::Uuid, model_a: String, response_a: String, model_b: String, response_b: String) {
+        let parts = vec![
+            Part::Text { text: format!("[{}]\n{}", model_a, response_a) },
+            Part::Text { text: format!("[{}]\n{}", model_b, response_b) },
+        ];
+        let message = ChatMessage {
+            id: uuid::Uuid::new_v4(),
+            parts,
+            is_user: false,
+            timestamp: chrono::Utc::now(),
+            comparison: true,
+            steps: Vec::new(),
+            is_system: false,
+            excluded: false,
+        };
+        {
+            let mut history_guard = self.history_manager.lock().unwrap();
+            if let Err(e) = history_guard.get_history_mut().add_chat_message_to(session_id, message.clone()) {
+                debug_log!("[handle_comparison_result] add_chat_message_to error: {:?}", e);
+            }
+        }
+        self.ui.unsaved = true;
+        self.autosave_history("handle_comparison_result");
+        if self.is_visible_session(session_id) {
+            self.messages.push(message);
+            self.auto_scroll_if_at_bottom();
+        }
+    }
+}
+
+/// 比較メッセージの各partを左右に並べた表示行を作る
+pub fn side_by_side_lines(parts: &[Part], max_width: usize, appearance: Appearance) -> Vec<String> {
+    let column_width = (max_width.saturating_sub(COLUMN_SEPARATOR.width()) / 2).max(1);
+    let columns: Vec<Vec<String>> = parts
+        .iter()
+        .take(2)
+        .map(|part| match part {
+            Part::Text { text } => wrap_text(&appearance.text(text), column_width).lines().map(str::to_string).collect(),
+            _ => Vec::new(),
+        })
+        .collect();
+    let empty = Vec::new();
+    let left = columns.first().unwrap_or(&empty);
+    let right = columns.get(1).unwrap_or(&empty);
+
+    (0..left.len().max(right.len()))
+        .map(|i| {
+            let left_line = left.get(i).map(String::as_str).unwrap_or("");
+            let right_line = right.get(i).map(String::as_str).unwrap_or("");
+            let padding = column_width.saturating_sub(left_line.width());
+            format!("{}{}{}{}", left_line, " ".repeat(padding), COLUMN_SEPARATOR, right_line)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_side_by_side_lines_pads_left_column() {
+        let parts = vec![
+            Part::Text { text: "[a]\nleft".to_string() },
+            Part::Text { text: "[b]\nright\nmore".to_string() },
+        ];
+        let lines = side_by_side_lines(&parts, 23, Appearance::default());
+        assert_eq!(lines, vec![
+            "[a]        │ [b]",
+            "left       │ right",
+            "           │ more",
+        ]);
+    }
+
+    #[test]
+    fn test_comparison_result_goes_to_the_session_it_started_in() {
+        let (_dir, mut app) = crate::app::file_operations::tests::test_app("comparison", false);
+        let source = app.history_manager.lock().unwrap().ensure_active_session();
+        app.create_new_session();
+        let shown = app.messages.len();
+        let comparison = ChatEvent::Comparison {
+            session_id: source,
+            model_a: "a".to_string(),
+            response_a: "left".to_string(),
+            model_b: "b".to_string(),
+            response_b: "right".to_string(),
+        };
+        app.handle_chat_event(ChatEvent::for_session(source, comparison));
+
+        // 切り替えた先のセッションには表示しない
+        assert_eq!(app.messages.len(), shown);
+        let history_guard = app.history_manager.lock().unwrap();
+        let saved = history_guard.get_history().sessions[&source].messages.last().unwrap();
+        assert!(saved.comparison);
+        assert!(matches!(&saved.parts[..], [Part::Text { text }, _] if text == "[a]\nleft"));
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use ratatui::style::Color;
+use uuid::Uuid;
+
+use crate::app::{ChatApp, CONTEXT_TOKEN_BUDGET};
+use crate::history::estimate_tokens;
+
+/// 入力が止まってからこの時間が経ったらゲージを計算し直す（キー入力のたびには数えない）
+pub const CONTEXT_GAUGE_DELAY: Duration = Duration::from_millis(300);
+/// これを超えたら黄色で表示する（%）
+const GAUGE_WARN_PERCENT: f64 = 80.0;
+/// これを超えたら赤で表示する（%）
+const GAUGE_DANGER_PERCENT: f64 = 95.0;
+
+/// 入力欄に表示する、次に送るリクエストのおおよそのトークン数とコンテキストウィンドウ
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContextGauge {
+    pub tokens: u64,
+    pub limit: u64,
+}
+
+/// ゲージの計算に使った入力（変わったら計算し直す）
+#[derive(Debug, Clone, PartialEq)]
+struct GaugeInput {
+    input: String,
+    selected_files: Vec<String>,
+    /// (セッション, 会話コンテキストに含めるメッセージと要約のハッシュ)
+    session: Option<(Uuid, u64)>,
+}
+
+/// ゲージの計算状態
+#[derive(Debug, Default)]
+pub struct ContextGaugeState {
+    /// 表示中のゲージ（コンテキストウィンドウが不明なモデルではNone）
+    pub gauge: Option<ContextGauge>,
+    seen: Option<GaugeInput>,
+    changed_at: Option<Instant>,
+    /// (セッション, 含めるメッセージと要約のハッシュ) ごとの、システムプロンプト・ツール定義と会話コンテキストのトークン数
+    base_tokens: Option<((Uuid, u64), u64)>,
+    /// 添付ファイルのサイズ（更新時刻が変わるまで調べ直さない）
+    file_sizes: HashMap<PathBuf, (SystemTime, u64)>,
+}
+
+impl ContextGauge {
+    pub fn percent(&self) -> f64 {
+        if self.limit == 0 {
+            return 100.0;
+        }
+        self.tokens as f64 * 100.0 / self.limit as f64
+    }
+
+    /// 「ctx: 21k/32k (66%)」
+    pub fn label(&self) -> String {
+        format!("ctx: {}/{} ({:.0}%)", compact_tokens(self.tokens), compact_tokens(self.limit), self.percent())
+    }
+
+    /// 80%を超えたら黄色、95%を超えたら赤
+    pub fn color(&self) -> Color {
+        match self.percent() {
+            percent if percent > GAUGE_DANGER_PERCENT => Color::Red,
+            percent if percent > GAUGE_WARN_PERCENT => Color::Yellow,
+            _ => Color::DarkGray,
+        }
+    }
+}
+
+/// 1234 → 1k、1048576 → 1.0M
+fn compact_tokens(tokens: u64) -> String {
+    if tokens >= 1_000_000 {
+        format!("{:.1}M", tokens as f64 / 1_000_000.0)
+    } else if tokens >= 1_000 {
+        format!("{}k", (tokens + 500) / 1_000)
+    } else {
+        tokens.to_string()
+    }
+}
+
+impl ChatApp {
+    /// run_appのループから呼ぶ。入力・添付ファイル・セッションが変わってからCONTEXT_GAUGE_DELAY経ったら計算し直す
+    pub fn tick_context_gauge(&mut self, now: Instant) {
+        let current = GaugeInput {
+            input: self.ui.input.clone(),
+            selected_files: self.ui.selected_files.clone(),
+            session: self.current_session_key(),
+        };
+        if self.context_gauge.seen.as_ref() != Some(&current) {
+            self.context_gauge.seen = Some(current);
+            self.context_gauge.changed_at = Some(now);
+        }
+        if self.context_gauge.changed_at.is_some_and(|changed_at| now.duration_since(changed_at) >= CONTEXT_GAUGE_DELAY) {
+            self.context_gauge.changed_at = None;
+            self.context_gauge.gauge = self.gemini_client.context_window().map(|limit| ContextGauge { tokens: self.estimate_prompt_tokens(), limit });
+        }
+    }
+
+    /// 会話コンテキストが変わったかを見分けるキー（含めるメッセージのIDと要約から作る）
+    fn current_session_key(&self) -> Option<(Uuid, u64)> {
+        let history_guard = self.history_manager.lock().unwrap();
+        history_guard.get_history().get_current_session().map(|session| {
+            let mut hasher = DefaultHasher::new();
+            session.messages.iter().filter(|message| !message.excluded).for_each(|message| message.id.hash(&mut hasher));
+            session.summary.as_ref().map(|summary| (&summary.text, summary.last_index)).hash(&mut hasher);
+            (session.id, hasher.finish())
+        })
+    }
+
+    /// 今の入力を送ったときのおおよそのトークン数（システムプロンプト + 会話コンテキスト + 添付ファイル + 入力）
+    pub fn estimate_prompt_tokens(&mut self) -> u64 {
+        let session = self.current_session_key();
+        let base = match self.context_gauge.base_tokens {
+            Some((key, tokens)) if Some(key) == session => tokens,
+            _ => {
+                let context = (*self.history_manager.lock().unwrap()).get_conversation_context_with_budget(CONTEXT_TOKEN_BUDGET);
+                let (overhead, context) = self.gemini_client.estimate_request_tokens(&context);
+                if let Some(key) = session {
+                    self.context_gauge.base_tokens = Some((key, overhead + context));
+                }
+                overhead + context
+            }
+        };
+        let (message, references) = self.parse_file_references(&self.ui.input);
+        let files: u64 = references.iter().map(|reference| self.file_size(Path::new(&reference.path)).div_ceil(4)).sum();
+        base + files + estimate_tokens(&message) as u64
+    }
+
+    /// ファイルのバイト数（読めなければ0）。更新時刻が同じなら前回の値を使う
+    fn file_size(&mut self, path: &Path) -> u64 {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return 0;
+        };
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        match self.context_gauge.file_sizes.get(path) {
+            Some((cached_at, size)) if *cached_at == modified => *size,
+            _ => {
+                self.context_gauge.file_sizes.insert(path.to_path_buf(), (modified, metadata.len()));
+                metadata.len()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemini::Part;
+
+    #[test]
+    fn test_context_gauge_estimate_and_levels() {
+        let (dir, mut app) = crate::app::file_operations::tests::test_app("gauge", false);
+        let empty = app.estimate_prompt_tokens();
+        assert!(empty > 0);
+
+        std::fs::write(dir.join("big.txt"), "x".repeat(4_000)).unwrap();
+        app.ui.selected_files.push(dir.join("big.txt").display().to_string());
+        app.ui.input = "abcdefgh".to_string();
+        assert_eq!(app.estimate_prompt_tokens(), empty + 1_000 + 2);
+
+        // 除外するメッセージを入れ替えて件数が同じでも数え直す
+        app.ui.selected_files.clear();
+        app.ui.input.clear();
+        app.history_manager.lock().unwrap().ensure_active_session();
+        let ids: Vec<_> = ["short".to_string(), "long ".repeat(400)]
+            .into_iter()
+            .map(|text| app.history_manager.lock().unwrap().get_history_mut().add_message(vec![Part::Text { text }], true).unwrap())
+            .collect();
+        let toggle = |app: &ChatApp, id| app.history_manager.lock().unwrap().get_history_mut().toggle_message_excluded(id).unwrap();
+        toggle(&app, ids[1]);
+        let with_short = app.estimate_prompt_tokens();
+        toggle(&app, ids[1]);
+        toggle(&app, ids[0]);
+        assert!(app.estimate_prompt_tokens() > with_short + 300);
+
+        assert_eq!(ContextGauge { tokens: 21_300, limit: 32_768 }.label(), "ctx: 21k/33k (65%)");
+        assert_eq!(ContextGauge { tokens: 5_000, limit: 1_048_576 }.label(), "ctx: 5k/1.0M (0%)");
+        assert_eq!(ContextGauge { tokens: 70, limit: 100 }.color(), Color::DarkGray);
+        assert_eq!(ContextGauge { tokens: 81, limit: 100 }.color(), Color::Yellow);
+        assert_eq!(ContextGauge { tokens: 96, limit: 100 }.color(), Color::Red);
+    }
+}
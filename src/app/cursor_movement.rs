@@ -17,6 +17,19 @@ impl ChatApp {
         }
     }
 
+    /// Ctrl+A: 現在の行の先頭（直前の改行の後）に移動
+    pub fn move_to_current_line_start(&mut self) {
+        let (current_line, _) = self.calculate_cursor_position();
+        self.ui.cursor_position = self.get_line_start_position(current_line);
+    }
+
+    /// Ctrl+E: 現在の行の末尾（次の改行の前、なければテキストの末尾）に移動
+    pub fn move_to_current_line_end(&mut self) {
+        let graphemes: Vec<&str> = self.ui.input.graphemes(true).collect();
+        let start = self.ui.cursor_position.min(graphemes.len());
+        self.ui.cursor_position = graphemes[start..].iter().position(|g| *g == "\n").map_or(graphemes.len(), |offset| start + offset);
+    }
+
     // 上方向への移動
     pub fn move_cursor_up(&mut self) {
         let lines: Vec<&str> = self.ui.input.lines().collect();
@@ -99,8 +112,6 @@ impl ChatApp {
     pub fn scroll_messages_up(&mut self) {
         if self.ui.scroll_offset > 0 {
             self.ui.scroll_offset -= 1;
-            // list_stateも更新して表示を同期
-            self.update_list_state_from_scroll();
         }
     }
 
@@ -108,10 +119,20 @@ impl ChatApp {
         self.ui.scroll_offset += 1;
     }
 
-    // scroll_offsetからlist_stateを更新
-    pub fn update_list_state_from_scroll(&mut self) {
-        if !self.messages.is_empty() {
-            self.ui.list_state.select(Some(self.ui.scroll_offset));
-        }
+    /// 選択中のメッセージのインデックス（未選択なら最新のメッセージ）
+    pub fn selected_message_index(&self) -> Option<usize> {
+        let last = self.messages.len().checked_sub(1)?;
+        Some(self.ui.selected_message_index.map_or(last, |i| i.min(last)))
+    }
+
+    /// メッセージ選択を前後に移動する（最新まで進むと再び最新を追従する）
+    pub fn move_message_selection(&mut self, offset: isize) {
+        let Some(current) = self.selected_message_index() else {
+            return;
+        };
+        let last = self.messages.len() - 1;
+        let next = current.saturating_add_signed(offset).min(last);
+        self.ui.selected_message_index = if next == last { None } else { Some(next) };
+        self.ui.scroll_to_selected_message = true;
     }
 }
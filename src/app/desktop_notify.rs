@@ -0,0 +1,107 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::app::message_view::part_display_text;
+use crate::app::{ChatApp, ChatEvent};
+use crate::debug_log;
+
+/// この秒数以内にキー入力があれば、ユーザーは画面を見ているとみなして通知しない
+const USER_ACTIVE_SECS: u64 = 5;
+/// デスクトップ通知の本文の最大文字数
+const NOTIFICATION_BODY_MAX_CHARS: usize = 100;
+
+/// 終わったリクエストをデスクトップに通知するか（時間がかかり、直近にキー入力がない）
+pub fn should_notify(elapsed: Duration, min_duration: Duration, last_input_at: Option<Instant>, now: Instant) -> bool {
+    let user_active = last_input_at.is_some_and(|at| now.duration_since(at) < Duration::from_secs(USER_ACTIVE_SECS));
+    elapsed >= min_duration && !user_active
+}
+
+/// デスクトップ通知を表示する（通知サーバーがなければ、設定に応じて端末のベルを鳴らす）
+fn send_desktop_notification(title: String, body: String, bell_fallback: bool) {
+    // D-Busへの送信は待つことがあるので、描画のループを止めない
+    tokio::task::spawn_blocking(move || {
+        let result = notify_rust::Notification::new().appname("contui").summary(&title).body(&body).show();
+        if let Err(e) = result {
+            debug_log!("[desktop_notify] {:?}", e);
+            if bell_fallback {
+                let mut stdout = std::io::stdout();
+                let _ = stdout.write_all(b"\x07").and_then(|_| stdout.flush());
+            }
+        }
+    });
+}
+
+impl ChatApp {
+    /// キー入力の時刻を記録する（入力中に終わったリクエストは通知しない）
+    pub fn record_user_input(&mut self) {
+        self.last_input_at = Some(Instant::now());
+    }
+
+    /// session_idへのリクエスト（エージェントループ・比較）を始めた時刻を記録する
+    pub fn start_request_timer(&mut self, session_id: Uuid) {
+        self.request_started_at.insert(session_id, Instant::now());
+        self.request_errors.remove(&session_id);
+    }
+
+    /// 裏のタブに振り分ける前のイベントから、リクエストのエラーと終了を拾う
+    pub fn track_request_outcome(&mut self, event: &ChatEvent) {
+        let ChatEvent::Session { session_id, event } = event else {
+            return;
+        };
+        match event.as_ref() {
+            ChatEvent::Error(msg) | ChatEvent::RetryableError(msg) => {
+                self.request_errors.insert(*session_id, msg.clone());
+            }
+            ChatEvent::TaskFinished => self.on_request_finished(*session_id, Instant::now()),
+            _ => {}
+        }
+    }
+
+    /// 時間のかかったリクエストが終わったら、セッション名と結果の1行をデスクトップに通知する
+    fn on_request_finished(&mut self, session_id: Uuid, now: Instant) {
+        let started_at = self.request_started_at.remove(&session_id);
+        let error = self.request_errors.remove(&session_id);
+        if !self.config.desktop_notifications {
+            return;
+        }
+        let Some(started_at) = started_at else {
+            return;
+        };
+        let min_duration = Duration::from_secs(self.config.desktop_notification_min_secs);
+        if !should_notify(now.duration_since(started_at), min_duration, self.last_input_at, now) {
+            return;
+        }
+        let (title, result) = {
+            let history_guard = self.history_manager.lock().unwrap();
+            let session = history_guard.get_history().sessions.get(&session_id);
+            let title = session.map_or_else(|| "contui".to_string(), |session| session.title.clone());
+            let last_response = session
+                .and_then(|session| session.messages.iter().rev().find(|msg| !msg.is_user && !msg.is_system))
+                .map(|msg| msg.parts.iter().map(|part| part_display_text(part, false)).collect::<Vec<_>>().join(" "));
+            (title, last_response)
+        };
+        // エージェントループの最後のメッセージは終了理由（「✅ The LLM reported ...」など）
+        let summary = error
+            .or_else(|| result.and_then(|text| text.lines().find(|line| !line.trim().is_empty()).map(str::to_string)))
+            .unwrap_or_else(|| "Finished".to_string());
+        send_desktop_notification(title, Self::truncate_string_safe(summary.trim(), NOTIFICATION_BODY_MAX_CHARS), self.config.terminal_bell);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_notify_only_long_unattended_requests() {
+        let now = Instant::now();
+        let min = Duration::from_secs(30);
+        assert!(should_notify(Duration::from_secs(45), min, None, now));
+        assert!(!should_notify(Duration::from_secs(10), min, None, now));
+        // 直近にキー入力があれば通知しない
+        assert!(!should_notify(Duration::from_secs(45), min, Some(now - Duration::from_secs(2)), now));
+        assert!(should_notify(Duration::from_secs(45), min, Some(now - Duration::from_secs(20)), now));
+    }
+}
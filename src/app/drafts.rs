@@ -0,0 +1,86 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+
+use crate::app::ChatApp;
+use crate::debug_log;
+use crate::drafts::{delete_draft, load_draft, save_draft, Draft};
+
+/// 最後の編集からこの時間が経ったら下書きを保存する
+pub const DRAFT_SAVE_DELAY: Duration = Duration::from_secs(2);
+
+impl ChatApp {
+    /// 下書きの自動保存を有効にし、前回の送信前の入力が残っていれば入力欄に戻す
+    pub fn enable_drafts(&mut self, path: PathBuf) {
+        match load_draft(&path) {
+            Ok(Some(draft)) => {
+                self.set_input(draft.input);
+                self.ui.cursor_position = draft.cursor_position.min(self.ui.cursor_position);
+                self.ui.selected_files = draft.selected_files;
+                self.notify("📝 restored unsent draft (Ctrl+K to discard)");
+            }
+            Ok(None) => {}
+            Err(e) => {
+                debug_log!("[enable_drafts] load_draft error: {:?}", e);
+            }
+        }
+        self.draft_seen = self.current_draft();
+        self.draft_path = Some(path);
+    }
+
+    fn current_draft(&self) -> Draft {
+        Draft {
+            input: self.ui.input.clone(),
+            cursor_position: self.ui.cursor_position,
+            selected_files: self.ui.selected_files.clone(),
+        }
+    }
+
+    /// run_appのループから呼ぶ。入力が変わってからDRAFT_SAVE_DELAY経ったら保存する
+    pub fn tick_draft(&mut self, now: Instant) {
+        if self.draft_path.is_none() {
+            return;
+        }
+        let current = self.current_draft();
+        if current != self.draft_seen {
+            self.draft_seen = current;
+            self.draft_changed_at = Some(now);
+        }
+        if self.draft_changed_at.is_some_and(|changed_at| now.duration_since(changed_at) >= DRAFT_SAVE_DELAY) {
+            self.flush_draft();
+        }
+    }
+
+    /// 現在の入力を下書きとしてすぐに保存する（空なら下書きを消す）
+    pub fn flush_draft(&mut self) {
+        let Some(path) = &self.draft_path else {
+            return;
+        };
+        let draft = self.current_draft();
+        let result = if draft.is_empty() { delete_draft(path) } else { save_draft(path, &draft) };
+        if let Err(e) = result {
+            debug_log!("[flush_draft] error: {:?}", e);
+        }
+        self.draft_seen = draft;
+        self.draft_changed_at = None;
+    }
+
+    /// 送信した・破棄した入力の下書きを消す
+    pub fn clear_saved_draft(&mut self) {
+        if let Some(path) = &self.draft_path {
+            if let Err(e) = delete_draft(path) {
+                debug_log!("[delete_draft] error: {:?}", e);
+            }
+        }
+        self.draft_seen = self.current_draft();
+        self.draft_changed_at = None;
+    }
+
+    /// Ctrl+K: 入力欄と選択中のファイルを空にして下書きを破棄する
+    pub fn discard_draft(&mut self) {
+        self.set_input(String::new());
+        self.ui.selected_files.clear();
+        self.clear_saved_draft();
+        self.notify("🗑 discarded the draft");
+    }
+}
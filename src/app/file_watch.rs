@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::app::task_queue::UserTask;
+use crate::app::{ChatApp, ChatEvent};
+use crate::debug_log;
+use crate::file_access::PathAccess;
+
+/// /watch でファイルの更新を確認する間隔
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+/// 1回の更新で送る行数の上限（超えた分は古い行から省く）
+pub const WATCH_MAX_LINES: usize = 200;
+
+/// /watch で監視中のファイル
+pub struct FileWatch {
+    pub path: String,
+    handle: JoinHandle<()>,
+}
+
+impl Drop for FileWatch {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// offsetより後に追記された行と次に読む位置を返す
+/// 改行で終わっていない最後の行は次回に回し、ファイルが短くなっていたら（切り詰め・置き換え）先頭から読み直す
+pub fn read_appended(path: &Path, offset: u64) -> io::Result<(String, u64)> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let start = if len < offset { 0 } else { offset };
+    file.seek(SeekFrom::Start(start))?;
+    let mut bytes = Vec::new();
+    file.take(len - start).read_to_end(&mut bytes)?;
+    let complete = bytes.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    Ok((String::from_utf8_lossy(&bytes[..complete]).into_owned(), start + complete as u64))
+}
+
+/// 行数がWATCH_MAX_LINESを超えていれば新しい行だけ残す
+fn limit_lines(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.len() <= WATCH_MAX_LINES {
+        return lines.join("\n");
+    }
+    let omitted = lines.len() - WATCH_MAX_LINES;
+    format!("({} earlier lines omitted)\n{}", omitted, lines[omitted..].join("\n"))
+}
+
+fn file_state(path: &Path) -> io::Result<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path)?;
+    Ok((metadata.modified()?, metadata.len()))
+}
+
+/// 更新時刻（とサイズ）が変わるたびに追記された行をChatEvent::FileUpdateで送る
+async fn watch_file(path: PathBuf, display: String, mut offset: u64, sender: mpsc::UnboundedSender<ChatEvent>) {
+    let mut last_state = file_state(&path).ok();
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    // 最初のtickはすぐに来るので読み飛ばす
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let state = match file_state(&path) {
+            Ok(state) => state,
+            Err(e) => {
+                // ログのローテーション中などは消えていることがあるので、次の確認を待つ
+                debug_log!("[watch_file] {}: {:?}", display, e);
+                continue;
+            }
+        };
+        if last_state == Some(state) {
+            continue;
+        }
+        last_state = Some(state);
+        match read_appended(&path, offset) {
+            Ok((text, next)) => {
+                offset = next;
+                if text.trim().is_empty() {
+                    continue;
+                }
+                let update = ChatEvent::FileUpdate { path: display.clone(), text: limit_lines(&text) };
+                if sender.send(update).is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                debug_log!("[watch_file] read error {}: {:?}", display, e);
+            }
+        }
+    }
+}
+
+impl ChatApp {
+    /// /watch: pathの監視を始める（監視中のファイルがあれば置き換える）。開始時点より後に追記された行だけを送る
+    pub fn start_watch(&mut self, path: &str) -> Result<(), String> {
+        match self.gemini_client.check_path_access(path) {
+            Ok(PathAccess::Allowed) => {}
+            Ok(_) => return Err(format!("{} is outside the allowed directories", path)),
+            Err(e) => return Err(e.to_string()),
+        }
+        let metadata = std::fs::metadata(path).map_err(|e| format!("{}: {}", path, e))?;
+        if !metadata.is_file() {
+            return Err(format!("{} is not a file", path));
+        }
+        let handle = tokio::spawn(watch_file(PathBuf::from(path), path.to_string(), metadata.len(), self.event_sender.clone()));
+        if let Some(previous) = self.file_watch.replace(FileWatch { path: path.to_string(), handle }) {
+            debug_log!("[start_watch] replaced watcher for {}", previous.path);
+        }
+        Ok(())
+    }
+
+    /// /unwatch: 監視をやめる（監視していたパスを返す）
+    pub fn stop_watch(&mut self) -> Option<String> {
+        self.file_watch.take().map(|watch| watch.path.clone())
+    }
+
+    /// 監視中のファイルに追記された行をユーザーメッセージとしてAIに送る（応答待ちならキューに積む）
+    pub fn send_file_update(&mut self, path: &str, text: &str) {
+        let Some(session_id) = self.current_session_id() else {
+            self.notify(format!("❌ No active session to send the update of {} to", path));
+            return;
+        };
+        let message = format!("[File update: {}]\n{}", path, text);
+        self.task_queue.enqueue(UserTask { message, files: Vec::new(), session_id });
+        if !self.process_next_task() {
+            self.notify(format!("⏳ {} の更新をキューに追加しました（待機中: {}件）", path, self.task_queue.pending()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_read_appended_lines() {
+        let path = std::env::temp_dir().join(format!("contui_watch_{}.log", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "old line\n").unwrap();
+        let start = std::fs::metadata(&path).unwrap().len();
+
+        let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"first\nsecond\npart").unwrap();
+        let (text, offset) = read_appended(&path, start).unwrap();
+        assert_eq!(text, "first\nsecond\n");
+
+        // 改行で終わっていなかった行は、続きが書かれてから送る
+        file.write_all(b"ial\n").unwrap();
+        let (text, offset) = read_appended(&path, offset).unwrap();
+        assert_eq!(text, "partial\n");
+        assert_eq!(read_appended(&path, offset).unwrap().0, "");
+
+        // 切り詰められたら先頭から読む
+        std::fs::write(&path, "rotated\n").unwrap();
+        assert_eq!(read_appended(&path, offset).unwrap(), ("rotated\n".to_string(), 8));
+        std::fs::remove_file(&path).unwrap();
+
+        let many: String = (0..WATCH_MAX_LINES + 5).map(|i| format!("{}\n", i)).collect();
+        let limited = limit_lines(&many);
+        assert!(limited.starts_with("(5 earlier lines omitted)\n5\n"));
+        assert!(limited.ends_with(&format!("{}", WATCH_MAX_LINES + 4)));
+    }
+
+    #[tokio::test]
+    async fn test_queued_update_goes_to_the_session_it_was_queued_in() {
+        let (_dir, mut app) = crate::app::file_operations::tests::test_app("watch_queue", false);
+        let source = app.history_manager.lock().unwrap().ensure_active_session();
+        app.task_queue.mark_processing();
+        app.send_file_update("app.log", "error: disk full");
+        assert_eq!(app.task_queue.pending(), 1);
+
+        // 応答待ちの間に別のセッションへ切り替えても、キューに積んだときのセッションに送る
+        app.create_new_session();
+        let other = app.history_manager.lock().unwrap().get_history().current_session_id.unwrap();
+        app.handle_chat_event(ChatEvent::for_session(source, ChatEvent::TaskFinished));
+        let history_guard = app.history_manager.lock().unwrap();
+        let history = history_guard.get_history();
+        assert_eq!(history.sessions[&source].messages.len(), 1);
+        assert!(history.sessions[&other].messages.is_empty());
+        drop(history_guard);
+        assert!(app.messages.iter().all(|message| !message.is_user));
+    }
+}
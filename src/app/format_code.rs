@@ -0,0 +1,153 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::app::{ChatApp, ChatEvent};
+use crate::debug_log;
+
+/// COMMAND_TIMEOUT_SECSが未設定のときのフォーマッタのタイムアウト（初回起動の確認待ちなどで止まらないように）
+const FORMAT_TIMEOUT_SECS: u64 = 30;
+
+/// 言語タグに対応するフォーマッタ（ファイルをその場で書き換えるコマンドと、一時ファイルの拡張子）
+pub fn formatter_for(language: &str) -> Option<(&'static str, &'static str)> {
+    match language.to_ascii_lowercase().as_str() {
+        "rust" | "rs" => Some(("rustfmt --edition 2021", "rs")),
+        "javascript" | "js" => Some(("prettier --write", "js")),
+        "typescript" | "ts" => Some(("prettier --write", "ts")),
+        "json" => Some(("prettier --write", "json")),
+        "python" | "py" => Some(("black -q", "py")),
+        "go" => Some(("gofmt -w", "go")),
+        _ => None,
+    }
+}
+
+/// 最初の ```lang ... ``` ブロックの言語タグと、textの中のコード部分の範囲（閉じていなければNone）
+pub fn find_fenced_block(text: &str) -> Option<(String, Range<usize>)> {
+    let open = text.find("```")?;
+    let tag_end = open + text[open..].find('\n')?;
+    let language = text[open + 3..tag_end].trim().to_string();
+    let code_start = tag_end + 1;
+    let close = text[code_start..].find("```")? + code_start;
+    // 閉じる ``` の直前の改行はコードに含めない
+    let code_end = if text[..close].ends_with('\n') && close > code_start { close - 1 } else { close };
+    Some((language, code_start..code_end.max(code_start)))
+}
+
+impl ChatApp {
+    /// /format-code: 入力欄の最初のコードブロックを言語に合ったフォーマッタで整形して置き換える
+    /// 入力欄からはコマンドの行を取り除き、整形の結果はChatEvent::CodeFormattedで受け取る
+    pub fn format_code_in_input(&mut self, command: &str) -> Result<(), String> {
+        let body = self.ui.input.trim_start().strip_prefix(command).unwrap_or(&self.ui.input).trim_start_matches([' ', '\t']);
+        let body = body.strip_prefix('\n').unwrap_or(body).to_string();
+        let Some((language, code)) = find_fenced_block(&body) else {
+            return Err("No fenced code block (```lang ... ```) in the input".to_string());
+        };
+        self.set_input(body.clone());
+        let Some((formatter, extension)) = formatter_for(&language) else {
+            self.notify(format!("No formatter for '{}'; left the block unchanged", language));
+            return Ok(());
+        };
+        self.notify(format!("🧹 Formatting {} with {}...", language, formatter));
+        self.run_formatter(body, code, formatter.to_string(), extension)
+    }
+
+    /// フォーマッタでコードの部分を書き換えた一時ファイルを読み戻し、結果をChatEventで送る
+    fn run_formatter(&mut self, body: String, code: Range<usize>, formatter: String, extension: &str) -> Result<(), String> {
+        let path = std::env::temp_dir().join(format!("contui_format_{}.{}", Uuid::new_v4(), extension));
+        std::fs::write(&path, format!("{}\n", &body[code.clone()])).map_err(|e| e.to_string())?;
+        let shell = self.gemini_client.shell();
+        let command = format!("{} {}", formatter, shell.quote(&path.to_string_lossy()));
+        let gemini_client = self.gemini_client.clone();
+        let sender = self.event_sender.clone();
+        let timeout = gemini_client.command_timeout().unwrap_or(Duration::from_secs(FORMAT_TIMEOUT_SECS));
+        tokio::spawn(async move {
+            let result = gemini_client.execute_command(&command, Some(timeout)).await;
+            let formatted = match result {
+                Ok(result) if result.success => std::fs::read_to_string(&path).map_err(|e| e.to_string()),
+                Ok(result) => Err(result.stderr.trim().to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            let _ = std::fs::remove_file(&path);
+            debug_log!("[format_code] {}: {:?}", command, formatted.as_ref().map(String::len));
+            let event = match formatted {
+                Ok(formatted) => {
+                    let after = format!("{}{}{}", &body[..code.start], formatted.trim_end_matches('\n'), &body[code.end..]);
+                    ChatEvent::CodeFormatted { before: body, after }
+                }
+                Err(e) => ChatEvent::Notification(format!("❌ {} failed: {}", formatter, e)),
+            };
+            let _ = sender.send(event);
+        });
+        Ok(())
+    }
+
+    /// 整形が終わった。その間に入力欄が書き換えられていなければ置き換える
+    pub fn apply_formatted_code(&mut self, before: &str, after: String) {
+        if self.ui.input != before {
+            self.notify("The input changed while formatting; the result was discarded");
+            return;
+        }
+        self.set_input(after);
+        self.notify("✅ Formatted the code block");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_fenced_block_and_formatter() {
+        let text = "please check\n```rust\nfn main(){println!(\"hi\");}\n```\nthanks";
+        let (language, code) = find_fenced_block(text).unwrap();
+        assert_eq!(language, "rust");
+        assert_eq!(&text[code], "fn main(){println!(\"hi\");}");
+        assert_eq!(find_fenced_block("```\n\n```").map(|(language, code)| (language, code.len())), Some((String::new(), 0)));
+        assert_eq!(find_fenced_block("```rust\nfn main() {}"), None);
+        assert_eq!(find_fenced_block("no code"), None);
+
+        assert_eq!(formatter_for("Rust").map(|(command, _)| command), Some("rustfmt --edition 2021"));
+        assert_eq!(formatter_for("json").map(|(_, extension)| extension), Some("json"));
+        assert_eq!(formatter_for("cobol"), None);
+    }
+
+    #[tokio::test]
+    async fn test_format_code_without_formatter_keeps_block() {
+        let (_dir, mut app) = crate::app::file_operations::tests::test_app("format", false);
+        app.ui.input = "/format-code\n```cobol\nDISPLAY 'HI'.\n```".to_string();
+        app.format_code_in_input("/format-code").unwrap();
+        assert_eq!(app.ui.input, "```cobol\nDISPLAY 'HI'.\n```");
+
+        // 整形中に入力が変わっていなければ結果で置き換える
+        let before = app.ui.input.clone();
+        app.apply_formatted_code(&before, "```cobol\nDISPLAY \"HI\".\n```".to_string());
+        assert_eq!(app.ui.input, "```cobol\nDISPLAY \"HI\".\n```");
+        app.apply_formatted_code(&before, "ignored".to_string());
+        assert_eq!(app.ui.input, "```cobol\nDISPLAY \"HI\".\n```");
+
+        app.ui.input = "/format-code".to_string();
+        assert!(app.format_code_in_input("/format-code").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_formatter_result_replaces_block() {
+        let (dir, mut app) = crate::app::file_operations::tests::test_app("format_run", false);
+        // 渡されたファイルをその場で書き換えるフォーマッタの代わり
+        let script = dir.join("fmt.sh");
+        std::fs::write(&script, "tr a-z A-Z < \"$1\" > \"$1.out\" && mv \"$1.out\" \"$1\"\n").unwrap();
+        let body = "see\n```rust\nfn main(){}\n```\nok".to_string();
+        app.set_input(body.clone());
+        let (_, code) = find_fenced_block(&body).unwrap();
+        app.run_formatter(body, code, format!("sh {}", script.display()), "rs").unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(10), app.event_receiver.recv()).await.unwrap().unwrap();
+        let ChatEvent::CodeFormatted { before, after } = event else {
+            panic!("unexpected event: {:?}", event);
+        };
+        assert_eq!(after, "see\n```rust\nFN MAIN(){}\n```\nok");
+        app.apply_formatted_code(&before, after);
+        assert_eq!(app.ui.input, "see\n```rust\nFN MAIN(){}\n```\nok");
+    }
+}
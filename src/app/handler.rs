@@ -1,39 +1,215 @@
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, KeyEventKind};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, KeyEventKind, MouseButton, MouseEvent, MouseEventKind};
 use anyhow::Result;
 
+use crate::app::file_operations::DirectoryGrant;
 use crate::app::{ChatApp, InputMode};
+use crate::emoji;
 // use crate::history::ChatMessage; // Unused import
 use uuid::Uuid;
 use chrono::Utc;
 use unicode_segmentation::UnicodeSegmentation;
 
+/// キーを押し続けたとき（KeyEventKind::Repeat）に繰り返してよい操作か
+/// 移動・スクロールと文字入力だけを繰り返し、送信や削除は押すたびに1回だけ実行する
+pub fn repeat_allowed(mode: &InputMode, key_event: &KeyEvent) -> bool {
+    if matches!(
+        key_event.code,
+        KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right | KeyCode::PageUp | KeyCode::PageDown
+    ) {
+        return true;
+    }
+    if key_event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) {
+        return false;
+    }
+    match (mode, key_event.code) {
+        (InputMode::Normal, KeyCode::Char(c)) => "hjklJK[]WBE".contains(c),
+        (InputMode::Visual | InputMode::VisualLine | InputMode::VisualBlock, KeyCode::Char(c)) => "hjklwbeWBE".contains(c),
+        (InputMode::SessionList | InputMode::FileBrowser | InputMode::DiffView | InputMode::MessageView | InputMode::Permissions, KeyCode::Char(c)) => "jk".contains(c),
+        // 入力欄での文字入力と1文字ずつの削除は通常のテキスト入力と同じく繰り返す
+        (InputMode::Insert, KeyCode::Char(_) | KeyCode::Backspace | KeyCode::Delete) => true,
+        _ => false,
+    }
+}
+
 impl ChatApp {
+    /// ホイールでメッセージ（セッション一覧では選択、/diffでは差分）をスクロールし、セッション一覧はクリックで選択する
+    pub fn handle_mouse_event(&mut self, mouse_event: MouseEvent) {
+        match (mouse_event.kind, &self.ui.input_mode) {
+            (MouseEventKind::ScrollUp, InputMode::SessionList) => self.session_list_previous(),
+            (MouseEventKind::ScrollDown, InputMode::SessionList) => self.session_list_next(),
+            (MouseEventKind::Down(MouseButton::Left), InputMode::SessionList) => {
+                self.select_session_at(mouse_event.column, mouse_event.row);
+            }
+            (MouseEventKind::ScrollUp, InputMode::DiffView) => self.ui.diff_scroll = self.ui.diff_scroll.saturating_sub(1),
+            (MouseEventKind::ScrollDown, InputMode::DiffView) => self.ui.diff_scroll += 1,
+            (MouseEventKind::ScrollUp, InputMode::MessageView) => self.scroll_message_view(-1),
+            (MouseEventKind::ScrollDown, InputMode::MessageView) => self.scroll_message_view(1),
+            (MouseEventKind::ScrollUp, InputMode::Normal | InputMode::Insert | InputMode::Visual | InputMode::VisualLine | InputMode::VisualBlock) => {
+                self.scroll_messages_up();
+            }
+            (MouseEventKind::ScrollDown, InputMode::Normal | InputMode::Insert | InputMode::Visual | InputMode::VisualLine | InputMode::VisualBlock) => {
+                self.scroll_messages_down();
+            }
+            _ => {}
+        }
+    }
+
+    /// 押したキーは常に受け付け、押しっぱなしの繰り返しは移動などの安全な操作だけ受け付ける
+    /// （確認ダイアログの回答は繰り返さない）
+    fn accepts_key_event(&self, key_event: &KeyEvent) -> bool {
+        match key_event.kind {
+            KeyEventKind::Press => true,
+            KeyEventKind::Repeat => {
+                self.ui.directory_prompt.is_none()
+                    && self.ui.quit_prompt.is_none()
+                    && self.ui.tab_close_prompt.is_none()
+                    && repeat_allowed(&self.ui.input_mode, key_event)
+            }
+            KeyEventKind::Release => false,
+        }
+    }
+
     pub async fn handle_key_event(&mut self, key_event: KeyEvent, terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>) -> Result<bool> {
-        self.ui.notification = None;
-        if key_event.kind != KeyEventKind::Press {
+        self.dismiss_notification();
+        if !self.accepts_key_event(&key_event) {
+            return Ok(false);
+        }
+        self.record_user_input();
+
+        // ディレクトリアクセスの確認中は回答のキーだけを受け付ける
+        if self.ui.directory_prompt.is_some() {
+            let grant = match key_event.code {
+                KeyCode::Char('s') => DirectoryGrant::Session,
+                KeyCode::Char('a') => DirectoryGrant::Always,
+                KeyCode::Char('d') | KeyCode::Char('n') | KeyCode::Esc => DirectoryGrant::Deny,
+                _ => return Ok(false),
+            };
+            if self.answer_directory_prompt(grant) {
+                self.send_message(terminal).await;
+            }
+            return Ok(false);
+        }
+
+        // 終了の確認中は y/n（もう一度 q、または Q でも終了）だけを受け付ける
+        if self.ui.quit_prompt.is_some() {
+            match key_event.code {
+                KeyCode::Char('y') | KeyCode::Char('q') | KeyCode::Char('Q') => {
+                    self.ui.quit_prompt = None;
+                    self.prepare_quit();
+                    return Ok(true);
+                }
+                KeyCode::Char('n') | KeyCode::Esc => self.ui.quit_prompt = None,
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        // タブを閉じる確認中は y/n だけを受け付ける
+        if self.ui.tab_close_prompt.is_some() {
+            match key_event.code {
+                KeyCode::Char('y') => {
+                    self.ui.tab_close_prompt = None;
+                    self.close_tab();
+                }
+                KeyCode::Char('n') | KeyCode::Esc => self.ui.tab_close_prompt = None,
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        if self.handle_tab_key(&key_event) {
+            return Ok(false);
+        }
+
+        // 分割表示の右ペイン（チャット画面でだけ使う）
+        if matches!(self.ui.input_mode, InputMode::Normal | InputMode::Insert | InputMode::Visual | InputMode::VisualLine | InputMode::VisualBlock)
+            && self.handle_split_pane_key(&key_event)
+        {
             return Ok(false);
         }
 
         match self.ui.input_mode {
             InputMode::Normal => self.handle_normal_mode_key(key_event, terminal).await,
             InputMode::Insert => self.handle_insert_mode_key(key_event, terminal).await,
-            InputMode::Visual => self.handle_visual_mode_key(key_event).await,
+            InputMode::Visual | InputMode::VisualLine | InputMode::VisualBlock => self.handle_visual_mode_key(key_event).await,
+            InputMode::DiffView => self.handle_diff_view_key(key_event),
+            InputMode::MessageView => self.handle_message_view_key(key_event),
+            InputMode::Permissions => self.handle_permissions_key(key_event),
             InputMode::SessionList => self.handle_session_list_key(key_event).await,
             InputMode::FileBrowser => self.handle_file_browser_key(key_event).await,
             // InputMode::TodoListは削除
         }
     }
 
+    /// どの画面でも使えるタブの操作（処理したらtrue）
+    fn handle_tab_key(&mut self, key_event: &KeyEvent) -> bool {
+        if !key_event.modifiers.contains(KeyModifiers::CONTROL) {
+            return false;
+        }
+        match key_event.code {
+            // セッション一覧では選択中のセッションを、それ以外では新しいセッションを新しいタブで開く
+            KeyCode::Char('t') => {
+                let session_id = if self.ui.input_mode == InputMode::SessionList { self.selected_session_id() } else { None };
+                self.open_tab(session_id);
+            }
+            KeyCode::Char('w') => self.request_close_tab(),
+            KeyCode::Tab => self.cycle_tab(1),
+            KeyCode::BackTab => self.cycle_tab(-1),
+            KeyCode::Char(c @ '1'..='9') => {
+                let index = c as usize - '1' as usize;
+                if index < self.tabs.len() {
+                    self.activate_tab(index);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
     pub async fn handle_normal_mode_key(&mut self, key_event: KeyEvent, terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>) -> Result<bool> {
         // Ctrl+H でヘルプ表示を切り替え
         if key_event.modifiers.contains(KeyModifiers::CONTROL) && key_event.code == KeyCode::Char('h') {
             self.ui.show_help = !self.ui.show_help;
             return Ok(false);
         }
+
+        // Ctrl+K で入力中の下書きを破棄
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) && key_event.code == KeyCode::Char('k') {
+            self.discard_draft();
+            return Ok(false);
+        }
+
+        // Ctrl+V で矩形選択
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) && key_event.code == KeyCode::Char('v') {
+            self.ui.input_mode = InputMode::VisualBlock;
+            self.ui.visual_start = Some(self.ui.cursor_position);
+            return Ok(false);
+        }
+
+        // ci" / ca" などの入力途中なら続きのキーとして処理
+        if !self.ui.pending_normal_keys.is_empty() {
+            match key_event.code {
+                KeyCode::Char(c) => self.handle_pending_normal_key(c),
+                _ => self.ui.pending_normal_keys.clear(),
+            }
+            return Ok(false);
+        }
         
         match key_event.code {
             // 終了
             KeyCode::Char('q') => {
+                // 応答待ちや未送信の入力があれば確認してから終了する
+                match self.quit_guard_reason() {
+                    Some(reason) => self.ui.quit_prompt = Some(reason),
+                    None => {
+                        self.prepare_quit();
+                        return Ok(true);
+                    }
+                }
+            }
+            // 確認せずに終了
+            KeyCode::Char('Q') => {
+                self.prepare_quit();
                 return Ok(true);
             }
             
@@ -95,19 +271,51 @@ impl ChatApp {
                     self.navigate_history_up();
                 }
             }
+            // チャットメッセージの選択を移動
+            KeyCode::Char('J') | KeyCode::Char(']') => {
+                self.move_message_selection(1);
+            }
+            KeyCode::Char('K') | KeyCode::Char('[') => {
+                self.move_message_selection(-1);
+            }
+            // カーソル位置の単語を含む次/前のメッセージを選択
+            KeyCode::Char('*') => {
+                self.search_word_under_cursor(true);
+            }
+            KeyCode::Char('#') => {
+                self.search_word_under_cursor(false);
+            }
             KeyCode::Char('0') => {
                 self.ui.cursor_position = 0;
             }
             KeyCode::Char('$') => {
                 self.ui.cursor_position = self.ui.input.graphemes(true).count();
             }
+            // 空白区切りのWORD単位で移動
+            KeyCode::Char('W') => {
+                self.move_to_next_WORD();
+            }
+            KeyCode::Char('B') => {
+                self.move_to_prev_WORD();
+            }
+            KeyCode::Char('E') => {
+                self.move_to_end_WORD();
+            }
             
             // Visual Mode
             KeyCode::Char('v') => {
                 self.ui.input_mode = InputMode::Visual;
                 self.ui.visual_start = Some(self.ui.cursor_position);
             }
+            KeyCode::Char('V') => {
+                self.ui.input_mode = InputMode::VisualLine;
+                self.ui.visual_start = Some(self.ui.cursor_position);
+            }
             
+            // 選択中のメッセージを会話コンテキストから外す・戻す
+            KeyCode::Char('x') if self.ui.input.is_empty() && self.ui.selected_message_index.is_some() => {
+                self.toggle_selected_message_excluded();
+            }
             // 削除
             KeyCode::Char('x') => {
                 self.delete_char_at_cursor();
@@ -116,19 +324,22 @@ impl ChatApp {
                 self.move_cursor_left();
                 self.delete_char_at_cursor();
             }
+            // ci" / ca" / ci( などのテキストオブジェクト
+            KeyCode::Char('c') => {
+                self.ui.pending_normal_keys.push('c');
+            }
             KeyCode::Char('d') => {
                 // TODO: dd for delete line
-                self.ui.input.clear();
-                self.ui.cursor_position = 0;
-                self.ui.input_line_count = 1;
+                self.set_input(String::new());
             }
             
             // 送信
             KeyCode::Enter => {
                 if !self.ui.input.trim().is_empty() {
                     self.send_message(terminal).await;
-                } else {
+                } else if !self.toggle_selected_tool_outcome() && !self.open_selected_message_view() {
                     // 入力が空の場合、選択されたメッセージを入力欄に挿入
+                    // （ツール実行結果なら詳細の展開・折りたたみ、折りたたんだ長いメッセージなら全文表示）
                     self.insert_selected_message();
                 }
             }
@@ -144,6 +355,32 @@ impl ChatApp {
             KeyCode::Char('y') => {
                 self.insert_selected_message();
             }
+
+            // 選択されたメッセージまでで新しいセッションに分岐
+            KeyCode::Char('F') => {
+                self.fork_session();
+            }
+
+            // エージェントループの途中のステップを展開・折りたたみ
+            KeyCode::Tab => {
+                self.toggle_selected_steps();
+            }
+
+            // 選択されたユーザーメッセージを編集して再送信
+            KeyCode::Char('e') => {
+                self.start_editing_selected_message();
+            }
+            // 一時的なエラーで失敗したリクエストを再送
+            KeyCode::Char('r') => {
+                self.retry_failed_request();
+            }
+            // 添付後に変更されたファイルを添付し直す
+            KeyCode::Char('R') => {
+                self.refresh_changed_attachments();
+            }
+            KeyCode::Esc => {
+                self.cancel_message_edit();
+            }
             
             _ => {}
         }
@@ -156,9 +393,38 @@ impl ChatApp {
             self.ui.show_help = !self.ui.show_help;
             return Ok(false);
         }
+
+        // Ctrl+K で入力中の下書きを破棄
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) && key_event.code == KeyCode::Char('k') {
+            self.discard_draft();
+            return Ok(false);
+        }
+
+        if let Some(index) = self.ui.emoji_picker {
+            self.handle_emoji_picker_key(index, key_event.code);
+            return Ok(false);
+        }
+
+        // Ctrl+G で絵文字ピッカーを開く
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) && key_event.code == KeyCode::Char('g') {
+            self.ui.emoji_picker = Some(0);
+            return Ok(false);
+        }
+
+        // Ctrl+A / Ctrl+E で現在の行の先頭・末尾に移動
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) && key_event.code == KeyCode::Char('a') {
+            self.move_to_current_line_start();
+            return Ok(false);
+        }
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) && key_event.code == KeyCode::Char('e') {
+            self.move_to_current_line_end();
+            return Ok(false);
+        }
         
         match key_event.code {
             KeyCode::Esc => {
+                // 矩形選択の I で入力した文字を他の行にも入れる
+                self.finish_block_insert();
                 self.ui.input_mode = InputMode::Normal;
                 if self.ui.cursor_position > 0 {
                     self.ui.cursor_position -= 1;
@@ -227,6 +493,60 @@ impl ChatApp {
         Ok(false)
     }
 
+    fn handle_diff_view_key(&mut self, key_event: KeyEvent) -> Result<bool> {
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) && key_event.code == KeyCode::Char('h') {
+            self.ui.show_help = !self.ui.show_help;
+            return Ok(false);
+        }
+        match key_event.code {
+            KeyCode::Char('q') | KeyCode::Esc => {
+                self.ui.input_mode = InputMode::Normal;
+                self.ui.diff_lines.clear();
+            }
+            KeyCode::Char('j') | KeyCode::Down => self.ui.diff_scroll += 1,
+            KeyCode::Char('k') | KeyCode::Up => self.ui.diff_scroll = self.ui.diff_scroll.saturating_sub(1),
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    fn handle_message_view_key(&mut self, key_event: KeyEvent) -> Result<bool> {
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) && key_event.code == KeyCode::Char('h') {
+            self.ui.show_help = !self.ui.show_help;
+            return Ok(false);
+        }
+        let page = self.ui.message_view.as_ref().map_or(1, |view| view.page_size) as isize;
+        match key_event.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.close_message_view(),
+            KeyCode::Char('j') | KeyCode::Down => self.scroll_message_view(1),
+            KeyCode::Char('k') | KeyCode::Up => self.scroll_message_view(-1),
+            KeyCode::PageDown => self.scroll_message_view(page),
+            KeyCode::PageUp => self.scroll_message_view(-page),
+            KeyCode::Char('g') => self.scroll_message_view(isize::MIN),
+            KeyCode::Char('G') => self.scroll_message_view(isize::MAX),
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    fn handle_emoji_picker_key(&mut self, index: usize, code: KeyCode) {
+        match code {
+            KeyCode::Left => self.ui.emoji_picker = Some(emoji::move_selection(index, -1, 0)),
+            KeyCode::Right => self.ui.emoji_picker = Some(emoji::move_selection(index, 1, 0)),
+            KeyCode::Up => self.ui.emoji_picker = Some(emoji::move_selection(index, 0, -1)),
+            KeyCode::Down => self.ui.emoji_picker = Some(emoji::move_selection(index, 0, 1)),
+            KeyCode::Enter => {
+                self.ui.emoji_picker = None;
+                self.reset_history_navigation();
+                if let Some(c) = emoji::EMOJI_PALETTE[index].0.chars().next() {
+                    self.insert_char(c);
+                }
+            }
+            KeyCode::Esc => self.ui.emoji_picker = None,
+            _ => {}
+        }
+    }
+
     pub async fn handle_visual_mode_key(&mut self, key_event: KeyEvent) -> Result<bool> {
         // Ctrl+H でヘルプ表示を切り替え
         if key_event.modifiers.contains(KeyModifiers::CONTROL) && key_event.code == KeyCode::Char('h') {
@@ -234,12 +554,30 @@ impl ChatApp {
             return Ok(false);
         }
         
+        // Ctrl+V で矩形選択に切り替え（矩形選択中なら終了）
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) && key_event.code == KeyCode::Char('v') {
+            if self.ui.input_mode == InputMode::VisualBlock {
+                self.ui.input_mode = InputMode::Normal;
+                self.ui.visual_start = None;
+            } else {
+                self.ui.input_mode = InputMode::VisualBlock;
+            }
+            return Ok(false);
+        }
+        
         match key_event.code {
             KeyCode::Esc => {
                 self.ui.input_mode = InputMode::Normal;
                 self.ui.visual_start = None;
             }
-            KeyCode::Char('v') => {
+            // 同じモードのキーなら終了、別のモードなら文字単位・行単位・矩形を切り替える
+            KeyCode::Char('v') if matches!(self.ui.input_mode, InputMode::VisualLine | InputMode::VisualBlock) => {
+                self.ui.input_mode = InputMode::Visual;
+            }
+            KeyCode::Char('V') if matches!(self.ui.input_mode, InputMode::Visual | InputMode::VisualBlock) => {
+                self.ui.input_mode = InputMode::VisualLine;
+            }
+            KeyCode::Char('v') | KeyCode::Char('V') => {
                 // Visual Modeを終了してNormalモードに戻る
                 self.ui.input_mode = InputMode::Normal;
                 self.ui.visual_start = None;
@@ -266,6 +604,32 @@ impl ChatApp {
                 // 前の単語の先頭へ
                 self.move_to_prev_word();
             }
+            KeyCode::Char('e') => {
+                // 単語の末尾へ
+                self.move_to_end_word();
+            }
+            KeyCode::Char('W') => {
+                // 次のWORD（空白区切り）の先頭へ
+                self.move_to_next_WORD();
+            }
+            KeyCode::Char('B') => {
+                // 前のWORDの先頭へ
+                self.move_to_prev_WORD();
+            }
+            KeyCode::Char('E') => {
+                // WORDの末尾へ
+                self.move_to_end_WORD();
+            }
+            
+            // 矩形選択: 各行の矩形を削除、I で各行の矩形の左端に入力
+            KeyCode::Char('d') | KeyCode::Char('x') if self.ui.input_mode == InputMode::VisualBlock => {
+                self.delete_visual_block();
+                self.ui.input_mode = InputMode::Normal;
+                self.ui.visual_start = None;
+            }
+            KeyCode::Char('I') if self.ui.input_mode == InputMode::VisualBlock => {
+                self.start_block_insert();
+            }
             
             // 削除（選択範囲を削除）
             KeyCode::Char('d') | KeyCode::Char('x') => {
@@ -276,7 +640,7 @@ impl ChatApp {
             
             // ヤンク（選択範囲をコピー）
             KeyCode::Char('y') => {
-                // 今回は実装を簡略化してクリップボードに保存しない
+                self.yank_visual_selection();
                 self.ui.input_mode = InputMode::Normal;
                 self.ui.visual_start = None;
             }
@@ -316,12 +680,27 @@ impl ChatApp {
             KeyCode::Down | KeyCode::Char('j') => {
                 self.session_list_next();
             }
+            KeyCode::PageUp => {
+                self.session_list_page(-1);
+            }
+            KeyCode::PageDown => {
+                self.session_list_page(1);
+            }
             KeyCode::Enter => {
                 self.switch_to_selected_session();
             }
             KeyCode::Char('d') => {
                 self.delete_selected_session();
             }
+            KeyCode::Char('u') => {
+                self.undo_delete_session(std::time::Instant::now());
+            }
+            KeyCode::Char('a') => {
+                self.toggle_archive_selected_session();
+            }
+            KeyCode::Char('A') => {
+                self.toggle_show_archived_sessions();
+            }
             KeyCode::Char('n') => {
                 self.ui.input_mode = InputMode::Normal;
                 self.create_new_session();
@@ -371,11 +750,16 @@ impl ChatApp {
     pub async fn save_history(&mut self) -> Result<()> {
         match self.history_manager.lock().unwrap().save() {
             Ok(_) => {
+                self.ui.unsaved = false;
                 self.messages.push(crate::history::ChatMessage {
                     id: Uuid::new_v4(),
                     parts: vec![crate::gemini::Part::Text { text: "History saved successfully!".to_string() }], // Changed content to parts
                     is_user: false,
                     timestamp: Utc::now(),
+                    comparison: false,
+                    steps: Vec::new(),
+                    is_system: false,
+                    excluded: false,
                 });
                 Ok(())
             }
@@ -385,9 +769,115 @@ impl ChatApp {
                     parts: vec![crate::gemini::Part::Text { text: format!("Error saving history: {}", e) }], // Changed content to parts
                     is_user: false,
                     timestamp: Utc::now(),
+                    comparison: false,
+                    steps: Vec::new(),
+                    is_system: false,
+                    excluded: false,
                 });
                 Err(e)
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::file_operations::tests::test_app;
+    use crossterm::event::KeyEventState;
+    use ratatui::backend::CrosstermBackend;
+    use ratatui::layout::Rect;
+    use ratatui::{Terminal, TerminalOptions, Viewport};
+
+    fn repeat(code: KeyCode) -> KeyEvent {
+        KeyEvent { code, modifiers: KeyModifiers::NONE, kind: KeyEventKind::Repeat, state: KeyEventState::NONE }
+    }
+
+    #[test]
+    fn test_repeat_allowed_only_for_navigation() {
+        for code in [KeyCode::Char('j'), KeyCode::Char('k'), KeyCode::Char('l'), KeyCode::Char('K'), KeyCode::PageDown, KeyCode::Up] {
+            assert!(repeat_allowed(&InputMode::Normal, &repeat(code)), "{:?}", code);
+        }
+        for code in [KeyCode::Enter, KeyCode::Char('d'), KeyCode::Char('x'), KeyCode::Char('X'), KeyCode::Char('q'), KeyCode::Char('i')] {
+            assert!(!repeat_allowed(&InputMode::Normal, &repeat(code)), "{:?}", code);
+        }
+        assert!(!repeat_allowed(&InputMode::SessionList, &repeat(KeyCode::Char('d'))));
+        assert!(!repeat_allowed(&InputMode::Visual, &repeat(KeyCode::Char('d'))));
+        assert!(repeat_allowed(&InputMode::Insert, &repeat(KeyCode::Char('a'))));
+        assert!(!repeat_allowed(&InputMode::Insert, &repeat(KeyCode::Enter)));
+        let ctrl_d = KeyEvent { modifiers: KeyModifiers::CONTROL, ..repeat(KeyCode::Char('d')) };
+        assert!(!repeat_allowed(&InputMode::Normal, &ctrl_d));
+    }
+
+    #[tokio::test]
+    async fn test_repeat_scrolls_but_does_not_send() {
+        let (_dir, mut app) = test_app("repeat", false);
+        // 描画しない範囲のキーだけを送るので、端末の大きさは問い合わせない固定の領域でよい
+        let options = TerminalOptions { viewport: Viewport::Fixed(Rect::new(0, 0, 80, 24)) };
+        let mut terminal = Terminal::with_options(CrosstermBackend::new(std::io::stdout()), options).unwrap();
+
+        for _ in 0..3 {
+            app.handle_key_event(repeat(KeyCode::Char('j')), &mut terminal).await.unwrap();
+        }
+        assert_eq!(app.ui.scroll_offset, 3);
+
+        let message_count = app.messages.len();
+        app.ui.input = "hello".to_string();
+        app.ui.cursor_position = 5;
+        app.handle_key_event(repeat(KeyCode::Enter), &mut terminal).await.unwrap();
+        assert_eq!(app.ui.input, "hello");
+        assert_eq!(app.messages.len(), message_count);
+
+        app.handle_key_event(repeat(KeyCode::Char('x')), &mut terminal).await.unwrap();
+        assert_eq!(app.ui.input, "hello");
+    }
+
+    #[tokio::test]
+    async fn test_ctrl_a_and_ctrl_e_move_within_current_line() {
+        let (_dir, mut app) = test_app("line_keys", false);
+        let options = TerminalOptions { viewport: Viewport::Fixed(Rect::new(0, 0, 80, 24)) };
+        let mut terminal = Terminal::with_options(CrosstermBackend::new(std::io::stdout()), options).unwrap();
+        let ctrl = |c| KeyEvent { modifiers: KeyModifiers::CONTROL, kind: KeyEventKind::Press, ..repeat(KeyCode::Char(c)) };
+        app.ui.input_mode = InputMode::Insert;
+        app.ui.input = "first\nsecond line\nthird".to_string();
+        app.ui.cursor_position = 9; // second の "o"
+
+        app.handle_key_event(ctrl('a'), &mut terminal).await.unwrap();
+        assert_eq!(app.ui.cursor_position, 6);
+        app.handle_key_event(ctrl('e'), &mut terminal).await.unwrap();
+        assert_eq!(app.ui.cursor_position, 17);
+        // 最後の行の末尾はテキストの末尾
+        app.ui.cursor_position = 19;
+        app.handle_key_event(ctrl('e'), &mut terminal).await.unwrap();
+        assert_eq!(app.ui.cursor_position, 23);
+        app.handle_key_event(ctrl('a'), &mut terminal).await.unwrap();
+        assert_eq!(app.ui.cursor_position, 18);
+        assert_eq!(app.ui.input, "first\nsecond line\nthird");
+
+        // 末尾の改行の後の空行
+        app.ui.input = "first\n".to_string();
+        app.ui.cursor_position = 6;
+        app.handle_key_event(ctrl('a'), &mut terminal).await.unwrap();
+        assert_eq!(app.ui.cursor_position, 6);
+    }
+
+    #[test]
+    fn test_mouse_scrolls_messages_and_selects_sessions() {
+        let (_dir, mut app) = test_app("mouse", false);
+        let mouse = |kind, column, row| MouseEvent { kind, column, row, modifiers: KeyModifiers::NONE };
+
+        app.handle_mouse_event(mouse(MouseEventKind::ScrollDown, 0, 0));
+        app.handle_mouse_event(mouse(MouseEventKind::ScrollDown, 0, 0));
+        assert_eq!(app.ui.scroll_offset, 2);
+
+        for title in ["a", "b", "c"] {
+            app.history_manager.lock().unwrap().get_history_mut().new_session(Some(title.to_string()));
+        }
+        app.ui.input_mode = InputMode::SessionList;
+        app.ui.session_list_area = ratatui::layout::Rect::new(0, 0, 40, 10);
+        app.handle_mouse_event(mouse(MouseEventKind::Down(MouseButton::Left), 5, 3));
+        assert_eq!(app.ui.session_list_state.selected(), Some(2));
+        app.handle_mouse_event(mouse(MouseEventKind::ScrollUp, 5, 3));
+        assert_eq!(app.ui.session_list_state.selected(), Some(1));
+        assert_eq!(app.ui.scroll_offset, 2);
+    }
+}
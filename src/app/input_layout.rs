@@ -0,0 +1,163 @@
+use unicode_segmentation::UnicodeSegmentation;
+use std::ops::Range;
+use unicode_width::UnicodeWidthStr;
+
+/// 入力欄の折り返し後のレイアウト
+/// ratatuiのWrapに任せず自前で折り返すことで、カーソル位置を表示座標で求められるようにする
+#[derive(Debug)]
+pub struct InputLayout {
+    /// 折り返し後の各表示行
+    lines: Vec<String>,
+    /// 各グラフェム（と末尾）の表示位置 (row, column)
+    positions: Vec<(usize, usize)>,
+    /// 各グラフェムの表示幅（改行は空白1つ分として扱う）
+    widths: Vec<usize>,
+}
+
+/// 表示行1行分のハイライト範囲
+#[derive(Debug, Clone, PartialEq)]
+pub struct HighlightSpan {
+    pub row: usize,
+    pub column: usize,
+    pub width: usize,
+    /// 対象のグラフェムのインデックス範囲
+    pub graphemes: Range<usize>,
+}
+
+impl InputLayout {
+    /// 文字単位で幅widthに収まるよう折り返す（全角文字は行をまたがない）
+    pub fn new(text: &str, width: usize) -> Self {
+        let width = width.max(1);
+        let mut lines = Vec::new();
+        let mut positions = Vec::new();
+        let mut widths = Vec::new();
+        let mut current = String::new();
+        let mut column = 0;
+
+        for grapheme in text.graphemes(true) {
+            if grapheme == "\n" || grapheme == "\r\n" {
+                // 幅いっぱいの行末の改行は枠の内側に収める
+                positions.push((lines.len(), column.min(width - 1)));
+                widths.push(1);
+                lines.push(std::mem::take(&mut current));
+                column = 0;
+                continue;
+            }
+            let grapheme_width = grapheme.width();
+            if column + grapheme_width > width && column > 0 {
+                lines.push(std::mem::take(&mut current));
+                column = 0;
+            }
+            positions.push((lines.len(), column));
+            widths.push(grapheme_width);
+            current.push_str(grapheme);
+            column += grapheme_width;
+        }
+
+        // 末尾のカーソルは、行が幅いっぱいなら次の行の先頭に置く
+        if column >= width {
+            positions.push((lines.len() + 1, 0));
+        } else {
+            positions.push((lines.len(), column));
+        }
+        lines.push(current);
+
+        Self { lines, positions, widths }
+    }
+
+    /// index番目のグラフェムの表示位置（範囲外なら末尾）
+    pub fn position(&self, index: usize) -> (usize, usize) {
+        self.positions[index.min(self.positions.len() - 1)]
+    }
+
+    /// 表示に必要な行数（末尾のカーソルだけが次の行にある場合も含む）
+    pub fn row_count(&self) -> usize {
+        self.lines.len().max(self.position(usize::MAX).0 + 1)
+    }
+
+    /// グラフェム範囲 [start, end) を表示行ごとのハイライト範囲に分ける
+    pub fn highlight_spans(&self, start: usize, end: usize) -> Vec<HighlightSpan> {
+        let mut spans: Vec<HighlightSpan> = Vec::new();
+        for i in start..end.min(self.widths.len()) {
+            let (row, column) = self.positions[i];
+            match spans.last_mut() {
+                Some(span) if span.row == row => {
+                    span.width = column + self.widths[i] - span.column;
+                    span.graphemes.end = i + 1;
+                }
+                _ => spans.push(HighlightSpan { row, column, width: self.widths[i], graphemes: i..i + 1 }),
+            }
+        }
+        spans
+    }
+
+    /// 折り返し済みのテキスト
+    pub fn text(&self) -> String {
+        self.lines.join("\n")
+    }
+}
+
+/// カーソル行が表示範囲に収まるよう、入力欄の縦スクロール位置を調整する
+pub fn scroll_to_row(scroll: usize, row: usize, height: usize) -> usize {
+    let height = height.max(1);
+    if row < scroll {
+        row
+    } else if row >= scroll + height {
+        row + 1 - height
+    } else {
+        scroll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wide_characters_do_not_split_at_wrap_boundary() {
+        // 幅5で "abcd" の後の全角文字は次の行に送られる
+        let layout = InputLayout::new("abcd日本語", 5);
+        assert_eq!(layout.text(), "abcd\n日本\n語");
+        assert_eq!(layout.position(4), (1, 0));
+        assert_eq!(layout.position(5), (1, 2));
+        assert_eq!(layout.position(7), (2, 2));
+        assert_eq!(layout.row_count(), 3);
+    }
+
+    #[test]
+    fn test_cursor_at_end_of_wrapped_line() {
+        // 幅ちょうどで終わる入力の末尾カーソルは次の行の先頭
+        let layout = InputLayout::new("abcdef", 3);
+        assert_eq!(layout.text(), "abc\ndef");
+        assert_eq!(layout.position(3), (1, 0));
+        assert_eq!(layout.position(6), (2, 0));
+        assert_eq!(layout.row_count(), 3);
+
+        // 改行の直前で幅いっぱいの場合は枠の内側に収める
+        let layout = InputLayout::new("abc\nd", 3);
+        assert_eq!(layout.position(3), (0, 2));
+        assert_eq!(layout.position(4), (1, 0));
+        assert_eq!(layout.row_count(), 2);
+
+        assert_eq!(scroll_to_row(0, 3, 8), 0);
+        assert_eq!(scroll_to_row(0, 9, 8), 2);
+        assert_eq!(scroll_to_row(5, 2, 8), 2);
+    }
+
+    #[test]
+    fn test_highlight_spans_across_three_lines_with_empty_line() {
+        // "b" から "c" までの選択は、空行を含む3行に分かれる（改行は空白1つ分）
+        let layout = InputLayout::new("ab\n\ncd", 10);
+        assert_eq!(
+            layout.highlight_spans(1, 5),
+            vec![
+                HighlightSpan { row: 0, column: 1, width: 2, graphemes: 1..3 },
+                HighlightSpan { row: 1, column: 0, width: 1, graphemes: 3..4 },
+                HighlightSpan { row: 2, column: 0, width: 1, graphemes: 4..5 },
+            ]
+        );
+        // 折り返しでも表示行ごとに分かれる
+        let layout = InputLayout::new("abcdef", 4);
+        assert_eq!(layout.highlight_spans(2, 6).iter().map(|s| s.row).collect::<Vec<_>>(), vec![0, 1]);
+    }
+}
@@ -0,0 +1,111 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::app::message_view::part_display_text;
+use crate::app::visual_mode::{word_class, CharClass};
+use crate::app::ChatApp;
+
+/// カーソル位置の単語（英数字と_の並び）。カーソルが単語の上になければ同じ行の右にある最初の単語
+pub fn word_under_cursor(graphemes: &[&str], cursor: usize) -> Option<String> {
+    let is_keyword = |grapheme: &str| word_class(grapheme) == CharClass::Keyword;
+    let start = (cursor..graphemes.len())
+        .take_while(|&i| graphemes[i] != "\n")
+        .find(|&i| is_keyword(graphemes[i]))?;
+    let begin = (0..start).rev().take_while(|&i| is_keyword(graphemes[i])).last().unwrap_or(start);
+    let end = (start..graphemes.len()).find(|&i| !is_keyword(graphemes[i])).unwrap_or(graphemes.len());
+    Some(graphemes[begin..end].concat())
+}
+
+/// textにwordが単語として（前後が英数字や_でない位置に）含まれるか
+pub fn contains_word(text: &str, word: &str) -> bool {
+    let is_keyword_char = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(word).any(|(index, _)| {
+        let before = text[..index].chars().next_back();
+        let after = text[index + word.len()..].chars().next();
+        !before.is_some_and(is_keyword_char) && !after.is_some_and(is_keyword_char)
+    })
+}
+
+impl ChatApp {
+    /// * / #: カーソル位置の単語を検索語にして、次（forwardがfalseなら前）のメッセージへ移動する
+    pub fn search_word_under_cursor(&mut self, forward: bool) {
+        let graphemes: Vec<&str> = self.ui.input.graphemes(true).collect();
+        match word_under_cursor(&graphemes, self.ui.cursor_position) {
+            Some(word) => {
+                self.ui.search_query = Some(word);
+                self.search_messages(forward);
+            }
+            None => self.notify("No word under the cursor".to_string()),
+        }
+    }
+
+    /// 選択中のメッセージの次（前）から検索語を含むメッセージを探して選択する（端まで来たら反対側から続ける）
+    pub fn search_messages(&mut self, forward: bool) -> bool {
+        let (Some(query), Some(current)) = (self.ui.search_query.clone(), self.selected_message_index()) else {
+            return false;
+        };
+        let count = self.messages.len();
+        let found = (1..=count)
+            .map(|step| if forward { (current + step) % count } else { (current + count - step) % count })
+            .find(|&index| {
+                self.messages[index]
+                    .parts
+                    .iter()
+                    .any(|part| contains_word(&part_display_text(part, false), &query))
+            });
+        let Some(index) = found else {
+            self.notify(format!("Pattern not found: {}", query));
+            return false;
+        };
+        if (forward && index <= current) || (!forward && index >= current) {
+            let (hit, continuing) = if forward { ("BOTTOM", "TOP") } else { ("TOP", "BOTTOM") };
+            self.notify(format!("Search hit {}, continuing at {}: {}", hit, continuing, query));
+        }
+        self.ui.selected_message_index = if index == count - 1 { None } else { Some(index) };
+        self.ui.scroll_to_selected_message = true;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_under_cursor_and_contains_word() {
+        let graphemes: Vec<&str> = "call foo_bar(x)\nnext".graphemes(true).collect();
+        assert_eq!(word_under_cursor(&graphemes, 7).as_deref(), Some("foo_bar"));
+        // 記号の上なら右の単語、行末より先は探さない
+        assert_eq!(word_under_cursor(&graphemes, 12).as_deref(), Some("x"));
+        assert_eq!(word_under_cursor(&graphemes, 14), None);
+        assert_eq!(word_under_cursor(&[], 0), None);
+
+        assert!(contains_word("see foo_bar.", "foo_bar"));
+        assert!(!contains_word("foo_barbaz", "foo_bar"));
+        assert!(contains_word("日本 foo", "foo"));
+    }
+
+    #[test]
+    fn test_star_and_hash_select_matching_messages() {
+        let (_dir, mut app) = crate::app::file_operations::tests::test_app("search", false);
+        app.messages.clear();
+        for text in ["fix parser", "the parser_v2 crate", "unrelated", "parser done", "last"] {
+            app.push_system_message(text.to_string());
+        }
+        app.ui.input = "parser".to_string();
+        app.ui.cursor_position = 2;
+        app.ui.selected_message_index = Some(0);
+
+        app.search_word_under_cursor(true);
+        assert_eq!(app.ui.search_query.as_deref(), Some("parser"));
+        assert_eq!(app.ui.selected_message_index, Some(3));
+        // 末尾まで来たら先頭から続ける
+        app.search_word_under_cursor(true);
+        assert_eq!(app.ui.selected_message_index, Some(0));
+        app.search_word_under_cursor(false);
+        assert_eq!(app.ui.selected_message_index, Some(3));
+
+        app.ui.input = "missing".to_string();
+        app.search_word_under_cursor(true);
+        assert_eq!(app.ui.selected_message_index, Some(3));
+    }
+}
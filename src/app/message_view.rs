@@ -0,0 +1,294 @@
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use ratatui::style::Color;
+use uuid::Uuid;
+
+use crate::app::appearance::Appearance;
+use crate::app::ui::RenderMode;
+use crate::app::{ChatApp, InputMode};
+use crate::gemini::{group_digits, CommandResult, Part, ToolOutcome};
+use crate::history::ChatMessage;
+use crate::markdown::hard_wrap;
+
+/// 折りたたんだメッセージのうち末尾に残す行の割合（1/4、残りは先頭に残す）
+const TAIL_DIVISOR: usize = 4;
+/// チャット画面でコマンドのstdout/stderrそれぞれに表示する行数（残りは全文表示で見る）
+const COMMAND_PREVIEW_LINES: usize = 10;
+
+/// 1件のメッセージを折り返した行（長いメッセージは先頭と末尾だけ）。幅や内容が変わるまで使い回す
+#[derive(Debug, Clone, PartialEq)]
+pub struct WrappedMessage {
+    key: u64,
+    pub lines: Vec<String>,
+    /// 行ごとの文字色（Noneはメッセージの色のまま）
+    pub colors: Vec<Option<Color>>,
+    /// 省略した行数と、linesの中の省略の案内の位置（折りたたんでいなければNone）
+    pub collapsed: Option<(usize, usize)>,
+    /// コマンドの出力を一部だけ表示している
+    pub truncated: bool,
+}
+
+/// 長いメッセージの全文表示（折り返しは表示する範囲だけ行う）
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageView {
+    /// 本文の行（折り返し前）
+    pub lines: Vec<String>,
+    /// 行ごとの文字色
+    pub colors: Vec<Option<Color>>,
+    /// 表示開始位置（折り返し前の行単位）
+    pub scroll: usize,
+    /// 前回の描画で表示できた行数（PageUp/PageDownの移動量）
+    pub page_size: usize,
+}
+
+/// チャット画面に表示するパートの本文
+pub fn part_display_text(part: &Part, tool_expanded: bool) -> Cow<'_, str> {
+    match part {
+        Part::Text { text } => Cow::Borrowed(text),
+        Part::FunctionCall { function_call } => Cow::Owned(format!(
+            "Function Call: {}({})",
+            function_call.name,
+            serde_json::to_string(&function_call.args).unwrap_or_default()
+        )),
+        Part::FunctionResponse { function_response } => Cow::Owned(format!(
+            "Function Response: {}: {}",
+            function_response.name,
+            serde_json::to_string(&function_response.response).unwrap_or_default()
+        )),
+        Part::ToolOutcome { tool_outcome: ToolOutcome { command_result: Some(result), .. } } => {
+            Cow::Owned(command_result_lines(result, None).0.into_iter().map(|(line, _)| line).collect::<Vec<_>>().join("\n"))
+        }
+        Part::ToolOutcome { tool_outcome } if tool_expanded => Cow::Owned(tool_outcome.expanded()),
+        Part::ToolOutcome { tool_outcome } => Cow::Owned(tool_outcome.chip()),
+    }
+}
+
+/// コマンドの実行結果の表示行と色。見出し（終了コードで緑/赤）、stdout、赤のstderrの順
+/// previewがSomeならstdout/stderrをそれぞれその行数までにし「… N more lines」を入れる（切ったらtrue）
+pub fn command_result_lines(result: &CommandResult, preview: Option<usize>) -> (Vec<(String, Option<Color>)>, bool) {
+    let badge = if result.success { Color::Green } else { Color::Red };
+    let mut lines = vec![(result.header(), Some(badge))];
+    let mut truncated = false;
+    for (output, color) in [(&result.stdout, None), (&result.stderr, Some(Color::Red))] {
+        let output: Vec<&str> = output.trim_end().lines().collect();
+        let shown = preview.unwrap_or(output.len()).min(output.len());
+        lines.extend(output[..shown].iter().map(|line| (format!("  {}", line), color)));
+        if shown < output.len() {
+            lines.push((format!("  … {} more lines", group_digits((output.len() - shown) as u64)), Some(Color::DarkGray)));
+            truncated = true;
+        }
+    }
+    (lines, truncated)
+}
+
+/// max_linesを超えたら先頭と末尾だけを残し、間に省略した行数の案内を入れる（max_linesが0なら折りたたまない）
+pub fn collapse_lines(mut lines: Vec<String>, max_lines: usize) -> (Vec<String>, Option<(usize, usize)>) {
+    let tail = (max_lines / TAIL_DIVISOR).max(1);
+    let head = max_lines.saturating_sub(tail + 1).max(1);
+    if max_lines == 0 || lines.len() <= max_lines || lines.len() <= head + tail + 1 {
+        return (lines, None);
+    }
+    let hidden = lines.len() - head - tail;
+    let tail_lines = lines.split_off(lines.len() - tail);
+    lines.truncate(head);
+    lines.push(format!("… {} lines hidden — press Enter to open full view …", group_digits(hidden as u64)));
+    lines.extend(tail_lines);
+    (lines, Some((hidden, head)))
+}
+
+/// メッセージ本文を折り返した行。内容・幅・表示形式が前回と同じならキャッシュを返す
+pub fn wrapped_message<'a>(
+    cache: &'a mut HashMap<Uuid, WrappedMessage>,
+    msg: &ChatMessage,
+    render_mode: RenderMode,
+    tool_expanded: bool,
+    appearance: Appearance,
+    max_width: usize,
+    max_lines: usize,
+) -> &'a WrappedMessage {
+    let prefix = msg.speaker();
+    let texts: Vec<String> = msg
+        .parts
+        .iter()
+        .map(|part| appearance.text(&part_display_text(part, tool_expanded)).into_owned())
+        .collect();
+    let mut hasher = DefaultHasher::new();
+    (prefix, render_mode, max_width, max_lines, &texts).hash(&mut hasher);
+    let key = hasher.finish();
+    let build = || {
+        let mut lines = Vec::new();
+        let mut colors = Vec::new();
+        let mut truncated = false;
+        for (part, text) in msg.parts.iter().zip(&texts) {
+            // コマンドの結果はMarkdownとして解釈せず、行ごとに色を付けてそのまま折り返す
+            if let Part::ToolOutcome { tool_outcome: ToolOutcome { command_result: Some(result), .. } } = part {
+                let (rows, cut) = command_result_lines(result, Some(COMMAND_PREVIEW_LINES));
+                truncated |= cut;
+                for (i, (row, color)) in rows.into_iter().enumerate() {
+                    let row = if i == 0 { format!("{}: {}", prefix, row) } else { row };
+                    for wrapped in hard_wrap(&appearance.text(&row), max_width).lines() {
+                        lines.push(wrapped.to_string());
+                        colors.push(color);
+                    }
+                }
+                continue;
+            }
+            for line in render_mode.layout(prefix, text, max_width).lines() {
+                lines.push(line.to_string());
+                colors.push(None);
+            }
+        }
+        let (lines, collapsed) = collapse_lines(lines, max_lines);
+        let colors = match collapsed {
+            Some((hidden, head)) => colors[..head].iter().copied().chain([None]).chain(colors[head + hidden..].iter().copied()).collect(),
+            None => colors,
+        };
+        WrappedMessage { key, lines, colors, collapsed, truncated }
+    };
+    match cache.entry(msg.id) {
+        Entry::Occupied(entry) if entry.get().key == key => entry.into_mut(),
+        Entry::Occupied(mut entry) => {
+            entry.insert(build());
+            entry.into_mut()
+        }
+        Entry::Vacant(entry) => entry.insert(build()),
+    }
+}
+
+impl ChatApp {
+    /// チャット画面の全メッセージの行数（前回の描画の幅で数え、折りたたんだメッセージは折りたたんだ行数）
+    pub fn total_message_lines(&mut self) -> usize {
+        self.message_line_counts().iter().sum()
+    }
+
+    /// 選択中のメッセージが折りたたまれているか、コマンドの出力を一部だけ表示していれば全文表示を開く（開いたらtrue）
+    /// チャット画面のスクロール位置はそのままなので、閉じると元の位置に戻る
+    pub fn open_selected_message_view(&mut self) -> bool {
+        let Some(msg) = self.selected_message_index().and_then(|index| self.messages.get(index)) else {
+            return false;
+        };
+        if self.ui.wrapped_messages.get(&msg.id).is_none_or(|wrapped| wrapped.collapsed.is_none() && !wrapped.truncated) {
+            return false;
+        }
+        let tool_expanded = self.ui.expanded_tool_messages.contains(&msg.id);
+        let mut lines = Vec::new();
+        let mut colors = Vec::new();
+        for part in &msg.parts {
+            let rows = match part {
+                Part::ToolOutcome { tool_outcome: ToolOutcome { command_result: Some(result), .. } } => command_result_lines(result, None).0,
+                _ => part_display_text(part, tool_expanded).lines().map(|line| (line.to_string(), None)).collect(),
+            };
+            for (line, color) in rows {
+                lines.push(self.appearance.text(&line).into_owned());
+                colors.push(color);
+            }
+        }
+        self.ui.message_view = Some(MessageView { lines, colors, scroll: 0, page_size: 1 });
+        self.ui.input_mode = InputMode::MessageView;
+        true
+    }
+
+    /// 全文表示を閉じてチャット画面に戻る
+    pub fn close_message_view(&mut self) {
+        self.ui.message_view = None;
+        self.ui.input_mode = InputMode::Normal;
+    }
+
+    /// 全文表示をdelta行スクロールする（先頭・末尾で止まる）
+    pub fn scroll_message_view(&mut self, delta: isize) {
+        if let Some(view) = &mut self.ui.message_view {
+            let last = view.lines.len().saturating_sub(1);
+            view.scroll = view.scroll.saturating_add_signed(delta).min(last);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collapse_long_messages_and_cache() {
+        let lines: Vec<String> = (0..20_000).map(|i| i.to_string()).collect();
+        let (collapsed, info) = collapse_lines(lines.clone(), 200);
+        assert_eq!(collapsed.len(), 200);
+        assert_eq!(info, Some((19_801, 149)));
+        assert_eq!(collapsed[148], "148");
+        assert_eq!(collapsed[149], "… 19,801 lines hidden — press Enter to open full view …");
+        assert_eq!(collapsed[150], "19950");
+        assert_eq!(collapsed.last().unwrap(), "19999");
+        assert_eq!(collapse_lines(lines.clone(), 0).0.len(), 20_000);
+        assert_eq!(collapse_lines(lines[..200].to_vec(), 200), (lines[..200].to_vec(), None));
+
+        let mut msg = ChatMessage {
+            id: Uuid::new_v4(),
+            parts: vec![Part::Text { text: lines.join("\n") }],
+            is_user: false,
+            timestamp: chrono::Utc::now(),
+            comparison: false,
+            steps: Vec::new(),
+            is_system: false,
+            excluded: false,
+        };
+        let mut cache = HashMap::new();
+        let first = wrapped_message(&mut cache, &msg, RenderMode::Raw, false, Appearance::default(), 40, 200).clone();
+        assert_eq!(first.lines.len(), 200);
+        assert_eq!(first.lines[0], "AI: 0");
+        assert_eq!(wrapped_message(&mut cache, &msg, RenderMode::Raw, false, Appearance::default(), 40, 200), &first);
+        // 内容が変わったら折り返し直す
+        msg.parts = vec![Part::Text { text: "short".to_string() }];
+        let short = wrapped_message(&mut cache, &msg, RenderMode::Raw, false, Appearance::default(), 40, 200);
+        assert_eq!((short.lines.clone(), short.collapsed), (vec!["AI: short".to_string()], None));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_command_result_rendered_with_streams() {
+        let result = CommandResult {
+            command: "cargo test".to_string(),
+            stdout: (1..=12).map(|i| format!("ok {}", i)).collect::<Vec<_>>().join("\n"),
+            stderr: "warning: unused".to_string(),
+            success: false,
+            exit_code: Some(101),
+            duration_ms: 3_200,
+        };
+        let tool_outcome = ToolOutcome {
+            tool: "execute_command".to_string(),
+            args_summary: "cargo test".to_string(),
+            success: false,
+            detail: String::new(),
+            duration_ms: Some(3_300),
+            response: serde_json::Value::Null,
+            command_result: Some(result.clone()),
+        };
+        let msg = ChatMessage {
+            id: Uuid::new_v4(),
+            parts: vec![Part::ToolOutcome { tool_outcome: tool_outcome.clone() }],
+            is_user: false,
+            timestamp: chrono::Utc::now(),
+            comparison: false,
+            steps: Vec::new(),
+            is_system: false,
+            excluded: false,
+        };
+        let mut cache = HashMap::new();
+        let wrapped = wrapped_message(&mut cache, &msg, RenderMode::Markdown, false, Appearance::default(), 80, 200);
+        assert_eq!(wrapped.lines[0], "AI: $ cargo test  [exit 101]  3.2s");
+        assert_eq!(wrapped.colors[0], Some(Color::Red));
+        assert_eq!(wrapped.lines[10], "  ok 10");
+        assert_eq!((wrapped.lines[11].as_str(), wrapped.colors[11]), ("  … 2 more lines", Some(Color::DarkGray)));
+        assert_eq!((wrapped.lines[12].as_str(), wrapped.colors[12]), ("  warning: unused", Some(Color::Red)));
+        assert_eq!(wrapped.lines.len(), 13);
+        assert!(wrapped.truncated);
+        // 全文表示では省略しない
+        assert_eq!(command_result_lines(&result, None).0.len(), 14);
+
+        // エクスポートではラベル付きのコードブロック
+        assert!(tool_outcome.expanded().ends_with("```stdout\nok 1\nok 2\nok 3\nok 4\nok 5\nok 6\nok 7\nok 8\nok 9\nok 10\nok 11\nok 12\n```\n```stderr\nwarning: unused\n```"));
+        let restored: Part = serde_json::from_str(&serde_json::to_string(&msg.parts[0]).unwrap()).unwrap();
+        assert!(matches!(restored, Part::ToolOutcome { tool_outcome: restored } if restored.command_result == Some(result)));
+    }
+}
@@ -1,17 +1,20 @@
 use crate::gemini::Part; // Add this import
 use crate::gemini::ResponsePart; // Add this import
 use crate::debug_log;
-use ratatui::{
-    widgets::ListState,
-};
 use uuid::Uuid;
 use chrono::Utc;
 use tokio::sync::mpsc;
-use crate::gemini::GeminiClient;
-use crate::history::HistoryManager;
+use crate::gemini::{is_transient_error, Content, GeminiClient};
+use crate::history::{CleanupTarget, FileChange, HistoryManager, StepRecord};
+use crate::config::AppConfig;
+use crate::i18n::tr;
+use crate::termination::{extract_is_finished_flag, response_similarity, TerminationConfig, TerminationReason};
+use crate::file_access::FileReference;
+use crate::bookmarks::{default_bookmarks_path, load_bookmarks, Bookmarks};
 // use anyhow::Result; // Unused import
 use unicode_width::UnicodeWidthStr;
 use unicode_segmentation::UnicodeSegmentation;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 
 // モジュール宣言
@@ -22,49 +25,129 @@ pub mod session_management;
 pub mod cursor_movement;
 pub mod visual_mode;
 pub mod terminal_util;
+pub mod commands;
+pub mod compaction;
+pub mod comparison;
+pub mod task_queue;
+pub mod input_layout;
+pub mod text_objects;
+pub mod session_diff;
+pub mod drafts;
+pub mod theme;
+pub mod appearance;
+pub mod file_watch;
+pub mod attachment_watch;
+pub mod message_view;
+pub mod message_search;
+pub mod setup_wizard;
+pub mod tabs;
+pub mod desktop_notify;
+pub mod response_hooks;
+pub mod context_gauge;
+pub mod split_pane;
+pub mod scroll_anchor;
+pub mod permissions;
+pub mod format_code;
 
 pub use crate::app::ui::ChatEvent;
 
 pub use crate::app::ui::UiState;
 
+use crate::app::commands::CommandRegistry;
+use crate::app::theme::Theme;
+use crate::app::appearance::Appearance;
+use crate::app::task_queue::{OutboundRequest, TaskQueue, UserTask};
+use crate::app::file_operations::{attachment_summary, requests_file_creation};
+
+/// LLMに送る会話コンテキストの最大メッセージ数
+pub const CONTEXT_MESSAGE_LIMIT: usize = 10;
+
+/// LLMに送る会話コンテキストの見積もりトークン数の上限（estimate_tokensで数える）
+pub const CONTEXT_TOKEN_BUDGET: usize = 16_000;
+
+/// 通知履歴に残す最大件数
+pub const NOTIFICATION_HISTORY_LIMIT: usize = 100;
+
 pub struct ChatApp {
     pub ui: UiState,
     pub messages: Vec<crate::history::ChatMessage>,
     pub gemini_client: GeminiClient,
     pub event_sender: mpsc::UnboundedSender<ChatEvent>,
     pub event_receiver: mpsc::UnboundedReceiver<ChatEvent>,
-    pub is_loading: bool,
     pub history_manager: Arc<Mutex<HistoryManager>>,
     //pub todo_manager: TodoManager,
     pub llm_task_handle: Option<tokio::task::JoinHandle<()>>, // LLMリクエスト用タスクハンドル
-    pub send_buffer: std::collections::VecDeque<String>, // チャット送信バッファ
+    pub task_queue: TaskQueue, // 応答待ちの間に送信されたメッセージ
+    pub command_registry: CommandRegistry, // スラッシュコマンド
+    pub command_raw_args: String, // 実行中のスラッシュコマンドの引数（分解する前の文字列）
+    pub config: AppConfig,
+    pub file_changes: Vec<FileChange>, // 現在のセッションでAIが変更したファイル
+    pub last_user_message_id: Option<Uuid>, // 直近に送信したユーザーメッセージのID
+    pub file_creation_requested: bool, // 直近のユーザーメッセージがファイル作成を依頼しているか（旧形式の```create_file用）
+    pub compaction_handle: Option<tokio::task::JoinHandle<()>>, // 会話要約タスクハンドル
+    pub compare_models: Option<(String, String)>, // 比較モードで使う2つのモデル
+    pub pending_cleanup: Option<(CleanupTarget, Vec<Uuid>)>, // /cleanup confirm 待ちの削除対象
+    pub session_scroll_positions: HashMap<Uuid, usize>, // セッション一覧から切り替えたときに戻すスクロール位置
+    pub unread_responses: HashMap<Uuid, usize>, // 別のセッションを表示中に届いた応答の数（セッション一覧に表示）
+    pub background_responses: HashSet<Uuid>, // 表示していない間に応答が届き始めたセッション（リクエストが終わったら未読を1つ数える）
+    pub last_request: Option<OutboundRequest>, // 応答待ちのリクエスト（応答を受け取ったら破棄）
+    pub pending_retry: Option<OutboundRequest>, // 一時的なエラーで失敗し、'r'で再送できるリクエスト
+    pub draft_path: Option<std::path::PathBuf>, // 入力の下書きの保存先（Noneなら保存しない）
+    pub draft_seen: crate::drafts::Draft, // 前回のtick_draftで見た入力（変化の検出用）
+    pub draft_changed_at: Option<std::time::Instant>, // 下書きを保存していない入力の最後の変更時刻
+    pub context_gauge: context_gauge::ContextGaugeState, // 入力欄に表示するコンテキストウィンドウの使用量
+    pub split_pane: split_pane::SplitPane, // チャットの右に並べる作業中のコンテキスト（Ctrl+P）
+    pub theme: Theme, // メッセージの配色
+    pub appearance: Appearance, // ASCIIモード・ハイコントラスト（/appearance）
+    pub file_watch: Option<file_watch::FileWatch>, // /watch で監視中のファイル
+    pub attachment_watcher: Option<attachment_watch::AttachmentWatcher>, // 添付したファイルの変更の監視（最初の添付で作る）
+    pub changed_attachments: std::collections::BTreeSet<std::path::PathBuf>, // 添付後にディスク上で変更されたファイル（/refresh で添付し直す）
+    pub last_input_at: Option<std::time::Instant>, // 最後のキー入力の時刻（入力中に終わったリクエストはデスクトップに通知しない）
+    pub request_started_at: HashMap<Uuid, std::time::Instant>, // 応答待ちのリクエストを送った時刻（セッションごと）
+    pub request_errors: HashMap<Uuid, String>, // 応答待ちのリクエストで起きたエラー（終了時の通知に使う）
+    pub response_texts: HashMap<Uuid, String>, // 応答待ちのリクエストで受け取ったテキスト（post_responseフックに渡す）
+    pub deleted_session: Option<(Uuid, std::time::Instant)>, // 直前にゴミ箱に入れたセッションと時刻（uで元に戻す）
+    pub task_session: Option<Uuid>, // 処理中のLLMタスクがリクエストを送ったセッション
+    pub tabs: Vec<Option<tabs::TabState>>, // 開いているタブ（表示中のタブはNoneで、その状態はこの構造体のフィールドにある）
+    pub active_tab: usize, // 表示中のタブの位置
+    pub redaction_overrides: HashMap<Uuid, bool>, // /redact on|off で切り替えたセッションごとの伏せ字の有効/無効（contuiを終了するまで）
     // pub terminal: Option<Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>>,
 }
 
 pub use crate::app::ui::InputMode;
+pub use crate::app::ui::RenderMode;
 
 
 impl ChatApp {
     pub fn new(
         mut gemini_client: GeminiClient,
         history_manager: Arc<Mutex<HistoryManager>>,
+        config: AppConfig,
     ) -> Self {
         let (event_sender, event_receiver) = mpsc::unbounded_channel();
         
+        // 保持期間を過ぎたゴミ箱のセッションを完全に削除
+        let purged = {
+            let mut history_guard = history_manager.lock().unwrap();
+            let retention = chrono::Duration::days(config.trash_retention_days as i64);
+            let purged = history_guard.get_history_mut().purge_trash(retention, Utc::now());
+            if purged > 0 {
+                if let Err(e) = history_guard.save() {
+                    debug_log!("[ChatApp::new] save_history error: {:?}", e);
+                }
+            }
+            purged
+        };
+
         // アクティブなセッションを確保
         let _session_id = (*history_manager.lock().unwrap()).ensure_active_session();
         
         // 現在のセッションからメッセージを読み込み
         let mut messages = Vec::new();
+        let mut file_changes = Vec::new();
                 if let Some(session) = (*history_manager.lock().unwrap()).get_history().get_current_session() {
-            for hist_msg in &session.messages {
-                messages.push(crate::history::ChatMessage {
-                    id: hist_msg.id,
-                    parts: hist_msg.parts.clone(), // Changed from content
-                    is_user: hist_msg.is_user,
-                    timestamp: hist_msg.timestamp,
-                });
-            }
+            file_changes = session.changes.clone();
+            messages.extend(session.messages.iter().cloned());
         }
 
         // 現在のディレクトリを取得
@@ -73,44 +156,90 @@ impl ChatApp {
             .to_string_lossy()
             .to_string();
 
-        // ファイルアクセス許可を設定（現在のディレクトリとホームディレクトリ）
-        if let Err(_e) = gemini_client.add_allowed_directory(&current_dir) {
-            // Directory access permission error - silently continue
-        }
-        if let Some(home_dir) = dirs::home_dir() {
-            if let Err(_e) = gemini_client.add_allowed_directory(&home_dir) {
+        // ファイルアクセス許可を設定（現在のディレクトリと設定ファイルで許可したディレクトリ）
+        // それ以外は参照したときに確認する。/permissions で外した作業ディレクトリは許可しない
+        if !permissions::is_excluded_directory(std::path::Path::new(&current_dir), &config.excluded_directories) {
+            if let Err(_e) = gemini_client.add_allowed_directory(&current_dir) {
                 // Directory access permission error - silently continue
             }
         }
+        for directory in &config.allowed_directories {
+            if let Err(e) = gemini_client.allow_configured_directory(directory) {
+                debug_log!("[ChatApp::new] allow_configured_directory {:?} error: {:?}", directory, e);
+            }
+        }
+        let bookmarks = match default_bookmarks_path().map(|path| load_bookmarks(&path)) {
+            Some(Ok(bookmarks)) => bookmarks,
+            Some(Err(e)) => {
+                debug_log!("[ChatApp::new] load_bookmarks error: {:?}", e);
+                Bookmarks::new()
+            }
+            None => Bookmarks::new(),
+        };
+
+        let theme = config.message_colors.as_deref().map(Theme::from_spec).transpose();
+        let redactor = crate::redact::Redactor::with_patterns(config.redact_patterns.as_deref().unwrap_or(""));
+        let redact_secrets = config.redact_secrets;
+        let appearance = Appearance { ascii_mode: config.ascii_mode, high_contrast: config.high_contrast };
+
         let mut app = Self {
-            ui: UiState {
-                input: String::new(),
-                cursor_position: 0,
-                visual_start: None,
-                input_mode: InputMode::Normal,
-                list_state: ListState::default(),
-                scroll_offset: 0,
-                session_list_state: ListState::default(),
-                file_browser_state: ListState::default(),
-                current_directory: current_dir,
-                directory_contents: Vec::new(),
-                selected_files: Vec::new(),
-                input_line_count: 1,
-                input_history: Vec::new(),
-                history_index: None,
-                temp_input: String::new(),
-                show_help: false,
-                notification: None,
-            },
+            ui: UiState::new(current_dir, bookmarks),
             messages,
             gemini_client,
             event_sender,
             event_receiver,
-            is_loading: false,
             history_manager,
             llm_task_handle: None,
-            send_buffer: std::collections::VecDeque::new(),
+            task_queue: TaskQueue::new(),
+            command_registry: CommandRegistry::new(),
+            command_raw_args: String::new(),
+            config,
+            file_changes,
+            last_user_message_id: None,
+            file_creation_requested: false,
+            compaction_handle: None,
+            compare_models: None,
+            pending_cleanup: None,
+            session_scroll_positions: HashMap::new(),
+            unread_responses: HashMap::new(),
+            background_responses: HashSet::new(),
+            last_request: None,
+            pending_retry: None,
+            draft_path: None,
+            draft_seen: crate::drafts::Draft::default(),
+            draft_changed_at: None,
+            context_gauge: context_gauge::ContextGaugeState::default(),
+            split_pane: split_pane::SplitPane::default(),
+            theme: Theme::default(),
+            appearance,
+            file_watch: None,
+            attachment_watcher: None,
+            changed_attachments: std::collections::BTreeSet::new(),
+            last_input_at: None,
+            request_started_at: HashMap::new(),
+            request_errors: HashMap::new(),
+            response_texts: HashMap::new(),
+            deleted_session: None,
+            task_session: None,
+            tabs: vec![None],
+            active_tab: 0,
+            redaction_overrides: HashMap::new(),
         };
+        match theme {
+            Ok(theme) => app.theme = theme.unwrap_or_default(),
+            Err(e) => app.notify(format!("⚠ Invalid MESSAGE_COLORS, using the default colors: {}", e)),
+        }
+        match redactor {
+            Ok(redactor) => app.gemini_client = app.gemini_client.clone().with_redactor(redactor),
+            Err(e) => app.notify(format!("⚠ Invalid REDACT_PATTERNS, using only the default patterns: {}", e)),
+        }
+        if purged > 0 {
+            app.notify(format!("🗑 Purged {} session(s) from the trash", purged));
+        }
+        if !redact_secrets {
+            app.gemini_client.redactor().set_enabled(false);
+            app.notify("⚠ REDACT_SECRETS=false: messages and attached files are sent without redacting secrets".to_string());
+        }
 
         // 歓迎メッセージを追加（履歴が空の場合のみ）
         // 歓迎メッセージを追加（履歴が空の場合のみ）
@@ -120,6 +249,10 @@ impl ChatApp {
                 parts: vec![crate::gemini::Part::Text { text: "Welcome to ConTUI!".to_string() }], // Changed from content
                 is_user: false,
                 timestamp: Utc::now(),
+                comparison: false,
+                steps: Vec::new(),
+                is_system: false,
+                excluded: false,
             });
         }
 
@@ -127,120 +260,202 @@ impl ChatApp {
     }
 
     pub fn handle_chat_event(&mut self, event: ChatEvent) {
+        self.track_request_outcome(&event);
+        self.collect_response_for_hook(&event);
+        // 裏のタブで開いているセッション宛てのイベントは、そのタブに切り替えて処理する
+        match event.session_id().and_then(|session_id| self.background_tab_for(session_id)) {
+            Some(index) => self.with_tab(index, |app| app.apply_chat_event(event)),
+            None => self.apply_chat_event(event),
+        }
+    }
+
+    fn apply_chat_event(&mut self, event: ChatEvent) {
         match event {
-            ChatEvent::AIResponse(response_part) => { // Changed msg to response_part and type
-                let response_text = match &response_part {
-                    ResponsePart::Text { text } => text.clone(),
-                    ResponsePart::FunctionCall { function_call } => {
-                        format!("FunctionCall: {}", serde_json::to_string_pretty(&function_call).unwrap_or_default())
-                    },
-                    ResponsePart::FunctionResponse { function_response } => {
-                        format!("FunctionResponse: {}", serde_json::to_string_pretty(&function_response).unwrap_or_default())
-                    },
-                };
-                debug_log!("[handle_chat_event] AIResponse: {}", response_text);
-
-                // ファイル作成要求を処理 (This part needs to be re-evaluated if it's still needed)
-                // For now, let's assume process_file_creation_requests expects a String
-                let processed_msg = self.process_file_creation_requests(&response_text);
-                
-                let final_msg_content = if processed_msg.is_empty() {
-                    "AIからの応答がありませんでした。".to_string()
-                } else {
-                    processed_msg
-                };
-                
-                // AIレスポンスをメッセージリストに追加
-                let ai_msg = crate::history::ChatMessage {
-                    id: Uuid::new_v4(),
-                    parts: vec![crate::gemini::Part::Text { text: final_msg_content.clone() }], // Changed content to parts
-                    is_user: false,
-                    timestamp: Utc::now(),
-                };
-                self.messages.push(ai_msg);
-                debug_log!("[handle_chat_event] メッセージ追加: {}", final_msg_content);
-                self.is_loading = false;
-                
-                // スクロール位置の自動調整
-                self.auto_scroll_if_at_bottom();
-                
-                // バッファがあれば自動送信イベントを発火
-                if let Some(next) = self.send_buffer.pop_front() {
-                    debug_log!("[handle_chat_event] バッファから自動送信イベント: {}", next);
-                    // ChatEvent::AIResponseでバッファ送信要求を通知
-                    let _ = self.event_sender.send(ChatEvent::AIResponse(ResponsePart::Text { text: format!("[BUFFERED_SEND]{}", next) }));
+            ChatEvent::Session { session_id, event } => {
+                // 最終応答・ツールの結果・終了メッセージで何回届いても、1回のリクエストで未読は1つ
+                if matches!(*event, ChatEvent::TaskFinished) && self.background_responses.remove(&session_id) {
+                    *self.unread_responses.entry(session_id).or_default() += 1;
+                }
+                self.apply_chat_event(*event)
+            }
+            ChatEvent::AIResponseFor { session_id, part: response_part } => {
+                let response_text = response_part.display_text();
+                debug_log!("[handle_chat_event] AIResponse ({}): {} bytes", session_id, response_text.len());
+
+                // 関数呼び出しによるファイル変更を記録
+                match &response_part {
+                    ResponsePart::FunctionResponse { function_response } => self.record_function_response_change(session_id, function_response),
+                    ResponsePart::ToolOutcome { tool_outcome } => self.record_function_response_change(session_id, &tool_outcome.function_response()),
+                    _ => {}
                 }
-                
-                // 履歴管理にAIレスポンスを追加（画面表示と同じ内容を保存）
-                // 必ず表示中セッションに保存する
-                {
-                    let mut history_guard = self.history_manager.lock().unwrap();
-                    if let Some(session) = history_guard.get_history().get_current_session() {
-                        let session_id = session.id;
-                        let _ = history_guard.get_history_mut().switch_session(session_id);
+
+                // 許可ディレクトリの外で失敗したツールは、そのディレクトリを許可するか確認する
+                if let ResponsePart::ToolOutcome { tool_outcome } = &response_part {
+                    if let Some(path) = tool_outcome.response["outside_path"].as_str() {
+                        self.request_directory_access(std::path::Path::new(path), false);
                     }
-                    // Add the original ResponsePart to history
-                    let parts_to_add_to_history = match response_part {
-                        ResponsePart::Text { text } => vec![Part::Text { text }],
-                        ResponsePart::FunctionCall { function_call } => vec![Part::Text { text: serde_json::to_string(&function_call).unwrap_or_default() }],
-                        ResponsePart::FunctionResponse { function_response } => vec![Part::Text { text: serde_json::to_string(&function_response).unwrap_or_default() }],
+                }
+
+                let display_parts = if let ResponsePart::ToolOutcome { tool_outcome } = &response_part {
+                    // ツール実行結果は構造化したままチップとして表示する
+                    vec![Part::ToolOutcome { tool_outcome: tool_outcome.clone() }]
+                } else {
+                    // 旧形式の```create_file```ブロックは、設定で有効にしていて、かつユーザーがファイル作成を
+                    // 依頼したときだけ処理する（添付ファイルの内容がそのまま返ってきても実行しない）
+                    let processed_msg = if self.config.legacy_file_blocks && self.file_creation_requested {
+                        self.process_file_creation_requests(session_id, &response_text)
+                    } else {
+                        response_text.clone()
+                    };
+
+                    let final_msg_content = if processed_msg.is_empty() {
+                        "AIからの応答がありませんでした。".to_string()
+                    } else {
+                        processed_msg
                     };
-                    let _ = (*history_guard).get_history_mut().add_message(parts_to_add_to_history, false);
-                    debug_log!("[handle_chat_event] current_session_id: {:?}", history_guard.get_history().current_session_id);
+                    vec![Part::Text { text: final_msg_content }]
+                };
+
+                // 履歴管理にAIレスポンスを追加（表示中のセッションではなく、リクエストを送ったセッションに保存する）
+                let parts_to_add_to_history = vec![response_part.to_history_part()];
+                let history_result = self.history_manager.lock().unwrap().get_history_mut().add_message_to(session_id, parts_to_add_to_history, false);
+                if let Err(e) = &history_result {
+                    debug_log!("[handle_chat_event] add_message_to error: {:?}", e);
                 }
 
-                // AIレスポンス追加直後に履歴保存
-                if let Err(e) = (*self.history_manager.lock().unwrap()).save() {
-                    debug_log!("[handle_chat_event] save_history error: {:?}", e);
+                if self.is_visible_session(session_id) {
+                    // AIレスポンスをメッセージリストに追加
+                    let ai_msg = crate::history::ChatMessage {
+                        id: history_result.unwrap_or_else(|_| Uuid::new_v4()),
+                        parts: display_parts,
+                        is_user: false,
+                        timestamp: Utc::now(),
+                        comparison: false,
+                        steps: Vec::new(),
+                        is_system: false,
+                        excluded: false,
+                    };
+                    self.messages.push(ai_msg);
+                    self.ui.unsaved = true;
+                    debug_log!("[handle_chat_event] メッセージ追加: {} bytes", response_text.len());
+
+                    // スクロール位置の自動調整
+                    self.auto_scroll_if_at_bottom();
+                } else {
+                    // 別のセッションを表示中なら、リクエストが終わったときにセッション一覧の未読として数える
+                    self.background_responses.insert(session_id);
                 }
+
+                // 上限を超えたら古いメッセージを削除してから、AIレスポンス追加直後に履歴保存
+                self.trim_session_to_limit(session_id);
+                self.autosave_history("handle_chat_event");
             }
             ChatEvent::Error(msg) => {
                 debug_log!("[handle_chat_event] Error: {}", msg);
-                self.ui.notification = Some(msg);
-                self.is_loading = false;
+                crate::logger::log_error(&msg);
+                self.notify(msg);
+            }
+            ChatEvent::RetryableError(msg) => {
+                debug_log!("[handle_chat_event] RetryableError: {}", msg);
+                crate::logger::log_error(&msg);
+                let msg = match self.last_request.take() {
+                    Some(request) => {
+                        self.pending_retry = Some(request);
+                        format!("{} [press r to retry]", msg)
+                    }
+                    None => msg,
+                };
+                self.notify(msg);
+            }
+            ChatEvent::ResponseReceived => {
+                self.last_request = None;
+            }
+            ChatEvent::Notification(msg) => {
+                debug_log!("[handle_chat_event] Notification: {}", msg);
+                self.notify(msg);
+            }
+            ChatEvent::Comparison { session_id, model_a, response_a, model_b, response_b } => {
+                debug_log!("[handle_chat_event] Comparison: {} vs {}", model_a, model_b);
+                self.handle_comparison_result(session_id, model_a, response_a, model_b, response_b);
+            }
+            ChatEvent::SystemMessage(text) => {
+                self.push_system_message(text);
+            }
+            ChatEvent::CodeFormatted { before, after } => {
+                self.apply_formatted_code(&before, after);
+            }
+            ChatEvent::AgentSteps { session_id, steps } => {
+                debug_log!("[handle_chat_event] AgentSteps: {}", steps.len());
+                if let Err(e) = self.history_manager.lock().unwrap().get_history_mut().attach_steps(session_id, steps.clone()) {
+                    debug_log!("[handle_chat_event] attach_steps error: {:?}", e);
+                }
+                // 別のセッションを表示中なら履歴に付けるだけ
+                let visible = self.is_visible_session(session_id);
+                if let Some(message) = self.messages.iter_mut().rev().find(|m| !m.is_user).filter(|_| visible) {
+                    message.steps = steps;
+                }
+                self.autosave_history("agent_steps");
+            }
+            ChatEvent::SessionImported { path, result } => match result {
+                Ok(imported) => self.open_imported_session(&path, imported),
+                Err(e) => self.push_system_message(format!("❌ Import from {} failed: {}", path, e)),
+            },
+            ChatEvent::FileUpdate { path, text } => {
+                debug_log!("[handle_chat_event] FileUpdate: {} ({} bytes)", path, text.len());
+                // /unwatch の直前に届いた更新は送らない
+                if self.file_watch.as_ref().is_some_and(|watch| watch.path == path) {
+                    self.send_file_update(&path, &text);
+                }
+            }
+            ChatEvent::AttachmentChanged(path) => {
+                debug_log!("[handle_chat_event] AttachmentChanged: {}", path.display());
+                self.on_attachment_changed(path);
+            }
+            ChatEvent::TaskFinished => {
+                debug_log!("[handle_chat_event] TaskFinished (pending: {})", self.task_queue.pending());
+                self.llm_task_handle = None;
+                self.task_session = None;
+                self.task_queue.finish();
+                self.process_next_task();
             }
         }
     }
 
     pub async fn send_message(&mut self, _terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>) {
-        self.ui.notification = None;
+        self.dismiss_notification();
         let original_message = self.ui.input.clone();
-        debug_log!("[send_message] called. input={}", original_message);
-        // LLM応答待ち中ならバッファに積むだけ
-        if self.is_loading {
-            debug_log!("[send_message] is_loading=true, bufferに積んだ: {}", original_message);
-            self.send_buffer.push_back(original_message.clone());
+        debug_log!("[send_message] called. input={} bytes", original_message.len());
+
+        // スラッシュコマンドはLLMに送らずローカルで処理
+        if original_message.trim_start().starts_with('/') {
+            self.execute_slash_command(&original_message);
+            // 入力欄を書き換えるコマンド（/template load）の結果は残す
+            if self.ui.input == original_message {
+                self.reset_input_after_command();
+            }
             return;
         }
 
-        // /clearlogコマンド判定
-        if original_message.trim() == "/clearlog" {
-            match (*self.history_manager.lock().unwrap()).clear_messages() {
-                Ok(_) => {
-                    self.messages.clear();
-                    self.messages.push(crate::history::ChatMessage {
-                        id: Uuid::new_v4(),
-                        parts: vec![Part::Text { text: "✅ ログを全て削除しました.".to_string() }], // Changed content to parts
-                        is_user: false,
-                        timestamp: Utc::now(),
-                    });
-                }
-                Err(e) => {
-                    self.messages.push(crate::history::ChatMessage {
-                        id: Uuid::new_v4(),
-                        parts: vec![Part::Text { text: format!("❌ ログ削除に失敗しました: {}", e) }], // Changed content to parts
-                        is_user: false,
-                        timestamp: Utc::now(),
-                    });
-                }
-            }
-            self.ui.input.clear();
-            self.ui.cursor_position = 0;
-            self.ui.input_mode = InputMode::Normal;
-            self.ui.input_line_count = 1;
-            self.ui.selected_files.clear();
-            self.ui.history_index = None;
-            self.ui.temp_input.clear();
+        // 応答待ち中に編集した再送信を受け付けると、処理中の会話を巻き戻してしまう
+        if self.task_queue.is_processing() && self.ui.editing_message_id.is_some() {
+            self.notify("⏳ 応答待ちの間は編集したメッセージを再送信できません".to_string());
+            return;
+        }
+        // 巻き戻すと、実行中の要約が記録する範囲がずれる
+        if self.is_compacting() && self.ui.editing_message_id.is_some() {
+            self.notify("⏳ 要約の実行中は編集したメッセージを再送信できません".to_string());
+            return;
+        }
+
+        // 送り先はこの時点で表示中のセッションに決める
+        let Some(session_id) = self.current_session_id() else {
+            self.notify("❌ No active session to send the message to".to_string());
+            return;
+        };
+
+        // 許可ディレクトリの外のファイル参照は、許可するか確認してから送る
+        let (_, references) = self.parse_file_references(&original_message);
+        if !self.confirm_file_access(&references) {
             return;
         }
 
@@ -249,11 +464,8 @@ impl ChatApp {
             self.add_to_input_history(original_message.clone());
         }
 
-        self.ui.input.clear();
-        self.ui.cursor_position = 0;
+        self.set_input(String::new());
         self.ui.input_mode = InputMode::Normal;
-        self.is_loading = true;
-        self.ui.input_line_count = 1;  // 送信後は1行にリセット
 
         // 履歴ナビゲーションをリセット
         self.ui.history_index = None;
@@ -261,154 +473,421 @@ impl ChatApp {
 
         // ファイル参照を解析
         let (clean_message, file_paths) = self.parse_file_references(&original_message);
+        self.watch_attachments(&file_paths);
         let message_to_send = if clean_message.is_empty() && !file_paths.is_empty() {
             "Please analyze these files:".to_string()
         } else {
             clean_message
         };
 
+        // 選択されたファイルをクリア
+        self.ui.selected_files.clear();
+        self.clear_saved_draft();
+
+        // キューに積み、処理中でなければすぐに開始する
+        self.task_queue.enqueue(UserTask { message: message_to_send, files: file_paths, session_id });
+        if !self.process_next_task() {
+            debug_log!("[send_message] 応答待ちのためキューに追加: {} bytes", original_message.len());
+            self.notify(format!("⏳ 応答待ちのためキューに追加しました（待機中: {}件）", self.task_queue.pending()));
+        }
+    }
 
-        // ユーザーメッセージを表示用に整形
-        let display_message = if file_paths.is_empty() {
-            message_to_send.clone()
+    /// session_idが画面に表示中のセッションか
+    pub fn is_visible_session(&self, session_id: Uuid) -> bool {
+        self.history_manager.lock().unwrap().get_history().current_session_id == Some(session_id)
+    }
+
+    /// Idleならキューから次のタスクを取り出して処理を開始する（開始したらtrue）
+    fn process_next_task(&mut self) -> bool {
+        match self.task_queue.next_task() {
+            Some(task) => {
+                self.start_task(task);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 履歴を保存し、成功すれば未保存フラグをクリアする
+    fn autosave_history(&mut self, context: &str) {
+        match self.history_manager.lock().unwrap().save() {
+            Ok(()) => self.ui.unsaved = false,
+            Err(e) => {
+                debug_log!("[{}] save_history error: {:?}", context, e);
+            }
+        }
+    }
+
+    /// 'q'で終了する前に確認が必要な理由（応答待ち・キュー・未送信の入力）
+    pub fn quit_guard_reason(&self) -> Option<String> {
+        if self.llm_task_handle.is_some() || self.task_queue.is_processing() {
+            Some("A request is in progress".to_string())
+        } else if self.task_queue.pending() > 0 {
+            Some(format!("{} queued message(s) have not been sent", self.task_queue.pending()))
+        } else if let Some(index) = self.busy_background_tab() {
+            Some(format!("A request is in progress in tab {}", index + 1))
+        } else if !self.ui.input.trim().is_empty() {
+            Some("The input has an unsent message".to_string())
         } else {
-            format!("{}\nFiles: {}", message_to_send, file_paths.join(", "))
+            None
+        }
+    }
+
+    /// 終了が確定したときの後始末（ファイルの監視と応答待ちのLLMタスクを止める）
+    pub fn prepare_quit(&mut self) {
+        self.stop_watch();
+        self.attachment_watcher = None;
+        self.abort_background_tabs();
+        if let Some(handle) = self.llm_task_handle.take() {
+            handle.abort();
+            self.task_queue.finish();
+            self.ui.input_mode = InputMode::Normal;
+            // abort時に必ずエラーイベント送信
+            let _ = self.event_sender.send(ChatEvent::Error("LLMタスクがabortされました".to_string()));
+        }
+    }
+
+    /// 通知バーに表示し、通知履歴（/notifications）にも残す
+    /// 表示中の通知を置き換えた場合は未読として数える
+    pub fn notify(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        if self.ui.notification.is_some() {
+            self.ui.unread_notifications += 1;
+        }
+        self.ui.notification_history.push((Utc::now(), message.clone()));
+        if self.ui.notification_history.len() > NOTIFICATION_HISTORY_LIMIT {
+            self.ui.notification_history.remove(0);
+        }
+        self.ui.notification = Some(message);
+        self.ui.notification_shown_at = Some(std::time::Instant::now());
+    }
+
+    /// run_appのループから呼ぶ。表示してからnotification_timeout_secs経った通知を消す
+    pub fn tick_notification(&mut self, now: std::time::Instant) {
+        let Some(timeout) = self.config.notification_timeout_secs.map(std::time::Duration::from_secs) else {
+            return;
+        };
+        if self.ui.notification_shown_at.is_some_and(|shown_at| now.duration_since(shown_at) > timeout) {
+            self.dismiss_notification();
+        }
+    }
+
+    /// run_appのループから呼ぶ。保存に失敗したメッセージがあれば間を空けて保存し直し、警告を更新する
+    pub fn tick_pending_writes(&mut self, now: std::time::Instant) {
+        let mut history_guard = self.history_manager.lock().unwrap();
+        if history_guard.retry_due(now) {
+            if let Err(e) = history_guard.save() {
+                debug_log!("[tick_pending_writes] save_history error: {:?}", e);
+            }
+        }
+        self.ui.save_warning = history_guard.pending_write_warning();
+    }
+
+    /// 通知バーを閉じる（置き換えられた通知も既読にする）
+    pub fn dismiss_notification(&mut self) {
+        self.ui.notification = None;
+        self.ui.notification_shown_at = None;
+        self.ui.unread_notifications = 0;
+    }
+
+    /// 入力中のテキストか未保存のメッセージがあるか（ターミナルタイトルの [*] 表示用）
+    pub fn has_unsaved_changes(&self) -> bool {
+        !self.ui.input.is_empty() || self.ui.unsaved
+    }
+
+    /// タスクのメッセージを表示・履歴に追加し、LLMへの送信を開始する
+    /// 終了時には必ずChatEvent::TaskFinishedを送る
+    fn start_task(&mut self, task: UserTask) {
+        let UserTask { message: message_to_send, files: file_paths, session_id } = task;
+
+        // 添付ファイルは正規化して重複を除き、読めないものは外して送る。ユーザーメッセージには要約を付ける
+        let prepared = self.gemini_client.prepare_attachments(&file_paths);
+        let display_message = match attachment_summary(&prepared) {
+            Some(summary) => format!("{}\n{}", message_to_send, summary),
+            None => message_to_send.clone(),
         };
+        let file_paths: Vec<FileReference> = prepared.files.into_iter().map(|(reference, _)| reference).collect();
+
+        // 編集中のメッセージがあれば、その位置まで会話を巻き戻してから送り直す
+        if let Some(edit_id) = self.ui.editing_message_id.take() {
+            self.rewind_for_edit(edit_id);
+        }
+
+        // キューに積んだときのセッションに送る（待っている間に別のセッションやタブに切り替えても変わらない）
+        let visible = self.is_visible_session(session_id);
+
+        // 履歴管理にメッセージを追加（表示用と同じ内容）
+        // 画面表示用メッセージも履歴と同じIDを使う
+        let history_result = (*self.history_manager.lock().unwrap()).get_history_mut().add_message_to(session_id, vec![Part::Text { text: display_message.clone() }], true); // Pass Vec<Part>
+        let user_msg_id = history_result.unwrap_or_else(|_| Uuid::new_v4());
+        self.last_user_message_id = Some(user_msg_id);
+        self.file_creation_requested = requests_file_creation(&message_to_send);
 
-        // ユーザーメッセージを即座に追加（新しいUUIDで）
+        // ユーザーメッセージを即座に追加
         let user_msg = crate::history::ChatMessage {
-            id: Uuid::new_v4(),
+            id: user_msg_id,
             parts: vec![Part::Text { text: display_message.clone() }], // Changed content to parts
             is_user: true,
             timestamp: Utc::now(),
+            comparison: false,
+            steps: Vec::new(),
+            is_system: false,
+            excluded: false,
         };
-        self.messages.push(user_msg.clone());
-        debug_log!("[send_message] メッセージ追加: {}", display_message); // Log the display_message
-
-        // 履歴管理にメッセージを追加（表示用と同じ内容）
-        if let Err(_) = (*self.history_manager.lock().unwrap()).get_history_mut().add_message(vec![Part::Text { text: display_message.clone() }], true) { // Pass Vec<Part>
-            // エラーは無視
+        if visible {
+            self.messages.push(user_msg.clone());
+            self.auto_scroll_if_at_bottom();
         }
+        self.ui.unsaved = true;
+        debug_log!("[start_task] メッセージ追加: {} bytes", display_message.len());
         
         // ユーザーメッセージ送信後に履歴保存
-        if let Err(e) = (*self.history_manager.lock().unwrap()).save() {
-            debug_log!("[send_message] save_history error: {:?}", e);
+        self.autosave_history("start_task");
+
+        // コンテキストから外れたメッセージがあれば裏で要約
+        self.maybe_compact_context();
+
+        // 比較モードでは2つのモデルに同時に送信
+        if let Some((model_a, model_b)) = self.compare_models.clone() {
+            self.start_comparison(message_to_send, model_a, model_b);
+            return;
         }
 
-        // 非同期でLLMに送信
+        // 失敗したときに同じ内容で再送できるよう、コンテキストと生成パラメータも含めて保持する
+        let context = self.history_manager.lock().unwrap().get_conversation_context_with_budget_for(session_id, CONTEXT_TOKEN_BUDGET);
+        let request = OutboundRequest {
+            session_id,
+            task: UserTask { message: message_to_send, files: file_paths, session_id },
+            context,
+            settings: self.gemini_client.effective_settings(),
+        };
+        self.last_request = Some(request.clone());
+        self.pending_retry = None;
+        self.spawn_agent_loop(request);
+    }
+
+    /// 一時的なエラーで失敗したリクエストを同じ内容で再送する（ユーザーメッセージは履歴に追加しない）
+    pub fn retry_failed_request(&mut self) {
+        if self.task_queue.is_processing() {
+            self.notify("⏳ 応答待ちの間は再送できません".to_string());
+            return;
+        }
+        let Some(request) = self.pending_retry.take() else {
+            return;
+        };
+        debug_log!("[retry_failed_request] message={} bytes", request.task.message.len());
+        self.last_request = Some(request.clone());
+        self.task_queue.mark_processing();
+        self.spawn_agent_loop(request);
+    }
+
+    /// session_idのセッションで秘密情報を伏せるか（/redact で切り替えていなければ REDACT_SECRETS のまま）
+    pub fn redaction_enabled(&self, session_id: Uuid) -> bool {
+        self.redaction_overrides.get(&session_id).copied().unwrap_or_else(|| self.gemini_client.redactor().is_enabled())
+    }
+
+    /// session_idのセッションの /redact の設定で伏せ字にし、使用量もそのセッションに記録するクライアント
+    pub fn client_for_session(&self, session_id: Uuid) -> GeminiClient {
+        let redactor = self.gemini_client.redactor().with_enabled(self.redaction_enabled(session_id));
+        self.gemini_client.clone().with_redactor(redactor).with_session(session_id)
+    }
+
+    /// 非同期でLLMに送信する
+    fn spawn_agent_loop(&mut self, request: OutboundRequest) {
         // 既存のLLMタスクがあればキャンセル
         if let Some(handle) = self.llm_task_handle.take() {
             handle.abort();
         }
-        let message = message_to_send.clone();
+        let OutboundRequest { session_id, task: UserTask { message, files: file_paths, .. }, context, settings } = request;
         let sender = self.event_sender.clone();
-        let gemini_client = self.gemini_client.clone();
+        let gemini_client = self.client_for_session(session_id).with_settings(settings);
         let history_manager_clone = self.history_manager.clone();
         let handle = tokio::spawn(async move {
-            debug_log!("[tokio::spawn] chat_loop_with_progress_static spawn. message={}", message);
-            let res = ChatApp::chat_loop_with_progress_static(gemini_client, &message, sender.clone(), history_manager_clone).await;
+            debug_log!("[tokio::spawn] chat_loop_with_progress_static spawn. message={} bytes", message.len());
+            let res = ChatApp::chat_loop_with_progress_static(gemini_client, session_id, &message, file_paths, Some(context), sender.clone(), history_manager_clone).await;
             if let Err(_e) = res {
                 // 通常のエラーは既に送信済み
             }
+            let _ = sender.send(ChatEvent::for_session(session_id, ChatEvent::TaskFinished));
         });
         self.llm_task_handle = Some(handle);
+        self.task_session = Some(session_id);
+        self.start_request_timer(session_id);
+    }
 
-        // 選択されたファイルをクリア
+    /// 入力欄を置き換えてカーソルを末尾に移す（矩形選択の I は破棄する）
+    pub(crate) fn set_input(&mut self, text: String) {
+        self.ui.input = text;
+        self.ui.block_insert = None;
+        self.ui.cursor_position = self.ui.input.graphemes(true).count();
+        self.update_input_line_count();
+    }
+
+    /// コマンド実行後に入力欄と関連状態をリセット
+    fn reset_input_after_command(&mut self) {
+        self.set_input(String::new());
+        // コマンドが開いた画面（/diff、/permissions）はそのまま表示する
+        if !matches!(self.ui.input_mode, InputMode::DiffView | InputMode::MessageView | InputMode::Permissions) {
+            self.ui.input_mode = InputMode::Normal;
+        }
+        self.ui.input_line_count = 1;
         self.ui.selected_files.clear();
-        self.is_loading = false;
+        self.ui.history_index = None;
+        self.ui.temp_input.clear();
     }
 
     /// LLMリクエストをspawn用にstatic化したバージョン
+    /// 応答はすべてsession_idのセッションに追加する（途中で表示中のセッションが変わっても送信元に残す）
     pub async fn chat_loop_with_progress_static(
         gemini_client: crate::gemini::GeminiClient,
+        session_id: Uuid,
         initial_message: &str,
+        mut file_references: Vec<FileReference>,
+        mut initial_context: Option<Vec<Content>>,
         sender: tokio::sync::mpsc::UnboundedSender<ChatEvent>,
         history_manager: Arc<Mutex<HistoryManager>>, // Added this
     ) -> anyhow::Result<()> {
+        let termination = gemini_client.termination();
         let mut message = initial_message.to_string();
         let mut step = 1;
-        debug_log!("[chat_loop_with_progress_static] start. message={}", message);
-        for _ in 0..10 {
+        // 途中のステップの応答はチャットに流さず、最終応答のメッセージにまとめて付ける
+        let mut steps: Vec<StepRecord> = Vec::new();
+        // is_finishedのない応答が続いた回数と、今回のプロンプトがフラグだけを尋ねるものか
+        let mut missing_flags = 0;
+        let mut nudging = false;
+        debug_log!("[chat_loop_with_progress_static] start. message={} bytes", message.len());
+        for _ in 0..termination.max_steps {
             debug_log!("[chat_loop_with_progress_static] step={}", step);
-            let progress_msg = format!("🤖 Step {}: LLMに問い合わせ中...", step);
-            let _ = sender.send(ChatEvent::AIResponse(ResponsePart::Text { text: progress_msg }));
-            let prompt = format!(
-                "{}
-
----
-次に何をすべきか、追加タスクがあるかを必ず明示してください。
-「完了」「終了」「何もする必要がない」などの場合は、その旨を明確に書いてください。",
-                message
-            );
-            debug_log!("[chat_loop_with_progress_static] prompt={}", prompt);
-
-            // Get conversation context from history_manager
-            let conversation_context = (*history_manager.lock().unwrap()).get_conversation_context(10); // Use history_manager
-            let response_part = match tokio::time::timeout(std::time::Duration::from_secs(30), gemini_client.chat(&prompt, Some(&conversation_context))).await {
+            let language = gemini_client.language();
+            let progress_msg = tr(language, "agent.progress").replace("{step}", &step.to_string());
+            let _ = sender.send(ChatEvent::for_session(session_id, ChatEvent::Notification(progress_msg)));
+            let prompt = if nudging {
+                tr(language, "agent.nudge").to_string()
+            } else {
+                format!("{}\n\n---\n{}", message, tr(language, "agent.suffix"))
+            };
+            debug_log!("[chat_loop_with_progress_static] prompt={} bytes", prompt.len());
+
+            // 最初のステップは送信時点のコンテキスト（指定があれば）、以降は履歴から組み立てる
+            let conversation_context = match initial_context.take() {
+                Some(context) => context,
+                None => (*history_manager.lock().unwrap()).get_conversation_context_with_budget_for(session_id, CONTEXT_TOKEN_BUDGET),
+            };
+            // 参照ファイルの内容は最初のステップでのみ送信する
+            let files = std::mem::take(&mut file_references);
+            let request = async {
+                if files.is_empty() {
+                    gemini_client.chat(&prompt, Some(&conversation_context)).await
+                } else {
+                    gemini_client.chat_with_file_context(&prompt, &files, Some(&conversation_context)).await
+                }
+            };
+            let started_at = std::time::Instant::now();
+            let response_parts = match tokio::time::timeout(std::time::Duration::from_secs(30), request).await {
                 Ok(r) => r,
                 Err(_) => {
                     debug_log!("[chat_loop_with_progress_static] LLMリクエストがタイムアウトしました");
-                    let error_msg = "❌ LLMリクエストがタイムアウトしました".to_string();
-                    let _ = sender.send(ChatEvent::Error(error_msg));
+                    let error_msg = tr(language, "agent.timeout").to_string();
+                    let _ = sender.send(ChatEvent::for_session(session_id, ChatEvent::RetryableError(error_msg)));
                     return Err(anyhow::anyhow!("LLMリクエストがタイムアウト"));
                 }
             };
-            match response_part {
-                Ok(response_part_content) => {
-                    let response_text = match &response_part_content {
-                        ResponsePart::Text { text } => text.clone(),
-                        ResponsePart::FunctionCall { function_call } => {
-                            format!("FunctionCall: {}", serde_json::to_string_pretty(&function_call).unwrap_or_default())
-                        },
-                        ResponsePart::FunctionResponse { function_response } => {
-                            format!("FunctionResponse: {}", serde_json::to_string_pretty(&function_response).unwrap_or_default())
-                        },
-                    };
-                    debug_log!("[chat_loop_with_progress_static] LLM response={}", response_text);
+            match response_parts {
+                Ok(response_parts) => {
+                    // テキストと関数呼び出しの結果を順に並べたものを次のプロンプトに使う
+                    let response_text = response_parts
+                        .iter()
+                        .map(ResponsePart::display_text)
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    debug_log!("[chat_loop_with_progress_static] LLM response={} bytes", response_text.len());
                     if response_text.is_empty() {
-                        let error_msg = "❌ LLMからの応答が空です。再試行してください。".to_string();
-                        let _ = sender.send(ChatEvent::Error(error_msg));
+                        let error_msg = tr(language, "agent.empty_response").to_string();
+                        let _ = sender.send(ChatEvent::for_session(session_id, ChatEvent::RetryableError(error_msg)));
                         return Err(anyhow::anyhow!("LLM応答が空"));
                     }
-                    let _response_msg = format!("🤖 Step {}: LLM応答\n{}", step, response_text);
-                    let _ = sender.send(ChatEvent::AIResponse(response_part_content.clone())); // Send ResponsePart
+                    let _ = sender.send(ChatEvent::for_session(session_id, ChatEvent::ResponseReceived));
+                    let redacted = gemini_client.redactor().take_redacted_count();
+                    if redacted > 0 {
+                        let note = format!("🔒 Redacted {} secret(s) before sending to the LLM", redacted);
+                        let _ = sender.send(ChatEvent::for_session(session_id, ChatEvent::Notification(note)));
+                    }
+                    for note in gemini_client.take_preflight_notes().into_iter().chain(gemini_client.take_hook_warnings()) {
+                        let _ = sender.send(ChatEvent::for_session(session_id, ChatEvent::Notification(note)));
+                    }
 
                     // Add AI's response to history
                     let mut history_guard = history_manager.lock().unwrap();
-                    let parts_to_add_to_history = match response_part_content.clone() {
-                        ResponsePart::Text { text } => vec![Part::Text { text }],
-                        ResponsePart::FunctionCall { function_call } => vec![Part::Text { text: serde_json::to_string(&function_call).unwrap_or_default() }],
-                        ResponsePart::FunctionResponse { function_response } => vec![Part::Text { text: serde_json::to_string(&function_response).unwrap_or_default() }],
+                    let parts_to_add_to_history: Vec<Part> = response_parts.iter().map(ResponsePart::to_history_part).collect();
+                    (*history_guard).get_history_mut().add_message_to(session_id, parts_to_add_to_history, false)?;
+                    drop(history_guard);
+
+                    let flag = extract_is_finished_flag(&response_text);
+                    let flag_label = flag.map_or_else(|| "missing".to_string(), |flag| flag.to_string());
+                    let step_result = tr(language, "agent.step_result").replace("{step}", &step.to_string()).replace("{flag}", &flag_label);
+                    let _ = sender.send(ChatEvent::for_session(session_id, ChatEvent::Notification(step_result)));
+                    // フラグだけを尋ねた応答は短いので、繰り返しの判定には使わない
+                    let similarity = steps.last().filter(|_| !nudging).map(|previous| response_similarity(&previous.response, &response_text));
+                    let reason = if flag == Some(true) {
+                        Some(TerminationReason::Finished)
+                    } else if let Some(similarity) = similarity.filter(|&similarity| termination.repeat_similarity.is_some_and(|threshold| similarity >= threshold)) {
+                        Some(TerminationReason::Repeating(similarity))
+                    } else if flag.is_none() && nudging {
+                        Some(TerminationReason::MissingFlag)
+                    } else {
+                        None
                     };
-                    (*history_guard).get_history_mut().add_message(parts_to_add_to_history, false)?;
-
-                    let lower = response_text.to_lowercase();
-                    if gemini_client.extract_is_finished_flag(&lower).unwrap_or(false) {
-                        // 最終的なAIレスポンスを送信（履歴保存用）
-                        let _ = sender.send(ChatEvent::AIResponse(response_part_content.clone())); // Send ResponsePart
-                        let finish_msg = "✅ LLMが終了を指示したためループを終了します。".to_string();
-                        let _ = sender.send(ChatEvent::AIResponse(ResponsePart::Text { text: finish_msg })); // Send as Text
-                        debug_log!("[chat_loop_with_progress_static] finish (done)");
-                        return Ok(())
+                    if let Some(reason) = reason {
+                        // フラグだけを尋ねた応答ではなく、その前の応答を最終応答にする
+                        let final_parts = if nudging {
+                            steps.pop();
+                            vec![ResponsePart::Text { text: message }]
+                        } else {
+                            response_parts
+                        };
+                        send_agent_result(&sender, session_id, final_parts, steps, termination_message(language, reason, termination));
+                        debug_log!("[chat_loop_with_progress_static] finish ({:?})", reason);
+                        return Ok(());
+                    }
+                    // ツールの実行結果は変更の記録などに使うのでそのまま送り、テキストはステップとして残す
+                    for part in response_parts.iter().filter(|part| !matches!(part, ResponsePart::Text { .. })) {
+                        let _ = sender.send(ChatEvent::AIResponseFor { session_id, part: part.clone() });
+                    }
+                    if flag.is_some() {
+                        missing_flags = 0;
+                    } else {
+                        missing_flags += 1;
                     }
-                    message = response_text; // Use extracted text for next prompt
+                    // フラグだけを尋ねた応答（is_finished: false）のあとは、その前の応答から続ける
+                    if !nudging {
+                        steps.push(StepRecord {
+                            prompt_excerpt: prompt_excerpt(&message),
+                            response: response_text.clone(),
+                            duration: started_at.elapsed(),
+                        });
+                        message = response_text; // Use extracted text for next prompt
+                    }
+                    nudging = termination.nudge_after_missing_flags.is_some_and(|after| missing_flags >= after);
                     step += 1;
                 }
                 Err(e) => {
                     debug_log!("[chat_loop_with_progress_static] LLM error={}", e);
-                    let error_msg = format!("❌ LLMとの通信に失敗しました: {}", e);
-                    let _ = sender.send(ChatEvent::Error(error_msg));
-                    return Err(e.into());
+                    let error_msg = tr(language, "agent.request_failed").replace("{error}", &e.to_string());
+                    if is_transient_error(&e) {
+                        let _ = sender.send(ChatEvent::for_session(session_id, ChatEvent::RetryableError(error_msg)));
+                    } else {
+                        let _ = sender.send(ChatEvent::for_session(session_id, ChatEvent::Error(error_msg)));
+                    }
+                    return Err(e);
                 }
             };
         }
-        // 最後のメッセージを最終レスポンスとして送信
-        if !message.is_empty() {
-            let _ = sender.send(ChatEvent::AIResponse(ResponsePart::Text { text: message }));
-        }
-        let finish_msg = "⚠️ LLM応答に「完了」等が含まれなかったため自動終了しました。".to_string();
-        let _ = sender.send(ChatEvent::AIResponse(ResponsePart::Text { text: finish_msg }));
-        debug_log!("[chat_loop_with_progress_static] finish (timeout)");
+        // 最後のメッセージを最終レスポンスとして送信（最後のステップはその応答そのものなので除く）
+        steps.pop();
+        let final_parts = if message.is_empty() { Vec::new() } else { vec![ResponsePart::Text { text: message }] };
+        let finish_msg = termination_message(gemini_client.language(), TerminationReason::StepLimit, termination);
+        send_agent_result(&sender, session_id, final_parts, steps, finish_msg);
+        debug_log!("[chat_loop_with_progress_static] finish (step limit)");
         Ok(())
     }
 
@@ -416,7 +895,7 @@ impl ChatApp {
     
 
     pub fn add_to_input_history(&mut self, message: String) {
-        if self.ui.input_history.last().map_or(true, |last| last != &message) {
+        if self.ui.input_history.last() != Some(&message) {
             self.ui.input_history.push(message);
         }
         if self.ui.input_history.len() > 50 {
@@ -425,31 +904,12 @@ impl ChatApp {
         self.ui.history_index = None;
     }
 
-    pub fn parse_file_references(&self, message: &str) -> (String, Vec<String>) {
-        let mut clean_message = message.to_string();
-        let mut file_paths = Vec::new();
-        let mut remaining = message;
-        loop {
-            if let Some(start) = remaining.find("@file:") {
-                let file_start = start + 6;
-                let after_prefix = &remaining[file_start..];
-                let end_pos = after_prefix.find(' ').unwrap_or(after_prefix.len());
-                let file_path = &after_prefix[..end_pos];
-                if !file_path.is_empty() {
-                    file_paths.push(file_path.to_string());
-                }
-                let full_reference = format!("@file:{}", file_path);
-                clean_message = clean_message.replace(&full_reference, "");
-                remaining = &remaining[start + 6 + file_path.len()..];
-            } else {
-                break;
-            }
-        }
-        let mut all_files = file_paths;
-        all_files.extend(self.ui.selected_files.clone());
+    pub fn parse_file_references(&self, message: &str) -> (String, Vec<FileReference>) {
+        let (clean_message, mut all_files) = crate::app::file_operations::split_file_references(message);
+        all_files.extend(self.ui.selected_files.iter().map(FileReference::whole));
         all_files.sort();
         all_files.dedup();
-        (clean_message.trim().to_string(), all_files)
+        (clean_message, all_files)
     }
 
     pub fn calculate_cursor_position(&self) -> (usize, usize) {
@@ -493,9 +953,7 @@ impl ChatApp {
             }
         };
         self.ui.history_index = Some(new_index);
-        self.ui.input = self.ui.input_history[new_index].clone();
-        self.ui.cursor_position = self.ui.input.graphemes(true).count();
-        self.update_input_line_count();
+        self.set_input(self.ui.input_history[new_index].clone());
     }
 
     pub fn navigate_history_down(&mut self) {
@@ -514,23 +972,19 @@ impl ChatApp {
             None => return,
         };
         self.ui.history_index = Some(new_index);
-        self.ui.input = self.ui.input_history[new_index].clone();
-        self.ui.cursor_position = self.ui.input.graphemes(true).count();
-        self.update_input_line_count();
+        self.set_input(self.ui.input_history[new_index].clone());
     }
 
     pub fn reset_history_navigation(&mut self) {
         if self.ui.history_index.is_some() {
-            self.ui.input = self.ui.temp_input.clone();
-            self.ui.temp_input.clear();
+            let text = std::mem::take(&mut self.ui.temp_input);
             self.ui.history_index = None;
-            self.ui.cursor_position = self.ui.input.graphemes(true).count();
-            self.update_input_line_count();
+            self.set_input(text);
         }
     }
 
     pub fn insert_selected_message(&mut self) {
-        if let Some(selected_index) = self.ui.list_state.selected() {
+        if let Some(selected_index) = self.selected_message_index() {
             if let Some(message) = self.messages.get(selected_index) {
                 let mut content_to_insert = String::new();
                 for part in &message.parts {
@@ -549,14 +1003,110 @@ impl ChatApp {
         }
     }
 
+    /// 選択中のメッセージがツール実行結果なら詳細の展開・折りたたみを切り替える
+    /// 切り替えた場合はtrueを返す
+    pub fn toggle_selected_tool_outcome(&mut self) -> bool {
+        let Some(message) = self.selected_message_index().and_then(|i| self.messages.get(i)) else {
+            return false;
+        };
+        // コマンドの結果は常に出力ごと表示する（続きは全文表示で見る）
+        if !message.parts.iter().any(|p| matches!(p, Part::ToolOutcome { tool_outcome } if tool_outcome.command_result.is_none())) {
+            return false;
+        }
+        let id = message.id;
+        if !self.ui.expanded_tool_messages.remove(&id) {
+            self.ui.expanded_tool_messages.insert(id);
+        }
+        self.ui.scroll_to_selected_message = true;
+        true
+    }
+
+    /// 選択中のメッセージを会話コンテキストから外す・戻す（画面には薄く取り消し線で残る）
+    pub fn toggle_selected_message_excluded(&mut self) {
+        let Some(index) = self.selected_message_index() else {
+            return;
+        };
+        let result = self.history_manager.lock().unwrap().get_history_mut().toggle_message_excluded(self.messages[index].id);
+        match result {
+            Ok(excluded) => {
+                self.messages[index].excluded = excluded;
+                self.autosave_history("toggle_selected_message_excluded");
+                self.notify(if excluded { "🚫 Excluded the message from the context" } else { "Included the message in the context again" });
+            }
+            Err(e) => self.notify(format!("❌ {}", e)),
+        }
+    }
+
+    /// 選択中のメッセージ（なければそれより前で最も近いもの）の途中のステップの展開・折りたたみを切り替える
+    pub fn toggle_selected_steps(&mut self) {
+        let Some(end) = self.selected_message_index().map(|i| i + 1) else {
+            return;
+        };
+        let Some(message) = self.messages[..end].iter().rev().find(|m| !m.steps.is_empty()) else {
+            self.notify("No agent steps to show");
+            return;
+        };
+        let id = message.id;
+        if !self.ui.expanded_steps.remove(&id) {
+            self.ui.expanded_steps.insert(id);
+        }
+        self.ui.scroll_to_selected_message = true;
+    }
+
+    /// 選択中（なければ最後）のユーザーメッセージのインデックス
+    fn selected_user_message_index(&self) -> Option<usize> {
+        let end = self.selected_message_index()? + 1;
+        self.messages[..end].iter().rposition(|m| m.is_user)
+    }
+
+    /// 選択中のユーザーメッセージを入力欄に読み込み、編集して再送信できるようにする
+    pub fn start_editing_selected_message(&mut self) {
+        let Some(index) = self.selected_user_message_index() else {
+            self.notify("編集できるメッセージがありません".to_string());
+            return;
+        };
+        let message = &self.messages[index];
+        let mut content = String::new();
+        for part in &message.parts {
+            if let crate::gemini::Part::Text { text } = part {
+                content.push_str(text);
+            }
+        }
+        self.ui.editing_message_id = Some(message.id);
+        self.ui.history_index = None;
+        self.ui.temp_input.clear();
+        self.set_input(content);
+        self.ui.input_mode = InputMode::Insert;
+    }
+
+    /// メッセージの編集をキャンセル（履歴は変更しない）
+    pub fn cancel_message_edit(&mut self) {
+        if self.ui.editing_message_id.take().is_some() {
+            self.set_input(String::new());
+        }
+    }
+
+    /// 編集中のメッセージの表示番号（1始まり）
+    pub fn editing_message_number(&self) -> Option<usize> {
+        let id = self.ui.editing_message_id?;
+        self.messages.iter().position(|m| m.id == id).map(|i| i + 1)
+    }
+
     pub fn create_new_session(&mut self) {
         let _session_id = (*self.history_manager.lock().unwrap()).get_history_mut().new_session(None);
         self.messages.clear();
+        self.file_changes.clear();
+        self.ui.editing_message_id = None;
+        self.ui.selected_message_index = None;
         self.messages.push(crate::history::ChatMessage {
             id: Uuid::new_v4(),
             parts: vec![Part::Text { text: "Started new conversation session.".to_string() }], // Changed content to parts
             is_user: false,
             timestamp: Utc::now(),
+            comparison: false,
+            steps: Vec::new(),
+            is_system: false,
+            excluded: false,
         });
         if let Err(e) = (*self.history_manager.lock().unwrap()).save() {
             debug_log!("[create_new_session] save_history error: {:?}", e);
@@ -565,20 +1115,10 @@ impl ChatApp {
 
     pub fn scroll_to_bottom(&mut self, visible_height: usize) {
         if !self.messages.is_empty() {
-            let total_lines = self.messages.iter().map(|msg| {
-                let prefix = if msg.is_user { "You" } else { "AI" };
-                let mut msg_content_text = String::new();
-                for part in &msg.parts {
-                    if let crate::gemini::Part::Text { text } = part {
-                        msg_content_text.push_str(text);
-                    }
-                }
-                let content = format!("{}: {}", prefix, msg_content_text);
-                crate::markdown::wrap_text(&content, 72).lines().count()
-            }).sum::<usize>();
+            let total_lines = self.total_message_lines();
             self.ui.scroll_offset = total_lines.saturating_sub(visible_height);
-            self.ui.list_state.select(Some(self.ui.scroll_offset));
         }
+        self.ui.selected_message_index = None;
     }
 
     pub fn truncate_string_safe(s: &str, max_chars: usize) -> String {
@@ -591,6 +1131,7 @@ impl ChatApp {
 
     fn get_line_start_position(&self, line: usize) -> usize {
         let mut current_line = 0;
+        let mut count = 0;
         for (i, c) in self.ui.input.graphemes(true).enumerate() {
             if current_line == line {
                 return i;
@@ -598,7 +1139,44 @@ impl ChatApp {
             if c == "\n" {
                 current_line += 1;
             }
+            count = i + 1;
         }
-        0
+        // 末尾の改行の後の空行はテキストの末尾から始まる
+        if current_line == line { count } else { 0 }
+    }
+}
+
+/// エージェントループの最終応答・途中のステップ・終了理由のメッセージを送る
+fn send_agent_result(
+    sender: &mpsc::UnboundedSender<ChatEvent>,
+    session_id: Uuid,
+    final_parts: Vec<ResponsePart>,
+    steps: Vec<StepRecord>,
+    finish_msg: String,
+) {
+    // 最終応答がなければ途中のステップも付ける先がない
+    let has_final = !final_parts.is_empty();
+    for part in final_parts {
+        let _ = sender.send(ChatEvent::AIResponseFor { session_id, part });
+    }
+    if has_final && !steps.is_empty() {
+        let _ = sender.send(ChatEvent::AgentSteps { session_id, steps });
     }
+    let _ = sender.send(ChatEvent::AIResponseFor { session_id, part: ResponsePart::Text { text: finish_msg } });
+}
+
+/// エージェントループを終えた理由ごとの最後の進捗メッセージ
+fn termination_message(language: crate::i18n::Language, reason: TerminationReason, termination: TerminationConfig) -> String {
+    let message = tr(language, reason.message_key());
+    match reason {
+        TerminationReason::Repeating(similarity) => message.replace("{percent}", &format!("{:.0}", similarity * 100.0)),
+        TerminationReason::StepLimit => message.replace("{steps}", &termination.max_steps.to_string()),
+        TerminationReason::Finished | TerminationReason::MissingFlag => message.to_string(),
+    }
+}
+
+/// ステップの一覧に出すプロンプトの冒頭（最初の行を最大60文字まで）
+fn prompt_excerpt(prompt: &str) -> String {
+    let first_line = prompt.lines().find(|line| !line.trim().is_empty()).unwrap_or_default();
+    ChatApp::truncate_string_safe(first_line.trim(), 60)
 }
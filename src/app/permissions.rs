@@ -0,0 +1,378 @@
+use std::fs;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::layout::Rect;
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, BorderType, Borders, Paragraph};
+use ratatui::Frame;
+
+use crate::app::{ChatApp, InputMode};
+use crate::debug_log;
+use crate::file_access::{
+    append_allowed_directory, append_excluded_directory, load_allowed_directories, normalize_path, remove_allowed_directory_from_file,
+    remove_excluded_directory_from_file, resolve_user_path,
+};
+
+/// /permissions の許可ディレクトリの管理画面
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PermissionsView {
+    /// 選択中の許可ディレクトリ
+    pub selected: usize,
+    /// 追加するディレクトリの入力（入力中でなければNone）
+    pub prompt: Option<String>,
+    /// 外す確認中のディレクトリ（作業ディレクトリを外すときだけ確認する）
+    pub confirm_remove: Option<PathBuf>,
+    /// allowed_directoriesファイルに保存されているディレクトリ
+    pub saved: Vec<PathBuf>,
+}
+
+/// 入力中のパスの最後の要素をディレクトリ名で補完する（候補が複数なら共通する部分まで。隠しディレクトリは . を入力したときだけ）
+pub fn complete_directory(input: &str, base: &Path, home: Option<&Path>) -> String {
+    let split = input.rfind(['/', MAIN_SEPARATOR]).map_or(0, |i| i + 1);
+    let (parent, prefix) = input.split_at(split);
+    let parent_path = if parent.is_empty() { base.to_path_buf() } else { resolve_user_path(parent, base, home) };
+    let Ok(entries) = fs::read_dir(&parent_path) else {
+        return input.to_string();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with(prefix) && (prefix.starts_with('.') || !name.starts_with('.')))
+        .collect();
+    names.sort();
+    match names.as_slice() {
+        [] => input.to_string(),
+        [name] => format!("{}{}{}", parent, name, MAIN_SEPARATOR),
+        [first, rest @ ..] => {
+            let common = rest.iter().fold(first.as_str(), |common, name| {
+                let len = common.chars().zip(name.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a.len_utf8()).sum();
+                &common[..len]
+            });
+            format!("{}{}", parent, common)
+        }
+    }
+}
+
+/// directoryがallowed_directoriesファイルの除外の行（!パス）にあるか（起動時に作業ディレクトリを許可するかの判定）
+pub fn is_excluded_directory(directory: &Path, excluded: &[PathBuf]) -> bool {
+    let normalize = |path: &Path| path.canonicalize().ok().and_then(|canonical| normalize_path(&canonical).ok()).unwrap_or_else(|| path.to_path_buf());
+    let directory = normalize(directory);
+    excluded.iter().any(|excluded| normalize(excluded) == directory)
+}
+
+impl ChatApp {
+    /// 許可ディレクトリの管理画面を開く（/permissions）
+    pub fn open_permissions(&mut self) {
+        self.ui.permissions = Some(PermissionsView { saved: self.saved_allowed_directories(), ..PermissionsView::default() });
+        self.ui.input_mode = InputMode::Permissions;
+    }
+
+    fn close_permissions(&mut self) {
+        self.ui.permissions = None;
+        self.ui.input_mode = InputMode::Normal;
+    }
+
+    /// allowed_directoriesファイルに保存されているディレクトリ（比較できるよう正規化する）
+    fn saved_allowed_directories(&self) -> Vec<PathBuf> {
+        let Some(file) = &self.config.allowed_directories_file else {
+            return Vec::new();
+        };
+        match load_allowed_directories(file) {
+            Ok(directories) => directories.iter().map(|directory| normalize_path(directory).unwrap_or_else(|_| directory.clone())).collect(),
+            Err(e) => {
+                debug_log!("[permissions] load_allowed_directories error: {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn working_directory(&self) -> Option<PathBuf> {
+        std::env::current_dir().ok().and_then(|dir| normalize_path(&dir).ok())
+    }
+
+    /// 入力したディレクトリを許可し、allowed_directoriesファイルにも保存する
+    /// 起動時にファイルから読み込むときと同じく、機密ディレクトリでも明示すれば許可する
+    pub fn add_permission(&mut self, input: &str) -> Result<PathBuf, String> {
+        let base = std::env::current_dir().unwrap_or_else(|_| PathBuf::from(&self.ui.current_directory));
+        let path = resolve_user_path(input.trim(), &base, dirs::home_dir().as_deref());
+        let directory = path.canonicalize().and_then(|canonical| normalize_path(&canonical).map_err(std::io::Error::other)).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if self.gemini_client.allowed_directories().contains(&directory) {
+            return Err(format!("{} is already allowed", directory.display()));
+        }
+        self.gemini_client.allow_configured_directory(&directory).map_err(|e| e.to_string())?;
+        if let Some(file) = &self.config.allowed_directories_file {
+            remove_excluded_directory_from_file(file, &directory)
+                .and_then(|_| append_allowed_directory(file, &directory))
+                .map_err(|e| format!("Allowed for this session, but failed to save: {}", e))?;
+        }
+        debug_log!("[permissions] added {:?}", directory);
+        self.refresh_directory_contents();
+        Ok(directory)
+    }
+
+    /// ディレクトリの許可を外し、allowed_directoriesファイルからも取り除く
+    /// 作業ディレクトリは次の起動でも許可しないよう除外の行を書く
+    pub fn remove_permission(&mut self, directory: &Path) {
+        self.gemini_client.remove_allowed_directory(directory);
+        let is_working_directory = Some(directory) == self.working_directory().as_deref();
+        if let Some(file) = &self.config.allowed_directories_file {
+            let result = remove_allowed_directory_from_file(file, directory).and_then(|_| {
+                if is_working_directory {
+                    append_excluded_directory(file, directory)
+                } else {
+                    Ok(())
+                }
+            });
+            if let Err(e) = result {
+                debug_log!("[permissions] remove_allowed_directory_from_file error: {:?}", e);
+                self.notify(format!("❌ Failed to update the allowed_directories file: {}", e));
+            }
+        }
+        debug_log!("[permissions] removed {:?}", directory);
+        self.notify(format!("🚫 Removed access to {}", directory.display()));
+    }
+
+    fn refresh_permissions(&mut self) {
+        let saved = self.saved_allowed_directories();
+        let count = self.gemini_client.allowed_directories().len();
+        if let Some(view) = &mut self.ui.permissions {
+            view.saved = saved;
+            view.selected = view.selected.min(count.saturating_sub(1));
+        }
+    }
+
+    /// 選択中のディレクトリを外す（作業ディレクトリなら先に確認する）
+    fn remove_selected_permission(&mut self) {
+        let Some(selected) = self.ui.permissions.as_ref().map(|view| view.selected) else {
+            return;
+        };
+        let Some(directory) = self.gemini_client.allowed_directories().get(selected).cloned() else {
+            return;
+        };
+        if Some(&directory) == self.working_directory().as_ref() {
+            if let Some(view) = &mut self.ui.permissions {
+                view.confirm_remove = Some(directory);
+            }
+            return;
+        }
+        self.remove_permission(&directory);
+        self.refresh_permissions();
+    }
+
+    pub(crate) fn handle_permissions_key(&mut self, key_event: KeyEvent) -> anyhow::Result<bool> {
+        let Some(view) = self.ui.permissions.as_mut() else {
+            self.ui.input_mode = InputMode::Normal;
+            return Ok(false);
+        };
+
+        // 作業ディレクトリを外す確認中は y/n だけを受け付ける
+        if let Some(directory) = view.confirm_remove.clone() {
+            view.confirm_remove = None;
+            if key_event.code == KeyCode::Char('y') {
+                self.remove_permission(&directory);
+                self.refresh_permissions();
+            }
+            return Ok(false);
+        }
+
+        if let Some(input) = view.prompt.as_mut() {
+            match key_event.code {
+                KeyCode::Esc => view.prompt = None,
+                KeyCode::Enter => {
+                    let input = view.prompt.take().unwrap_or_default();
+                    if !input.trim().is_empty() {
+                        match self.add_permission(&input) {
+                            Ok(directory) => {
+                                self.notify(format!("📂 Allowed access to {}", directory.display()));
+                                self.refresh_permissions();
+                                let count = self.gemini_client.allowed_directories().len();
+                                if let Some(view) = &mut self.ui.permissions {
+                                    view.selected = count.saturating_sub(1);
+                                }
+                            }
+                            Err(e) => self.notify(format!("❌ {}", e)),
+                        }
+                    }
+                }
+                KeyCode::Tab => {
+                    let base = std::env::current_dir().unwrap_or_else(|_| PathBuf::from(&self.ui.current_directory));
+                    *input = complete_directory(input, &base, dirs::home_dir().as_deref());
+                }
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) if !key_event.modifiers.contains(KeyModifiers::CONTROL) => input.push(c),
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) && key_event.code == KeyCode::Char('h') {
+            self.ui.show_help = !self.ui.show_help;
+            return Ok(false);
+        }
+        let last = self.gemini_client.allowed_directories().len().saturating_sub(1);
+        match key_event.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.close_permissions(),
+            KeyCode::Char('j') | KeyCode::Down => view.selected = (view.selected + 1).min(last),
+            KeyCode::Char('k') | KeyCode::Up => view.selected = view.selected.saturating_sub(1),
+            KeyCode::Char('a') => view.prompt = Some(String::new()),
+            KeyCode::Char('d') | KeyCode::Delete => self.remove_selected_permission(),
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    pub fn render_permissions(&mut self, f: &mut Frame, area: Rect) {
+        let Some(view) = &self.ui.permissions else {
+            return;
+        };
+        let working_directory = self.working_directory();
+        let allowed = self.gemini_client.allowed_directories();
+        let mut lines = vec![Line::from(Span::styled(
+            "Allowed directories (the AI can read and write inside these):",
+            Style::default().add_modifier(Modifier::BOLD),
+        ))];
+        if allowed.is_empty() {
+            lines.push(Line::from(Span::styled(
+                "  ⚠ No allowed directories — file features are disabled (press 'a' to add one)",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            )));
+        }
+        for (index, directory) in allowed.iter().enumerate() {
+            let mut notes = Vec::new();
+            if Some(directory) == working_directory.as_ref() {
+                notes.push("working directory");
+            }
+            notes.push(if view.saved.contains(directory) { "saved" } else { "this session" });
+            let style = if index == view.selected { Style::default().bg(Color::DarkGray) } else { Style::default() };
+            lines.push(Line::from(vec![
+                Span::styled(format!("  {}", directory.display()), style),
+                Span::styled(format!("  ({})", notes.join(", ")), style.fg(Color::Gray)),
+            ]));
+        }
+        lines.push(Line::from(""));
+        lines.push(Line::from(Span::styled(
+            "Sensitive directories (denied unless added here or listed in the allowed_directories file):",
+            Style::default().add_modifier(Modifier::BOLD),
+        )));
+        for directory in self.gemini_client.denied_directories() {
+            lines.push(Line::from(Span::styled(format!("  {}", directory.display()), Style::default().fg(Color::DarkGray))));
+        }
+        lines.push(Line::from(""));
+        if let Some(file) = &self.config.allowed_directories_file {
+            lines.push(Line::from(Span::styled(format!("Saved to {}", file.display()), Style::default().fg(Color::DarkGray))));
+        }
+        if let Some(input) = &view.prompt {
+            lines.push(Line::from(Span::styled(
+                format!("Add directory: {}▏ (Tab: complete, Enter: add, Esc: cancel)", input),
+                Style::default().fg(Color::Yellow),
+            )));
+        }
+        if let Some(directory) = &view.confirm_remove {
+            lines.push(Line::from(Span::styled(
+                format!("Remove the working directory {}? Most file features will stop working. (y/n)", directory.display()),
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            )));
+        }
+
+        f.render_widget(
+            Paragraph::new(lines).block(
+                Block::default()
+                    .title(" Permissions (j/k: move, a: add, d: remove, q/Esc: close) ")
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded),
+            ),
+            area,
+        );
+        if self.ui.show_help {
+            self.render_floating_help(f);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyEvent;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_permissions_add_remove_and_persist() {
+        let (test_dir, mut app) = crate::app::file_operations::tests::test_app("permissions", false);
+        fs::create_dir_all(test_dir.join("projects").join("alpha")).unwrap();
+        fs::create_dir_all(test_dir.join("projects").join("beta")).unwrap();
+        fs::create_dir_all(test_dir.join("pictures")).unwrap();
+        let dir = normalize_path(&test_dir.canonicalize().unwrap()).unwrap();
+        let base = dir.display().to_string();
+        assert_eq!(complete_directory("pro", &dir, None), format!("projects{}", MAIN_SEPARATOR));
+        assert_eq!(complete_directory("p", &dir, None), "p");
+        assert_eq!(complete_directory(&format!("{}/projects/al", base), &dir, None), format!("{}/projects/alpha{}", base, MAIN_SEPARATOR));
+        assert_eq!(complete_directory("missing/x", &dir, None), "missing/x");
+
+        let list = dir.join("allowed_directories");
+        fs::write(&list, "# kept\n").unwrap();
+        app.config.allowed_directories_file = Some(list.clone());
+        app.open_permissions();
+        assert_eq!(app.ui.input_mode, InputMode::Permissions);
+
+        // a でパスを入力し、Tabで補完してEnterで追加する
+        app.handle_permissions_key(key(KeyCode::Char('a'))).unwrap();
+        for c in format!("{}/projects/be", base).chars() {
+            app.handle_permissions_key(key(KeyCode::Char(c))).unwrap();
+        }
+        app.handle_permissions_key(key(KeyCode::Tab)).unwrap();
+        app.handle_permissions_key(key(KeyCode::Enter)).unwrap();
+        let beta = dir.join("projects").join("beta");
+        // test_appのディレクトリと作業ディレクトリの後ろに追加される
+        let allowed = app.gemini_client.allowed_directories().len();
+        assert_eq!(app.gemini_client.allowed_directories().last(), Some(&beta));
+        assert_eq!(app.ui.permissions.as_ref().unwrap().saved, vec![beta.clone()]);
+        assert!(app.add_permission(&beta.display().to_string()).is_err());
+
+        // 選択して d で外すと、ファイルからも消える（コメントは残す）
+        app.handle_permissions_key(key(KeyCode::Char('d'))).unwrap();
+        assert_eq!(app.gemini_client.allowed_directories().len(), allowed - 1);
+        assert!(!app.gemini_client.allowed_directories().contains(&beta));
+        assert_eq!(fs::read_to_string(&list).unwrap(), "# kept\n");
+        assert_eq!(app.ui.permissions.as_ref().unwrap().selected, allowed - 2);
+        app.handle_permissions_key(key(KeyCode::Char('q'))).unwrap();
+        assert_eq!(app.ui.input_mode, InputMode::Normal);
+    }
+
+    #[test]
+    fn test_removed_working_directory_stays_removed_after_restart() {
+        let (test_dir, mut app) = crate::app::file_operations::tests::test_app("permissions_cwd", false);
+        let list = test_dir.join("allowed_directories");
+        app.config.allowed_directories_file = Some(list.clone());
+        let working_directory = app.working_directory().unwrap();
+        app.open_permissions();
+        let index = app.gemini_client.allowed_directories().iter().position(|directory| directory == &working_directory).unwrap();
+        app.ui.permissions.as_mut().unwrap().selected = index;
+        app.handle_permissions_key(key(KeyCode::Char('d'))).unwrap();
+        app.handle_permissions_key(key(KeyCode::Char('y'))).unwrap();
+        assert!(!app.gemini_client.allowed_directories().contains(&working_directory));
+        assert_eq!(fs::read_to_string(&list).unwrap(), format!("!{}\n", working_directory.display()));
+        // 除外の行は許可ディレクトリとして読み込まない
+        assert!(load_allowed_directories(&list).unwrap().is_empty());
+
+        // 作り直しても作業ディレクトリは許可しない
+        let mut config = crate::app::file_operations::tests::test_config(false);
+        config.excluded_directories = crate::file_access::load_excluded_directories(&list).unwrap();
+        config.allowed_directories_file = Some(list.clone());
+        let mut restarted = crate::app::file_operations::tests::test_app_with(&test_dir, config);
+        assert!(!restarted.gemini_client.allowed_directories().contains(&working_directory));
+
+        // 追加し直すと除外の行を消して許可の行を書く（読み込むときと同じ扱い）
+        restarted.add_permission(&working_directory.display().to_string()).unwrap();
+        assert!(restarted.gemini_client.allowed_directories().contains(&working_directory));
+        assert_eq!(load_allowed_directories(&list).unwrap(), vec![working_directory.clone()]);
+        assert!(crate::file_access::load_excluded_directories(&list).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,46 @@
+use uuid::Uuid;
+
+use crate::app::{ChatApp, ChatEvent};
+use crate::gemini::ResponsePart;
+
+impl ChatApp {
+    /// リクエストの応答のテキストをため、終わったらpost_responseフックに渡す
+    pub fn collect_response_for_hook(&mut self, event: &ChatEvent) {
+        if self.gemini_client.hooks().post_response.is_none() {
+            return;
+        }
+        match event {
+            ChatEvent::AIResponseFor { session_id, part: ResponsePart::Text { text } } => {
+                let collected = self.response_texts.entry(*session_id).or_default();
+                if !collected.is_empty() {
+                    collected.push_str("\n\n");
+                }
+                collected.push_str(text);
+            }
+            ChatEvent::Session { session_id, event } if matches!(event.as_ref(), ChatEvent::TaskFinished) => {
+                if let Some(text) = self.response_texts.remove(session_id) {
+                    self.run_post_response_hook(*session_id, text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// post_responseフックを非同期に実行する（失敗は通知バーに警告を出す）
+    fn run_post_response_hook(&self, session_id: Uuid, text: String) {
+        let Some(hook) = self.gemini_client.hooks().post_response.clone() else {
+            return;
+        };
+        let shell = self.gemini_client.shell();
+        let sender = self.event_sender.clone();
+        tokio::spawn(async move {
+            let input = serde_json::json!({"event": "post_response", "session_id": session_id, "text": text});
+            let warning = match hook.run(shell, &input).await {
+                Ok(output) if output.success => return,
+                Ok(output) => format!("exited with an error: {}", output.message()),
+                Err(e) => e.to_string(),
+            };
+            let _ = sender.send(ChatEvent::Notification(format!("⚠ post_response hook: {}", warning)));
+        });
+    }
+}
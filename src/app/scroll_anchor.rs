@@ -0,0 +1,104 @@
+use crate::app::comparison::side_by_side_lines;
+use crate::app::message_view::wrapped_message;
+use crate::app::ChatApp;
+
+/// リサイズ前にチャット画面の先頭に表示していた位置（メッセージと、その中の行）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScrollAnchor {
+    /// 先頭に表示していたメッセージのインデックス
+    pub message: usize,
+    /// そのメッセージの中の行（折り返し後）
+    pub line: usize,
+    /// リサイズ前のそのメッセージの行数
+    pub lines: usize,
+    /// 最下部にいた（リサイズ後も最下部に置く）
+    pub at_bottom: bool,
+}
+
+/// メッセージごとの行数とスクロール位置から、先頭に表示している位置を求める
+pub fn scroll_anchor(line_counts: &[usize], scroll_offset: usize) -> Option<ScrollAnchor> {
+    let total: usize = line_counts.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let offset = scroll_offset.min(total - 1);
+    let mut start = 0;
+    for (message, &lines) in line_counts.iter().enumerate() {
+        if offset < start + lines {
+            return Some(ScrollAnchor { message, line: offset - start, lines, at_bottom: scroll_offset >= total - 1 });
+        }
+        start += lines;
+    }
+    None
+}
+
+/// リサイズ後のメッセージごとの行数で、anchorの位置に当たるスクロール位置を求める
+/// 折り返しで行数が変わったメッセージの中では、行の位置を行数の比で移す
+pub fn anchored_offset(anchor: &ScrollAnchor, line_counts: &[usize]) -> usize {
+    let total: usize = line_counts.iter().sum();
+    let last = total.saturating_sub(1);
+    if anchor.at_bottom || anchor.message >= line_counts.len() {
+        return last;
+    }
+    let start: usize = line_counts[..anchor.message].iter().sum();
+    let lines = line_counts[anchor.message];
+    let line = (anchor.line * lines).checked_div(anchor.lines).unwrap_or(0).min(lines.saturating_sub(1));
+    (start + line).min(last)
+}
+
+impl ChatApp {
+    /// 前回の描画の幅でのメッセージごとの行数（折りたたんだメッセージは折りたたんだ行数）
+    pub fn message_line_counts(&mut self) -> Vec<usize> {
+        let width = self.ui.message_width;
+        let mut counts = Vec::with_capacity(self.messages.len());
+        for msg in &self.messages {
+            let body = if msg.comparison {
+                1 + side_by_side_lines(&msg.parts, width, self.appearance).len()
+            } else {
+                let tool_expanded = self.ui.expanded_tool_messages.contains(&msg.id);
+                wrapped_message(&mut self.ui.wrapped_messages, msg, self.ui.render_mode, tool_expanded, self.appearance, width, self.config.message_max_lines)
+                    .lines
+                    .len()
+            };
+            counts.push(body + self.step_lines(msg, width).len());
+        }
+        counts
+    }
+
+    /// 端末のサイズが変わった。今の先頭の位置を覚えておき、次の描画で新しい幅の折り返しに合わせてスクロール位置を移す
+    pub fn handle_resize(&mut self) {
+        if self.ui.scroll_anchor.is_none() {
+            let counts = self.message_line_counts();
+            self.ui.scroll_anchor = scroll_anchor(&counts, self.ui.scroll_offset);
+        }
+        // 入力欄の高さとカーソル位置は描画のたびに求めるので、スクロールだけカーソルから求め直す
+        self.ui.input_scroll = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scroll_anchor_survives_width_change() {
+        // 幅80で [3, 10, 2] 行だったメッセージが、幅40で [5, 20, 2] 行になった
+        let before = [3, 10, 2];
+        let after = [5, 20, 2];
+        let anchor = scroll_anchor(&before, 7).unwrap();
+        assert_eq!(anchor, ScrollAnchor { message: 1, line: 4, lines: 10, at_bottom: false });
+        assert_eq!(anchored_offset(&anchor, &after), 5 + 8);
+        // 逆向き（広げて行数が減る）
+        let anchor = scroll_anchor(&after, 5 + 9).unwrap();
+        assert_eq!(anchored_offset(&anchor, &before), 3 + 4);
+
+        // 最下部にいたら最下部のまま、範囲外の位置は末尾に収める
+        let anchor = scroll_anchor(&before, 14).unwrap();
+        assert!(anchor.at_bottom);
+        assert_eq!(anchored_offset(&anchor, &after), 26);
+        assert_eq!(scroll_anchor(&before, 100).unwrap().message, 2);
+        // メッセージが減っていたら末尾に収める
+        assert_eq!(anchored_offset(&ScrollAnchor { message: 5, line: 0, lines: 1, at_bottom: false }, &after), 26);
+        assert_eq!(scroll_anchor(&[], 3), None);
+    }
+}
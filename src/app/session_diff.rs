@@ -0,0 +1,93 @@
+use similar::{ChangeTag, TextDiff};
+
+/// /diff で表示する行の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffLineKind {
+    /// ---/+++ と @@ の行
+    Header,
+    Context,
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffLine {
+    pub kind: DiffLineKind,
+    pub text: String,
+}
+
+impl DiffLine {
+    fn new(kind: DiffLineKind, text: impl Into<String>) -> Self {
+        Self { kind, text: text.into() }
+    }
+}
+
+/// 2つのテキストのunified diff（oldにない行がAdded、newにない行がRemoved）
+pub fn unified_diff_lines(old_label: &str, old: &str, new_label: &str, new: &str) -> Vec<DiffLine> {
+    let diff = TextDiff::from_lines(old, new);
+    let mut lines = vec![
+        DiffLine::new(DiffLineKind::Header, format!("--- {}", old_label)),
+        DiffLine::new(DiffLineKind::Header, format!("+++ {}", new_label)),
+    ];
+    for hunk in diff.unified_diff().context_radius(3).iter_hunks() {
+        lines.push(DiffLine::new(DiffLineKind::Header, hunk.header().to_string()));
+        for change in hunk.iter_changes() {
+            let (kind, sign) = match change.tag() {
+                ChangeTag::Equal => (DiffLineKind::Context, ' '),
+                ChangeTag::Insert => (DiffLineKind::Added, '+'),
+                ChangeTag::Delete => (DiffLineKind::Removed, '-'),
+            };
+            lines.push(DiffLine::new(kind, format!("{}{}", sign, change.value().trim_end_matches('\n'))));
+        }
+    }
+    lines
+}
+
+/// `git diff` の出力を種類ごとに分ける
+pub fn git_diff_lines(output: &str) -> Vec<DiffLine> {
+    let mut in_header = false;
+    output
+        .lines()
+        .map(|line| {
+            // ファイルごとの見出し（diff --git から最初の @@ まで）
+            if line.starts_with("diff --git") {
+                in_header = true;
+            } else if line.starts_with("@@") {
+                in_header = false;
+                return DiffLine::new(DiffLineKind::Header, line);
+            }
+            let kind = match line.chars().next() {
+                _ if in_header => DiffLineKind::Header,
+                Some('+') => DiffLineKind::Added,
+                Some('-') => DiffLineKind::Removed,
+                _ => DiffLineKind::Context,
+            };
+            DiffLine::new(kind, line)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff_lines() {
+        let lines = unified_diff_lines("A", "You: hi\nAI: hello\n", "B", "You: hi\nAI: hey\nYou: bye\n");
+        let texts: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(texts, vec!["--- A", "+++ B", "@@ -1,2 +1,3 @@", " You: hi", "-AI: hello", "+AI: hey", "+You: bye"]);
+        assert_eq!(lines[4].kind, DiffLineKind::Removed);
+        assert_eq!(lines[5].kind, DiffLineKind::Added);
+
+        // 差分がなければヘッダーのみ
+        assert_eq!(unified_diff_lines("A", "x\n", "B", "x\n").len(), 2);
+    }
+
+    #[test]
+    fn test_git_diff_lines() {
+        let output = "diff --git a/a.txt b/a.txt\nindex 1..2 100644\n--- a/a.txt\n+++ b/a.txt\n@@ -1,2 +1,2 @@\n keep\n-old\n+new\n";
+        let kinds: Vec<DiffLineKind> = git_diff_lines(output).iter().map(|line| line.kind).collect();
+        use DiffLineKind::*;
+        assert_eq!(kinds, vec![Header, Header, Header, Header, Header, Context, Removed, Added]);
+    }
+}
@@ -1,9 +1,27 @@
 use crate::app::{ChatApp, InputMode};
-use crate::history::ChatMessage;
+use crate::debug_log;
+use crate::history::{ChatMessage, GenerationOverrides};
+use crate::import::ImportedSession;
 use uuid::Uuid;
 use chrono::Utc;
+use ratatui::layout::{Margin, Position, Rect};
+use std::time::{Duration, Instant};
+
+/// セッションを削除してからこの時間内なら u で元に戻せる
+pub const UNDO_DELETE_WINDOW: Duration = Duration::from_secs(30);
 
 impl ChatApp {
+    /// セッション一覧でクリックした行のセッションを選択する
+    pub fn select_session_at(&mut self, column: u16, row: u16) {
+        let len = {
+            let history_guard = self.history_manager.lock().unwrap();
+            history_guard.get_history().get_visible_session_list(self.ui.show_archived_sessions).len()
+        };
+        if let Some(index) = session_index_at(self.ui.session_list_area, self.ui.session_list_offset, len, column, row) {
+            self.ui.session_list_state.select(Some(index));
+        }
+    }
+
     pub fn session_list_next(&mut self) {
         self.select_session_offset(1);
     }
@@ -14,7 +32,7 @@ impl ChatApp {
 
     fn select_session_offset(&mut self, offset: isize) {
         let history_guard = self.history_manager.lock().unwrap();
-        let sessions = history_guard.get_history().get_session_list();
+        let sessions = history_guard.get_history().get_visible_session_list(self.ui.show_archived_sessions);
         if sessions.is_empty() { return; }
         let len = sessions.len() as isize;
         let current = self.ui.session_list_state.selected().unwrap_or(0) as isize;
@@ -22,25 +40,68 @@ impl ChatApp {
         self.ui.session_list_state.select(Some(next));
     }
 
+    /// 1ページ分（表示件数）だけ表示位置と選択を動かす（directionは1で次、-1で前のページ）
+    pub fn session_list_page(&mut self, direction: isize) {
+        let len = {
+            let history_guard = self.history_manager.lock().unwrap();
+            history_guard.get_history().get_visible_session_list(self.ui.show_archived_sessions).len()
+        };
+        if len == 0 { return; }
+        let page_size = self.ui.session_list_page_size.max(1);
+        let max_offset = len.saturating_sub(page_size);
+        let selected = self.ui.session_list_state.selected().unwrap_or(0);
+        let (offset, selected) = if direction > 0 {
+            ((self.ui.session_list_offset + page_size).min(max_offset), (selected + page_size).min(len - 1))
+        } else {
+            (self.ui.session_list_offset.saturating_sub(page_size), selected.saturating_sub(page_size))
+        };
+        self.ui.session_list_offset = offset;
+        self.ui.session_list_state.select(Some(selected));
+    }
+
+    /// セッション一覧で選択中のセッション
+    pub fn selected_session_id(&self) -> Option<Uuid> {
+        let i = self.ui.session_list_state.selected()?;
+        let history_guard = self.history_manager.lock().unwrap();
+        let sessions = history_guard.get_history().get_visible_session_list(self.ui.show_archived_sessions);
+        sessions.get(i).map(|s| s.id)
+    }
+
     pub fn switch_to_selected_session(&mut self) {
+        // 別のタブで開いているセッションは、そのタブに切り替える
+        if let Some(index) = self.selected_session_id().and_then(|id| self.tab_index_of(id)).filter(|&index| index != self.active_tab) {
+            self.ui.input_mode = InputMode::Normal;
+            self.activate_tab(index);
+            return;
+        }
         if let Some(i) = self.ui.session_list_state.selected() {
             let session_id = {
                 let history_guard = self.history_manager.lock().unwrap();
-                let sessions = history_guard.get_history().get_session_list();
+                let sessions = history_guard.get_history().get_visible_session_list(self.ui.show_archived_sessions);
                 sessions.get(i).map(|s| s.id)
             };
 
             if let Some(session_id) = session_id {
                 let mut history_guard_mut = self.history_manager.lock().unwrap();
+                // 切り替え前のセッションのスクロール位置を覚えておく
+                if let Some(current_id) = history_guard_mut.get_history().current_session_id {
+                    self.session_scroll_positions.insert(current_id, self.ui.scroll_offset);
+                }
                 if history_guard_mut.get_history_mut().switch_session(session_id).is_err() {
                     return;
                 }
+                self.ui.editing_message_id = None;
                 drop(history_guard_mut); // Explicitly drop the guard
 
-                let _ = self.save_history();
+                if let Err(e) = self.history_manager.lock().unwrap().save() {
+                    debug_log!("[session_management] save_history error: {:?}", e);
+                }
                 self.restore_session_messages();
                 self.ui.input_mode = InputMode::Normal;
-                self.scroll_to_bottom(20);
+                match self.session_scroll_positions.get(&session_id) {
+                    Some(&offset) => self.ui.scroll_offset = offset,
+                    None => self.scroll_to_bottom(20),
+                }
             }
         }
     }
@@ -49,18 +110,26 @@ impl ChatApp {
         if let Some(i) = self.ui.session_list_state.selected() {
             let session_id = {
                 let history_guard = self.history_manager.lock().unwrap();
-                let sessions = history_guard.get_history().get_session_list();
+                let sessions = history_guard.get_history().get_visible_session_list(self.ui.show_archived_sessions);
                 sessions.get(i).map(|s| s.id)
             };
 
             if let Some(session_id) = session_id {
+                if self.tab_index_of(session_id).is_some_and(|index| index != self.active_tab) {
+                    self.notify("The session is open in another tab; close the tab first".to_string());
+                    return;
+                }
+                // 完全には削除せずゴミ箱に入れる（u または /trash restore で戻せる）
                 let mut history_guard_mut = self.history_manager.lock().unwrap();
-                if history_guard_mut.get_history_mut().delete_session(session_id).is_err() {
+                if history_guard_mut.get_history_mut().trash_session(session_id, Utc::now()).is_err() {
                     return;
                 }
                 drop(history_guard_mut); // Explicitly drop the guard
+                self.deleted_session = Some((session_id, Instant::now()));
 
-                let _ = self.save_history();
+                if let Err(e) = self.history_manager.lock().unwrap().save() {
+                    debug_log!("[session_management] save_history error: {:?}", e);
+                }
                 
                 let current_session_is_none = {
                     let history_guard_check = self.history_manager.lock().unwrap();
@@ -74,22 +143,237 @@ impl ChatApp {
                 }
                 self.scroll_to_bottom(20);
                 self.adjust_session_selection(i);
+                self.notify("🗑 Session deleted — press u to undo".to_string());
+            }
+        }
+    }
+
+    /// u: 直前に削除したセッションをゴミ箱から戻す（UNDO_DELETE_WINDOWを過ぎたら /trash restore を使う）
+    pub fn undo_delete_session(&mut self, now: Instant) {
+        match self.deleted_session.take() {
+            Some((session_id, deleted_at)) if now.duration_since(deleted_at) <= UNDO_DELETE_WINDOW => {
+                if let Err(e) = self.restore_trashed_session(session_id) {
+                    self.notify(format!("❌ {}", e));
+                }
+            }
+            _ => self.notify("Nothing to undo (see /trash)".to_string()),
+        }
+    }
+
+    /// ゴミ箱のセッションを一覧に戻して保存する
+    pub fn restore_trashed_session(&mut self, session_id: Uuid) -> anyhow::Result<()> {
+        let title = {
+            let mut history_guard = self.history_manager.lock().unwrap();
+            history_guard.get_history_mut().restore_session(session_id)?;
+            if let Err(e) = history_guard.save() {
+                debug_log!("[session_management] save_history error: {:?}", e);
+            }
+            history_guard.get_history().sessions.get(&session_id).map(|session| session.title.clone()).unwrap_or_default()
+        };
+        if self.deleted_session.is_some_and(|(deleted, _)| deleted == session_id) {
+            self.deleted_session = None;
+        }
+        self.adjust_session_selection(self.ui.session_list_state.selected().unwrap_or(0));
+        self.notify(format!("↩ Restored session: {}", title));
+        Ok(())
+    }
+
+    /// 選択中のセッションをアーカイブ／アーカイブ解除する
+    pub fn toggle_archive_selected_session(&mut self) {
+        let Some(i) = self.ui.session_list_state.selected() else {
+            return;
+        };
+        let result = {
+            let mut history_guard = self.history_manager.lock().unwrap();
+            let session_id = history_guard
+                .get_history()
+                .get_visible_session_list(self.ui.show_archived_sessions)
+                .get(i)
+                .map(|s| s.id);
+            match session_id {
+                Some(session_id) => {
+                    let result = history_guard.get_history_mut().toggle_archived(session_id);
+                    if let Err(e) = history_guard.save() {
+                        debug_log!("[session_management] save_history error: {:?}", e);
+                    }
+                    result
+                }
+                None => return,
+            }
+        };
+        match result {
+            Ok(archived) => {
+                self.notify(if archived { "🗄️ Session archived" } else { "Session unarchived" }.to_string());
+                self.adjust_session_selection(i);
+            }
+            Err(e) => {
+                debug_log!("[toggle_archive_selected_session] error: {:?}", e);
+            }
+        }
+    }
+
+    /// アーカイブ済みセッションの表示・非表示を切り替える
+    pub fn toggle_show_archived_sessions(&mut self) {
+        self.ui.show_archived_sessions = !self.ui.show_archived_sessions;
+        self.adjust_session_selection(self.ui.session_list_state.selected().unwrap_or(0));
+    }
+
+    /// 複数のセッションを一括削除して保存する（現在のセッションは削除されない）
+    pub fn delete_sessions_bulk(&mut self, session_ids: &[Uuid]) -> usize {
+        // 裏のタブで開いているセッションは残す
+        let session_ids: Vec<Uuid> = session_ids.iter().copied().filter(|&id| self.tab_index_of(id).is_none()).collect();
+        let session_ids = &session_ids[..];
+        let deleted = {
+            let mut history_guard = self.history_manager.lock().unwrap();
+            let deleted = history_guard.get_history_mut().delete_sessions(session_ids);
+            if let Err(e) = history_guard.save() {
+                debug_log!("[session_management] save_history error: {:?}", e);
+            }
+            deleted
+        };
+        self.adjust_session_selection(self.ui.session_list_state.selected().unwrap_or(0));
+        deleted
+    }
+
+    /// 現在のセッションの生成パラメータ上書きを変更して保存する
+    pub fn update_generation_overrides(&mut self, update: impl FnOnce(&mut GenerationOverrides)) -> anyhow::Result<()> {
+        let mut history_guard = self.history_manager.lock().unwrap();
+        let history = history_guard.get_history_mut();
+        let mut overrides = history.current_overrides();
+        update(&mut overrides);
+        history.set_overrides(overrides)?;
+        history_guard.save()
+    }
+
+    /// 編集したメッセージ以降を退避し、表示中のメッセージを履歴から作り直す
+    pub fn rewind_for_edit(&mut self, message_id: Uuid) {
+        {
+            let mut history_guard = self.history_manager.lock().unwrap();
+            let history = history_guard.get_history_mut();
+            let Some(session) = history.get_current_session() else {
+                return;
+            };
+            let Some(index) = session.messages.iter().position(|m| m.id == message_id) else {
+                debug_log!("[rewind_for_edit] message not found: {}", message_id);
+                return;
+            };
+            let preceding_id = index.checked_sub(1).map(|i| session.messages[i].id);
+            match history.truncate_after(preceding_id) {
+                Ok(tail) => {
+                    if let Err(e) = history.archive_branch(tail) {
+                        debug_log!("[rewind_for_edit] archive_branch error: {:?}", e);
+                    }
+                }
+                Err(e) => {
+                    debug_log!("[rewind_for_edit] truncate_after error: {:?}", e);
+                    return;
+                }
+            }
+        }
+        self.restore_session_messages();
+    }
+
+    /// 指定したセッションに切り替え、メッセージを選択して表示位置までスクロールする
+    /// セッションかメッセージが見つからなければfalseを返す
+    pub fn goto_message(&mut self, session_id: Uuid, message_id: Uuid) -> bool {
+        let is_current = {
+            let mut history_guard = self.history_manager.lock().unwrap();
+            let history = history_guard.get_history_mut();
+            let found = history.sessions.get(&session_id)
+                .is_some_and(|session| session.messages.iter().any(|m| m.id == message_id));
+            if !found {
+                return false;
+            }
+            let is_current = history.current_session_id == Some(session_id);
+            if !is_current && history.switch_session(session_id).is_err() {
+                return false;
+            }
+            is_current
+        };
+        if !is_current {
+            self.ui.editing_message_id = None;
+            if let Err(e) = self.history_manager.lock().unwrap().save() {
+                debug_log!("[session_management] save_history error: {:?}", e);
             }
+            self.restore_session_messages();
         }
+        let Some(index) = self.messages.iter().position(|m| m.id == message_id) else {
+            return false;
+        };
+        self.ui.selected_message_index = Some(index);
+        self.ui.scroll_to_selected_message = true;
+        self.ui.input_mode = InputMode::Normal;
+        true
     }
 
-    fn restore_session_messages(&mut self) {
+    /// 選択中のメッセージまでをコピーした新しいセッションを作って切り替える（'F'）
+    pub fn fork_session(&mut self) {
+        let Some(message_id) = self.selected_message_index().and_then(|i| self.messages.get(i)).map(|m| m.id) else {
+            self.notify("❌ No message selected");
+            return;
+        };
+        let result = {
+            let mut history_guard = self.history_manager.lock().unwrap();
+            let current_id = history_guard.get_history().current_session_id;
+            let result = history_guard.get_history_mut().fork_session_at(message_id);
+            if result.is_ok() {
+                if let Some(current_id) = current_id {
+                    self.session_scroll_positions.insert(current_id, self.ui.scroll_offset);
+                }
+                if let Err(e) = history_guard.save() {
+                    debug_log!("[session_management] save_history error: {:?}", e);
+                }
+            }
+            result
+        };
+        match result {
+            Ok(_) => {
+                self.ui.editing_message_id = None;
+                self.restore_session_messages();
+                self.scroll_to_bottom(20);
+                self.notify("🍴 Forked into a new session");
+            }
+            // 表示専用のメッセージ（システムメッセージなど）は履歴にないので分岐できない
+            Err(e) => self.notify(format!("❌ Cannot fork here: {}", e)),
+        }
+    }
+
+    /// /import で読み込んだセッションを追加して切り替える
+    pub fn open_imported_session(&mut self, path: &str, imported: ImportedSession) {
+        let message_count = imported.session.messages.len();
+        let title = imported.session.title.clone();
+        {
+            let mut history_guard = self.history_manager.lock().unwrap();
+            if let Some(current_id) = history_guard.get_history().current_session_id {
+                self.session_scroll_positions.insert(current_id, self.ui.scroll_offset);
+            }
+            history_guard.get_history_mut().import_session(imported.session);
+            if let Err(e) = history_guard.save() {
+                debug_log!("[session_management] save_history error: {:?}", e);
+            }
+        }
+        self.ui.editing_message_id = None;
+        self.restore_session_messages();
+        self.ui.input_mode = InputMode::Normal;
+        let source = if imported.conversations > 1 {
+            format!("{}, the most recent of {} conversations", imported.format, imported.conversations)
+        } else {
+            imported.format.to_string()
+        };
+        self.push_system_message(format!("📥 Imported {} messages from {} ({}) as \"{}\"", message_count, path, source, title));
+        self.scroll_to_bottom(20);
+    }
+
+    pub fn restore_session_messages(&mut self) {
         self.messages.clear();
+        self.ui.selected_message_index = None;
+        self.file_changes.clear();
         let history_guard = self.history_manager.lock().unwrap();
         if let Some(session) = history_guard.get_history().get_current_session() {
-            for hist_msg in &session.messages {
-                self.messages.push(crate::history::ChatMessage {
-                    id: hist_msg.id,
-                    parts: hist_msg.parts.clone(), // Changed content to parts
-                    is_user: hist_msg.is_user,
-                    timestamp: hist_msg.timestamp,
-                });
-            }
+            self.file_changes = session.changes.clone();
+            self.messages.extend(session.messages.iter().cloned());
+            self.unread_responses.remove(&session.id);
+            self.background_responses.remove(&session.id);
         }
         if self.messages.is_empty() {
             self.messages.push(ChatMessage {
@@ -97,13 +381,17 @@ impl ChatApp {
                 parts: vec![crate::gemini::Part::Text { text: "Welcome to ConTUI!".to_string() }],
                 is_user: false,
                 timestamp: Utc::now(),
+                comparison: false,
+                steps: Vec::new(),
+                is_system: false,
+                excluded: false,
             });
         }
     }
 
     fn adjust_session_selection(&mut self, prev_index: usize) {
         let history_guard = self.history_manager.lock().unwrap();
-        let sessions = history_guard.get_history().get_session_list();
+        let sessions = history_guard.get_history().get_visible_session_list(self.ui.show_archived_sessions);
         if sessions.is_empty() {
             self.ui.session_list_state.select(None);
         }
@@ -116,4 +404,223 @@ impl ChatApp {
             self.ui.session_list_state.select(Some(new_index));
         }
     }
-}
\ No newline at end of file
+}
+impl ChatApp {
+    /// 現在のセッションの共有用サマリーを作成（タイトル・期間・件数・直近10件）
+    pub fn build_session_summary(&self) -> Option<String> {
+        let history_guard = self.history_manager.lock().unwrap();
+        let session = history_guard.get_history().get_current_session()?;
+
+        let date_format = "%Y-%m-%d %H:%M";
+        let date_range = match (session.messages.first(), session.messages.last()) {
+            (Some(first), Some(last)) => format!(
+                "{} - {}",
+                first.timestamp.format(date_format),
+                last.timestamp.format(date_format)
+            ),
+            _ => session.created_at.format(date_format).to_string(),
+        };
+
+        let mut summary = format!(
+            "Session: {}\nDate: {}\nMessages: {}\n",
+            session.title,
+            date_range,
+            session.messages.len()
+        );
+
+        let start_index = session.messages.len().saturating_sub(10);
+        for msg in &session.messages[start_index..] {
+            let prefix = msg.speaker();
+            let mut msg_content_text = String::new();
+            for part in &msg.parts {
+                match part {
+                    crate::gemini::Part::Text { text } => msg_content_text.push_str(text),
+                    crate::gemini::Part::ToolOutcome { tool_outcome } => msg_content_text.push_str(&tool_outcome.expanded()),
+                    _ => {}
+                }
+            }
+            summary.push_str(&format!("\n{}: {}", prefix, msg_content_text));
+        }
+        Some(summary)
+    }
+
+    /// セッションのサマリーをクリップボードにコピー（/share）
+    pub fn share_session(&mut self) {
+        let Some(summary) = self.build_session_summary() else {
+            self.notify("❌ アクティブなセッションがありません".to_string());
+            return;
+        };
+        let char_count = summary.chars().count();
+        match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(summary)) {
+            Ok(_) => {
+                self.notify(format!("📋 Copied {} chars to clipboard", char_count));
+            }
+            Err(e) => {
+                debug_log!("[share_session] clipboard error: {:?}", e);
+                self.notify(format!("❌ クリップボードへのコピーに失敗しました: {}", e));
+            }
+        }
+    }
+}
+
+/// 選択中の項目が表示範囲に入るよう調整した表示開始位置
+pub fn visible_offset(offset: usize, selected: usize, page_size: usize, len: usize) -> usize {
+    let page_size = page_size.max(1);
+    let offset = if selected < offset {
+        selected
+    } else if selected >= offset + page_size {
+        selected + 1 - page_size
+    } else {
+        offset
+    };
+    offset.min(len.saturating_sub(page_size))
+}
+
+/// セッション一覧の枠の内側の座標から、その行に表示しているセッションのインデックスを求める
+pub fn session_index_at(area: Rect, offset: usize, len: usize, column: u16, row: u16) -> Option<usize> {
+    let inner = area.inner(Margin { horizontal: 1, vertical: 1 });
+    if !inner.contains(Position { x: column, y: row }) {
+        return None;
+    }
+    let index = offset + (row - inner.y) as usize;
+    (index < len).then_some(index)
+}
+
+/// 表示開始位置から求めた現在のページ番号と総ページ数（1始まり）
+pub fn page_indicator(offset: usize, page_size: usize, len: usize) -> (usize, usize) {
+    let page_size = page_size.max(1);
+    let pages = len.div_ceil(page_size).max(1);
+    ((offset.div_ceil(page_size) + 1).min(pages), pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::file_operations::tests::test_app;
+    use crate::app::{ChatEvent, CONTEXT_TOKEN_BUDGET};
+    use crate::gemini::{Part, ResponsePart};
+    use crate::history::StepRecord;
+
+    #[test]
+    fn test_delete_session_moves_to_trash_and_undo_restores() {
+        let (_dir, mut app) = test_app("trash", false);
+        let first = app.history_manager.lock().unwrap().ensure_active_session();
+        app.create_new_session();
+        let visible = |app: &ChatApp| app.history_manager.lock().unwrap().get_history().get_session_list().len();
+        assert_eq!(visible(&app), 2);
+
+        let index = app.history_manager.lock().unwrap().get_history().get_session_list().iter().position(|s| s.id == first).unwrap();
+        app.ui.session_list_state.select(Some(index));
+        app.delete_selected_session();
+        assert_eq!(visible(&app), 1);
+        assert_eq!(app.history_manager.lock().unwrap().get_history().trashed_sessions()[0].id, first);
+
+        // 猶予を過ぎたら u では戻せない
+        let deleted_at = app.deleted_session.unwrap().1;
+        app.undo_delete_session(deleted_at + UNDO_DELETE_WINDOW + Duration::from_secs(1));
+        assert_eq!(visible(&app), 1);
+
+        app.deleted_session = Some((first, deleted_at));
+        app.undo_delete_session(deleted_at + Duration::from_secs(2));
+        assert_eq!(visible(&app), 2);
+        assert!(app.history_manager.lock().unwrap().get_history().trashed_sessions().is_empty());
+    }
+
+    #[test]
+    fn test_session_list_paging() {
+        // 50件・1ページ20件
+        assert_eq!(page_indicator(0, 20, 50), (1, 3));
+        assert_eq!(page_indicator(20, 20, 50), (2, 3));
+        assert_eq!(page_indicator(30, 20, 50), (3, 3));
+        assert_eq!(page_indicator(0, 20, 0), (1, 1));
+
+        assert_eq!(visible_offset(0, 5, 20, 50), 0);
+        assert_eq!(visible_offset(0, 25, 20, 50), 6);
+        assert_eq!(visible_offset(20, 3, 20, 50), 3);
+        // 末尾を越えて空白が出ないように詰める
+        assert_eq!(visible_offset(40, 45, 20, 50), 30);
+    }
+
+    #[test]
+    fn test_session_index_at_click_position() {
+        // 枠線の内側の1行目が表示開始位置のセッション
+        let area = Rect::new(0, 0, 40, 10);
+        assert_eq!(session_index_at(area, 0, 50, 5, 1), Some(0));
+        assert_eq!(session_index_at(area, 20, 50, 5, 3), Some(22));
+        assert_eq!(session_index_at(area, 0, 50, 5, 0), None);
+        assert_eq!(session_index_at(area, 0, 50, 0, 2), None);
+        assert_eq!(session_index_at(area, 0, 3, 5, 5), None);
+    }
+
+    #[test]
+    fn test_excluded_message_is_kept_on_screen_but_not_sent() {
+        let (_dir, mut app) = test_app("exclude", false);
+        app.history_manager.lock().unwrap().ensure_active_session();
+        for (text, is_user) in [("huge log", true), ("noted", false), ("question", true)] {
+            app.history_manager.lock().unwrap().get_history_mut().add_message(vec![Part::Text { text: text.to_string() }], is_user).unwrap();
+        }
+        app.restore_session_messages();
+        let context_texts = |app: &ChatApp| -> Vec<String> {
+            let context = app.history_manager.lock().unwrap().get_conversation_context_with_budget(CONTEXT_TOKEN_BUDGET);
+            context.iter().flat_map(|content| &content.parts).map(|part| match part {
+                Part::Text { text } => text.clone(),
+                other => format!("{:?}", other),
+            }).collect()
+        };
+        assert_eq!(context_texts(&app), vec!["huge log", "noted", "question"]);
+
+        let index = app.messages.iter().position(|message| matches!(&message.parts[..], [Part::Text { text }] if text == "huge log")).unwrap();
+        app.ui.selected_message_index = Some(index);
+        app.toggle_selected_message_excluded();
+        assert_eq!(context_texts(&app), vec!["noted", "question"]);
+        assert!(app.messages[index].excluded);
+        assert_eq!(app.messages.len(), 3);
+        assert!(app.history_manager.lock().unwrap().get_history().get_current_session().unwrap().messages[0].excluded);
+
+        // もう一度で戻す
+        app.toggle_selected_message_excluded();
+        assert_eq!(context_texts(&app), vec!["huge log", "noted", "question"]);
+        assert!(!app.messages[index].excluded);
+    }
+
+    #[test]
+    fn test_response_after_session_switch_goes_to_origin_session() {
+        let (_dir, mut app) = test_app("switch", false);
+        let origin = app.history_manager.lock().unwrap().ensure_active_session();
+        app.history_manager.lock().unwrap().get_history_mut()
+            .add_message(vec![Part::Text { text: "question".to_string() }], true).unwrap();
+
+        // 応答待ちの間に新しいセッションへ切り替える
+        app.create_new_session();
+        let other = app.history_manager.lock().unwrap().get_history().current_session_id.unwrap();
+        let shown = app.messages.len();
+        let steps = vec![StepRecord { prompt_excerpt: "question".to_string(), response: "thinking".to_string(), duration: std::time::Duration::from_secs(1) }];
+        // エージェントループの終わりと同じく、最終応答・ステップ・終了メッセージを送ってからタスクを終える
+        let sender = app.event_sender.clone();
+        crate::app::send_agent_result(&sender, origin, vec![ResponsePart::Text { text: "answer".to_string() }], steps, "done".to_string());
+        while let Ok(event) = app.event_receiver.try_recv() {
+            app.handle_chat_event(event);
+        }
+        assert!(app.unread_responses.is_empty());
+        app.handle_chat_event(ChatEvent::for_session(origin, ChatEvent::TaskFinished));
+
+        {
+            let history_guard = app.history_manager.lock().unwrap();
+            let history = history_guard.get_history();
+            assert_eq!(history.current_session_id, Some(other));
+            let messages = &history.sessions[&origin].messages;
+            let answer = &messages[messages.len() - 2];
+            assert!(matches!(&answer.parts[..], [Part::Text { text }] if text == "answer"));
+            assert_eq!(answer.steps.len(), 1);
+            assert!(history.sessions[&other].messages.is_empty());
+        }
+        assert_eq!(app.messages.len(), shown);
+        assert_eq!(app.unread_responses.get(&origin), Some(&1));
+
+        // 送信元のセッションに戻ると応答が表示され、未読が消える
+        app.history_manager.lock().unwrap().get_history_mut().switch_session(origin).unwrap();
+        app.restore_session_messages();
+        assert!(app.unread_responses.is_empty());
+        assert!(matches!(&app.messages.last().unwrap().parts[..], [Part::Text { text }] if text == "done"));
+    }
+}
@@ -0,0 +1,438 @@
+use std::fs;
+use std::io::Stdout;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::Rect,
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Block, BorderType, Borders, Paragraph, Wrap},
+    Frame, Terminal,
+};
+
+use crate::config::DEFAULT_MODEL;
+use crate::diagnostics::{validate_api_key, KeyValidation};
+use crate::file_access::{append_allowed_directory, default_allowed_directories_path, resolve_user_path, strip_verbatim_prefix};
+
+/// 初回セットアップで選べるプロバイダー
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WizardProvider {
+    Gemini,
+    /// APIを呼ばずにスクリプトの応答を返す（動作確認用）
+    Mock,
+}
+
+impl WizardProvider {
+    const ALL: [WizardProvider; 2] = [WizardProvider::Gemini, WizardProvider::Mock];
+
+    fn label(&self) -> &'static str {
+        match self {
+            WizardProvider::Gemini => "Gemini (Google AI Studio API key)",
+            WizardProvider::Mock => "Mock (scripted responses, no API calls)",
+        }
+    }
+}
+
+/// セットアップの各画面
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WizardStep {
+    Provider,
+    ApiKey,
+    MockScript,
+    Model,
+    AllowDirectory,
+    /// APIキーを確認中
+    Validating,
+    /// APIに接続できなかったので、確認せずに保存するか尋ねる
+    SaveOffline,
+}
+
+/// キー入力の結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WizardAction {
+    None,
+    /// 何も書き込まずに終了する
+    Cancel,
+    /// APIキーを確認する
+    Validate,
+    Save,
+}
+
+/// 初回起動時のセットアップの状態（設定ファイルがなく、GEMINI_API_KEYも設定されていないとき）
+#[derive(Debug, Clone)]
+pub struct SetupWizard {
+    pub step: WizardStep,
+    provider: WizardProvider,
+    /// 入力中の文字列
+    input: String,
+    api_key: String,
+    mock_script: String,
+    model: String,
+    allow_directory: bool,
+    current_dir: PathBuf,
+    /// 直前の入力・確認の問題（赤で表示）
+    error: Option<String>,
+}
+
+impl SetupWizard {
+    pub fn new(current_dir: PathBuf) -> Self {
+        Self {
+            step: WizardStep::Provider,
+            provider: WizardProvider::Gemini,
+            input: String::new(),
+            api_key: String::new(),
+            mock_script: String::new(),
+            model: String::new(),
+            allow_directory: true,
+            current_dir,
+            error: None,
+        }
+    }
+
+    /// 入力を受け付ける画面へ進む（前に入力した値があれば編集できるように戻す）
+    fn go_to(&mut self, step: WizardStep) {
+        self.input = match step {
+            WizardStep::ApiKey => self.api_key.clone(),
+            WizardStep::MockScript => self.mock_script.clone(),
+            WizardStep::Model => self.model.clone(),
+            _ => String::new(),
+        };
+        self.step = step;
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> WizardAction {
+        if key.code == KeyCode::Esc || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL)) {
+            return WizardAction::Cancel;
+        }
+        match self.step {
+            WizardStep::Provider => match key.code {
+                KeyCode::Up | KeyCode::Down | KeyCode::Char('j') | KeyCode::Char('k') | KeyCode::Tab => {
+                    self.provider = match self.provider {
+                        WizardProvider::Gemini => WizardProvider::Mock,
+                        WizardProvider::Mock => WizardProvider::Gemini,
+                    };
+                }
+                KeyCode::Enter => {
+                    self.error = None;
+                    self.go_to(match self.provider {
+                        WizardProvider::Gemini => WizardStep::ApiKey,
+                        WizardProvider::Mock => WizardStep::MockScript,
+                    });
+                }
+                _ => {}
+            },
+            WizardStep::ApiKey | WizardStep::MockScript | WizardStep::Model => match key.code {
+                KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => self.input.push(c),
+                KeyCode::Backspace => {
+                    self.input.pop();
+                }
+                KeyCode::Enter => return self.submit_input(),
+                _ => {}
+            },
+            WizardStep::AllowDirectory => match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => return self.finish_questions(true),
+                KeyCode::Char('n') | KeyCode::Char('N') => return self.finish_questions(false),
+                KeyCode::Enter => return self.finish_questions(self.allow_directory),
+                _ => {}
+            },
+            WizardStep::Validating => {}
+            WizardStep::SaveOffline => match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => return WizardAction::Save,
+                KeyCode::Char('n') | KeyCode::Char('N') => self.go_to(WizardStep::ApiKey),
+                _ => {}
+            },
+        }
+        WizardAction::None
+    }
+
+    fn submit_input(&mut self) -> WizardAction {
+        let value = self.input.trim().to_string();
+        self.error = None;
+        match self.step {
+            WizardStep::ApiKey if value.is_empty() => self.error = Some("An API key is required".to_string()),
+            WizardStep::ApiKey => {
+                self.api_key = value;
+                self.go_to(WizardStep::Model);
+            }
+            // 設定ファイルはどこから起動しても読まれるので、絶対パスにして保存する
+            WizardStep::MockScript => match resolve_user_path(&value, &self.current_dir, dirs::home_dir().as_deref()).canonicalize() {
+                Ok(path) if path.is_file() => {
+                    self.mock_script = strip_verbatim_prefix(&path.to_string_lossy());
+                    self.go_to(WizardStep::Model);
+                }
+                _ => self.error = Some(format!("Not a file: {}", value)),
+            },
+            WizardStep::Model => {
+                self.model = if value.is_empty() { DEFAULT_MODEL.to_string() } else { value };
+                self.go_to(WizardStep::AllowDirectory);
+            }
+            _ => {}
+        }
+        WizardAction::None
+    }
+
+    /// 最後の質問に答えたら、GeminiならAPIキーを確認してから保存する
+    fn finish_questions(&mut self, allow_directory: bool) -> WizardAction {
+        self.allow_directory = allow_directory;
+        match self.provider {
+            WizardProvider::Gemini => {
+                self.step = WizardStep::Validating;
+                WizardAction::Validate
+            }
+            WizardProvider::Mock => WizardAction::Save,
+        }
+    }
+
+    /// APIキーの確認結果を反映する
+    pub fn apply_validation(&mut self, result: KeyValidation) -> WizardAction {
+        match result {
+            KeyValidation::Valid => WizardAction::Save,
+            KeyValidation::Rejected(reason) => {
+                self.error = Some(format!("{} — check the key and model", reason));
+                self.go_to(WizardStep::ApiKey);
+                WizardAction::None
+            }
+            KeyValidation::Unreachable(reason) => {
+                self.error = Some(format!("Could not reach the API: {}", reason));
+                self.step = WizardStep::SaveOffline;
+                WizardAction::None
+            }
+        }
+    }
+
+    /// 設定ファイルの内容（.envと同じ形式）
+    pub fn config_contents(&self) -> String {
+        let mut contents = String::from("# Created by the contui first-run setup\n");
+        match self.provider {
+            WizardProvider::Gemini => {
+                contents.push_str("LLM_PROVIDER=gemini\n");
+                contents.push_str(&format!("GEMINI_API_KEY={}\n", env_value(&self.api_key)));
+            }
+            WizardProvider::Mock => {
+                contents.push_str("LLM_PROVIDER=mock\n");
+                contents.push_str(&format!("MOCK_SCRIPT={}\n", env_value(&self.mock_script)));
+            }
+        }
+        contents.push_str(&format!("MODEL={}\n", env_value(&self.model)));
+        contents
+    }
+
+    /// 設定ファイルを書き込み、許可すると答えていればカレントディレクトリを常に許可する
+    /// 一時ファイルに書いてから置き換えるので、途中で失敗しても書きかけのファイルは残らない
+    pub fn save(&self, config_path: &Path) -> Result<()> {
+        if let Some(parent) = config_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_path = config_path.with_extension("env.tmp");
+        fs::write(&temp_path, self.config_contents())?;
+        // APIキーを含むので本人だけが読めるようにする
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o600))?;
+        }
+        if let Err(e) = fs::rename(&temp_path, config_path) {
+            fs::remove_file(&temp_path).ok();
+            return Err(e.into());
+        }
+        if self.allow_directory {
+            let allowed_file = std::env::var("ALLOWED_DIRECTORIES_FILE")
+                .ok()
+                .map(PathBuf::from)
+                .or_else(default_allowed_directories_path)
+                .ok_or_else(|| anyhow!("No config directory for allowed_directories"))?;
+            append_allowed_directory(&allowed_file, &self.current_dir)?;
+        }
+        Ok(())
+    }
+
+    pub fn render(&self, f: &mut Frame, config_path: &Path) {
+        let (number, total) = match self.step {
+            WizardStep::Provider => (1, 4),
+            WizardStep::ApiKey | WizardStep::MockScript => (2, 4),
+            WizardStep::Model => (3, 4),
+            _ => (4, 4),
+        };
+        let mut lines = vec![
+            Line::from(Span::styled(format!("Welcome to contui! Step {}/{}", number, total), Style::default().fg(Color::Cyan))),
+            Line::from(""),
+        ];
+        match self.step {
+            WizardStep::Provider => {
+                lines.push(Line::from("Which LLM provider do you want to use?"));
+                for provider in WizardProvider::ALL {
+                    let (marker, style) = if provider == self.provider {
+                        ("> ", Style::default().fg(Color::Yellow))
+                    } else {
+                        ("  ", Style::default())
+                    };
+                    lines.push(Line::from(Span::styled(format!("{}{}", marker, provider.label()), style)));
+                }
+            }
+            WizardStep::ApiKey => {
+                lines.push(Line::from("Gemini API key (https://aistudio.google.com/apikey):"));
+                lines.push(Line::from(format!("> {}", "*".repeat(self.input.chars().count()))));
+            }
+            WizardStep::MockScript => {
+                lines.push(Line::from("Path to the mock script (JSON):"));
+                lines.push(Line::from(format!("> {}", self.input)));
+            }
+            WizardStep::Model => {
+                lines.push(Line::from(format!("Model (Enter for {}):", DEFAULT_MODEL)));
+                lines.push(Line::from(format!("> {}", self.input)));
+            }
+            WizardStep::AllowDirectory => {
+                lines.push(Line::from("Always allow file access to the current directory?"));
+                lines.push(Line::from(Span::styled(self.current_dir.display().to_string(), Style::default().fg(Color::Yellow))));
+                lines.push(Line::from("  y - Yes (saved to allowed_directories)   n - Only for each session"));
+            }
+            WizardStep::Validating => lines.push(Line::from("Checking the API key…")),
+            WizardStep::SaveOffline => lines.push(Line::from("Save the configuration without checking the key? (y/n)")),
+        }
+        if let Some(error) = &self.error {
+            lines.push(Line::from(""));
+            lines.push(Line::from(Span::styled(error.clone(), Style::default().fg(Color::Red))));
+        }
+        lines.push(Line::from(""));
+        lines.push(Line::from(Span::styled(
+            format!("Enter: next   Esc/Ctrl+C: quit without saving   Config: {}", config_path.display()),
+            Style::default().fg(Color::DarkGray),
+        )));
+
+        let area = f.area();
+        let width = area.width.min(80);
+        let height = (lines.len() as u16 + 4).min(area.height);
+        let popup_area = Rect { x: (area.width - width) / 2, y: (area.height - height) / 2, width, height };
+        f.render_widget(
+            Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+                Block::default()
+                    .title("contui setup")
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(Color::Cyan)),
+            ),
+            popup_area,
+        );
+    }
+}
+
+/// .envの値として書く（空白や#を含むときはダブルクォートで囲む）
+fn env_value(value: &str) -> String {
+    if value.chars().any(|c| c.is_whitespace() || matches!(c, '#' | '"' | '\'' | '\\')) {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// セットアップを実行して設定ファイルを書く（中断したらfalseを返し、何も書き込まない）
+pub async fn run_setup_wizard(terminal: &mut Terminal<CrosstermBackend<Stdout>>, config_path: &Path) -> Result<bool> {
+    let mut wizard = SetupWizard::new(std::env::current_dir()?);
+    loop {
+        terminal.draw(|f| wizard.render(f, config_path))?;
+        if !event::poll(Duration::from_millis(100))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let mut action = wizard.handle_key(key);
+        if action == WizardAction::Validate {
+            terminal.draw(|f| wizard.render(f, config_path))?;
+            action = wizard.apply_validation(validate_api_key(&wizard.api_key, &wizard.model).await);
+        }
+        match action {
+            WizardAction::Cancel => return Ok(false),
+            WizardAction::Save => {
+                wizard.save(config_path)?;
+                return Ok(true);
+            }
+            WizardAction::None | WizardAction::Validate => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(wizard: &mut SetupWizard, code: KeyCode) -> WizardAction {
+        wizard.handle_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn type_text(wizard: &mut SetupWizard, text: &str) {
+        for c in text.chars() {
+            press(wizard, KeyCode::Char(c));
+        }
+    }
+
+    #[test]
+    fn test_setup_wizard_flow() {
+        let dir = std::env::temp_dir().join(format!("contui_setup_{}", uuid::Uuid::new_v4()));
+        let mut wizard = SetupWizard::new(dir.clone());
+        press(&mut wizard, KeyCode::Enter);
+        assert_eq!(wizard.step, WizardStep::ApiKey);
+        // 空のキーは受け付けない
+        press(&mut wizard, KeyCode::Enter);
+        assert_eq!((wizard.step, wizard.error.is_some()), (WizardStep::ApiKey, true));
+        type_text(&mut wizard, "secret key");
+        press(&mut wizard, KeyCode::Enter);
+        press(&mut wizard, KeyCode::Enter);
+        assert_eq!(wizard.model, DEFAULT_MODEL);
+        assert_eq!(press(&mut wizard, KeyCode::Char('n')), WizardAction::Validate);
+
+        // キーが拒否されたら入力し直す。接続できなければ確認せずに保存するか尋ねる
+        assert_eq!(wizard.apply_validation(KeyValidation::Rejected("HTTP 400".to_string())), WizardAction::None);
+        assert_eq!(wizard.step, WizardStep::ApiKey);
+        press(&mut wizard, KeyCode::Enter);
+        press(&mut wizard, KeyCode::Enter);
+        press(&mut wizard, KeyCode::Enter);
+        wizard.apply_validation(KeyValidation::Unreachable("offline".to_string()));
+        assert_eq!(wizard.step, WizardStep::SaveOffline);
+        assert_eq!(press(&mut wizard, KeyCode::Char('y')), WizardAction::Save);
+        assert_eq!(
+            wizard.config_contents(),
+            format!("# Created by the contui first-run setup\nLLM_PROVIDER=gemini\nGEMINI_API_KEY=\"secret key\"\nMODEL={}\n", DEFAULT_MODEL)
+        );
+
+        let config_path = dir.join("config.env");
+        wizard.allow_directory = false;
+        wizard.save(&config_path).unwrap();
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), wizard.config_contents());
+        assert!(!config_path.with_extension("env.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Ctrl+Cはどの画面でも中断
+        let mut wizard = SetupWizard::new(dir);
+        assert_eq!(wizard.handle_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)), WizardAction::Cancel);
+        assert_eq!(press(&mut wizard, KeyCode::Esc), WizardAction::Cancel);
+    }
+
+    #[test]
+    fn test_mock_script_path_is_saved_absolute() {
+        let dir = crate::test_common::TempDir::new("setup_mock");
+        fs::write(dir.join("script.json"), "[]").unwrap();
+        let mut wizard = SetupWizard::new(dir.to_path_buf());
+        press(&mut wizard, KeyCode::Down);
+        press(&mut wizard, KeyCode::Enter);
+        assert_eq!(wizard.step, WizardStep::MockScript);
+        type_text(&mut wizard, "missing.json");
+        press(&mut wizard, KeyCode::Enter);
+        assert_eq!((wizard.step, wizard.error.is_some()), (WizardStep::MockScript, true));
+
+        // 起動したディレクトリからの相対パスは絶対パスにして保存する
+        wizard.input = "script.json".to_string();
+        press(&mut wizard, KeyCode::Enter);
+        assert_eq!(wizard.step, WizardStep::Model);
+        let expected = strip_verbatim_prefix(&dir.join("script.json").canonicalize().unwrap().to_string_lossy());
+        assert_eq!(wizard.mock_script, expected);
+        assert!(wizard.config_contents().contains(&format!("MOCK_SCRIPT={}\n", env_value(&expected))));
+    }
+}
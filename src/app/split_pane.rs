@@ -0,0 +1,298 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, BorderType, Borders, Paragraph, Tabs};
+use ratatui::Frame;
+
+use crate::app::ChatApp;
+use crate::gemini::Part;
+
+/// これより狭い端末では分割せず、チャットだけを表示する
+pub const MIN_SPLIT_WIDTH: u16 = 100;
+/// 右ペインの幅（%）
+const CONTEXT_PANE_PERCENT: u16 = 40;
+
+/// 右ペインに表示する内容
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContextView {
+    /// 選択中のファイルとサイズ
+    #[default]
+    Files,
+    /// このセッションでAIが変更したファイル
+    Changes,
+    /// 最後に実行したコマンドの出力
+    CommandOutput,
+}
+
+impl ContextView {
+    const ALL: [ContextView; 3] = [ContextView::Files, ContextView::Changes, ContextView::CommandOutput];
+
+    fn title(&self) -> &'static str {
+        match self {
+            ContextView::Files => "Files",
+            ContextView::Changes => "Changes",
+            ContextView::CommandOutput => "Output",
+        }
+    }
+}
+
+/// チャットの右に作業中のコンテキストを並べる分割表示の状態
+#[derive(Debug, Default)]
+pub struct SplitPane {
+    /// Ctrl+Pで切り替えた表示状態（Noneなら SPLIT_PANE_MIN_WIDTH に従う）
+    pub visible: Option<bool>,
+    /// 右ペインがキー入力を受け取っているか
+    pub focused: bool,
+    pub view: ContextView,
+    /// 右ペインで選択中の行
+    pub selected: usize,
+    /// 最後に描画した画面の幅（キー入力時に分割表示中かを判断する）
+    width: u16,
+}
+
+impl ChatApp {
+    /// 幅widthの画面で分割表示するか（狭すぎる端末では表示しない）
+    pub fn split_pane_active(&self, width: u16) -> bool {
+        let auto = self.config.split_pane_min_width.is_some_and(|min_width| width >= min_width);
+        width >= MIN_SPLIT_WIDTH && self.split_pane.visible.unwrap_or(auto)
+    }
+
+    /// Ctrl+P: 分割表示 → 右ペインにフォーカス → 分割をやめる、の順に切り替える
+    pub fn toggle_split_pane(&mut self) {
+        if !self.split_pane_active(self.split_pane.width) {
+            self.split_pane.visible = Some(true);
+            self.split_pane.focused = false;
+            if self.split_pane.width < MIN_SPLIT_WIDTH {
+                self.notify(format!("The terminal is too narrow for the split pane (needs {} columns)", MIN_SPLIT_WIDTH));
+            }
+        } else if !self.split_pane.focused {
+            self.split_pane.focused = true;
+            self.split_pane.selected = 0;
+            if !self.context_view_available(self.split_pane.view) {
+                self.cycle_context_view(1);
+            }
+        } else {
+            self.split_pane.visible = Some(false);
+            self.split_pane.focused = false;
+        }
+    }
+
+    fn context_view_available(&self, view: ContextView) -> bool {
+        match view {
+            ContextView::Files => !self.ui.selected_files.is_empty(),
+            ContextView::Changes => !self.file_changes.is_empty(),
+            ContextView::CommandOutput => self.last_command_output().is_some(),
+        }
+    }
+
+    /// Tab / Shift+Tab: 内容のあるビューを順に切り替える（どれもなければそのまま）
+    pub fn cycle_context_view(&mut self, offset: isize) {
+        let current = ContextView::ALL.iter().position(|view| *view == self.split_pane.view).unwrap_or(0) as isize;
+        let count = ContextView::ALL.len() as isize;
+        for step in 1..=count {
+            let view = ContextView::ALL[(current + offset * step).rem_euclid(count) as usize];
+            if self.context_view_available(view) {
+                self.split_pane.view = view;
+                self.split_pane.selected = 0;
+                return;
+            }
+        }
+    }
+
+    /// 最後に実行したコマンド（execute_command）の引数と出力
+    fn last_command_output(&self) -> Option<(&str, &str)> {
+        self.messages.iter().rev().flat_map(|msg| msg.parts.iter().rev()).find_map(|part| match part {
+            Part::ToolOutcome { tool_outcome } if tool_outcome.tool == "execute_command" => {
+                Some((tool_outcome.args_summary.as_str(), tool_outcome.detail.as_str()))
+            }
+            _ => None,
+        })
+    }
+
+    /// 右ペインの行（選択の対象）
+    fn context_lines(&self) -> Vec<String> {
+        match self.split_pane.view {
+            ContextView::Files => self
+                .ui
+                .selected_files
+                .iter()
+                .map(|path| match std::fs::metadata(path) {
+                    Ok(metadata) => format!("{} ({})", path, format_size(metadata.len())),
+                    Err(_) => format!("{} (missing)", path),
+                })
+                .collect(),
+            ContextView::Changes => self
+                .file_changes
+                .iter()
+                .rev()
+                .map(|change| format!("{} {:?} {} ({} bytes)", change.timestamp.format("%H:%M"), change.operation, change.path, change.bytes))
+                .collect(),
+            ContextView::CommandOutput => match self.last_command_output() {
+                Some((command, output)) => std::iter::once(format!("$ {}", command)).chain(output.lines().map(str::to_string)).collect(),
+                None => Vec::new(),
+            },
+        }
+    }
+
+    /// 右ペインにフォーカスがあればキーを処理する（処理したらtrue）。Ctrl+Pはどの画面でも使える
+    pub fn handle_split_pane_key(&mut self, key_event: &KeyEvent) -> bool {
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) && key_event.code == KeyCode::Char('p') {
+            self.toggle_split_pane();
+            return true;
+        }
+        if !self.split_pane.focused {
+            return false;
+        }
+        let len = self.context_lines().len();
+        match key_event.code {
+            KeyCode::Esc => self.split_pane.focused = false,
+            KeyCode::Char('j') | KeyCode::Down => self.split_pane.selected = (self.split_pane.selected + 1).min(len.saturating_sub(1)),
+            KeyCode::Char('k') | KeyCode::Up => self.split_pane.selected = self.split_pane.selected.saturating_sub(1),
+            KeyCode::Tab => self.cycle_context_view(1),
+            KeyCode::BackTab => self.cycle_context_view(-1),
+            KeyCode::Enter | KeyCode::Char('d') => self.activate_context_item(),
+            _ => {}
+        }
+        true
+    }
+
+    /// Enter: 選択中のファイルを外す（Files）、変更したファイルのgit diffを開く（Changes）
+    fn activate_context_item(&mut self) {
+        let selected = self.split_pane.selected;
+        match self.split_pane.view {
+            ContextView::Files if selected < self.ui.selected_files.len() => {
+                let removed = self.ui.selected_files.remove(selected);
+                self.split_pane.selected = selected.min(self.ui.selected_files.len().saturating_sub(1));
+                self.notify(format!("Removed {} from the selected files", removed));
+            }
+            ContextView::Changes => {
+                let Some(path) = self.file_changes.iter().rev().nth(selected).map(|change| change.path.clone()) else {
+                    return;
+                };
+                self.split_pane.focused = false;
+                if let Err(crate::app::commands::CommandError::Failed(e)) = crate::app::commands::show_git_diff(self, vec![path]) {
+                    self.notify(format!("❌ {}", e));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 分割表示ならareaを左右に分けて右ペインを描画し、チャットを描画する左側を返す
+    pub fn split_chat_area(&mut self, f: &mut Frame, area: Rect) -> Rect {
+        self.split_pane.width = area.width;
+        if !self.split_pane_active(area.width) {
+            self.split_pane.focused = false;
+            return area;
+        }
+        let chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(100 - CONTEXT_PANE_PERCENT), Constraint::Percentage(CONTEXT_PANE_PERCENT)])
+            .split(area);
+        self.render_context_pane(f, chunks[1]);
+        chunks[0]
+    }
+
+    fn render_context_pane(&self, f: &mut Frame, area: Rect) {
+        let border_style = if self.split_pane.focused { Style::default().fg(Color::Cyan) } else { Style::default().fg(Color::DarkGray) };
+        let hint = if self.split_pane.focused { " j/k: move · Tab: view · Enter: action · Esc: back " } else { " Ctrl+P: focus " };
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(border_style)
+            .title(" Context ")
+            .title_bottom(Line::from(hint).right_aligned());
+        let inner = block.inner(area);
+        f.render_widget(block, area);
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(1), Constraint::Min(0)])
+            .split(inner);
+        let titles: Vec<Line> = ContextView::ALL
+            .iter()
+            .map(|view| {
+                let style = if self.context_view_available(*view) { Style::default() } else { Style::default().fg(Color::DarkGray) };
+                Line::from(Span::styled(view.title(), style))
+            })
+            .collect();
+        let selected_tab = ContextView::ALL.iter().position(|view| *view == self.split_pane.view).unwrap_or(0);
+        let tabs = Tabs::new(titles).select(selected_tab).highlight_style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD));
+        f.render_widget(tabs, chunks[0]);
+
+        let lines = self.context_lines();
+        let body = chunks[1];
+        if lines.is_empty() {
+            let empty = match self.split_pane.view {
+                ContextView::Files => "No files selected",
+                ContextView::Changes => "No files changed in this session",
+                ContextView::CommandOutput => "No commands run yet",
+            };
+            f.render_widget(Paragraph::new(Span::styled(empty, Style::default().fg(Color::DarkGray))), body);
+            return;
+        }
+        // 選択中の行が見えるようにスクロールする
+        let height = body.height as usize;
+        let offset = self.split_pane.selected.saturating_sub(height.saturating_sub(1));
+        let text: Vec<Line> = lines
+            .into_iter()
+            .enumerate()
+            .skip(offset)
+            .take(height)
+            .map(|(index, line)| {
+                if self.split_pane.focused && index == self.split_pane.selected {
+                    Line::from(Span::styled(line, Style::default().fg(Color::Black).bg(Color::Cyan)))
+                } else {
+                    Line::from(line)
+                }
+            })
+            .collect();
+        f.render_widget(Paragraph::new(text), body);
+    }
+}
+
+/// 1536 → 1.5 KB
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_pane_toggle_focus_and_file_removal() {
+        let (dir, mut app) = crate::app::file_operations::tests::test_app("split", false);
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        let ctrl_p = KeyEvent::new(KeyCode::Char('p'), KeyModifiers::CONTROL);
+        app.split_pane.width = 160;
+        assert!(!app.split_pane_active(160));
+        // 狭い端末では分割しない
+        assert!(!app.handle_split_pane_key(&key(KeyCode::Char('j'))));
+
+        std::fs::write(dir.join("a.rs"), "fn a() {}\n").unwrap();
+        app.ui.selected_files = vec![dir.join("a.rs").display().to_string(), dir.join("gone.rs").display().to_string()];
+        assert!(app.handle_split_pane_key(&ctrl_p));
+        assert!(app.split_pane_active(160) && !app.split_pane_active(MIN_SPLIT_WIDTH - 1));
+        assert!(!app.split_pane.focused);
+        app.handle_split_pane_key(&ctrl_p);
+        assert!(app.split_pane.focused);
+        assert_eq!(app.context_lines(), vec![format!("{} (10 B)", app.ui.selected_files[0]), format!("{} (missing)", app.ui.selected_files[1])]);
+
+        // 右ペインにフォーカスがある間はキーをチャットに渡さない
+        app.handle_split_pane_key(&key(KeyCode::Char('j')));
+        app.handle_split_pane_key(&key(KeyCode::Enter));
+        assert_eq!(app.ui.selected_files, vec![dir.join("a.rs").display().to_string()]);
+        // 内容のないビューには切り替えない
+        app.handle_split_pane_key(&key(KeyCode::Tab));
+        assert_eq!(app.split_pane.view, ContextView::Files);
+
+        app.handle_split_pane_key(&ctrl_p);
+        assert!(!app.split_pane_active(160) && !app.split_pane.focused);
+    }
+}
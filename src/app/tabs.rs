@@ -0,0 +1,294 @@
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::app::task_queue::{OutboundRequest, TaskQueue};
+use crate::app::{ChatApp, UiState};
+use crate::bookmarks::Bookmarks;
+use crate::debug_log;
+use crate::history::{ChatMessage, FileChange};
+
+/// タブバーに表示するセッション名の最大文字数
+const TAB_TITLE_MAX_CHARS: usize = 20;
+
+/// 裏にあるタブの状態（表示中のタブの状態はChatAppの同名のフィールドにあり、切り替えるときに入れ替える）
+pub struct TabState {
+    /// タブで開いているセッション
+    pub session_id: Uuid,
+    pub ui: UiState,
+    pub messages: Vec<ChatMessage>,
+    pub file_changes: Vec<FileChange>,
+    pub llm_task_handle: Option<JoinHandle<()>>,
+    pub task_queue: TaskQueue,
+    pub task_session: Option<Uuid>,
+    pub last_request: Option<OutboundRequest>,
+    pub pending_retry: Option<OutboundRequest>,
+    pub last_user_message_id: Option<Uuid>,
+    pub file_creation_requested: bool,
+}
+
+impl TabState {
+    fn new(session_id: Uuid, current_directory: String) -> Self {
+        Self {
+            session_id,
+            ui: UiState::new(current_directory, Bookmarks::new()),
+            messages: Vec::new(),
+            file_changes: Vec::new(),
+            llm_task_handle: None,
+            task_queue: TaskQueue::new(),
+            task_session: None,
+            last_request: None,
+            pending_retry: None,
+            last_user_message_id: None,
+            file_creation_requested: false,
+        }
+    }
+
+    /// 応答待ち（またはキューの処理中）か
+    pub fn is_busy(&self) -> bool {
+        self.llm_task_handle.is_some() || self.task_queue.is_processing()
+    }
+
+    /// このタブ宛てのイベントか（開いているセッション、または処理中のタスクを送ったセッション）
+    fn owns(&self, session_id: Uuid) -> bool {
+        self.session_id == session_id || self.task_session == Some(session_id)
+    }
+}
+
+/// タブバーに表示する1つのタブ
+#[derive(Debug, Clone, PartialEq)]
+pub struct TabLabel {
+    pub title: String,
+    pub active: bool,
+    pub busy: bool,
+}
+
+impl ChatApp {
+    /// 表示中のタブが応答待ち（またはキューの処理中）か
+    pub fn is_busy(&self) -> bool {
+        self.llm_task_handle.is_some() || self.task_queue.is_processing()
+    }
+
+    pub(crate) fn current_session_id(&self) -> Option<Uuid> {
+        self.history_manager.lock().unwrap().get_history().current_session_id
+    }
+
+    /// session_idのセッション宛てのイベントを処理する裏のタブ（表示中のタブ宛て、またはどのタブのものでもなければNone）
+    pub fn background_tab_for(&self, session_id: Uuid) -> Option<usize> {
+        if self.task_session == Some(session_id) || self.current_session_id() == Some(session_id) {
+            return None;
+        }
+        self.tabs.iter().position(|tab| tab.as_ref().is_some_and(|tab| tab.owns(session_id)))
+    }
+
+    /// session_idのセッションを開いているタブの位置
+    pub fn tab_index_of(&self, session_id: Uuid) -> Option<usize> {
+        if self.current_session_id() == Some(session_id) {
+            return Some(self.active_tab);
+        }
+        self.tabs.iter().position(|tab| tab.as_ref().is_some_and(|tab| tab.session_id == session_id))
+    }
+
+    /// 表示中のタブの状態とtabを入れ替える（ブックマークと入力履歴はタブ間で共有する）
+    fn swap_tab_state(&mut self, tab: &mut TabState) {
+        std::mem::swap(&mut self.ui, &mut tab.ui);
+        std::mem::swap(&mut self.ui.bookmarks, &mut tab.ui.bookmarks);
+        std::mem::swap(&mut self.ui.input_history, &mut tab.ui.input_history);
+        std::mem::swap(&mut self.messages, &mut tab.messages);
+        std::mem::swap(&mut self.file_changes, &mut tab.file_changes);
+        std::mem::swap(&mut self.llm_task_handle, &mut tab.llm_task_handle);
+        std::mem::swap(&mut self.task_queue, &mut tab.task_queue);
+        std::mem::swap(&mut self.task_session, &mut tab.task_session);
+        std::mem::swap(&mut self.last_request, &mut tab.last_request);
+        std::mem::swap(&mut self.pending_retry, &mut tab.pending_retry);
+        std::mem::swap(&mut self.last_user_message_id, &mut tab.last_user_message_id);
+        std::mem::swap(&mut self.file_creation_requested, &mut tab.file_creation_requested);
+
+        let mut history_guard = self.history_manager.lock().unwrap();
+        let history = history_guard.get_history_mut();
+        let previous = history.current_session_id;
+        if let Err(e) = history.switch_session(tab.session_id) {
+            debug_log!("[swap_tab_state] switch_session {}: {:?}", tab.session_id, e);
+        }
+        if let Some(previous) = previous {
+            tab.session_id = previous;
+        }
+    }
+
+    /// indexのタブを表示する
+    pub fn activate_tab(&mut self, index: usize) {
+        if index == self.active_tab {
+            return;
+        }
+        let Some(mut tab) = self.tabs.get_mut(index).and_then(Option::take) else {
+            return;
+        };
+        self.swap_tab_state(&mut tab);
+        self.tabs[self.active_tab] = Some(tab);
+        self.active_tab = index;
+    }
+
+    /// 一時的にindexのタブに切り替えてfを実行し、元のタブに戻す
+    pub fn with_tab<R>(&mut self, index: usize, f: impl FnOnce(&mut Self) -> R) -> R {
+        let active = self.active_tab;
+        self.activate_tab(index);
+        let result = f(self);
+        self.activate_tab(active);
+        result
+    }
+
+    /// Ctrl+T: session_idのセッション（Noneなら新しいセッション）を新しいタブで開く。既にタブで開いていればそのタブに切り替える
+    pub fn open_tab(&mut self, session_id: Option<Uuid>) {
+        if let Some(index) = session_id.and_then(|session_id| self.tab_index_of(session_id)) {
+            self.activate_tab(index);
+            return;
+        }
+        let session_id = {
+            let mut history_guard = self.history_manager.lock().unwrap();
+            let history = history_guard.get_history_mut();
+            let previous = history.current_session_id;
+            let session_id = session_id.unwrap_or_else(|| history.new_session(None));
+            // new_sessionで変わった現在のセッションは、タブを切り替えるときに入れ替える
+            history.current_session_id = previous;
+            if let Err(e) = history_guard.save() {
+                debug_log!("[open_tab] save_history error: {:?}", e);
+            }
+            session_id
+        };
+        self.tabs.push(Some(TabState::new(session_id, self.ui.current_directory.clone())));
+        self.activate_tab(self.tabs.len() - 1);
+        self.restore_session_messages();
+        self.scroll_to_bottom(20);
+    }
+
+    /// Ctrl+Tab / Ctrl+Shift+Tab: offsetだけ隣のタブに切り替える（端では反対側に回る）
+    pub fn cycle_tab(&mut self, offset: isize) {
+        let count = self.tabs.len() as isize;
+        self.activate_tab((self.active_tab as isize + offset).rem_euclid(count) as usize);
+    }
+
+    /// Ctrl+W: 表示中のタブを閉じる。応答待ちなら確認してから閉じる
+    pub fn request_close_tab(&mut self) {
+        if self.tabs.len() <= 1 {
+            self.notify("This is the only tab".to_string());
+        } else if self.is_busy() {
+            self.ui.tab_close_prompt = Some("A request is in progress in this tab".to_string());
+        } else {
+            self.close_tab();
+        }
+    }
+
+    /// 表示中のタブを閉じて隣のタブを表示する（応答待ちのタスクは止める。セッションは履歴に残る）
+    pub fn close_tab(&mut self) {
+        if self.tabs.len() <= 1 {
+            return;
+        }
+        let closing = self.active_tab;
+        self.activate_tab(if closing == 0 { 1 } else { closing - 1 });
+        if let Some(tab) = self.tabs.remove(closing) {
+            if let Some(handle) = tab.llm_task_handle {
+                handle.abort();
+            }
+        }
+        if self.active_tab > closing {
+            self.active_tab -= 1;
+        }
+    }
+
+    /// 裏のタブで応答待ちのものがあればその位置
+    pub fn busy_background_tab(&self) -> Option<usize> {
+        self.tabs.iter().position(|tab| tab.as_ref().is_some_and(TabState::is_busy))
+    }
+
+    /// 終了時に裏のタブのLLMタスクを止める
+    pub fn abort_background_tabs(&mut self) {
+        for tab in self.tabs.iter_mut().flatten() {
+            if let Some(handle) = tab.llm_task_handle.take() {
+                handle.abort();
+            }
+        }
+    }
+
+    /// タブバーに表示するタブ（セッション名と応答待ちか）
+    pub fn tab_labels(&self) -> Vec<TabLabel> {
+        let history_guard = self.history_manager.lock().unwrap();
+        let history = history_guard.get_history();
+        self.tabs
+            .iter()
+            .enumerate()
+            .map(|(index, tab)| {
+                let (session_id, busy) = match tab {
+                    Some(tab) => (Some(tab.session_id), tab.is_busy()),
+                    None => (history.current_session_id, self.is_busy()),
+                };
+                let title = session_id
+                    .and_then(|session_id| history.sessions.get(&session_id))
+                    .map_or_else(|| "(no session)".to_string(), |session| Self::truncate_string_safe(&session.title, TAB_TITLE_MAX_CHARS));
+                TabLabel { title, active: index == self.active_tab, busy }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::ChatEvent;
+    use crate::gemini::{Part, ResponsePart};
+
+    #[test]
+    fn test_tabs_keep_state_and_route_events() {
+        let (_dir, mut app) = crate::app::file_operations::tests::test_app("tabs", false);
+        let first = app.history_manager.lock().unwrap().ensure_active_session();
+        app.ui.input = "draft in the first tab".to_string();
+
+        app.open_tab(None);
+        let second = app.current_session_id().unwrap();
+        assert_ne!(second, first);
+        assert_eq!((app.tabs.len(), app.active_tab), (2, 1));
+        assert!(app.ui.input.is_empty());
+
+        // 裏のタブのセッションへの応答は、そのタブのメッセージに追加する
+        let shown = app.messages.len();
+        app.handle_chat_event(ChatEvent::AIResponseFor { session_id: first, part: ResponsePart::Text { text: "answer".to_string() } });
+        assert_eq!(app.messages.len(), shown);
+        assert_eq!(app.current_session_id(), Some(second));
+        assert!(app.unread_responses.is_empty());
+
+        app.cycle_tab(1);
+        assert_eq!(app.current_session_id(), Some(first));
+        assert_eq!(app.ui.input, "draft in the first tab");
+        assert!(matches!(&app.messages.last().unwrap().parts[..], [Part::Text { text }] if text == "answer"));
+        // 既に開いているセッションはそのタブに切り替える
+        app.open_tab(Some(second));
+        assert_eq!((app.tabs.len(), app.active_tab), (2, 1));
+
+        app.close_tab();
+        assert_eq!((app.tabs.len(), app.active_tab), (1, 0));
+        assert_eq!(app.current_session_id(), Some(first));
+    }
+
+    #[tokio::test]
+    async fn test_usage_is_recorded_in_the_requesting_session_after_switching_tabs() {
+        let (_dir, mut app) = crate::app::file_operations::tests::test_app("tabs_usage", false);
+        let script = crate::mock_llm::MockScript::parse(r#"[{"expect_substring": "question", "respond_text": "answer\nis_finished: true"}]"#).unwrap();
+        app.gemini_client = app.gemini_client.clone().with_mock_script(script);
+        let first = app.history_manager.lock().unwrap().ensure_active_session();
+        app.task_queue.enqueue(crate::app::task_queue::UserTask { message: "question".to_string(), files: Vec::new(), session_id: first });
+        assert!(app.process_next_task());
+
+        // 応答が届く前に別のタブに切り替える
+        app.open_tab(None);
+        let second = app.current_session_id().unwrap();
+        loop {
+            let event = tokio::time::timeout(std::time::Duration::from_secs(10), app.event_receiver.recv()).await.unwrap().unwrap();
+            let finished = matches!(&event, ChatEvent::Session { event, .. } if matches!(**event, ChatEvent::TaskFinished));
+            app.handle_chat_event(event);
+            if finished {
+                break;
+            }
+        }
+        let history_guard = app.history_manager.lock().unwrap();
+        let sessions = &history_guard.get_history().sessions;
+        assert_eq!(sessions[&first].usage.models["gemini-test"].requests, 1);
+        assert!(sessions[&second].usage.models.is_empty());
+    }
+}
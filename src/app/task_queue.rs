@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+
+use uuid::Uuid;
+
+use crate::file_access::FileReference;
+use crate::gemini::{Content, EffectiveSettings};
+
+/// ユーザーが送信したメッセージ1件分のタスク
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserTask {
+    pub message: String,
+    pub files: Vec<FileReference>,
+    /// 送り先のセッション（キューに積んだときに表示していたセッション）
+    pub session_id: Uuid,
+}
+
+/// LLMに送ったリクエストの内容（失敗したときに同じ内容で再送する）
+#[derive(Debug, Clone)]
+pub struct OutboundRequest {
+    /// リクエストを送ったセッション（応答はこのセッションに追加する）
+    pub session_id: Uuid,
+    pub task: UserTask,
+    /// 送信時点の会話コンテキスト
+    pub context: Vec<Content>,
+    pub settings: EffectiveSettings,
+}
+
+/// LLMへの送信処理の状態
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProcessingState {
+    Idle,
+    Processing,
+}
+
+/// 応答待ちの間に送信されたメッセージを順番に処理するキュー
+/// Idle → Processing → Idle の順に遷移し、Idleのときだけ次のタスクを取り出す
+#[derive(Debug)]
+pub struct TaskQueue {
+    tasks: VecDeque<UserTask>,
+    state: ProcessingState,
+}
+
+impl Default for TaskQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TaskQueue {
+    pub fn new() -> Self {
+        Self {
+            tasks: VecDeque::new(),
+            state: ProcessingState::Idle,
+        }
+    }
+
+    pub fn enqueue(&mut self, task: UserTask) {
+        self.tasks.push_back(task);
+    }
+
+    /// Idleなら次のタスクを取り出してProcessingに遷移する
+    pub fn next_task(&mut self) -> Option<UserTask> {
+        if self.state == ProcessingState::Processing {
+            return None;
+        }
+        let task = self.tasks.pop_front()?;
+        self.state = ProcessingState::Processing;
+        Some(task)
+    }
+
+    /// キューを経由せずに処理を始めた（失敗したリクエストの再送など）
+    pub fn mark_processing(&mut self) {
+        self.state = ProcessingState::Processing;
+    }
+
+    /// 処理中のタスクが終わったのでIdleに戻す
+    pub fn finish(&mut self) {
+        self.state = ProcessingState::Idle;
+    }
+
+    pub fn state(&self) -> ProcessingState {
+        self.state
+    }
+
+    pub fn is_processing(&self) -> bool {
+        self.state == ProcessingState::Processing
+    }
+
+    /// 処理待ちのタスク数（処理中のものは含まない）
+    pub fn pending(&self) -> usize {
+        self.tasks.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(message: &str) -> UserTask {
+        UserTask {
+            message: message.to_string(),
+            files: Vec::new(),
+            session_id: Uuid::nil(),
+        }
+    }
+
+    #[test]
+    fn test_tasks_are_processed_one_at_a_time_in_order() {
+        let mut queue = TaskQueue::new();
+        queue.enqueue(task("first"));
+        queue.enqueue(task("second"));
+
+        assert_eq!(queue.next_task(), Some(task("first")));
+        assert_eq!(queue.state(), ProcessingState::Processing);
+        assert_eq!(queue.next_task(), None);
+        assert_eq!(queue.pending(), 1);
+
+        queue.finish();
+        assert_eq!(queue.next_task(), Some(task("second")));
+        queue.finish();
+        assert_eq!(queue.next_task(), None);
+        assert_eq!(queue.state(), ProcessingState::Idle);
+    }
+}
@@ -1,7 +1,10 @@
 use crossterm::{
     execute,
-    terminal::{enable_raw_mode, disable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
-    event::{EnableMouseCapture, DisableMouseCapture},
+    terminal::{enable_raw_mode, disable_raw_mode, supports_keyboard_enhancement, EnterAlternateScreen, LeaveAlternateScreen, SetTitle},
+    event::{
+        EnableMouseCapture, DisableMouseCapture, KeyboardEnhancementFlags,
+        PushKeyboardEnhancementFlags, PopKeyboardEnhancementFlags,
+    },
 };
 use ratatui::{
     backend::CrosstermBackend,
@@ -15,6 +18,13 @@ pub fn setup_terminal() -> Result<Terminal<CrosstermBackend<Stdout>>> {
     enable_raw_mode()?;
     let mut stdout = stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    // 対応端末ではShift+Enterなどの修飾キーをKeyModifiersで受け取れるようにする
+    if supports_keyboard_enhancement().unwrap_or(false) {
+        execute!(
+            stdout,
+            PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+        )?;
+    }
     let backend = CrosstermBackend::new(stdout);
     let terminal = Terminal::new(backend)?;
     Ok(terminal)
@@ -22,6 +32,9 @@ pub fn setup_terminal() -> Result<Terminal<CrosstermBackend<Stdout>>> {
 
 /// ターミナルクリーンアップ共通関数
 pub fn cleanup_terminal<B: ratatui::backend::Backend + std::io::Write>(terminal: &mut Terminal<B>) -> Result<()> {
+    if supports_keyboard_enhancement().unwrap_or(false) {
+        execute!(terminal.backend_mut(), PopKeyboardEnhancementFlags)?;
+    }
     disable_raw_mode()?;
     execute!(
         terminal.backend_mut(),
@@ -30,4 +43,11 @@ pub fn cleanup_terminal<B: ratatui::backend::Backend + std::io::Write>(terminal:
     )?;
     terminal.show_cursor()?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// 未保存の変更があればターミナルのタイトルに [*] を付ける
+pub fn set_terminal_title(unsaved: bool) -> Result<()> {
+    let title = if unsaved { "contui [*]" } else { "contui" };
+    execute!(stdout(), SetTitle(title))?;
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::app::{ChatApp, InputMode};
+
+impl ChatApp {
+    /// Normal modeの2キー以上の操作（ci" / ca" など）の途中入力を処理する
+    /// 対応しないキーが来たら入力途中の操作は破棄する
+    pub fn handle_pending_normal_key(&mut self, c: char) {
+        let mut pending = std::mem::take(&mut self.ui.pending_normal_keys);
+        match (pending.as_str(), c) {
+            ("c", 'i' | 'a') => {
+                pending.push(c);
+                self.ui.pending_normal_keys = pending;
+            }
+            ("ci", delimiter) => self.change_text_object(delimiter, true),
+            ("ca", delimiter) => self.change_text_object(delimiter, false),
+            _ => {}
+        }
+    }
+
+    /// カーソルを囲む区切り文字の内側（insideがfalseなら区切り文字ごと）を削除してInsert modeに入る
+    pub fn change_text_object(&mut self, delimiter: char, inside: bool) {
+        let Some((open, close)) = delimiter_pair(delimiter) else {
+            return;
+        };
+        let graphemes: Vec<&str> = self.ui.input.graphemes(true).collect();
+        let Some((start, end)) = delimited_range(&graphemes, self.ui.cursor_position, open, close, inside) else {
+            return;
+        };
+        self.ui.input = graphemes[..start].concat() + &graphemes[end..].concat();
+        self.ui.cursor_position = start;
+        self.update_input_line_count();
+        self.ui.input_mode = InputMode::Insert;
+    }
+}
+
+/// テキストオブジェクトとして扱う区切り文字の組
+fn delimiter_pair(delimiter: char) -> Option<(&'static str, &'static str)> {
+    match delimiter {
+        '"' => Some(("\"", "\"")),
+        '\'' => Some(("'", "'")),
+        '(' | ')' => Some(("(", ")")),
+        '[' | ']' => Some(("[", "]")),
+        _ => None,
+    }
+}
+
+/// カーソル行の中で、カーソルを囲む区切り文字の組が占めるグラフェム範囲 [start, end) を返す
+/// 引用符はカーソルを含む組、なければカーソルより右の最初の組を使う
+fn delimited_range(
+    graphemes: &[&str],
+    cursor: usize,
+    open: &str,
+    close: &str,
+    inside: bool,
+) -> Option<(usize, usize)> {
+    if graphemes.is_empty() {
+        return None;
+    }
+    let cursor = cursor.min(graphemes.len() - 1);
+    let line_start = graphemes[..cursor].iter().rposition(|g| *g == "\n").map_or(0, |i| i + 1);
+    let line_end = graphemes[cursor..].iter().position(|g| *g == "\n").map_or(graphemes.len(), |i| cursor + i);
+
+    let (open_pos, close_pos) = if open == close {
+        let quotes: Vec<usize> = (line_start..line_end).filter(|&i| graphemes[i] == open).collect();
+        quotes
+            .chunks_exact(2)
+            .map(|pair| (pair[0], pair[1]))
+            .find(|&(_, close_pos)| cursor <= close_pos)?
+    } else {
+        // 左に向かって対応の取れていない開き括弧を探す
+        let mut depth = 0usize;
+        let mut open_pos = None;
+        for i in (line_start..=cursor.min(line_end.saturating_sub(1))).rev() {
+            if graphemes[i] == close && i != cursor {
+                depth += 1;
+            } else if graphemes[i] == open {
+                if depth == 0 {
+                    open_pos = Some(i);
+                    break;
+                }
+                depth -= 1;
+            }
+        }
+        let open_pos = open_pos?;
+        // 右に向かって対応する閉じ括弧を探す
+        let mut depth = 0usize;
+        let close_pos = (open_pos + 1..line_end).find(|&i| {
+            if graphemes[i] == open {
+                depth += 1;
+            } else if graphemes[i] == close {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            false
+        })?;
+        (open_pos, close_pos)
+    };
+
+    if inside {
+        Some((open_pos + 1, close_pos))
+    } else {
+        Some((open_pos, close_pos + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(text: &str, cursor: usize, delimiter: char, inside: bool) -> Option<String> {
+        let graphemes: Vec<&str> = text.graphemes(true).collect();
+        let (open, close) = delimiter_pair(delimiter).unwrap();
+        delimited_range(&graphemes, cursor, open, close, inside).map(|(start, end)| graphemes[start..end].concat())
+    }
+
+    #[test]
+    fn test_delimited_range() {
+        let text = r#"say "hello" and "bye""#;
+        assert_eq!(range(text, 6, '"', true).as_deref(), Some("hello"));
+        assert_eq!(range(text, 6, '"', false).as_deref(), Some("\"hello\""));
+        // カーソルが引用符の外なら右側の最初の組
+        assert_eq!(range(text, 0, '"', true).as_deref(), Some("hello"));
+        assert_eq!(range(text, 13, '"', true).as_deref(), Some("bye"));
+
+        let text = "f(a, g(b), [c])";
+        assert_eq!(range(text, 7, '(', true).as_deref(), Some("b"));
+        assert_eq!(range(text, 3, '(', true).as_deref(), Some("a, g(b), [c]"));
+        assert_eq!(range(text, 8, ')', false).as_deref(), Some("(b)"));
+        assert_eq!(range(text, 9, '(', false).as_deref(), Some("(a, g(b), [c])"));
+        assert_eq!(range(text, 13, '[', false).as_deref(), Some("[c]"));
+
+        // 現在の行の外にある区切り文字は使わない
+        assert_eq!(range("'a\nb'", 3, '\'', true), None);
+        assert_eq!(range("日本'語'です", 3, '\'', true).as_deref(), Some("語"));
+    }
+}
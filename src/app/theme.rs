@@ -0,0 +1,123 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use ratatui::style::{Color, Style};
+
+use crate::history::ChatMessage;
+
+/// メッセージ1件分の色（背景色を指定すると行全体を塗る）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageStyle {
+    pub fg: Color,
+    pub bg: Option<Color>,
+}
+
+impl MessageStyle {
+    pub const fn new(fg: Color) -> Self {
+        Self { fg, bg: None }
+    }
+
+    pub fn style(&self) -> Style {
+        let style = Style::default().fg(self.fg);
+        match self.bg {
+            Some(bg) => style.bg(bg),
+            None => style,
+        }
+    }
+
+    /// "fg" または "fg/bg"（色名・#rrggbb・0〜255の番号）
+    fn parse(spec: &str) -> Result<Self> {
+        let color = |name: &str| Color::from_str(name.trim()).map_err(|_| anyhow!("Unknown color: {}", name.trim()));
+        match spec.split_once('/') {
+            Some((fg, bg)) => Ok(Self { fg: color(fg)?, bg: Some(color(bg)?) }),
+            None => Ok(Self::new(color(spec)?)),
+        }
+    }
+}
+
+/// チャット画面の配色
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub user: MessageStyle,
+    pub ai: MessageStyle,
+    /// /echo で挿入したメモ
+    pub system: MessageStyle,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            user: MessageStyle::new(Color::Green),
+            ai: MessageStyle::new(Color::Blue),
+            system: MessageStyle::new(Color::Gray),
+        }
+    }
+}
+
+impl Theme {
+    /// MESSAGE_COLORS の "user=green/black,ai=#87afff" 形式を読み込む（指定のない役割は既定の色）
+    pub fn from_spec(spec: &str) -> Result<Self> {
+        let mut theme = Self::default();
+        for entry in spec.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (role, colors) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected role=fg[/bg], got: {}", entry))?;
+            let style = MessageStyle::parse(colors)?;
+            match role.trim() {
+                "user" => theme.user = style,
+                "ai" => theme.ai = style,
+                "system" => theme.system = style,
+                other => return Err(anyhow!("Unknown role: {} (expected user, ai or system)", other)),
+            }
+        }
+        Ok(theme)
+    }
+
+    pub fn message_style(&self, msg: &ChatMessage) -> Style {
+        if msg.is_system {
+            self.system.style()
+        } else if msg.is_user {
+            self.user.style()
+        } else {
+            self.ai.style()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme_from_spec() {
+        assert_eq!(Theme::from_spec("").unwrap(), Theme::default());
+
+        let theme = Theme::from_spec("user=yellow/black, ai=#87afff").unwrap();
+        assert_eq!(theme.user, MessageStyle { fg: Color::Yellow, bg: Some(Color::Black) });
+        assert_eq!(theme.ai, MessageStyle::new(Color::Rgb(0x87, 0xaf, 0xff)));
+        assert_eq!(theme.system, MessageStyle::new(Color::Gray));
+        assert_eq!(Theme::from_spec("system=darkgray").unwrap().system, MessageStyle::new(Color::DarkGray));
+
+        assert!(Theme::from_spec("user=notacolor").is_err());
+        assert!(Theme::from_spec("tool=red").is_err());
+        assert!(Theme::from_spec("red").is_err());
+    }
+
+    #[test]
+    fn test_message_style_by_role() {
+        let theme = Theme::from_spec("user=yellow/black, ai=#87afff, system=darkgray").unwrap();
+        let message = |is_user: bool, is_system: bool| ChatMessage {
+            id: uuid::Uuid::new_v4(),
+            parts: Vec::new(),
+            is_user,
+            timestamp: chrono::Utc::now(),
+            comparison: false,
+            steps: Vec::new(),
+            is_system,
+            excluded: false,
+        };
+        assert_eq!(theme.message_style(&message(true, false)), Style::default().fg(Color::Yellow).bg(Color::Black));
+        assert_eq!(theme.message_style(&message(false, false)), Style::default().fg(Color::Rgb(0x87, 0xaf, 0xff)));
+        assert_eq!(theme.message_style(&message(false, true)), Style::default().fg(Color::DarkGray));
+    }
+}
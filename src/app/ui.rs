@@ -1,21 +1,74 @@
 // 状態管理用構造体・enum
 #[derive(Debug)]
 pub enum ChatEvent {
-    AIResponse(ResponsePart),
+    /// session_idのセッションから送ったリクエストへの応答（表示中のセッションが変わっていても送信元に追加する）
+    AIResponseFor { session_id: Uuid, part: ResponsePart },
     Error(String),
+    /// 再送すれば成功しうるエラー（タイムアウト・通信エラーなど）
+    RetryableError(String),
+    /// LLMから応答を受け取った（再送用に保持しているリクエストを破棄する）
+    ResponseReceived,
+    Notification(String),
+    /// /compare の結果（session_idは比較を始めたセッション）
+    Comparison {
+        session_id: Uuid,
+        model_a: String,
+        response_a: String,
+        model_b: String,
+        response_b: String,
+    },
+    /// LLMへの送信タスクが終了した（成功・失敗を問わない）
+    TaskFinished,
+    /// バックグラウンド処理の結果をシステムメッセージとして表示する
+    SystemMessage(String),
+    /// /import で読み込んだセッション（失敗したらエラーの説明）
+    SessionImported { path: String, result: Result<ImportedSession, String> },
+    /// エージェントループの途中のステップ（送信元のセッションの直前の最終応答に付ける）
+    AgentSteps { session_id: Uuid, steps: Vec<StepRecord> },
+    /// /watch で監視中のファイルに追記された行
+    FileUpdate { path: String, text: String },
+    /// /format-code の整形結果（整形前の入力欄の内容と、コードブロックを置き換えた後の内容）
+    CodeFormatted { before: String, after: String },
+    /// 添付したファイルがディスク上で変更された
+    AttachmentChanged(std::path::PathBuf),
+    /// session_idのセッションから送ったリクエストについてのイベント（そのセッションを開いているタブで処理する）
+    Session { session_id: Uuid, event: Box<ChatEvent> },
+}
+
+impl ChatEvent {
+    pub fn for_session(session_id: Uuid, event: ChatEvent) -> Self {
+        ChatEvent::Session { session_id, event: Box::new(event) }
+    }
+
+    /// イベントの宛先のセッション
+    pub fn session_id(&self) -> Option<Uuid> {
+        match self {
+            ChatEvent::AIResponseFor { session_id, .. }
+            | ChatEvent::AgentSteps { session_id, .. }
+            | ChatEvent::Session { session_id, .. } => Some(*session_id),
+            _ => None,
+        }
+    }
 }
 
 pub struct UiState {
     pub input: String,
     pub cursor_position: usize,
     pub visual_start: Option<usize>,
+    pub block_insert: Option<BlockInsert>, // 矩形選択の I で入力中（Escで他の行にも入れる。入力欄を送信・置き換えたら破棄）
     pub input_mode: InputMode,
-    pub list_state: ratatui::widgets::ListState,
+    pub selected_message_index: Option<usize>, // Noneなら最新のメッセージを選択し続ける
+    pub scroll_to_selected_message: bool, // 次の描画で選択中のメッセージが見えるようにスクロールする
     pub scroll_offset: usize,
     pub session_list_state: ratatui::widgets::ListState,
+    pub session_list_offset: usize, // セッション一覧の表示開始位置（選択位置とは別に管理）
+    pub session_list_page_size: usize, // セッション一覧に一度に表示できる件数（描画時に更新）
+    pub session_list_area: Rect, // セッション一覧を描画した領域（クリック位置の判定に使う）
     pub file_browser_state: ratatui::widgets::ListState,
     pub current_directory: String,
     pub directory_contents: Vec<String>,
+    pub binary_entries: HashSet<String>, // directory_contentsのうちバイナリと判定したファイル（[binary]を付けて表示）
+    pub git_statuses: HashMap<String, GitStatus>, // directory_contentsのうちgitで変更のある項目（リポジトリ外なら空）
     pub selected_files: Vec<String>,
     pub input_line_count: usize,
     pub input_history: Vec<String>,
@@ -23,6 +76,140 @@ pub struct UiState {
     pub temp_input: String,
     pub show_help: bool,
     pub notification: Option<String>,
+    pub notification_shown_at: Option<std::time::Instant>, // 表示中の通知を表示した時刻（一定時間で自動的に消す）
+    pub save_warning: Option<String>, // 履歴を保存できていないメッセージがある間、通知がなければ表示し続ける警告
+    pub editing_message_id: Option<Uuid>, // 編集して再送信する対象のユーザーメッセージ
+    pub show_archived_sessions: bool, // セッション一覧にアーカイブ済みを表示するか
+    pub unsaved: bool, // 最後の履歴保存以降にメッセージが追加されたか
+    pub expanded_tool_messages: HashSet<Uuid>, // ツール実行結果の詳細を展開表示しているメッセージ
+    pub expanded_steps: HashSet<Uuid>, // エージェントループの途中のステップを展開表示しているメッセージ
+    pub pending_normal_keys: String, // Normal modeで入力途中のキー列（ci" など）
+    pub render_mode: RenderMode, // チャットメッセージの表示形式（/format）
+    pub input_scroll: usize, // 入力欄の縦スクロール位置（折り返し後の行単位）
+    pub bookmarks: Bookmarks, // /bookmark で付けたラベル → (セッションID, メッセージID)
+    pub emoji_picker: Option<usize>, // 絵文字ピッカーを開いていれば選択中のインデックス
+    pub directory_prompt: Option<DirectoryPrompt>, // 許可ディレクトリ外へのアクセスの確認待ち
+    pub quit_prompt: Option<String>, // 終了の確認待ち（確認が必要な理由）
+    pub tab_close_prompt: Option<String>, // タブを閉じる確認待ち（確認が必要な理由）
+    pub diff_lines: Vec<DiffLine>, // /diff の結果（DiffViewで表示）
+    pub diff_scroll: usize,
+    pub wrapped_messages: HashMap<Uuid, WrappedMessage>, // メッセージを折り返した行のキャッシュ（長いメッセージは折りたたんだもの）
+    pub message_width: usize, // 前回の描画でメッセージを折り返した幅（スクロール量の計算に使う）
+    pub scroll_anchor: Option<ScrollAnchor>, // リサイズ前に先頭に表示していた位置（次の描画でスクロール位置を移す）
+    pub message_view: Option<MessageView>, // 長いメッセージの全文表示（MessageViewで表示）
+    pub permissions: Option<PermissionsView>, // 許可ディレクトリの管理画面（Permissionsで表示）
+    pub search_query: Option<String>, // * / # で最後に検索した単語
+    pub notification_history: Vec<(DateTime<Utc>, String)>, // これまでの通知（古いものから最大NOTIFICATION_HISTORY_LIMIT件）
+    pub unread_notifications: usize, // 表示中の通知に置き換えられて読まれていない通知の数
+}
+
+impl UiState {
+    pub fn new(current_directory: String, bookmarks: Bookmarks) -> Self {
+        Self {
+            input: String::new(),
+            cursor_position: 0,
+            visual_start: None,
+            block_insert: None,
+            input_mode: InputMode::Normal,
+            selected_message_index: None,
+            scroll_to_selected_message: false,
+            scroll_offset: 0,
+            session_list_state: ratatui::widgets::ListState::default(),
+            session_list_offset: 0,
+            session_list_page_size: 1,
+            session_list_area: Rect::default(),
+            file_browser_state: ratatui::widgets::ListState::default(),
+            current_directory,
+            directory_contents: Vec::new(),
+            binary_entries: HashSet::new(),
+            git_statuses: HashMap::new(),
+            selected_files: Vec::new(),
+            input_line_count: 1,
+            input_history: Vec::new(),
+            history_index: None,
+            temp_input: String::new(),
+            show_help: false,
+            notification: None,
+            notification_shown_at: None,
+            save_warning: None,
+            editing_message_id: None,
+            show_archived_sessions: false,
+            unsaved: false,
+            expanded_tool_messages: HashSet::new(),
+            expanded_steps: HashSet::new(),
+            pending_normal_keys: String::new(),
+            render_mode: RenderMode::default(),
+            input_scroll: 0,
+            bookmarks,
+            emoji_picker: None,
+            directory_prompt: None,
+            quit_prompt: None,
+            tab_close_prompt: None,
+            diff_lines: Vec::new(),
+            notification_history: Vec::new(),
+            unread_notifications: 0,
+            diff_scroll: 0,
+            wrapped_messages: HashMap::new(),
+            message_width: 72,
+            scroll_anchor: None,
+            message_view: None,
+            permissions: None,
+            search_query: None,
+        }
+    }
+}
+
+/// 許可ディレクトリの外を参照したときに表示する確認
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectoryPrompt {
+    pub directory: std::path::PathBuf,
+    /// ユーザーの送信で開いた場合は、許可したら入力欄のメッセージを送り直す
+    pub resend: bool,
+    /// /cd で開いた場合は、許可したらこのディレクトリに移動する（拒否したら移動しない）
+    pub change_directory: Option<std::path::PathBuf>,
+}
+
+/// チャットメッセージの表示形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum RenderMode {
+    /// 整形せず、空白を保ったまま幅で折り返す
+    Raw,
+    /// 単語単位で折り返す（既定）
+    #[default]
+    Markdown,
+    /// JSONを検出して整形する
+    Json,
+}
+
+impl RenderMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "raw" => Some(RenderMode::Raw),
+            "markdown" | "md" => Some(RenderMode::Markdown),
+            "json" => Some(RenderMode::Json),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RenderMode::Raw => "raw",
+            RenderMode::Markdown => "markdown",
+            RenderMode::Json => "json",
+        }
+    }
+
+    /// メッセージ本文をこの形式で整形し、max_widthで折り返す
+    pub fn layout(&self, prefix: &str, text: &str, max_width: usize) -> String {
+        match self {
+            RenderMode::Raw => hard_wrap(&format!("{}: {}", prefix, text), max_width),
+            RenderMode::Markdown => wrap_text(&format!("{}: {}", prefix, text), max_width),
+            RenderMode::Json => match pretty_print_json(text) {
+                Some(pretty) => hard_wrap(&format!("{}:\n{}", prefix, pretty), max_width),
+                None => wrap_text(&format!("{}: {}", prefix, text), max_width),
+            },
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -30,29 +217,58 @@ pub enum InputMode {
     Normal,
     Insert,
     Visual,
+    VisualLine,
+    /// Ctrl+V の矩形選択
+    VisualBlock,
     SessionList,
     FileBrowser,
+    /// /diff の結果を表示する読み取り専用の画面
+    DiffView,
+    /// 折りたたんだ長いメッセージの全文を表示する読み取り専用の画面
+    MessageView,
+    /// /permissions の許可ディレクトリの管理画面
+    Permissions,
     // TodoList, // 削除
 }
 use crate::gemini::ResponsePart; // Add this import
+use crate::gemini::SettingSource;
+use crate::config::format_cost;
 use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    text::{Text},
+    text::{Line, Span, Text},
     widgets::{
-        Block, BorderType, Borders, List, ListItem, Paragraph,
+        Block, BorderType, Borders, Clear, List, ListItem, Paragraph,
     },
     Frame,
 };
+use std::collections::{HashMap, HashSet};
 use unicode_width::UnicodeWidthStr;
 use uuid::Uuid;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use unicode_segmentation::UnicodeSegmentation;
 
 
 use crate::app::ChatApp;
-use crate::history::ChatMessage;
-use crate::markdown::wrap_text;
+use crate::app::session_diff::{DiffLine, DiffLineKind};
+use crate::i18n::tr;
+use crate::emoji::{EMOJI_GRID_COLUMNS, EMOJI_PALETTE};
+use crate::git::GitStatus;
+use crate::history::{ChatMessage, StepRecord};
+use crate::import::ImportedSession;
+use crate::markdown::{hard_wrap, pretty_print_json, wrap_text};
+use crate::app::comparison::side_by_side_lines;
+use crate::app::appearance::Glyph;
+use crate::app::input_layout::{scroll_to_row, InputLayout};
+use crate::app::session_management::{page_indicator, visible_offset};
+use crate::app::message_view::{wrapped_message, MessageView, WrappedMessage};
+use crate::app::scroll_anchor::{anchored_offset, ScrollAnchor};
+use crate::app::permissions::PermissionsView;
+use crate::app::visual_mode::BlockInsert;
+use crate::bookmarks::Bookmarks;
+
+/// 入力欄の最大の高さ（枠線を含む）
+const MAX_INPUT_HEIGHT: usize = 10;
 
 impl ChatApp {
     /// AI進行状態メッセージを逐次追加し即時描画する
@@ -66,6 +282,10 @@ impl ChatApp {
             parts: vec![crate::gemini::Part::Text { text: msg }], // Changed content to parts
             is_user: false,
             timestamp: Utc::now(),
+            comparison: false,
+            steps: Vec::new(),
+            is_system: false,
+            excluded: false,
         });
         
         // スクロール位置の自動調整（最下部付近にいる場合のみ自動スクロール）
@@ -77,34 +297,98 @@ impl ChatApp {
         let _ = terminal.draw(render_fn);
     }
 
+    /// 最終応答の下に付けるエージェントループのステップの行（折りたたみ中は1行の案内だけ）
+    pub(crate) fn step_lines(&self, msg: &ChatMessage, max_width: usize) -> Vec<String> {
+        if msg.steps.is_empty() {
+            return Vec::new();
+        }
+        let count = msg.steps.len();
+        let noun = if count == 1 { "step" } else { "steps" };
+        if !self.ui.expanded_steps.contains(&msg.id) {
+            return vec![format!("  {} {} {} (press Tab to expand)", self.appearance.glyph(Glyph::Collapsed), count, noun)];
+        }
+        let mut lines = vec![format!("  {} {} {} (press Tab to collapse)", self.appearance.glyph(Glyph::Expanded), count, noun)];
+        for (i, step) in msg.steps.iter().enumerate() {
+            let excerpt = self.appearance.text(&step.prompt_excerpt);
+            lines.push(format!("    Step {} ({:.1}s): {}", i + 1, step.duration.as_secs_f64(), excerpt));
+            let wrapped = wrap_text(&self.appearance.text(&step.response), max_width.saturating_sub(6).max(1));
+            lines.extend(wrapped.lines().map(|line| format!("      {}", line)));
+        }
+        lines
+    }
+
     /// 最下部付近にいる場合のみ自動スクロールする
     pub fn auto_scroll_if_at_bottom(&mut self) {
-        let total_lines = self.messages.iter().map(|msg| {
-            let prefix = if msg.is_user { "You" } else { "AI" };
-            let mut msg_content_text = String::new();
-            for part in &msg.parts {
-                if let crate::gemini::Part::Text { text } = part {
-                    msg_content_text.push_str(text);
-                }
-            }
-            let content = format!("{}: {}", prefix, msg_content_text);
-            wrap_text(&content, 72).lines().count()
-        }).sum::<usize>();
+        let total_lines = self.total_message_lines();
         
-        // 現在のスクロール位置が最下部から3行以内にある場合のみ自動スクロール
+        // 現在のスクロール位置が最下部から3行以内にある場合のみ自動スクロールし、選択も最新に追従させる
         let max_scroll = total_lines.saturating_sub(1);
         if self.ui.scroll_offset + 3 >= max_scroll {
             self.ui.scroll_offset = max_scroll;
+            self.ui.selected_message_index = None;
         }
     }
     pub fn render(&mut self, f: &mut Frame) {
+        // タブが1つだけならタブバーは出さない
+        let area = if self.tabs.len() > 1 {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(1), Constraint::Min(0)])
+                .split(f.area());
+            self.render_tab_bar(f, chunks[0]);
+            chunks[1]
+        } else {
+            f.area()
+        };
+        self.render_screen(f, area);
+        if let Some(prompt) = &self.ui.directory_prompt {
+            self.render_directory_prompt(f, prompt);
+        }
+        if let Some(reason) = &self.ui.quit_prompt {
+            self.render_quit_prompt(f, reason);
+        }
+        if let Some(reason) = &self.ui.tab_close_prompt {
+            self.render_confirm_prompt(f, "Close tab", &format!("{} — close it anyway?", reason), "  y - Close the tab (stops the request)");
+        }
+        self.appearance.apply_high_contrast(f.buffer_mut());
+    }
+
+    /// 1行のタブバー（番号とセッション名、応答待ちのタブにはスピナー）
+    fn render_tab_bar(&self, f: &mut Frame, area: Rect) {
+        let mut spans = Vec::new();
+        for (index, tab) in self.tab_labels().into_iter().enumerate() {
+            if index > 0 {
+                spans.push(Span::styled("│", Style::default().fg(Color::DarkGray)));
+            }
+            let spinner = if tab.busy { format!(" {}", self.appearance.spinner()) } else { String::new() };
+            let style = if tab.active {
+                Style::default().fg(Color::Black).bg(Color::Cyan).add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(Color::Gray)
+            };
+            spans.push(Span::styled(format!(" {}:{}{} ", index + 1, tab.title, spinner), style));
+        }
+        f.render_widget(Paragraph::new(Line::from(spans)), area);
+    }
+
+    fn render_screen(&mut self, f: &mut Frame, area: Rect) {
         if self.ui.input_mode == InputMode::SessionList {
-            self.render_session_list(f);
+            self.render_session_list(f, area);
         } else if self.ui.input_mode == InputMode::FileBrowser {
-            self.render_file_browser(f);
+            self.render_file_browser(f, area);
+        } else if self.ui.input_mode == InputMode::DiffView {
+            self.render_diff_view(f, area);
+        } else if self.ui.input_mode == InputMode::MessageView {
+            self.render_message_view(f, area);
+        } else if self.ui.input_mode == InputMode::Permissions {
+            self.render_permissions(f, area);
         } else {
-            let input_height = (self.ui.input_line_count + 2).clamp(3, 10) as u16;
-            let notification_height = if self.ui.notification.is_some() { 2 } else { 0 };
+            // 分割表示なら右ペインを描画し、チャットと入力欄は左側に収める
+            let area = self.split_chat_area(f, area);
+            // 折り返し後の行数に合わせて入力欄を広げる（最大10行、超えた分はスクロール）
+            let input_rows = InputLayout::new(&self.ui.input, area.width.saturating_sub(2) as usize).row_count();
+            let input_height = (input_rows + 2).clamp(3, MAX_INPUT_HEIGHT) as u16;
+            let notification_height = if self.ui.notification.is_some() || self.ui.save_warning.is_some() { 2 } else { 0 };
             
             // 通常表示（TODOパネル分割は削除）
             let chunks = Layout::default()
@@ -114,17 +398,26 @@ impl ChatApp {
                     Constraint::Length(notification_height),
                     Constraint::Length(input_height),
                 ])
-                .split(f.area());
+                .split(area);
 
             self.render_messages(f, chunks[0]);
             if let Some(ref note) = self.ui.notification {
-                self.render_notification(f, chunks[1], note);
+                let note = match self.ui.unread_notifications {
+                    0 => note.clone(),
+                    unread => format!("{} (+{} more)", note, unread),
+                };
+                self.render_notification(f, chunks[1], &note, Color::Cyan);
+            } else if let Some(ref warning) = self.ui.save_warning {
+                self.render_notification(f, chunks[1], warning, Color::Red);
             }
             self.render_input(f, chunks[2]);
             
             if self.ui.show_help {
                 self.render_floating_help(f);
             }
+            if let Some(selected) = self.ui.emoji_picker {
+                self.render_emoji_picker(f, selected);
+            }
         }
     }
 
@@ -132,35 +425,78 @@ impl ChatApp {
         // 1. メッセージ全体をラップして仮想行リストを作成
         let mut virtual_lines: Vec<(String, Style)> = Vec::new();
         let max_width = if area.width > 8 { area.width as usize - 8 } else { 1 };
-        for msg in &self.messages {
-            let style = if msg.is_user {
-                Style::default().fg(Color::Green)
-            } else {
-                Style::default().fg(Color::Blue)
-            };
-            let prefix = if msg.is_user { "You" } else { "AI" };
+        let selected_message = self.selected_message_index();
+        let mut selected_lines = 0..0;
+        let mut line_counts = Vec::with_capacity(self.messages.len());
+        for (index, msg) in self.messages.iter().enumerate() {
+            let mut style = self.theme.message_style(msg);
+            // 会話コンテキストから外したメッセージ
+            if msg.excluded {
+                style = style.add_modifier(Modifier::DIM | Modifier::CROSSED_OUT);
+            }
+            let first_line = virtual_lines.len();
+            if selected_message == Some(index) {
+                style = style.bg(Color::DarkGray);
+            }
+            let prefix = msg.speaker();
+
+            // 比較結果は左右に並べて表示
+            if msg.comparison {
+                virtual_lines.push((format!("{} (compare):", prefix), style));
+                for line in side_by_side_lines(&msg.parts, max_width, self.appearance) {
+                    virtual_lines.push((line, style));
+                }
+                if selected_message == Some(index) {
+                    selected_lines = first_line..virtual_lines.len();
+                }
+                line_counts.push(virtual_lines.len() - first_line);
+                continue;
+            }
             
-            for part in &msg.parts {
-                let content_str = match part {
-                    crate::gemini::Part::Text { text } => text.clone(),
-                    crate::gemini::Part::FunctionCall { function_call } => {
-                        format!("Function Call: {}({})", function_call.name, serde_json::to_string(&function_call.args).unwrap_or_default())
-                    },
-                    crate::gemini::Part::FunctionResponse { function_response } => {
-                        format!("Function Response: {}: {}", function_response.name, serde_json::to_string(&function_response.response).unwrap_or_default())
-                    },
+            // 折り返しはキャッシュし、長いメッセージは先頭と末尾だけ表示する（全文はEnterで開く）
+            let tool_expanded = self.ui.expanded_tool_messages.contains(&msg.id);
+            let wrapped = wrapped_message(&mut self.ui.wrapped_messages, msg, self.ui.render_mode, tool_expanded, self.appearance, max_width, self.config.message_max_lines);
+            for (i, line) in wrapped.lines.iter().enumerate() {
+                let line_style = match (wrapped.collapsed, wrapped.colors[i]) {
+                    (Some((_, marker)), _) if marker == i => style.add_modifier(Modifier::ITALIC | Modifier::DIM),
+                    (_, Some(color)) => style.fg(color),
+                    _ => style,
                 };
-                let content = format!("{}: {}", prefix, content_str);
-                let wrapped = wrap_text(&content, max_width);
-                for line in wrapped.lines() {
-                    virtual_lines.push((line.to_string(), style));
-                }
+                virtual_lines.push((line.clone(), line_style));
             }
+            let step_style = style.add_modifier(Modifier::DIM);
+            for line in self.step_lines(msg, max_width) {
+                virtual_lines.push((line, step_style));
+            }
+            if selected_message == Some(index) {
+                selected_lines = first_line..virtual_lines.len();
+            }
+            line_counts.push(virtual_lines.len() - first_line);
+        }
+
+        self.ui.message_width = max_width;
+        // リサイズ前に先頭に表示していた位置を、新しい幅の折り返しでの位置に移す
+        if let Some(anchor) = self.ui.scroll_anchor.take() {
+            self.ui.scroll_offset = anchored_offset(&anchor, &line_counts);
+        }
+        // 消えたメッセージ（セッションの切り替えなど）のキャッシュを捨てる
+        if self.ui.wrapped_messages.len() > self.messages.len() * 2 {
+            let ids: HashSet<Uuid> = self.messages.iter().map(|msg| msg.id).collect();
+            self.ui.wrapped_messages.retain(|id, _| ids.contains(id));
         }
 
         // 2. スクロールオフセットで表示範囲を決定
         let total_lines = virtual_lines.len();
         let height = area.height.saturating_sub(2) as usize; // 枠線分
+
+        // 選択が移動した場合は選択中のメッセージが見える位置までスクロール
+        if std::mem::take(&mut self.ui.scroll_to_selected_message) && !selected_lines.is_empty() {
+            if selected_lines.start < self.ui.scroll_offset {
+                self.ui.scroll_offset = selected_lines.start;
+            } else if selected_lines.end > self.ui.scroll_offset + height {
+                self.ui.scroll_offset = selected_lines.end.saturating_sub(height).min(selected_lines.start);
+            }
+        }
         let visible_lines: Vec<ListItem>;
 
         if total_lines != 0 {
@@ -193,54 +529,95 @@ impl ChatApp {
         f.render_widget(messages_list, area);
 
         // ローディング表示
-        if self.is_loading {
+        if self.task_queue.is_processing() {
             let loading_area = Rect {
                 x: area.x + 2,
                 y: area.y + area.height - 2,
                 width: area.width - 4,
                 height: 1,
             };
-            let loading_text = Paragraph::new("🤖 AI is thinking...")
+            let ai = self.appearance.glyph(Glyph::Ai);
+            let loading_message = match self.task_queue.pending() {
+                0 => format!("{} AI is thinking...", ai),
+                pending => format!("{} AI is thinking... ({} queued)", ai, pending),
+            };
+            let loading_text = Paragraph::new(loading_message)
                 .style(Style::default().fg(Color::Yellow).add_modifier(Modifier::ITALIC));
             f.render_widget(loading_text, loading_area);
         }
     }
 
-    pub fn render_input(&self, f: &mut Frame, area: Rect) {
+    pub fn render_input(&mut self, f: &mut Frame, area: Rect) {
         let input_style = match self.ui.input_mode {
             InputMode::Normal => Style::default(),
             InputMode::Insert => Style::default().fg(Color::Yellow),
-            InputMode::Visual => Style::default().fg(Color::Magenta),
+            InputMode::Visual | InputMode::VisualLine | InputMode::VisualBlock => Style::default().fg(Color::Magenta),
             InputMode::SessionList => Style::default().fg(Color::Cyan),
             InputMode::FileBrowser => Style::default().fg(Color::Cyan),
+            InputMode::DiffView | InputMode::MessageView | InputMode::Permissions => Style::default().fg(Color::Cyan),
             // InputMode::TodoListは削除済み
         };
 
-        let title = match self.ui.input_mode {
+        let base_title = match self.ui.input_mode {
             InputMode::Normal => "Input (Press 'i' to insert, 'v' for visual, 'q' to quit)",
             InputMode::Insert => "Insert Mode (Shift+Enter: new line, Enter: send, Esc: normal mode)",
             InputMode::Visual => "Visual Mode (Select text, press 'd' to delete, 'y' to yank, Esc to exit)",
-            InputMode::SessionList => "Session List (Press Enter to select, 'd' to delete, 'n' for new)",
+            InputMode::VisualLine => "Visual Line Mode (Select lines, press 'd' to delete, 'y' to yank, Esc to exit)",
+            InputMode::VisualBlock => "Visual Block Mode (Select a rectangle, 'd' to delete, 'I' to insert on each line, Esc to exit)",
+            InputMode::SessionList => "Session List (Press Enter to select, 'd' to delete, 'u' to undo, 'n' for new)",
             InputMode::FileBrowser => "File Browser (Press Enter to open, 'd' to delete, 'n' for new)",
+            InputMode::DiffView => "Diff View (Press 'q' or Esc to close)",
+            InputMode::MessageView => "Message View (Press 'q' or Esc to close)",
+            InputMode::Permissions => "Permissions (Press 'q' or Esc to close)",
             // InputMode::TodoListは削除済み
         };
+        let title = match self.editing_message_number() {
+            Some(number) => format!("Editing message #{} (Enter: resend, Esc in Normal mode: cancel)", number),
+            None => base_title.to_string(),
+        };
 
-        let input = Paragraph::new(self.ui.input.as_str())
-            .style(input_style)
-            .wrap(ratatui::widgets::Wrap { trim: false })
-            .block(
-                Block::default()
-                    .borders(Borders::ALL)
-                    .title(title)
-                    .border_type(BorderType::Rounded),
+        let mut block = Block::default()
+            .borders(Borders::ALL)
+            .title(title)
+            .border_type(BorderType::Rounded);
+        if let Some(status) = self.input_status() {
+            block = block.title_bottom(Line::from(status).right_aligned());
+        }
+        // 許可ディレクトリが1つもなければファイルの機能が使えないので目立たせる
+        if self.gemini_client.allowed_directories().is_empty() {
+            block = block.title_bottom(
+                Line::from(Span::styled(" ⚠ No allowed directories — file access is disabled (/permissions) ", Style::default().fg(Color::White).bg(Color::Red)))
+                    .centered(),
             );
+        }
+        if let Some(gauge) = self.context_gauge.gauge {
+            block = block.title_bottom(Line::from(Span::styled(format!(" {} ", gauge.label()), Style::default().fg(gauge.color()))).left_aligned());
+        }
+
+        // 自前で折り返したテキストを描画し、カーソル位置も折り返し後の表示座標で求める
+        let inner_width = area.width.saturating_sub(2) as usize;
+        let inner_height = area.height.saturating_sub(2) as usize;
+        let layout = InputLayout::new(&self.ui.input, inner_width);
+        let (cursor_row, cursor_column) = layout.position(self.ui.cursor_position);
+        self.ui.input_scroll = scroll_to_row(self.ui.input_scroll, cursor_row, inner_height)
+            .min(layout.row_count().saturating_sub(inner_height));
+        let scroll = self.ui.input_scroll;
+
+        let input = Paragraph::new(layout.text())
+            .style(input_style)
+            .scroll((scroll as u16, 0))
+            .block(block);
 
         f.render_widget(input, area);
 
-        // カーソル位置を計算（複数行対応）
-        let (cursor_line, cursor_column) = self.calculate_cursor_position();
-        let cursor_pos_x = area.x + cursor_column as u16 + 1;
-        let cursor_pos_y = area.y + cursor_line as u16 + 1;
+        // グラフェムの表示位置を画面座標に変換（表示範囲外ならNone）
+        let screen_position = |index: usize| -> Option<(u16, u16)> {
+            let (row, column) = layout.position(index);
+            let row = row.checked_sub(scroll).filter(|&row| row < inner_height)?;
+            Some((area.x + 1 + column.min(inner_width.saturating_sub(1)) as u16, area.y + 1 + row as u16))
+        };
+        let (cursor_pos_x, cursor_pos_y) = screen_position(self.ui.cursor_position)
+            .unwrap_or((area.x + 1 + cursor_column as u16, area.y + 1));
 
         match self.ui.input_mode {
             InputMode::Insert => {
@@ -254,7 +631,10 @@ impl ChatApp {
                 // 現在のカーソル位置の文字をハイライト表示
                 let graphemes: Vec<&str> = self.ui.input.graphemes(true).collect();
                 if self.ui.cursor_position < graphemes.len() {
-                    let char_at_cursor = graphemes[self.ui.cursor_position];
+                    let char_at_cursor = match graphemes[self.ui.cursor_position] {
+                        "\n" | "\r\n" => " ",
+                        grapheme => grapheme,
+                    };
                     let highlight_area = Rect {
                         x: cursor_pos_x,
                         y: cursor_pos_y,
@@ -277,31 +657,33 @@ impl ChatApp {
                     f.render_widget(highlight_text, highlight_area);
                 }
             }
-            InputMode::Visual => {
+            InputMode::Visual | InputMode::VisualLine | InputMode::VisualBlock => {
                 // Visual Modeでは選択範囲をハイライト
                 f.set_cursor_position((cursor_pos_x, cursor_pos_y));
                 
                 if let Some((start_pos, end_pos)) = self.get_visual_selection_range() {
                     let graphemes: Vec<&str> = self.ui.input.graphemes(true).collect();
-                    let mut x_offset = 0;
-                    
-                    for (i, grapheme) in graphemes.iter().enumerate() {
-                        let char_width = UnicodeWidthStr::width(*grapheme).max(1);
-                        
-                        if i >= start_pos && i < end_pos {
-                            // 選択範囲内の文字は明るい背景色でハイライト
-                            let highlight_area = Rect {
-                                x: area.x + x_offset as u16 + 1,
-                                y: cursor_pos_y,
-                                width: char_width as u16,
-                                height: 1,
-                            };
-                            let highlight_text = Paragraph::new(*grapheme)
-                                .style(Style::default().bg(Color::LightBlue).fg(Color::Black));
-                            f.render_widget(highlight_text, highlight_area);
-                        }
-                        
-                        x_offset += char_width;
+                    // 矩形選択では各行の矩形の部分だけ
+                    let ranges = if self.ui.input_mode == InputMode::VisualBlock { self.visual_block_ranges() } else { vec![(start_pos, end_pos)] };
+
+                    // 選択範囲を表示行ごとに分け、行ごとに明るい背景色でハイライト（改行は空白として表示）
+                    for span in ranges.into_iter().flat_map(|(start, end)| layout.highlight_spans(start, end)) {
+                        let Some(row) = span.row.checked_sub(scroll).filter(|&row| row < inner_height) else {
+                            continue;
+                        };
+                        let text: String = graphemes[span.graphemes.clone()]
+                            .iter()
+                            .map(|g| if *g == "\n" || *g == "\r\n" { " " } else { *g })
+                            .collect();
+                        let highlight_area = Rect {
+                            x: area.x + 1 + span.column as u16,
+                            y: area.y + 1 + row as u16,
+                            width: (span.width as u16).min(inner_width.saturating_sub(span.column) as u16),
+                            height: 1,
+                        };
+                        let highlight_text = Paragraph::new(text)
+                            .style(Style::default().bg(Color::LightBlue).fg(Color::Black));
+                        f.render_widget(highlight_text, highlight_area);
                     }
                     
                     // 選択範囲が空の場合でも視覚的フィードバックを提供
@@ -324,9 +706,211 @@ impl ChatApp {
             InputMode::FileBrowser => {
                 // ファイルブラウザモードではカーソル非表示
             }
+            InputMode::DiffView | InputMode::MessageView | InputMode::Permissions => {
+                // 差分表示・全文表示ではカーソル非表示
+            }
+        }
+    }
+
+    pub fn render_diff_view(&mut self, f: &mut Frame, area: Rect) {
+        // 枠線を除いた高さ分だけ表示し、最後の行が下端に来るところでスクロールを止める
+        let height = area.height.saturating_sub(2) as usize;
+        self.ui.diff_scroll = self.ui.diff_scroll.min(self.ui.diff_lines.len().saturating_sub(height));
+        let lines: Vec<Line> = self
+            .ui
+            .diff_lines
+            .iter()
+            .skip(self.ui.diff_scroll)
+            .take(height)
+            .map(|line| {
+                let style = match line.kind {
+                    DiffLineKind::Header => Style::default().fg(Color::Cyan),
+                    DiffLineKind::Context => Style::default(),
+                    DiffLineKind::Added => Style::default().fg(Color::Green),
+                    DiffLineKind::Removed => Style::default().fg(Color::Red),
+                };
+                Line::from(Span::styled(line.text.clone(), style))
+            })
+            .collect();
+
+        f.render_widget(
+            Paragraph::new(lines).block(
+                Block::default()
+                    .title(" Diff (j/k: scroll, q/Esc: close) ")
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded),
+            ),
+            area,
+        );
+        if self.ui.show_help {
+            self.render_floating_help(f);
+        }
+    }
+
+    /// 長いメッセージの全文表示。表示する範囲の行だけを折り返す
+    pub fn render_message_view(&mut self, f: &mut Frame, area: Rect) {
+        let height = area.height.saturating_sub(2) as usize;
+        let width = area.width.saturating_sub(2).max(1) as usize;
+        let Some(view) = &mut self.ui.message_view else {
+            return;
+        };
+        view.page_size = height.max(1);
+        let mut lines: Vec<Line> = Vec::with_capacity(height);
+        for (line, color) in view.lines.iter().zip(&view.colors).skip(view.scroll) {
+            if lines.len() >= height {
+                break;
+            }
+            let style = color.map_or_else(Style::default, |color| Style::default().fg(color));
+            lines.extend(hard_wrap(line, width).lines().map(|row| Line::styled(row.to_string(), style)));
+        }
+        lines.truncate(height);
+        let title = format!(
+            " Message — line {}/{} (j/k: scroll, PageUp/PageDown, g/G: top/bottom, q/Esc: close) ",
+            (view.scroll + 1).min(view.lines.len()),
+            view.lines.len()
+        );
+
+        f.render_widget(
+            Paragraph::new(lines).block(
+                Block::default()
+                    .title(title)
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded),
+            ),
+            area,
+        );
+        if self.ui.show_help {
+            self.render_floating_help(f);
         }
     }
 
+    pub fn render_directory_prompt(&self, f: &mut Frame, prompt: &DirectoryPrompt) {
+        let lines = vec![
+            Line::from("Allow access to this directory?"),
+            Line::from(""),
+            Line::from(Span::styled(prompt.directory.display().to_string(), Style::default().fg(Color::Yellow))),
+            Line::from(""),
+            Line::from("  s - Allow for this session"),
+            Line::from("  a - Always allow (saved to allowed_directories)"),
+            Line::from("  d - Deny"),
+        ];
+        let area = f.area();
+        let content_width = prompt.directory.display().to_string().width().max(50) as u16;
+        let popup_width = (content_width + 4).min(area.width);
+        let popup_height = (lines.len() as u16 + 2).min(area.height);
+        let popup_area = Rect {
+            x: (area.width - popup_width) / 2,
+            y: (area.height - popup_height) / 2,
+            width: popup_width,
+            height: popup_height,
+        };
+
+        f.render_widget(Clear, popup_area);
+        f.render_widget(
+            Paragraph::new(lines)
+                .style(Style::default().bg(Color::Black))
+                .block(
+                    Block::default()
+                        .title("File access")
+                        .borders(Borders::ALL)
+                        .border_type(BorderType::Rounded)
+                        .border_style(Style::default().fg(Color::Yellow)),
+                ),
+            popup_area,
+        );
+    }
+
+    pub fn render_quit_prompt(&self, f: &mut Frame, reason: &str) {
+        self.render_confirm_prompt(f, "Quit", &format!("{} — quit anyway?", reason), "  y / q / Q - Quit");
+    }
+
+    /// 中央に出すy/nの確認（yesはyで実行する操作の説明）
+    fn render_confirm_prompt(&self, f: &mut Frame, title: &str, question: &str, yes: &str) {
+        let lines = vec![
+            Line::from(Span::styled(question.to_string(), Style::default().fg(Color::Yellow))),
+            Line::from(""),
+            Line::from(yes.to_string()),
+            Line::from("  n / Esc   - Keep working"),
+        ];
+        let area = f.area();
+        let popup_width = (lines[0].width().max(30) as u16 + 4).min(area.width);
+        let popup_height = (lines.len() as u16 + 2).min(area.height);
+        let popup_area = Rect {
+            x: (area.width - popup_width) / 2,
+            y: (area.height - popup_height) / 2,
+            width: popup_width,
+            height: popup_height,
+        };
+
+        f.render_widget(Clear, popup_area);
+        f.render_widget(
+            Paragraph::new(lines)
+                .style(Style::default().bg(Color::Black))
+                .block(
+                    Block::default()
+                        .title(title)
+                        .borders(Borders::ALL)
+                        .border_type(BorderType::Rounded)
+                        .border_style(Style::default().fg(Color::Yellow)),
+                ),
+            popup_area,
+        );
+    }
+
+    pub fn render_emoji_picker(&self, f: &mut Frame, selected: usize) {
+        // 1マス = 絵文字（幅2）+ 左右の空白
+        let cell_width = 4;
+        let rows: Vec<&[(&str, &str)]> = EMOJI_PALETTE.chunks(EMOJI_GRID_COLUMNS).collect();
+        let area = f.area();
+        let popup_width = ((EMOJI_GRID_COLUMNS * cell_width) as u16 + 2).min(area.width);
+        let popup_height = (rows.len() as u16 + 4).min(area.height);
+        let popup_area = Rect {
+            x: (area.width - popup_width) / 2,
+            y: (area.height - popup_height) / 2,
+            width: popup_width,
+            height: popup_height,
+        };
+
+        let mut lines: Vec<Line> = rows
+            .iter()
+            .enumerate()
+            .map(|(row, entries)| {
+                let spans: Vec<Span> = entries
+                    .iter()
+                    .enumerate()
+                    .map(|(column, (emoji, _))| {
+                        let style = if row * EMOJI_GRID_COLUMNS + column == selected {
+                            Style::default().bg(Color::Cyan).fg(Color::Black)
+                        } else {
+                            Style::default()
+                        };
+                        Span::styled(format!(" {} ", emoji), style)
+                    })
+                    .collect();
+                Line::from(spans)
+            })
+            .collect();
+        lines.push(Line::from(""));
+        lines.push(Line::from(Span::styled(
+            format!(" {}  (Enter: insert, Esc: close)", EMOJI_PALETTE[selected].1),
+            Style::default().fg(Color::DarkGray),
+        )));
+
+        f.render_widget(Clear, popup_area);
+        f.render_widget(
+            Paragraph::new(lines)
+                .style(Style::default().bg(Color::Black))
+                .block(
+                    Block::default()
+                        .title("Emoji")
+                        .borders(Borders::ALL)
+                        .border_type(BorderType::Rounded)
+                        .border_style(Style::default().fg(Color::Cyan)),
+                ),
+            popup_area,
+        );
+    }
+
     pub fn render_floating_help(&self, f: &mut Frame) {
         // 画面中央にフローティングウィンドウを配置
         let area = f.area();
@@ -357,131 +941,26 @@ impl ChatApp {
             popup_area,
         );
 
-        let help_text = match self.ui.input_mode {
-            InputMode::Normal => vec![
-                "=== Normal Mode ===",
-                "",
-                "Movement:",
-                "  h/j/k/l or ←/↓/↑/→  - Move cursor",
-                "  0                   - Move to beginning of line",
-                "  $                   - Move to end of line",
-                "",
-                "Editing:",
-                "  i                   - Insert mode",
-                "  a                   - Append (insert after cursor)",
-                "  A                   - Append at end of line",
-                "  I                   - Insert at beginning of line",
-                "  o                   - Open new line below",
-                "  O                   - Open new line above",
-                "  v                   - Visual mode",
-                "",
-                "Actions:",
-                "  Enter               - Send message",
-                "  y                   - Yank (copy) current message",
-                "",
-                "Session:",
-                "  n                   - New session",
-                "  s                   - Save history",
-                "  S                   - Session list",
-                "  f                   - File browser",
-                
-                "  q                   - Quit",
-                "",
-                "Help:",
-                "  Ctrl+H              - Toggle this help window",
-            ],
-            InputMode::Insert => vec![
-                "=== Insert Mode ===",
-                "",
-                "Text Input:",
-                "  Type normally to enter text",
-                "  Shift+Enter         - New line (multi-line input)",
-                "  Enter               - Send message",
-                "  Esc                 - Return to Normal mode",
-                "",
-                "File References:",
-                "  @file:path          - Reference a file in your message",
-                "  Example: @file:./config.json",
-                "",
-                "AI Features:",
-                "  Ask AI to create files:",
-                "    'Create a file called test.txt with hello world'",
-                "  Ask AI to run commands:",
-                "    'List files in current directory'",
-                "    'Show git status'",
-                "",
-                "History:",
-                "  ↑/↓                 - Navigate input history",
-                "",
-                "Help:",
-                "  Ctrl+H              - Toggle this help window",
-            ],
-            InputMode::Visual => vec![
-                "=== Visual Mode ===",
-                "",
-                "Selection:",
-                "  h/j/k/l or ←/↓/↑/→  - Extend selection",
-                "  w                   - Move forward by word",
-                "  b                   - Move backward by word",
-                "",
-                "Actions:",
-                "  d                   - Delete selected text",
-                "  y                   - Yank (copy) selected text",
-                "",
-                "Exit:",
-                "  v                   - Exit Visual mode",
-                "  Esc                 - Exit Visual mode",
-                "",
-                "Help:",
-                "  Ctrl+H              - Toggle this help window",
-            ],
-            InputMode::SessionList => vec![
-                "=== Session List ===",
-                "",
-                "Navigation:",
-                "  j/k or ↓/↑          - Navigate sessions",
-                "",
-                "Actions:",
-                "  Enter               - Select session",
-                "  d                   - Delete session",
-                "  n                   - Create new session",
-                "",
-                "Exit:",
-                "  q or Esc            - Return to chat",
-                "",
-                "Help:",
-                "  Ctrl+H              - Toggle this help window",
-            ],
-            InputMode::FileBrowser => vec![
-                "=== File Browser ===",
-                "",
-                "Navigation:",
-                "  j/k or ↓/↑          - Navigate files",
-                "  u                   - Go to parent directory",
-                "  r                   - Refresh directory",
-                "",
-                "Actions:",
-                "  Enter               - Add file path to input",
-                "  Space               - Toggle file selection",
-                "  i                   - Edit selected file",
-                "",
-                "Exit:",
-                "  q                   - Return to chat",
-                "",
-                "Help:",
-                "  Ctrl+H              - Toggle this help window",
-            ],
-            // InputMode::TodoListは削除済み
+        let help_key = match self.ui.input_mode {
+            InputMode::Normal => "help.normal",
+            InputMode::Insert => "help.insert",
+            InputMode::Visual | InputMode::VisualLine | InputMode::VisualBlock => "help.visual",
+            InputMode::SessionList => "help.session_list",
+            InputMode::FileBrowser => "help.file_browser",
+            InputMode::DiffView => "help.diff_view",
+            InputMode::MessageView => "help.message_view",
+            InputMode::Permissions => "help.permissions",
         };
+        let help_text = tr(self.config.language, help_key);
 
         // ヘルプテキストを上から重ねてレンダリング
-        let content = Text::from(help_text.join("\n"));
+        let content = Text::from(help_text);
         let help_paragraph = Paragraph::new(content)
             .style(Style::default().fg(Color::White).bg(Color::Black))
             .block(
                 Block::default()
                     .borders(Borders::ALL)
-                    .title(" Help (Press Ctrl+H to close) ")
+                    .title(tr(self.config.language, "help.title"))
                     .title_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
                     .border_type(BorderType::Rounded)
                     .border_style(Style::default().fg(Color::Cyan))
@@ -492,18 +971,23 @@ impl ChatApp {
         f.render_widget(help_paragraph, popup_area);
     }
 
-    pub fn render_session_list(&mut self, f: &mut Frame) {
+    pub fn render_session_list(&mut self, f: &mut Frame, area: Rect) {
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
                 Constraint::Min(3),
+                Constraint::Length(1),
                 Constraint::Length(3),
             ])
-            .split(f.area());
+            .split(area);
+
+        // 枠線を除いた高さを1ページとし、選択中のセッションが見える位置に表示開始位置を合わせる
+        let page_size = (chunks[0].height.saturating_sub(2) as usize).max(1);
+        self.ui.session_list_page_size = page_size;
 
         // セッション一覧を表示
         let history_guard = self.history_manager.lock().unwrap();
-        let sessions = (*history_guard).get_history().get_session_list();
+        let sessions = (*history_guard).get_history().get_visible_session_list(self.ui.show_archived_sessions);
         let session_items: Vec<ListItem> = sessions
             .iter()
             .map(|session| {
@@ -520,12 +1004,21 @@ impl ChatApp {
                         format!(" - {}", preview)
                     })
                     .unwrap_or_else(|| " - No messages".to_string());
-                let title = format!("{} ({} messages){}", 
+                let unread = self.unread_responses.get(&session.id).copied().unwrap_or(0);
+                let title = format!("{}{}{} ({} messages){}", 
+                    if session.archived { "[archived] " } else { "" },
                     session.title, 
+                    if unread > 0 { format!(" [{} unread]", unread) } else { String::new() },
                     message_count, 
                     last_message
                 );
-                ListItem::new(title)
+                if unread > 0 {
+                    ListItem::new(title).style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+                } else if session.archived {
+                    ListItem::new(title).style(Style::default().fg(Color::DarkGray))
+                } else {
+                    ListItem::new(title)
+                }
             })
             .collect();
 
@@ -533,16 +1026,28 @@ impl ChatApp {
             .block(
                 Block::default()
                     .borders(Borders::ALL)
-                    .title("Chat Sessions")
+                    .title(if self.ui.show_archived_sessions { "Chat Sessions (including archived)" } else { "Chat Sessions" })
                     .border_type(BorderType::Rounded)
             )
             .highlight_style(Style::default().bg(Color::Blue).fg(Color::White))
             .highlight_symbol(">> ");
 
+        let session_count = sessions.len();
+        drop(history_guard);
+        let selected = self.ui.session_list_state.selected().unwrap_or(0);
+        self.ui.session_list_offset = visible_offset(self.ui.session_list_offset, selected, page_size, session_count);
+        *self.ui.session_list_state.offset_mut() = self.ui.session_list_offset;
         f.render_stateful_widget(session_list, chunks[0], &mut self.ui.session_list_state);
+        self.ui.session_list_area = chunks[0];
+
+        let (page, pages) = page_indicator(self.ui.session_list_offset, page_size, session_count);
+        let indicator = Paragraph::new(format!("Page {}/{}", page, pages))
+            .alignment(ratatui::layout::Alignment::Right)
+            .style(Style::default().fg(Color::Gray));
+        f.render_widget(indicator, chunks[1]);
 
         // ヘルプテキストを表示
-        let help = Paragraph::new("Use j/k to navigate, Enter to select, d to delete, n for new session, q/Esc to go back")
+        let help = Paragraph::new("Use j/k to navigate, PgUp/PgDn to page, Enter to select, d to delete, a to archive, A to show archived, n for new session, q/Esc to go back")
             .block(
                 Block::default()
                     .borders(Borders::ALL)
@@ -551,10 +1056,10 @@ impl ChatApp {
             )
             .style(Style::default().fg(Color::Gray));
 
-        f.render_widget(help, chunks[1]);
+        f.render_widget(help, chunks[2]);
     }
 
-    pub fn render_file_browser(&mut self, f: &mut Frame) {
+    pub fn render_file_browser(&mut self, f: &mut Frame, area: Rect) {
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
@@ -563,7 +1068,7 @@ impl ChatApp {
                 Constraint::Length(3),
                 Constraint::Length(4),
             ])
-            .split(f.area());
+            .split(area);
 
         // タイトル
         let title = Paragraph::new(format!("File Browser: {}", self.ui.current_directory))
@@ -573,9 +1078,8 @@ impl ChatApp {
         // ディレクトリコンテンツ
         let items: Vec<ListItem> = self.ui.directory_contents
             .iter()
-            .enumerate()
-            .map(|(_i, item)| {
-                let style = if item.ends_with('/') {
+            .map(|item| {
+                let style = if crate::file_access::is_directory_entry(item) {
                     Style::default().fg(Color::Blue)
                 } else {
                     let mut path = std::path::PathBuf::from(&self.ui.current_directory);
@@ -588,11 +1092,24 @@ impl ChatApp {
                         Style::default().fg(Color::White)
                     }
                 };
-                let prefix = if item.ends_with('/') { "📁" } else { "📄" };
-                ListItem::new(format!("{} {}", prefix, item)).style(style)
+                let prefix = self.appearance.glyph(if crate::file_access::is_directory_entry(item) { Glyph::Directory } else { Glyph::File });
+                let mut path = std::path::PathBuf::from(&self.ui.current_directory);
+                path.push(item);
+                let touched = if self.is_file_touched(&path.to_string_lossy()) { format!(" {}", self.appearance.glyph(Glyph::Touched)) } else { String::new() };
+                let binary = if self.ui.binary_entries.contains(item) { " [binary]" } else { "" };
+                let changed = if self.is_attachment_changed(&path) { " [changed]" } else { "" };
+                let label = Span::styled(format!("{} {}{}{}{}", prefix, item, binary, touched, changed), style);
+                match self.ui.git_statuses.get(item) {
+                    Some(status) => ListItem::new(Line::from(vec![
+                        label,
+                        Span::styled(format!(" {}", status.marker()), Style::default().fg(git_status_color(*status))),
+                    ])),
+                    None => ListItem::new(Line::from(label)),
+                }
             })
             .collect();
 
+        let highlight_symbol = format!("{} ", self.appearance.glyph(Glyph::Pointer));
         let list = List::new(items)
             .block(
                 Block::default()
@@ -605,7 +1122,7 @@ impl ChatApp {
                     .bg(Color::DarkGray)
                     .add_modifier(Modifier::BOLD),
             )
-            .highlight_symbol("➤ ");
+            .highlight_symbol(&highlight_symbol);
 
         f.render_stateful_widget(list, chunks[1], &mut self.ui.file_browser_state);
 
@@ -639,15 +1156,65 @@ impl ChatApp {
         f.render_widget(help, chunks[3]);
     }
 
-    pub fn render_notification(&self, f: &mut Frame, area: Rect, note: &str) {
-        let notification_paragraph = Paragraph::new(note)
+    /// 入力欄の下枠に出すステータス（上書き中の生成パラメータとセッションの料金）
+    fn input_status(&self) -> Option<String> {
+        let settings = self.gemini_client.effective_settings();
+        let mut items = Vec::new();
+        if settings.temperature_source == SettingSource::Session {
+            items.push(format!("temp {}", settings.temperature));
+        }
+        if settings.max_tokens_source == SettingSource::Session {
+            items.push(format!("max_tokens {}", settings.max_tokens));
+        }
+        if let Some(cost) = self.session_cost_status() {
+            items.push(cost);
+        }
+        if items.is_empty() {
+            None
+        } else {
+            Some(format!(" {} ", items.join(" · ")))
+        }
+    }
+
+    /// 「~$0.042 this session」形式の料金表示（使用量がなければNone）
+    fn session_cost_status(&self) -> Option<String> {
+        let history_guard = self.history_manager.lock().unwrap();
+        let usage = &history_guard.get_history().get_current_session()?.usage;
+        if usage.is_empty() {
+            return None;
+        }
+        let mut status = match usage.total_cost() {
+            Some(cost) => format!("~{} this session", format_cost(Some(cost))),
+            None => format!("{} this session", format_cost(None)),
+        };
+        if usage.total_cost().is_some() && usage.has_unpriced() {
+            status.push_str(" (+n/a)");
+        }
+        if usage.has_estimates() {
+            status.push_str(" (est.)");
+        }
+        Some(status)
+    }
+
+    pub fn render_notification(&self, f: &mut Frame, area: Rect, note: &str, color: Color) {
+        let notification_paragraph = Paragraph::new(self.appearance.text(note))
             .block(
                 Block::default()
                     .borders(Borders::ALL)
                     .title("Notification")
                     .border_type(BorderType::Rounded),
             )
-            .style(Style::default().fg(Color::Cyan));
+            .style(Style::default().fg(color));
         f.render_widget(notification_paragraph, area);
     }
 }
+
+/// gitの状態の印の色
+fn git_status_color(status: GitStatus) -> Color {
+    match status {
+        GitStatus::Modified | GitStatus::Renamed => Color::Yellow,
+        GitStatus::Added => Color::Green,
+        GitStatus::Deleted | GitStatus::Conflicted => Color::Red,
+        GitStatus::Untracked => Color::Magenta,
+    }
+}
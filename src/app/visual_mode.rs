@@ -1,56 +1,66 @@
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
-use crate::app::ChatApp;
+use crate::app::{ChatApp, InputMode};
+use crate::debug_log;
+
+/// 矩形選択の I で入力を始めたときの状態（Escで他の行にも同じ文字を入れる）
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockInsert {
+    /// 入力を始めた位置（先頭の行）
+    pub origin: usize,
+    /// 同じ文字を入れる他の行の位置
+    pub rows: Vec<usize>,
+    /// 入力を始めたときのグラフェム数
+    pub length: usize,
+}
 
 impl ChatApp {
     // Visual Modeで使用するヘルパーメソッド
+    // w/b/e は英数字と記号を別の単語として扱い、W/B/E は空白だけで区切る（Vimのword/WORD）
     pub fn move_to_next_word(&mut self) {
-        let graphemes: Vec<&str> = self.ui.input.graphemes(true).collect();
-        let mut pos = self.ui.cursor_position;
-        
-        // 現在の位置が空白でない場合、空白まで移動
-        while pos < graphemes.len() && !graphemes[pos].chars().all(char::is_whitespace) {
-            pos += 1;
-        }
-        
-        // 空白をスキップ
-        while pos < graphemes.len() && graphemes[pos].chars().all(char::is_whitespace) {
-            pos += 1;
-        }
-        
-        self.ui.cursor_position = pos.min(graphemes.len());
+        self.apply_word_motion(next_word_start, word_class);
     }
-    
+
     pub fn move_to_prev_word(&mut self) {
-        if self.ui.cursor_position == 0 {
-            return;
-        }
-        
+        self.apply_word_motion(prev_word_start, word_class);
+    }
+
+    pub fn move_to_end_word(&mut self) {
+        self.apply_word_motion(word_end, word_class);
+    }
+
+    #[allow(non_snake_case)]
+    pub fn move_to_next_WORD(&mut self) {
+        self.apply_word_motion(next_word_start, big_word_class);
+    }
+
+    #[allow(non_snake_case)]
+    pub fn move_to_prev_WORD(&mut self) {
+        self.apply_word_motion(prev_word_start, big_word_class);
+    }
+
+    #[allow(non_snake_case)]
+    pub fn move_to_end_WORD(&mut self) {
+        self.apply_word_motion(word_end, big_word_class);
+    }
+
+    fn apply_word_motion(
+        &mut self,
+        motion: WordMotion,
+        class: ClassifyFn,
+    ) {
         let graphemes: Vec<&str> = self.ui.input.graphemes(true).collect();
-        let mut pos = self.ui.cursor_position - 1;
-        
-        // 空白をスキップ
-        while pos > 0 && graphemes[pos].chars().all(char::is_whitespace) {
-            pos -= 1;
-        }
-        
-        // 単語の先頭まで移動
-        while pos > 0 && !graphemes[pos - 1].chars().all(char::is_whitespace) {
-            pos -= 1;
-        }
-        
-        self.ui.cursor_position = pos;
+        self.ui.cursor_position = motion(&graphemes, self.ui.cursor_position, class);
     }
     
     pub fn delete_visual_selection(&mut self) {
-        if let Some(start) = self.ui.visual_start {
-            let (start_pos, end_pos) = if start <= self.ui.cursor_position {
-                (start, self.ui.cursor_position + 1)
-            } else {
-                (self.ui.cursor_position, start + 1)
-            };
-            
+        if let Some((mut start_pos, end_pos)) = self.get_visual_selection_range() {
             let graphemes: Vec<&str> = self.ui.input.graphemes(true).collect();
+            // 最終行まで行単位で消す場合は、直前の改行も消して空行を残さない
+            if self.ui.input_mode == InputMode::VisualLine && end_pos >= graphemes.len() && start_pos > 0 {
+                start_pos -= 1;
+            }
             let mut new_input = String::new();
             
             for (i, grapheme) in graphemes.iter().enumerate() {
@@ -61,19 +71,360 @@ impl ChatApp {
             
             self.ui.input = new_input;
             self.ui.cursor_position = start_pos.min(self.ui.input.graphemes(true).count());
+            self.update_input_line_count();
         }
     }
     
-    pub fn get_visual_selection_range(&self) -> Option<(usize, usize)> {
-        if let Some(start) = self.ui.visual_start {
-            let end = self.ui.cursor_position;
-            if start <= end {
-                Some((start, end + 1))
-            } else {
-                Some((end, start + 1))
+    /// 矩形選択の各行のグラフェム範囲 [start, end)
+    pub fn visual_block_ranges(&self) -> Vec<(usize, usize)> {
+        let Some(start) = self.ui.visual_start else {
+            return Vec::new();
+        };
+        let graphemes: Vec<&str> = self.ui.input.graphemes(true).collect();
+        block_ranges(&graphemes, start, self.ui.cursor_position)
+    }
+
+    /// 矩形選択の範囲を各行から削除し、カーソルを矩形の左上に置く
+    pub fn delete_visual_block(&mut self) {
+        let ranges = self.visual_block_ranges();
+        let Some(&(first, _)) = ranges.first() else {
+            return;
+        };
+        let mut graphemes: Vec<&str> = self.ui.input.graphemes(true).collect();
+        for &(start, end) in ranges.iter().rev() {
+            graphemes.drain(start..end);
+        }
+        let len = graphemes.len();
+        self.ui.input = graphemes.concat();
+        self.ui.cursor_position = first.min(len);
+        self.update_input_line_count();
+    }
+
+    /// 矩形選択の I。左上の位置から入力を始め、Escで同じ文字を他の行の矩形の左端にも入れる
+    pub fn start_block_insert(&mut self) {
+        let ranges = self.visual_block_ranges();
+        let Some(&(origin, _)) = ranges.first() else {
+            return;
+        };
+        self.ui.block_insert = Some(BlockInsert {
+            origin,
+            rows: ranges[1..].iter().map(|&(start, _)| start).collect(),
+            length: self.ui.input.graphemes(true).count(),
+        });
+        self.ui.cursor_position = origin;
+        self.ui.visual_start = None;
+        self.ui.input_mode = InputMode::Insert;
+    }
+
+    /// Insertモードを抜けるときに呼ぶ。矩形選択の I で入力した文字を他の行にも入れる
+    /// 入力した位置から前に書き足しただけのとき（改行なし）に限る
+    pub fn finish_block_insert(&mut self) {
+        let Some(insert) = self.ui.block_insert.take() else {
+            return;
+        };
+        let mut graphemes: Vec<&str> = self.ui.input.graphemes(true).collect();
+        let Some(typed) = self.ui.cursor_position.checked_sub(insert.origin).filter(|&typed| typed > 0 && graphemes.len() == insert.length + typed) else {
+            return;
+        };
+        let text: Vec<&str> = graphemes[insert.origin..self.ui.cursor_position].to_vec();
+        if text.iter().any(|g| *g == "\n" || *g == "\r\n") {
+            return;
+        }
+        for &row in insert.rows.iter().rev() {
+            let position = row + typed;
+            graphemes.splice(position..position, text.iter().copied());
+        }
+        self.ui.input = graphemes.concat();
+        self.update_input_line_count();
+    }
+
+    /// 選択範囲をクリップボードにコピーし、カーソルを選択範囲の先頭に戻す
+    /// VisualLineでは行全体を末尾の改行付きでコピーし、VisualBlockでは矩形の各行を改行でつなぐ
+    pub fn yank_visual_selection(&mut self) {
+        if self.ui.input_mode == InputMode::VisualBlock {
+            let ranges = self.visual_block_ranges();
+            let graphemes: Vec<&str> = self.ui.input.graphemes(true).collect();
+            let text = ranges.iter().map(|&(start, end)| graphemes[start..end].concat()).collect::<Vec<_>>().join("\n");
+            match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(text)) {
+                Ok(_) => self.notify(format!("📋 Yanked a block of {} line(s)", ranges.len())),
+                Err(e) => {
+                    debug_log!("[yank_visual_selection] clipboard error: {:?}", e);
+                    self.notify(format!("❌ クリップボードへのコピーに失敗しました: {}", e));
+                }
+            }
+            if let Some(&(first, _)) = ranges.first() {
+                self.ui.cursor_position = first;
+            }
+            return;
+        }
+        let Some((start_pos, end_pos)) = self.get_visual_selection_range() else {
+            return;
+        };
+        let graphemes: Vec<&str> = self.ui.input.graphemes(true).collect();
+        let end_pos = end_pos.min(graphemes.len());
+        let mut text = graphemes[start_pos.min(end_pos)..end_pos].concat();
+        if self.ui.input_mode == InputMode::VisualLine && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        let line_count = text.lines().count();
+        match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(text)) {
+            Ok(_) if self.ui.input_mode == InputMode::VisualLine => {
+                self.notify(format!("📋 Yanked {} line(s)", line_count));
+            }
+            Ok(_) => {
+                self.notify(format!("📋 Yanked {} chars", end_pos.saturating_sub(start_pos)));
+            }
+            Err(e) => {
+                debug_log!("[yank_visual_selection] clipboard error: {:?}", e);
+                self.notify(format!("❌ クリップボードへのコピーに失敗しました: {}", e));
             }
+        }
+        self.ui.cursor_position = start_pos;
+    }
+
+    /// 選択範囲のグラフェム範囲 [start, end)
+    /// VisualLineでは選択範囲を含む行全体（末尾の改行を含む）に広げる
+    pub fn get_visual_selection_range(&self) -> Option<(usize, usize)> {
+        let start = self.ui.visual_start?;
+        let end = self.ui.cursor_position;
+        let (from, to) = if start <= end { (start, end) } else { (end, start) };
+        if self.ui.input_mode == InputMode::VisualLine {
+            let graphemes: Vec<&str> = self.ui.input.graphemes(true).collect();
+            Some(line_range(&graphemes, from, to))
         } else {
-            None
+            Some((from, to + 1))
+        }
+    }
+}
+
+fn is_line_break(grapheme: &str) -> bool {
+    grapheme == "\n" || grapheme == "\r\n"
+}
+
+/// anchorとcursorを対角とする矩形の、各行のグラフェム範囲 [start, end)
+/// 列は表示幅で数え、全角文字は一部でも矩形にかかれば含める。矩形の左端に届かない短い行は含めない
+fn block_ranges(graphemes: &[&str], anchor: usize, cursor: usize) -> Vec<(usize, usize)> {
+    // 各グラフェム（と末尾）の (行, 表示列, 幅)
+    let mut cells = Vec::with_capacity(graphemes.len() + 1);
+    let (mut row, mut column) = (0, 0);
+    for grapheme in graphemes {
+        if is_line_break(grapheme) {
+            cells.push((row, column, 1));
+            row += 1;
+            column = 0;
+        } else {
+            let width = grapheme.width().max(1);
+            cells.push((row, column, width));
+            column += width;
+        }
+    }
+    cells.push((row, column, 1));
+
+    let (a, c) = (cells[anchor.min(graphemes.len())], cells[cursor.min(graphemes.len())]);
+    let (top, bottom) = (a.0.min(c.0), a.0.max(c.0));
+    let (left, right) = (a.1.min(c.1), (a.1 + a.2).max(c.1 + c.2));
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    let mut last_row = None;
+    for (index, grapheme) in graphemes.iter().enumerate() {
+        let (row, column, width) = cells[index];
+        if row < top || row > bottom || is_line_break(grapheme) || column >= right || column + width <= left {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(range) if last_row == Some(row) => range.1 = index + 1,
+            _ => ranges.push((index, index + 1)),
+        }
+        last_row = Some(row);
+    }
+    ranges
+}
+
+/// from〜toを含む行全体のグラフェム範囲 [start, end)（最後の行の改行を含む）
+fn line_range(graphemes: &[&str], from: usize, to: usize) -> (usize, usize) {
+    let from = from.min(graphemes.len());
+    let to = to.min(graphemes.len());
+    let start = graphemes[..from].iter().rposition(|g| *g == "\n").map_or(0, |i| i + 1);
+    let end = graphemes[to..].iter().position(|g| *g == "\n").map_or(graphemes.len(), |i| to + i + 1);
+    (start, end)
+}
+
+/// 単語移動で使う文字の種類
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CharClass {
+    Whitespace,
+    Keyword,
+    Punctuation,
+}
+
+type ClassifyFn = fn(&str) -> CharClass;
+type WordMotion = fn(&[&str], usize, ClassifyFn) -> usize;
+
+/// word用: 英数字と_をキーワード、それ以外の記号を別の単語として分類
+pub(crate) fn word_class(grapheme: &str) -> CharClass {
+    if grapheme.chars().all(char::is_whitespace) {
+        CharClass::Whitespace
+    } else if grapheme.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        CharClass::Keyword
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+/// WORD用: 空白以外は全て同じ種類として分類
+fn big_word_class(grapheme: &str) -> CharClass {
+    if grapheme.chars().all(char::is_whitespace) {
+        CharClass::Whitespace
+    } else {
+        CharClass::Keyword
+    }
+}
+
+/// 次の単語の先頭位置
+fn next_word_start(graphemes: &[&str], pos: usize, class: ClassifyFn) -> usize {
+    let mut pos = pos;
+    if pos >= graphemes.len() {
+        return graphemes.len();
+    }
+    let start_class = class(graphemes[pos]);
+    if start_class != CharClass::Whitespace {
+        while pos < graphemes.len() && class(graphemes[pos]) == start_class {
+            pos += 1;
         }
     }
-}
\ No newline at end of file
+    while pos < graphemes.len() && class(graphemes[pos]) == CharClass::Whitespace {
+        pos += 1;
+    }
+    pos
+}
+
+/// 前の単語の先頭位置
+fn prev_word_start(graphemes: &[&str], pos: usize, class: ClassifyFn) -> usize {
+    if pos == 0 || graphemes.is_empty() {
+        return 0;
+    }
+    let mut pos = pos.min(graphemes.len()) - 1;
+    while pos > 0 && class(graphemes[pos]) == CharClass::Whitespace {
+        pos -= 1;
+    }
+    let word_class = class(graphemes[pos]);
+    while pos > 0 && class(graphemes[pos - 1]) == word_class {
+        pos -= 1;
+    }
+    pos
+}
+
+/// 現在または次の単語の末尾位置
+fn word_end(graphemes: &[&str], pos: usize, class: ClassifyFn) -> usize {
+    if graphemes.is_empty() {
+        return 0;
+    }
+    let last = graphemes.len() - 1;
+    let mut pos = (pos + 1).min(last);
+    while pos < last && class(graphemes[pos]) == CharClass::Whitespace {
+        pos += 1;
+    }
+    let word_class = class(graphemes[pos]);
+    while pos < last && class(graphemes[pos + 1]) == word_class {
+        pos += 1;
+    }
+    pos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_and_big_word_motions() {
+        let text = "foo.bar(baz) qux";
+        let graphemes: Vec<&str> = text.graphemes(true).collect();
+
+        assert_eq!(next_word_start(&graphemes, 0, word_class), 3);
+        assert_eq!(next_word_start(&graphemes, 0, big_word_class), 13);
+        assert_eq!(next_word_start(&graphemes, 13, big_word_class), graphemes.len());
+
+        assert_eq!(prev_word_start(&graphemes, 13, word_class), 11);
+        assert_eq!(prev_word_start(&graphemes, 13, big_word_class), 0);
+        assert_eq!(prev_word_start(&graphemes, 6, word_class), 4);
+
+        assert_eq!(word_end(&graphemes, 0, word_class), 2);
+        assert_eq!(word_end(&graphemes, 0, big_word_class), 11);
+        assert_eq!(word_end(&graphemes, 11, big_word_class), 15);
+    }
+
+    #[test]
+    fn test_visual_block_delete_and_insert() {
+        let text = "ab日本cd\nx\nefghij";
+        let graphemes: Vec<&str> = text.graphemes(true).collect();
+        // "b"（1行目の列1）から "h"（3行目の列3）まで: 1行目は "b日"（日は列2-3）、2行目は短いので含めない
+        assert_eq!(block_ranges(&graphemes, 1, 12), vec![(1, 3), (10, 13)]);
+        // 列3（日の右半分）から列5まで: 一部でもかかる全角文字は含める
+        assert_eq!(block_ranges(&graphemes, 3, 12), vec![(2, 4), (12, 15)]);
+
+        let (_dir, mut app) = crate::app::file_operations::tests::test_app("block", false);
+        app.ui.input = text.to_string();
+        app.ui.input_mode = InputMode::VisualBlock;
+        app.ui.visual_start = Some(1);
+        app.ui.cursor_position = 12;
+        app.delete_visual_block();
+        assert_eq!(app.ui.input, "a本cd\nx\neij");
+        assert_eq!(app.ui.cursor_position, 1);
+
+        // I で入力した文字を各行の矩形の左端に入れる
+        app.ui.input = "abc\ndef\nghi".to_string();
+        app.ui.visual_start = Some(1);
+        app.ui.cursor_position = 9;
+        app.start_block_insert();
+        assert_eq!(app.ui.input_mode, InputMode::Insert);
+        assert_eq!(app.ui.cursor_position, 1);
+        app.insert_char('-');
+        app.insert_char('>');
+        app.finish_block_insert();
+        assert_eq!(app.ui.input, "a->bc\nd->ef\ng->hi");
+
+        // 入力欄をリセットしたら、同じ長さの別の入力でEscを押しても他の行に入れない
+        app.ui.input = "abc\ndef\nghi".to_string();
+        app.ui.input_mode = InputMode::VisualBlock;
+        app.ui.visual_start = Some(1);
+        app.ui.cursor_position = 9;
+        app.start_block_insert();
+        app.reset_input_after_command();
+        assert!(app.ui.block_insert.is_none());
+        app.ui.input = "xyz\nuvw\nrstuv".to_string();
+        app.ui.cursor_position = 3;
+        app.finish_block_insert();
+        assert_eq!(app.ui.input, "xyz\nuvw\nrstuv");
+    }
+
+    #[tokio::test]
+    async fn test_ctrl_v_then_d_deletes_block() {
+        use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+        let (_dir, mut app) = crate::app::file_operations::tests::test_app("block_keys", false);
+        app.ui.input = "abc\ndef\nghi".to_string();
+        app.ui.input_mode = InputMode::Visual;
+        app.ui.visual_start = Some(1);
+        app.ui.cursor_position = 1;
+
+        app.handle_visual_mode_key(KeyEvent::new(KeyCode::Char('v'), KeyModifiers::CONTROL)).await.unwrap();
+        assert_eq!(app.ui.input_mode, InputMode::VisualBlock);
+        for code in [KeyCode::Char('j'), KeyCode::Char('j'), KeyCode::Char('l')] {
+            app.handle_visual_mode_key(KeyEvent::new(code, KeyModifiers::NONE)).await.unwrap();
+        }
+        app.handle_visual_mode_key(KeyEvent::new(KeyCode::Char('d'), KeyModifiers::NONE)).await.unwrap();
+        assert_eq!(app.ui.input, "a\nd\ng");
+        assert_eq!(app.ui.input_mode, InputMode::Normal);
+        assert_eq!(app.ui.visual_start, None);
+    }
+
+    #[test]
+    fn test_line_range_spans_three_lines_with_empty_line() {
+        let text = "ab\n\ncd\nef";
+        let graphemes: Vec<&str> = text.graphemes(true).collect();
+        // "b"（1行目）から "c"（3行目）まで選ぶと空行を含む3行全体
+        assert_eq!(line_range(&graphemes, 1, 4), (0, 7));
+        // 空行だけ
+        assert_eq!(line_range(&graphemes, 3, 3), (3, 4));
+        // 最終行は改行なしで末尾まで
+        assert_eq!(line_range(&graphemes, 8, 8), (7, 9));
+    }
+}
@@ -0,0 +1,50 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// ラベル → (セッションID, メッセージID)
+pub type Bookmarks = HashMap<String, (Uuid, Uuid)>;
+
+/// ブックマークの保存先（<config_dir>/contui/bookmarks.json）
+pub fn default_bookmarks_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("contui").join("bookmarks.json"))
+}
+
+/// ブックマークを読み込む（ファイルがなければ空）
+pub fn load_bookmarks(path: &Path) -> Result<Bookmarks> {
+    if !path.exists() {
+        return Ok(Bookmarks::new());
+    }
+    let content = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+pub fn save_bookmarks(path: &Path, bookmarks: &Bookmarks) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(bookmarks)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bookmarks_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("contui_bookmarks_{}", Uuid::new_v4()))
+            .join("bookmarks.json");
+        assert!(load_bookmarks(&path).unwrap().is_empty());
+
+        let mut bookmarks = Bookmarks::new();
+        bookmarks.insert("design".to_string(), (Uuid::new_v4(), Uuid::new_v4()));
+        save_bookmarks(&path, &bookmarks).unwrap();
+        assert_eq!(load_bookmarks(&path).unwrap(), bookmarks);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
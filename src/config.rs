@@ -1,8 +1,18 @@
 use anyhow::Result;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::custom_tools::{default_tools_path, load_custom_tools, CustomTool};
+use crate::hooks::{default_hooks_path, load_hooks, Hooks};
+use crate::file_access::{default_allowed_directories_path, load_allowed_directories, load_excluded_directories, parse_encodings, DEFAULT_FALLBACK_ENCODINGS};
+use encoding_rs::Encoding;
+use crate::i18n::Language;
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub llm: LlmConfig,
+    pub app: AppConfig,
 }
 
 #[derive(Debug, Clone)]
@@ -11,16 +21,413 @@ pub struct LlmConfig {
     pub max_tokens: Option<u32>,
     pub temperature: Option<f32>,
     pub gemini_api_key: String,
+    pub pricing: PricingTable,
+    pub provider: ProviderKind,
+    pub context_windows: ContextWindowTable,
+    /// 送信前にcountTokensでトークン数を確かめるか（TOKEN_PREFLIGHT=falseで省略）
+    pub token_preflight: bool,
+}
+
+/// LLMの呼び出し先（LLM_PROVIDER / --mock）
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ProviderKind {
+    #[default]
+    Gemini,
+    /// スクリプトの応答を返すモック（APIキー・ネットワーク不要）
+    Mock { script: PathBuf },
+}
+
+/// モデルごとの料金（USD / 1kトークン）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPricing {
+    pub input_per_1k: f64,
+    pub output_per_1k: f64,
+}
+
+impl ModelPricing {
+    pub fn cost(&self, input_tokens: u64, output_tokens: u64) -> f64 {
+        input_tokens as f64 / 1000.0 * self.input_per_1k + output_tokens as f64 / 1000.0 * self.output_per_1k
+    }
+}
+
+/// モデル名から料金を引く表
+#[derive(Debug, Clone, PartialEq)]
+pub struct PricingTable {
+    entries: HashMap<String, ModelPricing>,
 }
 
+impl Default for PricingTable {
+    /// 主要なGeminiモデルの公開料金
+    fn default() -> Self {
+        let mut entries = HashMap::new();
+        for (model, input_per_1k, output_per_1k) in [
+            ("gemini-2.5-pro", 0.00125, 0.01),
+            ("gemini-2.5-flash", 0.0003, 0.0025),
+            ("gemini-2.5-flash-lite", 0.0001, 0.0004),
+            ("gemini-2.0-flash", 0.0001, 0.0004),
+            ("gemini-2.0-flash-lite", 0.000075, 0.0003),
+        ] {
+            entries.insert(model.to_string(), ModelPricing { input_per_1k, output_per_1k });
+        }
+        Self { entries }
+    }
+}
+
+impl PricingTable {
+    /// MODEL_PRICING環境変数の形式（model=input:output,...）で料金を上書き・追加する
+    pub fn apply_overrides(&mut self, spec: &str) -> Result<()> {
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (model, prices) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Invalid pricing entry: {}", entry))?;
+            let (input, output) = prices
+                .split_once(':')
+                .ok_or_else(|| anyhow::anyhow!("Invalid pricing entry: {}", entry))?;
+            self.entries.insert(
+                model.trim().to_string(),
+                ModelPricing {
+                    input_per_1k: input.trim().parse()?,
+                    output_per_1k: output.trim().parse()?,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// 完全一致がなければ最長の前方一致で探す（例: gemini-2.5-flash-preview-05-20）
+    pub fn get(&self, model: &str) -> Option<ModelPricing> {
+        if let Some(pricing) = self.entries.get(model) {
+            return Some(*pricing);
+        }
+        self.entries
+            .iter()
+            .filter(|(name, _)| model.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, pricing)| *pricing)
+    }
+}
+
+/// モデル名からコンテキストウィンドウ（入力トークンの上限）を引く表
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextWindowTable {
+    entries: HashMap<String, u64>,
+}
+
+impl Default for ContextWindowTable {
+    /// 主要なGeminiモデルの入力トークン上限
+    fn default() -> Self {
+        let entries = [
+            ("gemini-2.5-pro", 1_048_576),
+            ("gemini-2.5-flash", 1_048_576),
+            ("gemini-2.5-flash-lite", 1_048_576),
+            ("gemini-2.0-flash", 1_048_576),
+            ("gemini-2.0-flash-lite", 1_048_576),
+            ("gemini-1.5-pro", 2_097_152),
+            ("gemini-1.5-flash", 1_048_576),
+        ]
+        .into_iter()
+        .map(|(model, tokens)| (model.to_string(), tokens))
+        .collect();
+        Self { entries }
+    }
+}
+
+impl ContextWindowTable {
+    /// MODEL_CONTEXT_WINDOWS環境変数の形式（model=tokens,...）で上限を上書き・追加する
+    pub fn apply_overrides(&mut self, spec: &str) -> Result<()> {
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (model, tokens) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Invalid context window entry: {}", entry))?;
+            self.entries.insert(model.trim().to_string(), tokens.trim().parse()?);
+        }
+        Ok(())
+    }
+
+    /// 完全一致がなければ最長の前方一致で探す（不明なモデルはNone）
+    pub fn get(&self, model: &str) -> Option<u64> {
+        if let Some(tokens) = self.entries.get(model) {
+            return Some(*tokens);
+        }
+        self.entries
+            .iter()
+            .filter(|(name, _)| model.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, tokens)| *tokens)
+    }
+}
+
+/// 料金の表示（小数点以下3桁、料金不明はn/a）
+pub fn format_cost(cost: Option<f64>) -> String {
+    match cost {
+        Some(cost) => format!("${:.3}", cost),
+        None => "n/a".to_string(),
+    }
+}
+
+/// MAX_PROMPT_BYTESを指定しないときのプロンプトの上限バイト数
+pub const DEFAULT_MAX_PROMPT_BYTES: usize = 200_000;
+
+/// MESSAGE_MAX_LINESを指定しないときにメッセージを折りたたむ行数
+pub const DEFAULT_MESSAGE_MAX_LINES: usize = 300;
+
+/// アプリケーション動作に関する設定
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    /// execute_commandの実行も変更履歴に記録するか
+    pub track_command_changes: bool,
+    /// コンテキストから外れた古いメッセージを自動で要約するか
+    pub auto_compact: bool,
+    /// セッションのメッセージ数の上限（超えたら古いものから自動で削除する。Noneなら無制限）
+    pub max_session_messages: Option<usize>,
+    /// 時間のかかったリクエストが終わったらデスクトップに通知するか
+    pub desktop_notifications: bool,
+    /// デスクトップに通知するリクエストの最短の所要秒数
+    pub desktop_notification_min_secs: u64,
+    /// ゴミ箱のセッションを起動時に完全に削除するまでの日数
+    pub trash_retention_days: u64,
+    /// 端末の幅がこれ以上なら起動時から分割表示にする（Noneなら Ctrl+P で切り替えるまで分割しない）
+    pub split_pane_min_width: Option<u16>,
+    /// デスクトップ通知を表示できないときに端末のベルを鳴らすか
+    pub terminal_bell: bool,
+    /// execute_commandで使うシェル
+    pub shell: ShellKind,
+    /// execute_commandのタイムアウト秒数（Noneなら無制限）
+    pub command_timeout_secs: Option<u64>,
+    /// 通知バーの通知を自動で消すまでの秒数（Noneならキーを押すまで表示する）
+    pub notification_timeout_secs: Option<u64>,
+    /// エージェントループの最大ステップ数
+    pub agent_max_steps: usize,
+    /// 直前とこれ以上似た応答が続いたらエージェントループを止める（Noneなら判定しない）
+    pub agent_repeat_similarity: Option<f64>,
+    /// is_finishedのない応答がこの回数続いたらフラグだけを尋ねる（Noneなら尋ねない）
+    pub agent_nudge_after: Option<usize>,
+    /// 添付ファイルを含むプロンプトの上限バイト数（超えると省略または分割して送る）
+    pub max_prompt_bytes: usize,
+    /// tools.tomlで定義したカスタムツール
+    pub custom_tools: Vec<CustomTool>,
+    /// hooks.tomlで定義したフック
+    pub hooks: Hooks,
+    /// delete_directoryでディレクトリを中身ごと削除することを許可するか
+    pub allow_recursive_delete: bool,
+    /// AIの応答に含まれる旧形式の```create_file:...```ブロックでファイルを作成するか
+    pub legacy_file_blocks: bool,
+    /// 起動時に許可するディレクトリ（allowed_directoriesファイルで明示したもの）
+    pub allowed_directories: Vec<PathBuf>,
+    /// 起動時に作業ディレクトリでも許可しないディレクトリ（/permissions で外した作業ディレクトリ）
+    pub excluded_directories: Vec<PathBuf>,
+    /// 「常に許可」したディレクトリを書き込むファイル
+    pub allowed_directories_file: Option<PathBuf>,
+    /// UTF-8で読めなかったファイルに試すエンコーディング
+    pub fallback_encodings: Vec<&'static Encoding>,
+    /// AIが読んだファイルの内容を、更新時刻が変わるまでキャッシュするか
+    pub file_cache: bool,
+    /// メッセージの配色（MESSAGE_COLORS、例: "user=green/black,ai=blue"）
+    pub message_colors: Option<String>,
+    /// 応答・表示の言語
+    pub language: Language,
+    /// チャット画面で1件のメッセージを折りたたむ行数（折り返し後。0なら折りたたまない）
+    pub message_max_lines: usize,
+    /// 絵文字・記号をASCII表記（[DIR], [OK] など）で表示するか
+    pub ascii_mode: bool,
+    /// 明るい色と太字だけで表示するか
+    pub high_contrast: bool,
+    /// LLMに送る前に秘密情報を伏せるか（falseなら起動時から /redact off と同じ）
+    pub redact_secrets: bool,
+    /// 既定に加える伏せ字のパターン（REDACT_PATTERNS、空白区切りの「種類=正規表現」）
+    pub redact_patterns: Option<String>,
+}
+
+/// execute_commandでコマンドを渡すシェル
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShellKind {
+    Sh,
+    Cmd,
+    PowerShell,
+}
+
+impl ShellKind {
+    /// COMMAND_SHELL環境変数の値（sh / cmd / powershell）から変換
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "sh" => Some(ShellKind::Sh),
+            "cmd" => Some(ShellKind::Cmd),
+            "powershell" | "pwsh" => Some(ShellKind::PowerShell),
+            _ => None,
+        }
+    }
+
+    /// プラットフォームの標準シェル（Windowsはcmd、それ以外はsh）
+    pub fn platform_default() -> Self {
+        if cfg!(windows) {
+            ShellKind::Cmd
+        } else {
+            ShellKind::Sh
+        }
+    }
+
+    /// コマンド引数として1語になるようにクォートする
+    pub fn quote(&self, arg: &str) -> String {
+        match self {
+            ShellKind::Sh => format!("'{}'", arg.replace('\'', "'\\''")),
+            ShellKind::Cmd => quote_for_cmd(arg),
+            ShellKind::PowerShell => format!("'{}'", arg.replace('\'', "''")),
+        }
+    }
+
+    /// コマンドを実行するプログラムと引数
+    pub fn program_and_args(&self, command: &str) -> (&'static str, Vec<String>) {
+        match self {
+            ShellKind::Sh => ("sh", vec!["-c".to_string(), command.to_string()]),
+            ShellKind::Cmd => ("cmd", vec!["/C".to_string(), command.to_string()]),
+            ShellKind::PowerShell => (
+                "powershell",
+                vec!["-NoProfile".to_string(), "-Command".to_string(), command.to_string()],
+            ),
+        }
+    }
+}
+
+/// cmd用のクォート。プログラムが受け取る形（CommandLineToArgvWの規則）でクォートしてから、
+/// cmdが解釈する文字（% ! ^ & | < > ( ) "）を全て ^ でエスケープする
+fn quote_for_cmd(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                continue;
+            }
+            '"' => quoted.push_str(&"\\".repeat(backslashes * 2 + 1)),
+            _ => quoted.push_str(&"\\".repeat(backslashes)),
+        }
+        backslashes = 0;
+        quoted.push(c);
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+        .chars()
+        .flat_map(|c| if "%!^&|<>()\"".contains(c) { vec!['^', c] } else { vec![c] })
+        .collect()
+}
+
+/// MODELを指定しないときのモデル
+pub const DEFAULT_MODEL: &str = "gemini-2.5-flash";
+
+/// 初回セットアップで作る設定ファイルの場所（<config_dir>/contui/config.env、.envと同じ形式）
+pub fn default_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("contui").join("config.env"))
+}
+
+/// カレントディレクトリの.envと設定ファイルを読み込む（先に設定された値が優先。読み込んだ.envのパスを返す）
+pub fn load_env_files() -> Option<PathBuf> {
+    let dotenv_path = dotenv::dotenv().ok();
+    if let Some(path) = default_config_path() {
+        dotenv::from_path(path).ok();
+    }
+    dotenv_path
+}
+
+/// GEMINI_API_KEYがどこにも設定されていないときのエラー（初回起動ならセットアップを始める）
+#[derive(Debug)]
+pub struct MissingApiKey;
+
+impl fmt::Display for MissingApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GEMINI_API_KEY is not set. Set it in .env or the environment")?;
+        if let Some(path) = default_config_path() {
+            write!(f, ", or in {}", path.display())?;
+        }
+        write!(f, " (run contui without --no-wizard to create it interactively)")
+    }
+}
+
+impl std::error::Error for MissingApiKey {}
+
 impl Config {
     pub fn load() -> Result<Self> {
-        dotenv::dotenv().ok();
+        Self::load_with_provider(None)
+    }
 
-        let model: String = std::env::var("MODEL").unwrap_or_else(|_| "gemini-2.5-flash".to_string());
-        let gemini_api_key: String = std::env::var("GEMINI_API_KEY").expect("GEMINI_API_KEY must be set");
+    /// providerを指定した場合は環境変数のLLM_PROVIDERより優先する
+    pub fn load_with_provider(provider: Option<ProviderKind>) -> Result<Self> {
+        load_env_files();
+
+        let provider = match provider {
+            Some(provider) => provider,
+            None => match std::env::var("LLM_PROVIDER").ok().as_deref() {
+                None | Some("gemini") => ProviderKind::Gemini,
+                Some("mock") => ProviderKind::Mock {
+                    script: std::env::var("MOCK_SCRIPT")
+                        .map(PathBuf::from)
+                        .map_err(|_| anyhow::anyhow!("MOCK_SCRIPT must be set when LLM_PROVIDER=mock"))?,
+                },
+                Some(other) => return Err(anyhow::anyhow!("Unknown LLM_PROVIDER: {} (expected gemini or mock)", other)),
+            },
+        };
+        let model: String = std::env::var("MODEL").unwrap_or_else(|_| DEFAULT_MODEL.to_string());
+        // モックはAPIを呼ばないのでAPIキーは不要
+        let gemini_api_key: String = match provider {
+            ProviderKind::Gemini => std::env::var("GEMINI_API_KEY").ok().filter(|key| !key.trim().is_empty()).ok_or(MissingApiKey)?,
+            ProviderKind::Mock { .. } => std::env::var("GEMINI_API_KEY").unwrap_or_default(),
+        };
         let max_tokens: Option<u32> = std::env::var("MAX_TOKENS").ok().and_then(|v| v.parse().ok()).or(Some(4096));
         let temperature: Option<f32> = std::env::var("TEMPERATURE").ok().and_then(|v| v.parse().ok()).or(Some(0.5));
+        let mut pricing = PricingTable::default();
+        if let Ok(spec) = std::env::var("MODEL_PRICING") {
+            pricing.apply_overrides(&spec)?;
+        }
+        let mut context_windows = ContextWindowTable::default();
+        if let Ok(spec) = std::env::var("MODEL_CONTEXT_WINDOWS") {
+            context_windows.apply_overrides(&spec)?;
+        }
+        let token_preflight: bool = std::env::var("TOKEN_PREFLIGHT").ok().and_then(|v| v.parse().ok()).unwrap_or(true);
+        let track_command_changes: bool = std::env::var("TRACK_COMMAND_CHANGES").ok().and_then(|v| v.parse().ok()).unwrap_or(false);
+        let auto_compact: bool = std::env::var("AUTO_COMPACT").ok().and_then(|v| v.parse().ok()).unwrap_or(true);
+        let desktop_notifications: bool = std::env::var("DESKTOP_NOTIFICATIONS").ok().and_then(|v| v.parse().ok()).unwrap_or(false);
+        let desktop_notification_min_secs: u64 = std::env::var("DESKTOP_NOTIFICATION_MIN_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30);
+        let trash_retention_days: u64 = std::env::var("TRASH_RETENTION_DAYS").ok().and_then(|v| v.parse().ok()).unwrap_or(7);
+        let split_pane_min_width: Option<u16> = std::env::var("SPLIT_PANE_MIN_WIDTH").ok().and_then(|v| v.parse().ok()).filter(|&w| w > 0);
+        let terminal_bell: bool = std::env::var("TERMINAL_BELL").ok().and_then(|v| v.parse().ok()).unwrap_or(true);
+        let max_session_messages: Option<usize> = std::env::var("MAX_SESSION_MESSAGES").ok().and_then(|v| v.parse().ok()).filter(|&max| max > 0);
+        let shell: ShellKind = std::env::var("COMMAND_SHELL").ok().and_then(|v| ShellKind::parse(&v)).unwrap_or_else(ShellKind::platform_default);
+        // 0を指定するとタイムアウトなし
+        let command_timeout_secs: Option<u64> = std::env::var("COMMAND_TIMEOUT_SECS").ok().and_then(|v| v.parse().ok()).or(Some(30)).filter(|&secs| secs > 0);
+        let notification_timeout_secs: Option<u64> = std::env::var("NOTIFICATION_TIMEOUT_SECS").ok().and_then(|v| v.parse().ok()).or(Some(5)).filter(|&secs| secs > 0);
+        let agent_max_steps: usize = std::env::var("AGENT_MAX_STEPS").ok().and_then(|v| v.parse().ok()).filter(|&steps| steps > 0).unwrap_or(10);
+        let agent_repeat_similarity: Option<f64> = std::env::var("AGENT_REPEAT_SIMILARITY").ok().and_then(|v| v.parse().ok()).or(Some(0.9)).filter(|&similarity| similarity > 0.0);
+        let agent_nudge_after: Option<usize> = std::env::var("AGENT_NUDGE_AFTER").ok().and_then(|v| v.parse().ok()).or(Some(2)).filter(|&count| count > 0);
+        let max_prompt_bytes: usize = std::env::var("MAX_PROMPT_BYTES").ok().and_then(|v| v.parse().ok()).filter(|&bytes| bytes > 0).unwrap_or(DEFAULT_MAX_PROMPT_BYTES);
+        let allow_recursive_delete: bool = std::env::var("ALLOW_RECURSIVE_DELETE").ok().and_then(|v| v.parse().ok()).unwrap_or(false);
+        let legacy_file_blocks: bool = std::env::var("LEGACY_FILE_BLOCKS").ok().and_then(|v| v.parse().ok()).unwrap_or(false);
+        let file_cache: bool = std::env::var("FILE_CACHE").ok().and_then(|v| v.parse().ok()).unwrap_or(false);
+        let allowed_directories_file = std::env::var("ALLOWED_DIRECTORIES_FILE").ok().map(PathBuf::from).or_else(default_allowed_directories_path);
+        let (allowed_directories, excluded_directories) = match &allowed_directories_file {
+            Some(path) => (load_allowed_directories(path)?, load_excluded_directories(path)?),
+            None => (Vec::new(), Vec::new()),
+        };
+        let fallback_encodings = match std::env::var("FILE_ENCODINGS") {
+            Ok(labels) => parse_encodings(&labels.split(',').filter(|label| !label.trim().is_empty()).collect::<Vec<_>>())?,
+            Err(_) => parse_encodings(DEFAULT_FALLBACK_ENCODINGS)?,
+        };
+        let message_colors = std::env::var("MESSAGE_COLORS").ok();
+        let message_max_lines: usize = std::env::var("MESSAGE_MAX_LINES").ok().and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_MESSAGE_MAX_LINES);
+        let ascii_mode: bool = std::env::var("ASCII_MODE").ok().and_then(|v| v.parse().ok()).unwrap_or(false);
+        let high_contrast: bool = std::env::var("HIGH_CONTRAST").ok().and_then(|v| v.parse().ok()).unwrap_or(false);
+        let redact_secrets: bool = std::env::var("REDACT_SECRETS").ok().and_then(|v| v.parse().ok()).unwrap_or(true);
+        let redact_patterns = std::env::var("REDACT_PATTERNS").ok();
+        let language = Language::from_setting(std::env::var("CONTUI_LANGUAGE").ok().as_deref())
+            .ok_or_else(|| anyhow::anyhow!("Unknown CONTUI_LANGUAGE (expected en, ja or auto)"))?;
+        let custom_tools = match std::env::var("CUSTOM_TOOLS_FILE").ok().map(PathBuf::from).or_else(default_tools_path) {
+            Some(path) => load_custom_tools(&path)?,
+            None => Vec::new(),
+        };
+        let hooks = match std::env::var("HOOKS_FILE").ok().map(PathBuf::from).or_else(default_hooks_path) {
+            Some(path) => load_hooks(&path)?,
+            None => Hooks::default(),
+        };
 
         Ok(Config {
             llm: LlmConfig {
@@ -28,7 +435,81 @@ impl Config {
                 max_tokens,
                 temperature,
                 gemini_api_key,
+                pricing,
+                provider,
+                context_windows,
+                token_preflight,
+            },
+            app: AppConfig {
+                track_command_changes,
+                auto_compact,
+                max_session_messages,
+                desktop_notifications,
+                desktop_notification_min_secs,
+                trash_retention_days,
+                split_pane_min_width,
+                terminal_bell,
+                shell,
+                command_timeout_secs,
+                notification_timeout_secs,
+                agent_max_steps,
+                agent_repeat_similarity,
+                agent_nudge_after,
+                max_prompt_bytes,
+                custom_tools,
+                hooks,
+                allow_recursive_delete,
+                legacy_file_blocks,
+                allowed_directories,
+                excluded_directories,
+                allowed_directories_file,
+                fallback_encodings,
+                file_cache,
+                message_colors,
+                language,
+                message_max_lines,
+                ascii_mode,
+                high_contrast,
+                redact_secrets,
+                redact_patterns,
             },
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pricing_lookup_and_overrides() {
+        let mut pricing = PricingTable::default();
+        pricing.apply_overrides("my-model=0.5:1.5").unwrap();
+        assert_eq!(pricing.get("my-model").unwrap().cost(2000, 1000), 2.5);
+        assert_eq!(pricing.get("gemini-2.5-flash-lite-preview").unwrap().input_per_1k, 0.0001);
+        assert!(pricing.get("unknown-model").is_none());
+        assert!(pricing.apply_overrides("broken").is_err());
+        assert_eq!(format_cost(Some(0.04249)), "$0.042");
+        assert_eq!(format_cost(None), "n/a");
+    }
+
+    #[test]
+    fn test_context_window_lookup_and_overrides() {
+        let mut windows = ContextWindowTable::default();
+        assert_eq!(windows.get("gemini-2.5-flash-preview-05-20"), Some(1_048_576));
+        assert_eq!(windows.get("unknown-model"), None);
+        windows.apply_overrides("my-model=32768, gemini-2.5-flash=500000").unwrap();
+        assert_eq!(windows.get("my-model"), Some(32_768));
+        assert_eq!(windows.get("gemini-2.5-flash"), Some(500_000));
+        assert!(windows.apply_overrides("my-model=lots").is_err());
+    }
+
+    #[test]
+    fn test_cmd_quote_escapes_metacharacters() {
+        let cmd = ShellKind::Cmd;
+        assert_eq!(cmd.quote(r"C:\my dir\a.txt"), r#"^"C:\my dir\a.txt^""#);
+        assert_eq!(cmd.quote("%PATH% ^ !x! & echo"), r#"^"^%PATH^% ^^ ^!x^! ^& echo^""#);
+        assert_eq!(cmd.quote(r#"say "hi"\"#), r#"^"say \^"hi\^"\\^""#);
+        assert_eq!(ShellKind::Sh.quote("it's"), r"'it'\''s'");
+    }
+}
@@ -0,0 +1,166 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::ShellKind;
+
+/// 組み込みの関数呼び出しと同じ名前のツールは定義できない
+const RESERVED_TOOL_NAMES: &[&str] = &["create_file", "edit_file", "execute_command", "create_directory", "delete_directory"];
+
+/// 設定ファイルで定義するカスタムツール
+/// commandの {param} はモデルから渡された引数をシェル用にエスケープして置き換える
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct CustomTool {
+    pub name: String,
+    pub description: String,
+    #[serde(default = "empty_object_schema")]
+    pub parameters: Value,
+    pub command: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolsFile {
+    #[serde(default)]
+    tools: Vec<CustomTool>,
+}
+
+fn empty_object_schema() -> Value {
+    serde_json::json!({"type": "object", "properties": {}})
+}
+
+/// カスタムツール定義ファイルの既定の場所（<config_dir>/contui/tools.toml）
+pub fn default_tools_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("contui").join("tools.toml"))
+}
+
+/// tools.tomlの [[tools]] を読み込む（ファイルがなければ空）
+pub fn load_custom_tools(path: &Path) -> Result<Vec<CustomTool>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(path)?;
+    parse_custom_tools(&content).with_context(|| format!("Invalid tools file: {}", path.display()))
+}
+
+fn parse_custom_tools(content: &str) -> Result<Vec<CustomTool>> {
+    let file: ToolsFile = toml::from_str(content)?;
+    for (i, tool) in file.tools.iter().enumerate() {
+        if RESERVED_TOOL_NAMES.contains(&tool.name.as_str()) {
+            return Err(anyhow!("Tool name '{}' is reserved", tool.name));
+        }
+        if file.tools[..i].iter().any(|t| t.name == tool.name) {
+            return Err(anyhow!("Tool '{}' is defined more than once", tool.name));
+        }
+    }
+    Ok(file.tools)
+}
+
+impl CustomTool {
+    /// 宣言されたスキーマ（type / required / properties.*.type / enum）で引数を検証する
+    /// 違反があればその内容を全て返す
+    pub fn validate_args(&self, args: &Value) -> std::result::Result<(), Vec<String>> {
+        let Some(args) = args.as_object() else {
+            return Err(vec!["arguments must be an object".to_string()]);
+        };
+        let mut violations = Vec::new();
+        if let Some(required) = self.parameters["required"].as_array() {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !args.contains_key(name) {
+                    violations.push(format!("missing required parameter '{}'", name));
+                }
+            }
+        }
+        let properties = self.parameters["properties"].as_object();
+        for (name, value) in args {
+            let Some(schema) = properties.and_then(|p| p.get(name)) else {
+                violations.push(format!("unknown parameter '{}'", name));
+                continue;
+            };
+            if let Some(expected) = schema["type"].as_str() {
+                if !matches_type(value, expected) {
+                    violations.push(format!("parameter '{}' must be of type {}", name, expected));
+                    continue;
+                }
+            }
+            if let Some(allowed) = schema["enum"].as_array() {
+                if !allowed.contains(value) {
+                    violations.push(format!("parameter '{}' must be one of {}", name, Value::Array(allowed.clone())));
+                }
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// commandテンプレートの {param} を引数で置き換えたコマンドを作る
+    /// 渡されなかった引数は空文字列として扱う
+    pub fn render_command(&self, args: &Value, shell: ShellKind) -> String {
+        let mut command = self.command.clone();
+        if let Some(properties) = self.parameters["properties"].as_object() {
+            for name in properties.keys() {
+                let value = match &args[name] {
+                    Value::Null => String::new(),
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                command = command.replace(&format!("{{{}}}", name), &shell.quote(&value));
+            }
+        }
+        command
+    }
+}
+
+fn matches_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOOLS: &str = r#"
+[[tools]]
+name = "run_tests"
+description = "Run the test suite"
+command = "cargo test {filter} --release={release}"
+
+[tools.parameters]
+type = "object"
+required = ["filter"]
+
+[tools.parameters.properties.filter]
+type = "string"
+
+[tools.parameters.properties.release]
+type = "boolean"
+"#;
+
+    #[test]
+    fn test_custom_tools_parse_validate_and_render() {
+        let tools = parse_custom_tools(TOOLS).unwrap();
+        assert_eq!(tools.len(), 1);
+        let tool = &tools[0];
+
+        assert!(tool.validate_args(&serde_json::json!({"filter": "history", "release": true})).is_ok());
+        let violations = tool.validate_args(&serde_json::json!({"release": "yes", "extra": 1})).unwrap_err();
+        assert_eq!(violations.len(), 3);
+
+        let command = tool.render_command(&serde_json::json!({"filter": "it's; rm -rf /", "release": false}), ShellKind::Sh);
+        assert_eq!(command, r#"cargo test 'it'\''s; rm -rf /' --release='false'"#);
+
+        assert!(parse_custom_tools("[[tools]]\nname = \"edit_file\"\ndescription = \"\"\ncommand = \"true\"").is_err());
+    }
+}
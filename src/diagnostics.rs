@@ -0,0 +1,256 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::custom_tools::{default_tools_path, load_custom_tools};
+
+/// Gemini APIのホスト
+const API_HOST: &str = "generativelanguage.googleapis.com";
+/// ネットワーク系チェックのタイムアウト
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 診断項目1件の結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String,
+    /// 失敗時に表示する対処方法
+    pub remedy: Option<&'static str>,
+}
+
+impl CheckResult {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, passed: true, detail: detail.into(), remedy: None }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, remedy: &'static str) -> Self {
+        Self { name, passed: false, detail: detail.into(), remedy: Some(remedy) }
+    }
+}
+
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mark = if self.passed { "✅" } else { "❌" };
+        write!(f, "{} {}: {}", mark, self.name, self.detail)?;
+        if let (false, Some(remedy)) = (self.passed, self.remedy) {
+            write!(f, "\n   → {}", remedy)?;
+        }
+        Ok(())
+    }
+}
+
+/// /doctor・contui --doctor の診断結果
+#[derive(Debug, Clone, Default)]
+pub struct DiagnosticsReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl DiagnosticsReport {
+    pub fn all_passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+}
+
+impl fmt::Display for DiagnosticsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "contui doctor")?;
+        for check in &self.checks {
+            writeln!(f, "{}", check)?;
+        }
+        let failed = self.checks.iter().filter(|check| !check.passed).count();
+        match failed {
+            0 => write!(f, "All {} checks passed.", self.checks.len()),
+            n => write!(f, "{} of {} checks failed.", n, self.checks.len()),
+        }
+    }
+}
+
+/// 全ての診断を順に実行する
+/// 設定は環境変数（.env）から直接読むので、Config::loadが失敗する状態でも実行できる
+/// 端末への問い合わせはイベントループと競合しないよう、呼び出し側で行った結果を受け取る
+pub async fn run_diagnostics(keyboard_enhancement: bool) -> DiagnosticsReport {
+    let dotenv_path = crate::config::load_env_files();
+    let mut checks = vec![check_config(dotenv_path.as_deref())];
+
+    let api_key = std::env::var("GEMINI_API_KEY").ok().filter(|key| !key.trim().is_empty());
+    checks.push(match &api_key {
+        Some(key) => CheckResult::pass("API key", format!("GEMINI_API_KEY is set ({} chars)", key.len())),
+        None => CheckResult::fail("API key", "GEMINI_API_KEY is not set", "Set GEMINI_API_KEY in .env or the environment"),
+    });
+
+    let dns = check_dns().await;
+    let dns_ok = dns.passed;
+    checks.push(dns);
+    let client = reqwest::Client::builder().timeout(NETWORK_TIMEOUT).build().unwrap_or_default();
+    if dns_ok {
+        checks.push(check_tls(&client).await);
+    }
+    let model = std::env::var("MODEL").unwrap_or_else(|_| crate::config::DEFAULT_MODEL.to_string());
+    if let (Some(key), true) = (&api_key, dns_ok) {
+        checks.push(check_model(&client, key, &model).await);
+    }
+
+    if let Some(history_dir) = crate::history::history_dir() {
+        checks.push(check_writable("History directory", &history_dir));
+    }
+    checks.push(check_writable("Log directory", Path::new(".")));
+    checks.push(check_terminal(keyboard_enhancement));
+
+    DiagnosticsReport { checks }
+}
+
+fn check_config(dotenv_path: Option<&Path>) -> CheckResult {
+    let env_file = match dotenv_path {
+        Some(path) => format!(".env loaded from {}", path.display()),
+        None => "no .env file, using environment variables".to_string(),
+    };
+    let tools_path = std::env::var("CUSTOM_TOOLS_FILE").ok().map(Into::into).or_else(default_tools_path);
+    match tools_path.map(|path| load_custom_tools(&path)).transpose() {
+        Ok(tools) => CheckResult::pass(
+            "Config",
+            format!("{}; {} custom tool(s)", env_file, tools.map_or(0, |tools| tools.len())),
+        ),
+        Err(e) => CheckResult::fail("Config", format!("{:#}", e), "Fix the syntax of tools.toml or unset CUSTOM_TOOLS_FILE"),
+    }
+}
+
+async fn check_dns() -> CheckResult {
+    match tokio::time::timeout(NETWORK_TIMEOUT, tokio::net::lookup_host((API_HOST, 443))).await {
+        Ok(Ok(mut addresses)) => match addresses.next() {
+            Some(address) => CheckResult::pass("DNS", format!("{} → {}", API_HOST, address.ip())),
+            None => CheckResult::fail("DNS", format!("{} has no addresses", API_HOST), "Check your DNS settings"),
+        },
+        Ok(Err(e)) => CheckResult::fail("DNS", e.to_string(), "Check your network connection and DNS settings"),
+        Err(_) => CheckResult::fail("DNS", "lookup timed out", "Check your network connection and DNS settings"),
+    }
+}
+
+async fn check_tls(client: &reqwest::Client) -> CheckResult {
+    match client.get(format!("https://{}/", API_HOST)).send().await {
+        Ok(response) => CheckResult::pass("TLS", format!("https://{} reachable (HTTP {})", API_HOST, response.status().as_u16())),
+        Err(e) => CheckResult::fail("TLS", e.to_string(), "Check proxy/firewall settings (HTTPS_PROXY) and system certificates"),
+    }
+}
+
+/// countTokensを1回呼ぶ（生成は行わない）
+async fn count_tokens_request(client: &reqwest::Client, api_key: &str, model: &str) -> reqwest::Result<reqwest::Response> {
+    let url = format!("https://{}/v1beta/models/{}:countTokens?key={}", API_HOST, model, api_key);
+    let body = serde_json::json!({"contents": [{"parts": [{"text": "ping"}]}]});
+    client.post(url).json(&body).send().await
+}
+
+/// 初回セットアップでのAPIキーの確認結果
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyValidation {
+    Valid,
+    /// APIが拒否した（キーかモデル名が誤り）
+    Rejected(String),
+    /// APIに接続できなかった（オフラインなど）
+    Unreachable(String),
+}
+
+/// countTokensでAPIキーとモデル名が使えるか確認する
+pub async fn validate_api_key(api_key: &str, model: &str) -> KeyValidation {
+    let client = reqwest::Client::builder().timeout(NETWORK_TIMEOUT).build().unwrap_or_default();
+    match count_tokens_request(&client, api_key, model).await {
+        Ok(response) if response.status().is_success() => KeyValidation::Valid,
+        Ok(response) => {
+            let status = response.status().as_u16();
+            let reason = match status {
+                404 => format!("HTTP 404: model {} was not found", model),
+                400 | 401 | 403 => format!("HTTP {}: the API key was rejected", status),
+                _ => format!("HTTP {}", status),
+            };
+            match status {
+                400..=499 if status != 429 => KeyValidation::Rejected(reason),
+                _ => KeyValidation::Unreachable(reason),
+            }
+        }
+        Err(e) => KeyValidation::Unreachable(e.to_string()),
+    }
+}
+
+/// countTokensでAPIキーとモデル名を確認する（生成は行わない）
+async fn check_model(client: &reqwest::Client, api_key: &str, model: &str) -> CheckResult {
+    match count_tokens_request(client, api_key, model).await {
+        Ok(response) if response.status().is_success() => {
+            CheckResult::pass("API request", format!("countTokens succeeded for {}", model))
+        }
+        Ok(response) => {
+            let status = response.status().as_u16();
+            let remedy = match status {
+                400 | 401 | 403 => "Check that GEMINI_API_KEY is valid and the Generative Language API is enabled",
+                404 => "Check the MODEL name (e.g. gemini-2.5-flash)",
+                429 => "Rate limited; wait a moment or check your quota",
+                _ => "Retry later; the API may be temporarily unavailable",
+            };
+            CheckResult::fail("API request", format!("HTTP {} for model {}", status, model), remedy)
+        }
+        Err(e) => CheckResult::fail("API request", e.to_string(), "Check your network connection"),
+    }
+}
+
+/// ディレクトリに一時ファイルを作成・削除できるか
+fn check_writable(name: &'static str, dir: &Path) -> CheckResult {
+    let probe = dir.join(format!(".contui_doctor_{}", std::process::id()));
+    let result = fs::create_dir_all(dir).and_then(|_| fs::write(&probe, b"ok")).and_then(|_| fs::remove_file(&probe));
+    match result {
+        Ok(()) => CheckResult::pass(name, format!("{} is writable", dir.display())),
+        Err(e) => CheckResult::fail(name, format!("{}: {}", dir.display(), e), "Fix the directory permissions or free up disk space"),
+    }
+}
+
+fn check_terminal(keyboard_enhancement: bool) -> CheckResult {
+    let colors = color_depth(std::env::var("COLORTERM").ok().as_deref(), std::env::var("TERM").ok().as_deref());
+    let detail = format!(
+        "keyboard enhancement: {}, mouse capture: enabled, colors: {}",
+        if keyboard_enhancement { "yes" } else { "no" },
+        colors
+    );
+    if keyboard_enhancement {
+        CheckResult::pass("Terminal", detail)
+    } else {
+        CheckResult::fail(
+            "Terminal",
+            detail,
+            "Shift+Enter may not insert a newline; use a terminal with the kitty keyboard protocol (kitty, WezTerm, foot, Alacritty)",
+        )
+    }
+}
+
+/// COLORTERM・TERMから色数を推定する
+fn color_depth(colorterm: Option<&str>, term: Option<&str>) -> &'static str {
+    if matches!(colorterm, Some("truecolor" | "24bit")) {
+        "24-bit"
+    } else if term.is_some_and(|term| term.contains("256color")) {
+        "256"
+    } else {
+        "16"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_format_and_color_depth() {
+        let report = DiagnosticsReport {
+            checks: vec![
+                CheckResult::pass("DNS", "ok"),
+                CheckResult::fail("API key", "GEMINI_API_KEY is not set", "Set GEMINI_API_KEY"),
+            ],
+        };
+        assert!(!report.all_passed());
+        assert_eq!(
+            report.to_string(),
+            "contui doctor\n✅ DNS: ok\n❌ API key: GEMINI_API_KEY is not set\n   → Set GEMINI_API_KEY\n1 of 2 checks failed."
+        );
+
+        assert_eq!(color_depth(Some("truecolor"), Some("xterm")), "24-bit");
+        assert_eq!(color_depth(None, Some("xterm-256color")), "256");
+        assert_eq!(color_depth(None, None), "16");
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::history::history_dir;
+
+/// 送信前の入力欄の内容（終了やクラッシュのあとに復元する）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Draft {
+    pub input: String,
+    pub cursor_position: usize,
+    #[serde(default)]
+    pub selected_files: Vec<String>,
+}
+
+impl Draft {
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty() && self.selected_files.is_empty()
+    }
+}
+
+/// 下書きの保存先（<data_dir>/contui/draft.json）
+pub fn default_draft_path() -> Option<PathBuf> {
+    history_dir().map(|dir| dir.join("draft.json"))
+}
+
+/// 下書きを読み込む（ファイルがないか空ならNone）
+pub fn load_draft(path: &Path) -> Result<Option<Draft>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let draft: Draft = serde_json::from_str(&content)?;
+    Ok(Some(draft).filter(|draft| !draft.is_empty()))
+}
+
+/// 一時ファイルに書いてから置き換えるので、書き込み中に落ちても前の下書きは壊れない
+pub fn save_draft(path: &Path, draft: &Draft) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_string_pretty(draft)?)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// 下書きを削除する（なければ何もしない）
+pub fn delete_draft(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draft_round_trip_and_delete() {
+        let path = std::env::temp_dir()
+            .join(format!("contui_draft_{}", uuid::Uuid::new_v4()))
+            .join("draft.json");
+        assert_eq!(load_draft(&path).unwrap(), None);
+
+        let draft = Draft {
+            input: "half-written\nprompt".to_string(),
+            cursor_position: 4,
+            selected_files: vec!["src/main.rs".to_string()],
+        };
+        save_draft(&path, &draft).unwrap();
+        assert_eq!(load_draft(&path).unwrap(), Some(draft));
+        assert!(!path.with_extension("json.tmp").exists());
+
+        save_draft(&path, &Draft::default()).unwrap();
+        assert_eq!(load_draft(&path).unwrap(), None);
+
+        delete_draft(&path).unwrap();
+        delete_draft(&path).unwrap();
+        assert!(!path.exists());
+        fs::remove_dir(path.parent().unwrap()).unwrap();
+    }
+}
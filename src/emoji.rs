@@ -0,0 +1,80 @@
+/// 絵文字ピッカー（Insert modeで Ctrl+G）に並べる絵文字と名前
+/// insert_charで1文字として挿入するので、異体字セレクタなどを含まない1コードポイントの絵文字に限る
+pub const EMOJI_PALETTE: &[(&str, &str)] = &[
+    ("😀", "grinning"),
+    ("😂", "joy"),
+    ("😊", "blush"),
+    ("😉", "wink"),
+    ("😎", "sunglasses"),
+    ("🤔", "thinking"),
+    ("😅", "sweat smile"),
+    ("😢", "cry"),
+    ("👍", "thumbs up"),
+    ("👎", "thumbs down"),
+    ("👀", "eyes"),
+    ("🙏", "pray"),
+    ("👏", "clap"),
+    ("💪", "muscle"),
+    ("🎉", "tada"),
+    ("🔥", "fire"),
+    ("✅", "check"),
+    ("❌", "cross"),
+    ("⚠", "warning"),
+    ("❓", "question"),
+    ("💡", "bulb"),
+    ("⭐", "star"),
+    ("🚀", "rocket"),
+    ("🐛", "bug"),
+    ("📁", "folder"),
+    ("📄", "file"),
+    ("📝", "memo"),
+    ("📌", "pin"),
+    ("🔧", "wrench"),
+    ("🔍", "search"),
+    ("🔒", "lock"),
+    ("⏳", "hourglass"),
+    ("🤖", "robot"),
+    ("💻", "computer"),
+    ("📦", "package"),
+    ("🧪", "test tube"),
+];
+
+/// グリッドの列数
+pub const EMOJI_GRID_COLUMNS: usize = 8;
+
+/// グリッド上で選択位置を (dx, dy) だけ動かす（端では止まる）
+pub fn move_selection(index: usize, dx: isize, dy: isize) -> usize {
+    let last = EMOJI_PALETTE.len() - 1;
+    let row = (index / EMOJI_GRID_COLUMNS) as isize + dy;
+    let column = (index % EMOJI_GRID_COLUMNS) as isize + dx;
+    let last_row = (last / EMOJI_GRID_COLUMNS) as isize;
+    if row < 0 || row > last_row || column < 0 || column >= EMOJI_GRID_COLUMNS as isize {
+        return index;
+    }
+    (row as usize * EMOJI_GRID_COLUMNS + column as usize).min(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palette_entries_are_single_chars() {
+        assert!(EMOJI_PALETTE.iter().all(|(emoji, _)| emoji.chars().count() == 1));
+    }
+
+    #[test]
+    fn test_move_selection_stays_inside_grid() {
+        assert_eq!(move_selection(0, -1, 0), 0);
+        assert_eq!(move_selection(0, 0, -1), 0);
+        assert_eq!(move_selection(0, 1, 0), 1);
+        assert_eq!(move_selection(1, 0, 1), 1 + EMOJI_GRID_COLUMNS);
+        assert_eq!(move_selection(EMOJI_GRID_COLUMNS - 1, 1, 0), EMOJI_GRID_COLUMNS - 1);
+
+        // 最終行が埋まっていない列から下に動くと最後の絵文字に止まる
+        let last = EMOJI_PALETTE.len() - 1;
+        let above_gap = (last / EMOJI_GRID_COLUMNS) * EMOJI_GRID_COLUMNS - 1;
+        assert_eq!(move_selection(above_gap, 0, 1), last);
+        assert_eq!(move_selection(last, 0, 1), last);
+    }
+}
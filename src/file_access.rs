@@ -1,67 +1,697 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use anyhow::{Result, anyhow};
+use encoding_rs::Encoding;
+
+/// @file:path または @file:path:L10-L50 で指定されたファイル参照
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileReference {
+    pub path: String,
+    /// 1始まりの行範囲（両端を含む）
+    pub lines: Option<(usize, usize)>,
+    /// @file!:path で指定され、大きすぎても省略せずに分割して送るか
+    pub full: bool,
+}
+
+impl FileReference {
+    /// ファイル全体への参照
+    pub fn whole(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            lines: None,
+            full: false,
+        }
+    }
+
+    /// 末尾の :L<start>-L<end> を行範囲として解釈する（なければファイル全体）
+    pub fn parse(reference: &str) -> Self {
+        let parsed = reference.rsplit_once(":L").and_then(|(path, range)| {
+            let (start, end) = range.split_once("-L")?;
+            Some((path, start.parse().ok()?, end.parse().ok()?))
+        });
+        match parsed {
+            Some((path, start, end)) if !path.is_empty() => Self {
+                path: path.to_string(),
+                lines: Some((start, end)),
+                full: false,
+            },
+            _ => Self::whole(reference),
+        }
+    }
+}
+
+impl fmt::Display for FileReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.lines {
+            Some((start, end)) => write!(f, "{}:L{}-L{}", self.path, start, end),
+            None => write!(f, "{}", self.path),
+        }
+    }
+}
+
+/// list_directoryでディレクトリ名の末尾に付ける区切り文字
+pub const DIRECTORY_SUFFIX: char = std::path::MAIN_SEPARATOR;
+
+/// list_directoryのエントリがディレクトリかどうか
+pub fn is_directory_entry(entry: &str) -> bool {
+    entry.ends_with(DIRECTORY_SUFFIX)
+}
+
+/// list_directoryのエントリから末尾の区切り文字を除いた名前
+pub fn entry_name(entry: &str) -> &str {
+    entry.trim_end_matches(DIRECTORY_SUFFIX)
+}
+
+/// Windowsのcanonicalizeが付ける \\?\ プレフィックスを取り除く
+/// （\\?\UNC\server\share は \\server\share に戻す）
+pub fn strip_verbatim_prefix(path: &str) -> String {
+    if let Some(rest) = path.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{}", rest)
+    } else if let Some(rest) = path.strip_prefix(r"\\?\") {
+        rest.to_string()
+    } else {
+        path.to_string()
+    }
+}
+
+/// ユーザーが入力したパスを絶対パスにする（~ と ~/ はホームディレクトリ、相対パスはbaseから）
+pub fn resolve_user_path(input: &str, base: &Path, home: Option<&Path>) -> PathBuf {
+    let expanded = match (input.strip_prefix('~'), home) {
+        (Some(""), Some(home)) => home.to_path_buf(),
+        (Some(rest), Some(home)) if rest.starts_with(['/', std::path::MAIN_SEPARATOR]) => home.join(&rest[1..]),
+        _ => PathBuf::from(input),
+    };
+    if expanded.is_absolute() {
+        expanded
+    } else {
+        base.join(expanded)
+    }
+}
+
+/// 比較用にパスを正規化する
+/// . と .. を字句的に解決した後、存在する最も近い祖先をcanonicalizeして残りを連結する
+pub fn normalize_path(path: &Path) -> Result<PathBuf> {
+    let absolute_path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+
+    let mut lexical = PathBuf::new();
+    for component in absolute_path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                lexical.pop();
+            }
+            other => lexical.push(other.as_os_str()),
+        }
+    }
+
+    let mut existing = lexical.as_path();
+    let mut missing = Vec::new();
+    let canonical = loop {
+        match existing.canonicalize() {
+            Ok(canonical) => break canonical,
+            Err(_) => match (existing.parent(), existing.file_name()) {
+                (Some(parent), Some(name)) => {
+                    missing.push(name.to_os_string());
+                    existing = parent;
+                }
+                // ルートまで存在しない場合は字句的な正規化のみ
+                _ => return Ok(lexical),
+            },
+        }
+    };
+
+    let mut normalized = PathBuf::from(strip_verbatim_prefix(&canonical.to_string_lossy()));
+    for name in missing.iter().rev() {
+        normalized.push(name);
+    }
+    Ok(normalized)
+}
+
+/// ディレクトリの1項目（/ls で表示する）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    pub name: String,
+    pub is_dir: bool,
+    /// バイト数（ディレクトリはファイルシステムが返す値）
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// 読み込んだファイルの内容
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileContent {
+    /// テキスト（UTF-8以外から変換したときはそのエンコーディング名）
+    Text { text: String, encoding: Option<&'static str> },
+    /// ヌルバイトを含むか、どのエンコーディングでも読めなかったファイル（中身は送らない）
+    Binary { size: u64 },
+}
+
+impl FileContent {
+    /// テキストとして取り出す（バイナリはpathを添えたエラー）
+    pub fn into_text(self, path: &Path) -> Result<String> {
+        match self {
+            FileContent::Text { text, .. } => Ok(text),
+            FileContent::Binary { .. } => Err(anyhow!("Binary file not supported: {:?}", path)),
+        }
+    }
+}
+
+/// バイナリ判定でヌルバイトを探す先頭のバイト数
+const BINARY_SNIFF_LEN: usize = 512;
+
+/// 先頭512バイトにヌルバイトがあればバイナリファイルとみなす（読めなければfalse）
+pub fn is_binary_file(path: &Path) -> bool {
+    let mut head = Vec::with_capacity(BINARY_SNIFF_LEN);
+    match fs::File::open(path) {
+        Ok(file) => file.take(BINARY_SNIFF_LEN as u64).read_to_end(&mut head).is_ok() && head.contains(&0),
+        Err(_) => false,
+    }
+}
+
+/// FILE_ENCODINGSを指定しないときに、UTF-8で読めなかったファイルに試すエンコーディング
+pub const DEFAULT_FALLBACK_ENCODINGS: &[&str] = &["shift_jis", "euc-jp"];
+
+/// エンコーディング名（shift_jis, euc-jp など）の一覧を解釈する
+pub fn parse_encodings<S: AsRef<str>>(labels: &[S]) -> Result<Vec<&'static Encoding>> {
+    labels
+        .iter()
+        .map(|label| {
+            let label = label.as_ref().trim();
+            Encoding::for_label(label.as_bytes()).ok_or_else(|| anyhow!("Unknown encoding: {}", label))
+        })
+        .collect()
+}
+
+/// 先頭にヌルバイトがあればバイナリ、UTF-8（BOM付きも可）で読めなければfallbacksの順に試す
+/// どのエンコーディングでも不正なバイト列になる場合もバイナリとみなす
+pub fn decode_file_bytes(bytes: &[u8], fallbacks: &[&'static Encoding]) -> FileContent {
+    let binary = FileContent::Binary { size: bytes.len() as u64 };
+    if bytes[..bytes.len().min(BINARY_SNIFF_LEN)].contains(&0) {
+        return binary;
+    }
+    let without_bom = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    if let Ok(text) = std::str::from_utf8(without_bom) {
+        return FileContent::Text { text: text.to_string(), encoding: None };
+    }
+    fallbacks
+        .iter()
+        .find_map(|encoding| {
+            let text = encoding.decode_without_bom_handling_and_without_replacement(bytes)?;
+            Some(FileContent::Text { text: text.into_owned(), encoding: Some(encoding.name()) })
+        })
+        .unwrap_or(binary)
+}
+
+/// 許可ディレクトリの設定ファイルの既定の場所（<config_dir>/contui/allowed_directories）
+pub fn default_allowed_directories_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("contui").join("allowed_directories"))
+}
+
+/// 許可ディレクトリを1行1パスで読み込む（空行と#で始まる行、!で始まる除外の行は無視、ファイルがなければ空）
+pub fn load_allowed_directories(path: &Path) -> Result<Vec<PathBuf>> {
+    Ok(directory_entries(path)?.into_iter().filter_map(|(excluded, directory)| (!excluded).then_some(directory)).collect())
+}
+
+/// 起動時に作業ディレクトリでも許可しないディレクトリ（!で始まる行。/permissions で作業ディレクトリを外したとき）
+pub fn load_excluded_directories(path: &Path) -> Result<Vec<PathBuf>> {
+    Ok(directory_entries(path)?.into_iter().filter_map(|(excluded, directory)| excluded.then_some(directory)).collect())
+}
+
+/// 設定ファイルの (除外の行か, パス) の一覧
+fn directory_entries(path: &Path) -> Result<Vec<(bool, PathBuf)>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.strip_prefix('!') {
+            Some(excluded) => (true, PathBuf::from(excluded.trim())),
+            None => (false, PathBuf::from(line)),
+        })
+        .collect())
+}
+
+/// 許可ディレクトリを設定ファイルに追記する
+pub fn append_allowed_directory(path: &Path, directory: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", directory.display())?;
+    Ok(())
+}
+
+/// 作業ディレクトリを起動時に許可しないよう、除外の行（!パス）を設定ファイルに追記する
+pub fn append_excluded_directory(path: &Path, directory: &Path) -> Result<()> {
+    append_allowed_directory(path, &PathBuf::from(format!("!{}", directory.display())))
+}
+
+/// 許可ディレクトリの設定ファイルからdirectoryの行を取り除く（コメントや他の行はそのまま残す。取り除いたらtrue）
+pub fn remove_allowed_directory_from_file(path: &Path, directory: &Path) -> Result<bool> {
+    remove_directory_entry(path, directory, false)
+}
+
+/// 設定ファイルからdirectoryの除外の行（!パス）を取り除く（取り除いたらtrue）
+pub fn remove_excluded_directory_from_file(path: &Path, directory: &Path) -> Result<bool> {
+    remove_directory_entry(path, directory, true)
+}
+
+fn remove_directory_entry(path: &Path, directory: &Path, excluded: bool) -> Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    let content = fs::read_to_string(path)?;
+    let mut removed = false;
+    let mut kept = String::new();
+    for line in content.lines() {
+        let entry = line.trim();
+        // 許可の行と除外の行（!パス）は別に扱う
+        let entry = match entry.strip_prefix('!') {
+            Some(rest) if excluded => rest.trim(),
+            None if !excluded => entry,
+            _ => "",
+        };
+        let matches = !entry.is_empty()
+            && !entry.starts_with('#')
+            && (Path::new(entry) == directory || normalize_path(Path::new(entry)).is_ok_and(|entry| entry == directory));
+        if matches {
+            removed = true;
+        } else {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    if removed {
+        fs::write(path, kept)?;
+    }
+    Ok(removed)
+}
+
+/// 設定で明示しない限りアクセスさせない機密ディレクトリ（鍵・認証情報・ブラウザプロファイル）
+pub fn default_denied_directories() -> Vec<PathBuf> {
+    let Some(home) = dirs::home_dir() else {
+        return Vec::new();
+    };
+    [
+        ".ssh",
+        ".gnupg",
+        ".aws",
+        ".mozilla",
+        ".config/google-chrome",
+        ".config/chromium",
+        ".config/BraveSoftware",
+        "Library/Application Support/Google/Chrome",
+        "Library/Application Support/Firefox",
+        "Library/Application Support/BraveSoftware",
+        "AppData/Local/Google/Chrome/User Data",
+        "AppData/Roaming/Mozilla/Firefox",
+    ]
+    .iter()
+    .filter_map(|relative| normalize_path(&home.join(relative)).ok())
+    .collect()
+}
+
+/// 許可ディレクトリの外、または機密ディレクトリへのアクセスを拒否したときのエラー
+#[derive(Debug)]
+pub struct AccessDenied {
+    pub path: PathBuf,
+    pub sensitive: bool,
+}
+
+impl fmt::Display for AccessDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.sensitive {
+            write!(f, "Access denied to sensitive path: {:?}", self.path)
+        } else {
+            write!(f, "Access denied to path: {:?}", self.path)
+        }
+    }
+}
+
+impl std::error::Error for AccessDenied {}
+
+/// パスへのアクセス可否
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathAccess {
+    Allowed,
+    /// 許可ディレクトリの外（確認すれば許可できる）
+    Outside,
+    /// 機密ディレクトリ（設定ファイルでのみ許可できる）
+    Sensitive,
+}
+
+/// パスを許可するときの対象ディレクトリ（ファイルや存在しないパスなら存在する最も近い祖先）
+pub fn grant_directory(path: &Path) -> Result<PathBuf> {
+    let mut directory = normalize_path(path)?;
+    while !directory.is_dir() {
+        if !directory.pop() {
+            return Err(anyhow!("No existing directory for path: {:?}", path));
+        }
+    }
+    Ok(directory)
+}
+
+/// キャッシュした内容（読んだときの更新時刻, サイズ, 内容）
+type CachedFile = (SystemTime, u64, FileContent);
+
+/// read_fileで読んだ内容のキャッシュ（パス → 読んだときの更新時刻・サイズと内容）
+/// FileAccessManagerを複製しても同じキャッシュを共有する
+#[derive(Clone, Default)]
+pub struct FileCache {
+    entries: Arc<Mutex<HashMap<PathBuf, CachedFile>>>,
+}
+
+impl FileCache {
+    /// 更新時刻とサイズが読んだときと同じなら内容を返す
+    fn get(&self, path: &Path, metadata: &fs::Metadata) -> Option<FileContent> {
+        let modified = metadata.modified().ok()?;
+        let entries = self.entries.lock().ok()?;
+        let (cached_modified, cached_len, content) = entries.get(path)?;
+        (*cached_modified == modified && *cached_len == metadata.len()).then(|| content.clone())
+    }
+
+    fn insert(&self, path: PathBuf, metadata: &fs::Metadata, content: &FileContent) {
+        // 更新時刻を取れないファイルシステムではキャッシュしない
+        let (Ok(modified), Ok(mut entries)) = (metadata.modified(), self.entries.lock()) else {
+            return;
+        };
+        entries.insert(path, (modified, metadata.len(), content.clone()));
+    }
+
+    fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().map_or(0, |entries| entries.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// 送信前に確認した添付ファイル（同じファイルへの参照は1つにまとめる）
+#[derive(Debug, Default, PartialEq)]
+pub struct PreparedAttachments {
+    /// 送るファイル（パスは正規化したもの）とそのバイト数
+    pub files: Vec<(FileReference, u64)>,
+    /// 別の書き方で同じファイルを指していたため外した参照（外した参照, 残した参照）
+    pub duplicates: Vec<(String, String)>,
+    /// 読めないため外した参照と理由
+    pub skipped: Vec<(FileReference, String)>,
+}
+
+/// 上書きする前にバックアップを取るファイルの大きさの上限（これより大きいファイルはバックアップしない）
+const MAX_BACKUP_BYTES: u64 = 5 * 1024 * 1024;
+
+/// write_fileで上書きする前の内容を残すパス（<path>.bak）
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    PathBuf::from(backup)
+}
 
 #[derive(Clone)]
 pub struct FileAccessManager {
     allowed_directories: Vec<PathBuf>,
+    denied_directories: Vec<PathBuf>,
+    /// 設定ファイルで許可したディレクトリ（機密ディレクトリの内側でも許可する）
+    configured_directories: Vec<PathBuf>,
+    /// UTF-8で読めなかったファイルに試すエンコーディング
+    fallback_encodings: Vec<&'static Encoding>,
+    /// ファイル読み取りのキャッシュ（FILE_CACHE=trueのときだけ使う）
+    file_cache: Option<FileCache>,
+    /// contui自身が書き込んだファイルと書き込み後の更新時刻（添付ファイルの監視で自分の変更を無視する）
+    own_writes: Arc<Mutex<HashMap<PathBuf, SystemTime>>>,
+}
+
+impl Default for FileAccessManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FileAccessManager {
     pub fn new() -> Self {
         Self {
             allowed_directories: Vec::new(),
+            denied_directories: default_denied_directories(),
+            configured_directories: Vec::new(),
+            fallback_encodings: parse_encodings(DEFAULT_FALLBACK_ENCODINGS).unwrap_or_default(),
+            file_cache: None,
+            own_writes: Arc::default(),
         }
     }
 
-    /// 許可されたディレクトリを追加
-    pub fn add_allowed_directory<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+    /// UTF-8で読めなかったファイルに試すエンコーディングを設定する
+    pub fn set_fallback_encodings(&mut self, encodings: Vec<&'static Encoding>) {
+        self.fallback_encodings = encodings;
+        // 変換結果が変わるので読み直す
+        if let Some(cache) = &self.file_cache {
+            cache.clear();
+        }
+    }
+
+    /// ファイル読み取りのキャッシュを有効・無効にする
+    pub fn set_file_cache(&mut self, enabled: bool) {
+        self.file_cache = enabled.then(FileCache::default);
+    }
+
+    pub fn file_cache(&self) -> Option<&FileCache> {
+        self.file_cache.as_ref()
+    }
+
+    /// 書き込んだファイルの更新時刻を記録する
+    fn record_own_write(&self, path: &Path) {
+        let (Ok(key), Ok(modified)) = (normalize_path(path), fs::metadata(path).and_then(|metadata| metadata.modified())) else {
+            return;
+        };
+        if let Ok(mut own_writes) = self.own_writes.lock() {
+            own_writes.insert(key, modified);
+        }
+    }
+
+    /// pathの現在の内容がcontui自身の書き込みによるものか（その後に別のプログラムが変更していればfalse）
+    pub fn is_own_write(&self, path: &Path) -> bool {
+        let (Ok(key), Ok(modified)) = (normalize_path(path), fs::metadata(path).and_then(|metadata| metadata.modified())) else {
+            return false;
+        };
+        self.own_writes.lock().is_ok_and(|own_writes| own_writes.get(&key) == Some(&modified))
+    }
+
+    fn canonical_directory<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
         let canonical_path = fs::canonicalize(path)?;
         if !canonical_path.is_dir() {
             return Err(anyhow!("Path is not a directory: {:?}", canonical_path));
         }
-        self.allowed_directories.push(canonical_path);
+        // チェック対象のパスと同じ形式で保持する（Windowsの \\?\ を外す）
+        normalize_path(&canonical_path)
+    }
+
+    /// 許可されたディレクトリを追加（機密ディレクトリの内側は追加できない）
+    pub fn add_allowed_directory<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let directory = Self::canonical_directory(path)?;
+        if self.denied_directories.iter().any(|denied| directory.starts_with(denied)) {
+            return Err(anyhow!("{:?} is a sensitive directory and can only be allowed in the allowed_directories file", directory));
+        }
+        self.allowed_directories.push(directory);
         Ok(())
     }
 
-    /// パスがアクセス許可されているかチェック
-    fn is_path_allowed<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
-        let path_ref = path.as_ref();
-        
-        // 絶対パスに変換
-        let absolute_path = if path_ref.is_absolute() {
-            path_ref.to_path_buf()
+    /// 設定ファイルで明示したディレクトリを許可する（機密ディレクトリでも許可する）
+    pub fn allow_configured_directory<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let directory = Self::canonical_directory(path)?;
+        self.configured_directories.push(directory.clone());
+        self.allowed_directories.push(directory);
+        Ok(())
+    }
+
+    /// 許可ディレクトリの一覧（/permissions で表示する）
+    pub fn list_allowed(&self) -> &[PathBuf] {
+        &self.allowed_directories
+    }
+
+    /// 機密ディレクトリの一覧
+    pub fn list_denied(&self) -> &[PathBuf] {
+        &self.denied_directories
+    }
+
+    /// 許可ディレクトリから外す（設定ファイルで明示した扱いも外す）。外したらtrue
+    pub fn remove_allowed(&mut self, directory: &Path) -> bool {
+        let before = self.allowed_directories.len();
+        self.allowed_directories.retain(|allowed| allowed != directory);
+        self.configured_directories.retain(|configured| configured != directory);
+        self.allowed_directories.len() != before
+    }
+
+    /// 機密ディレクトリとして扱うディレクトリを追加
+    pub fn add_denied_directory<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.denied_directories.push(normalize_path(path.as_ref())?);
+        Ok(())
+    }
+
+    /// パスへのアクセス可否を判定する
+    pub fn check_access<P: AsRef<Path>>(&self, path: P) -> Result<PathAccess> {
+        // 許可ディレクトリと同じ形式に正規化（存在しないパスも祖先で解決する）
+        let check_path = normalize_path(path.as_ref())?;
+
+        // 機密ディレクトリは、その内側を設定ファイルで許可した場合だけ通す
+        let sensitive = self.denied_directories.iter().any(|denied| {
+            check_path.starts_with(denied)
+                && !self
+                    .configured_directories
+                    .iter()
+                    .any(|configured| configured.starts_with(denied) && check_path.starts_with(configured))
+        });
+        if sensitive {
+            return Ok(PathAccess::Sensitive);
+        }
+        if self.allowed_directories.iter().any(|allowed_dir| check_path.starts_with(allowed_dir)) {
+            Ok(PathAccess::Allowed)
         } else {
-            std::env::current_dir()?.join(path_ref)
-        };
-        
-        // 可能であれば正規化
-        let check_path = absolute_path.canonicalize().unwrap_or(absolute_path);
-        
-        for allowed_dir in &self.allowed_directories {
-            if check_path.starts_with(allowed_dir) {
-                return Ok(true);
+            Ok(PathAccess::Outside)
+        }
+    }
+
+    /// アクセスできなければAccessDeniedエラーを返す
+    fn ensure_allowed<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        match self.check_access(&path)? {
+            PathAccess::Allowed => Ok(()),
+            access => Err(AccessDenied {
+                path: path.as_ref().to_path_buf(),
+                sensitive: access == PathAccess::Sensitive,
             }
+            .into()),
         }
-        
-        Ok(false)
     }
 
-    /// ファイルの内容を読み取り
+    /// ファイルの内容を読み取り（バイナリはエラー）
     pub fn read_file<P: AsRef<Path>>(&self, path: P) -> Result<String> {
-        if !self.is_path_allowed(&path)? {
-            return Err(anyhow!("Access denied to path: {:?}", path.as_ref()));
-        }
+        self.read_file_content(&path)?.into_text(path.as_ref())
+    }
+
+    /// ファイルの内容をテキストかバイナリかを判定して読み取る
+    /// キャッシュが有効なら、更新時刻とサイズが前回読んだときと同じファイルは読み直さない
+    pub fn read_file_content<P: AsRef<Path>>(&self, path: P) -> Result<FileContent> {
+        self.ensure_allowed(&path)?;
 
-        let content = fs::read_to_string(path)?;
+        let Some(cache) = &self.file_cache else {
+            let bytes = fs::read(path)?;
+            return Ok(decode_file_bytes(&bytes, &self.fallback_encodings));
+        };
+        let key = normalize_path(path.as_ref())?;
+        let metadata = fs::metadata(&key)?;
+        if let Some(content) = cache.get(&key, &metadata) {
+            return Ok(content);
+        }
+        let content = decode_file_bytes(&fs::read(&key)?, &self.fallback_encodings);
+        cache.insert(key, &metadata, &content);
         Ok(content)
     }
 
+    /// 指定した1始まりの行範囲（両端を含む）だけを読み取る（バイナリはエラー）
+    pub fn read_file_range<P: AsRef<Path>>(&self, path: P, start_line: usize, end_line: usize) -> Result<String> {
+        self.read_file_range_content(&path, start_line, end_line)?.into_text(path.as_ref())
+    }
+
+    /// 行範囲だけをテキストかバイナリかを判定して読み取る
+    /// ファイル全体を読み込まず、end_lineに達した時点で読み取りをやめる
+    pub fn read_file_range_content<P: AsRef<Path>>(&self, path: P, start_line: usize, end_line: usize) -> Result<FileContent> {
+        self.ensure_allowed(&path)?;
+        if start_line == 0 || end_line < start_line {
+            return Err(anyhow!("Invalid line range: L{}-L{}", start_line, end_line));
+        }
+
+        let mut reader = BufReader::new(fs::File::open(&path)?);
+        let mut lines: Vec<Vec<u8>> = Vec::new();
+        let mut line_number = 0;
+        while line_number < end_line {
+            let mut line = Vec::new();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            line_number += 1;
+            if line_number >= start_line {
+                let trimmed = line.strip_suffix(b"\n").unwrap_or(&line);
+                lines.push(trimmed.strip_suffix(b"\r").unwrap_or(trimmed).to_vec());
+            }
+        }
+        if lines.is_empty() {
+            return Err(anyhow!("Line range L{}-L{} is beyond the end of the file", start_line, end_line));
+        }
+        Ok(match decode_file_bytes(&lines.join(&b'\n'), &self.fallback_encodings) {
+            FileContent::Binary { .. } => FileContent::Binary { size: fs::metadata(&path)?.len() },
+            text => text,
+        })
+    }
+
+    /// 添付ファイルのパスを正規化し、同じファイル（と行範囲）への参照を1つにまとめる
+    /// 存在しない・ディレクトリ・許可されていないファイルは理由を付けてskippedに入れる
+    pub fn prepare_attachments(&self, references: &[FileReference]) -> PreparedAttachments {
+        let mut prepared = PreparedAttachments::default();
+        // 残した参照の元の書き方（重複の案内に使う）
+        let mut spellings: Vec<String> = Vec::new();
+        for reference in references {
+            let checked = fs::canonicalize(&reference.path).map_err(anyhow::Error::from).and_then(|canonical| {
+                self.ensure_allowed(&canonical)?;
+                let metadata = fs::metadata(&canonical)?;
+                if metadata.is_dir() {
+                    return Err(anyhow!("is a directory"));
+                }
+                Ok((canonical, metadata.len()))
+            });
+            let (canonical, size) = match checked {
+                Ok(checked) => checked,
+                Err(e) => {
+                    prepared.skipped.push((reference.clone(), e.to_string()));
+                    continue;
+                }
+            };
+            let resolved = FileReference { path: strip_verbatim_prefix(&canonical.to_string_lossy()), ..reference.clone() };
+            match prepared.files.iter().position(|(kept, _)| kept.path == resolved.path && kept.lines == resolved.lines) {
+                Some(index) => {
+                    prepared.files[index].0.full |= resolved.full;
+                    prepared.duplicates.push((reference.to_string(), spellings[index].clone()));
+                }
+                None => {
+                    prepared.files.push((resolved, size));
+                    spellings.push(reference.to_string());
+                }
+            }
+        }
+        prepared
+    }
+
+    /// ファイル参照の内容を読み取る（行範囲があればその範囲のみ、バイナリはエラー）
+    pub fn read_reference(&self, reference: &FileReference) -> Result<String> {
+        self.read_reference_content(reference)?.into_text(Path::new(&reference.path))
+    }
+
+    /// ファイル参照の内容をテキストかバイナリかを判定して読み取る
+    pub fn read_reference_content(&self, reference: &FileReference) -> Result<FileContent> {
+        match reference.lines {
+            Some((start, end)) => self.read_file_range_content(&reference.path, start, end),
+            None => self.read_file_content(&reference.path),
+        }
+    }
+
     /// ディレクトリの内容をリスト
     pub fn list_directory<P: AsRef<Path>>(&self, path: P) -> Result<Vec<String>> {
-        if !self.is_path_allowed(&path)? {
-            return Err(anyhow!("Access denied to path: {:?}", path.as_ref()));
-        }
+        self.ensure_allowed(&path)?;
 
         let mut entries = Vec::new();
         let dir_entries = fs::read_dir(path)?;
@@ -72,7 +702,7 @@ impl FileAccessManager {
             let metadata = entry.metadata()?;
             
             if metadata.is_dir() {
-                entries.push(format!("{}/", file_name));
+                entries.push(format!("{}{}", file_name, DIRECTORY_SUFFIX));
             } else {
                 entries.push(file_name);
             }
@@ -82,6 +712,45 @@ impl FileAccessManager {
         Ok(entries)
     }
 
+    /// ディレクトリの項目を種類・サイズ・更新時刻付きで読み取る（ディレクトリが先、それぞれ名前順）
+    pub fn list_directory_with_metadata<P: AsRef<Path>>(&self, path: P) -> Result<Vec<DirectoryEntry>> {
+        self.ensure_allowed(&path)?;
+
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            entries.push(DirectoryEntry {
+                name: entry.file_name().to_string_lossy().to_string(),
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+            });
+        }
+        entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+        Ok(entries)
+    }
+
+    /// ディレクトリを作成（途中のディレクトリも作成し、既に存在する場合は何もしない）
+    pub fn create_directory(&self, path: &Path) -> Result<()> {
+        self.ensure_allowed(path)?;
+        fs::create_dir_all(path)?;
+        Ok(())
+    }
+
+    /// ディレクトリを中身ごと削除（許可ディレクトリそのものとシンボリックリンクは削除しない）
+    pub fn delete_directory_recursive(&self, path: &Path) -> Result<()> {
+        self.ensure_allowed(path)?;
+        if self.allowed_directories.contains(&normalize_path(path)?) {
+            return Err(anyhow!("Refusing to delete an allowed root directory: {:?}", path));
+        }
+        if !fs::symlink_metadata(path)?.is_dir() {
+            return Err(anyhow!("Not a directory: {:?}", path));
+        }
+        fs::remove_dir_all(path)?;
+        Ok(())
+    }
+
     /// ファイルを作成（重複チェック付き）- ユニークなファイル名を生成
     pub fn create_file_with_unique_name<P: AsRef<Path>>(&self, path: P, content: &str) -> Result<PathBuf> {
         let original_path = path.as_ref();
@@ -94,25 +763,44 @@ impl FileAccessManager {
 
         // 親ディレクトリが存在しない場合は作成
         if !parent_dir.exists() {
-            if !self.is_path_allowed(parent_dir).unwrap_or(false) {
-                return Err(anyhow!("Access denied to directory: {:?}", parent_dir));
-            }
+            self.ensure_allowed(parent_dir)?;
             fs::create_dir_all(parent_dir)?;
         }
 
         // ファイル作成権限をチェック
-        if !self.is_path_allowed(parent_dir)? {
-            return Err(anyhow!("Access denied to create file in: {:?}", parent_dir));
-        }
+        self.ensure_allowed(parent_dir)?;
 
         // ユニークなファイル名を生成
         let unique_path = self.generate_unique_filename(original_path, content)?;
         
         // ファイルを作成
         fs::write(&unique_path, content)?;
+        self.record_own_write(&unique_path);
         Ok(unique_path)
     }
 
+    /// ファイルを上書きする。既存のファイルは先に <path>.bak にコピーしておく（1つ前の内容だけ残す）
+    /// 5MBを超えるファイルはバックアップせずに上書きし、エラーログに警告を書く
+    pub fn write_file(&self, path: &Path, content: &str) -> Result<()> {
+        self.ensure_allowed(path)?;
+        if let Ok(metadata) = fs::metadata(path) {
+            if metadata.is_dir() {
+                return Err(anyhow!("Path is a directory: {:?}", path));
+            }
+            if metadata.len() > MAX_BACKUP_BYTES {
+                crate::logger::log_error(&format!(
+                    "Warning: {:?} is larger than {} bytes; overwriting without a backup",
+                    path, MAX_BACKUP_BYTES
+                ));
+            } else {
+                fs::copy(path, backup_path(path))?;
+            }
+        }
+        fs::write(path, content)?;
+        self.record_own_write(path);
+        Ok(())
+    }
+
     /// ユニークなファイル名を生成する
     fn generate_unique_filename<P: AsRef<Path>>(&self, path: P, content: &str) -> Result<PathBuf> {
         let original_path = path.as_ref();
@@ -167,9 +855,7 @@ impl FileAccessManager {
         end_line: usize,
         new_content: &str,
     ) -> Result<()> {
-        if !self.is_path_allowed(&path)? {
-            return Err(anyhow!("Access denied to path: {:?}", path.as_ref()));
-        }
+        self.ensure_allowed(&path)?;
         let file_path = path.as_ref();
         let original = fs::read_to_string(file_path)?;
         let lines: Vec<&str> = original.lines().collect();
@@ -190,6 +876,219 @@ impl FileAccessManager {
         result.extend_from_slice(&lines[end..]);
 
         fs::write(file_path, result.join("\n"))?;
+        self.record_own_write(file_path);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_user_path() {
+        let base = Path::new("/work/project");
+        let home = Path::new("/home/me");
+        assert_eq!(resolve_user_path("~", base, Some(home)), home);
+        assert_eq!(resolve_user_path("~/src", base, Some(home)), home.join("src"));
+        assert_eq!(resolve_user_path("../other", base, Some(home)), base.join("../other"));
+        assert_eq!(resolve_user_path("/tmp", base, Some(home)), Path::new("/tmp"));
+        // ~user や、ホームディレクトリが分からないときの ~ はそのままの名前として扱う
+        assert_eq!(resolve_user_path("~user", base, Some(home)), base.join("~user"));
+        assert_eq!(resolve_user_path("~", base, None), base.join("~"));
+    }
+
+    #[test]
+    fn test_strip_verbatim_prefix() {
+        assert_eq!(strip_verbatim_prefix(r"\\?\C:\Users\me"), r"C:\Users\me");
+        assert_eq!(strip_verbatim_prefix(r"\\?\UNC\server\share\dir"), r"\\server\share\dir");
+        assert_eq!(strip_verbatim_prefix("/home/me"), "/home/me");
+    }
+
+    #[test]
+    fn test_normalize_path_resolves_missing_components() {
+        let base = std::env::temp_dir().canonicalize().unwrap();
+        let base = PathBuf::from(strip_verbatim_prefix(&base.to_string_lossy()));
+        let missing = std::env::temp_dir()
+            .join("contui_missing_dir")
+            .join("..")
+            .join("contui_missing_file.txt");
+        assert_eq!(normalize_path(&missing).unwrap(), base.join("contui_missing_file.txt"));
+    }
+
+    #[test]
+    fn test_file_cache_follows_mtime() {
+        let dir = std::env::temp_dir().join(format!("contui_file_cache_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.txt");
+        fs::write(&path, "first").unwrap();
+        let mut manager = FileAccessManager::new();
+        manager.add_allowed_directory(&dir).unwrap();
+        manager.set_file_cache(true);
+        let clone = manager.clone();
+        assert_eq!(manager.read_file(&path).unwrap(), "first");
+        assert_eq!(clone.file_cache().unwrap().len(), 1);
+
+        // 更新時刻とサイズが同じなら読み直さない
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        fs::write(&path, "FIRST").unwrap();
+        fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        assert_eq!(clone.read_file(&path).unwrap(), "first");
+
+        // 更新時刻が変われば読み直す
+        let later = modified + std::time::Duration::from_secs(5);
+        fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        assert_eq!(manager.read_file(&path).unwrap(), "FIRST");
+        fs::write(&path, "second version").unwrap();
+        assert_eq!(manager.read_file(&path).unwrap(), "second version");
+
+        manager.set_file_cache(false);
+        assert!(manager.file_cache().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_file_keeps_one_backup() {
+        let dir = std::env::temp_dir().join(format!("contui_write_file_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut manager = FileAccessManager::new();
+        manager.add_allowed_directory(&dir).unwrap();
+        let path = dir.join("notes.txt");
+        manager.write_file(&path, "first").unwrap();
+        assert!(!backup_path(&path).exists());
+        manager.write_file(&path, "second").unwrap();
+        manager.write_file(&path, "third").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third");
+        assert_eq!(fs::read_to_string(dir.join("notes.txt.bak")).unwrap(), "second");
+        assert!(manager.is_own_write(&path));
+
+        // 5MBを超えるファイルはバックアップしない
+        let big = dir.join("big.log");
+        fs::write(&big, "x".repeat(MAX_BACKUP_BYTES as usize + 1)).unwrap();
+        manager.write_file(&big, "small").unwrap();
+        assert_eq!(fs::read_to_string(&big).unwrap(), "small");
+        assert!(!backup_path(&big).exists());
+        assert!(manager.write_file(&dir, "x").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_new_file_in_allowed_directory_is_allowed() {
+        let mut manager = FileAccessManager::new();
+        manager.add_allowed_directory(std::env::temp_dir()).unwrap();
+        let new_file = std::env::temp_dir().join("contui_not_created_yet").join("file.txt");
+        assert_eq!(manager.check_access(&new_file).unwrap(), PathAccess::Allowed);
+        assert_eq!(manager.check_access(std::env::temp_dir().join("..").join("..")).unwrap(), PathAccess::Outside);
+    }
+
+    #[test]
+    fn test_sensitive_directories_need_explicit_config() {
+        let root = std::env::temp_dir().join(format!("contui_sensitive_{}", std::process::id()));
+        let secret = root.join(".ssh");
+        fs::create_dir_all(&secret).unwrap();
+        fs::write(secret.join("config"), "Host *").unwrap();
+        let mut manager = FileAccessManager::new();
+        manager.add_denied_directory(&secret).unwrap();
+        manager.add_allowed_directory(&root).unwrap();
+
+        // 親ディレクトリを許可しても機密ディレクトリは読めず、確認での許可もできない
+        assert_eq!(manager.check_access(root.join("notes.txt")).unwrap(), PathAccess::Allowed);
+        assert_eq!(manager.check_access(secret.join("config")).unwrap(), PathAccess::Sensitive);
+        let error = manager.read_file(secret.join("config")).unwrap_err();
+        assert!(error.downcast_ref::<AccessDenied>().is_some_and(|denied| denied.sensitive));
+        assert!(manager.add_allowed_directory(&secret).is_err());
+
+        // 設定ファイルで明示すれば許可される
+        manager.allow_configured_directory(&secret).unwrap();
+        assert_eq!(manager.read_file(secret.join("config")).unwrap(), "Host *");
+
+        let list = root.join("allowed_directories");
+        append_allowed_directory(&list, &secret).unwrap();
+        fs::write(&list, format!("# comment\n\n{}", fs::read_to_string(&list).unwrap())).unwrap();
+        assert_eq!(load_allowed_directories(&list).unwrap(), vec![secret.clone()]);
+        assert_eq!(grant_directory(&secret.join("missing").join("file.txt")).unwrap(), normalize_path(&secret).unwrap());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_directory_entries() {
+        let entry = format!("src{}", DIRECTORY_SUFFIX);
+        assert!(is_directory_entry(&entry));
+        assert_eq!(entry_name(&entry), "src");
+        assert!(!is_directory_entry("main.rs"));
+    }
+
+    #[test]
+    fn test_file_reference_parse() {
+        assert_eq!(FileReference::parse("src/main.rs:L10-L50"), FileReference { path: "src/main.rs".to_string(), lines: Some((10, 50)), full: false });
+        assert_eq!(FileReference::parse("src/main.rs"), FileReference::whole("src/main.rs"));
+        assert_eq!(FileReference::parse(r"C:\src\main.rs:L1-L2").path, r"C:\src\main.rs");
+        assert_eq!(FileReference::parse("notes:Lx-L2"), FileReference::whole("notes:Lx-L2"));
+        assert_eq!(FileReference::parse("a.rs:L3-L4").to_string(), "a.rs:L3-L4");
+    }
+
+    #[test]
+    fn test_create_and_delete_directory() {
+        let root = std::env::temp_dir().join(format!("contui_dirs_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut manager = FileAccessManager::new();
+        manager.add_allowed_directory(&root).unwrap();
+
+        let nested = root.join("a").join("b");
+        manager.create_directory(&nested).unwrap();
+        fs::write(nested.join("file.txt"), "x").unwrap();
+        assert!(nested.is_dir());
+
+        manager.delete_directory_recursive(&root.join("a")).unwrap();
+        assert!(!root.join("a").exists());
+
+        // 許可ディレクトリそのもの・許可外・ファイルは削除しない
+        assert!(manager.delete_directory_recursive(&root).is_err());
+        assert!(manager.create_directory(&root.join("..").join("contui_outside")).is_err());
+        fs::write(root.join("file.txt"), "x").unwrap();
+        assert!(manager.delete_directory_recursive(&root.join("file.txt")).is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_decode_file_bytes_fixtures() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/encoding");
+        let fallbacks = parse_encodings(DEFAULT_FALLBACK_ENCODINGS).unwrap();
+        let decode = |name: &str| decode_file_bytes(&fs::read(fixtures.join(name)).unwrap(), &fallbacks);
+
+        let expected = "こんにちは\nhello\n".to_string();
+        assert_eq!(decode("utf8.txt"), FileContent::Text { text: expected.clone(), encoding: None });
+        assert_eq!(decode("shift_jis.txt"), FileContent::Text { text: expected, encoding: Some("Shift_JIS") });
+        assert_eq!(decode("binary.png"), FileContent::Binary { size: fs::metadata(fixtures.join("binary.png")).unwrap().len() });
+
+        // フォールバックがなければUTF-8以外はバイナリ扱い、BOMは取り除く
+        assert!(matches!(decode_file_bytes(&fs::read(fixtures.join("shift_jis.txt")).unwrap(), &[]), FileContent::Binary { .. }));
+        assert_eq!(decode_file_bytes(b"\xEF\xBB\xBFbom", &[]), FileContent::Text { text: "bom".to_string(), encoding: None });
+        assert!(parse_encodings(&["no-such-encoding"]).is_err());
+
+        let mut manager = FileAccessManager::new();
+        manager.add_allowed_directory(&fixtures).unwrap();
+        assert_eq!(manager.read_file_range(fixtures.join("shift_jis.txt"), 1, 1).unwrap(), "こんにちは");
+        let error = manager.read_file(fixtures.join("binary.png")).unwrap_err().to_string();
+        assert!(error.starts_with("Binary file not supported:"));
+        assert!(is_binary_file(&fixtures.join("binary.png")));
+        assert!(!is_binary_file(&fixtures.join("shift_jis.txt")));
+        assert!(!is_binary_file(&fixtures.join("missing.bin")));
+    }
+
+    #[test]
+    fn test_read_file_range() {
+        let path = std::env::temp_dir().join(format!("contui_range_{}.txt", std::process::id()));
+        fs::write(&path, "one\ntwo\nthree\nfour\n").unwrap();
+        let mut manager = FileAccessManager::new();
+        manager.add_allowed_directory(std::env::temp_dir()).unwrap();
+
+        assert_eq!(manager.read_file_range(&path, 2, 3).unwrap(), "two\nthree");
+        assert_eq!(manager.read_file_range(&path, 4, 100).unwrap(), "four");
+        assert!(manager.read_file_range(&path, 5, 6).is_err());
+        assert!(manager.read_file_range(&path, 0, 1).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,25 +1,176 @@
 use serde::{Deserialize, Serialize};
-use anyhow::Result;
-use crate::config::LlmConfig;
-use crate::file_access::FileAccessManager;
-// use crate::history::ChatMessage; // Unused import
+use anyhow::{Context, Result};
+use crate::config::{LlmConfig, ShellKind, DEFAULT_MAX_PROMPT_BYTES};
+use crate::custom_tools::CustomTool;
+use crate::hooks::Hooks;
+use crate::file_access::{AccessDenied, FileAccessManager, FileContent, FileReference, PathAccess, PreparedAttachments};
+use crate::history::{estimate_tokens, ChatMessage, GenerationOverrides};
 use std::io::Write;
+use std::ops::RangeInclusive;
 use crate::debug_log;
 use crate::history::HistoryManager;
+use crate::i18n::{tr, Language};
+use crate::llm::LlmProvider;
+use crate::mock_llm::MockScript;
+use crate::redact::Redactor;
+use crate::termination::TerminationConfig;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// /temp で指定できるtemperatureの範囲
+pub const TEMPERATURE_RANGE: RangeInclusive<f32> = 0.0..=2.0;
+/// /maxtokens で指定できる最大出力トークン数の範囲
+pub const MAX_TOKENS_RANGE: RangeInclusive<u32> = 1..=65536;
+const DEFAULT_TEMPERATURE: f32 = 0.7;
+const DEFAULT_MAX_TOKENS: u32 = 1000;
+
+/// 生成パラメータの値がどこから来たか
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SettingSource {
+    Session,
+    Config,
+    Default,
+}
+
+impl SettingSource {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SettingSource::Session => "session override",
+            SettingSource::Config => "config",
+            SettingSource::Default => "default",
+        }
+    }
+}
+
+/// 実際にリクエストで使われる生成パラメータ
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EffectiveSettings {
+    pub temperature: f32,
+    pub temperature_source: SettingSource,
+    pub max_tokens: u32,
+    pub max_tokens_source: SettingSource,
+}
+
+fn resolve_setting<T>(session: Option<T>, config: Option<T>, default: T) -> (T, SettingSource) {
+    match (session, config) {
+        (Some(value), _) => (value, SettingSource::Session),
+        (None, Some(value)) => (value, SettingSource::Config),
+        (None, None) => (default, SettingSource::Default),
+    }
+}
+
+/// セッションの上書き → 設定 → デフォルトの順に生成パラメータを決める
+pub fn resolve_settings(config: &LlmConfig, overrides: &GenerationOverrides) -> EffectiveSettings {
+    let (temperature, temperature_source) =
+        resolve_setting(overrides.temperature, config.temperature, DEFAULT_TEMPERATURE);
+    let (max_tokens, max_tokens_source) =
+        resolve_setting(overrides.max_tokens, config.max_tokens, DEFAULT_MAX_TOKENS);
+    EffectiveSettings {
+        temperature,
+        temperature_source,
+        max_tokens,
+        max_tokens_source,
+    }
+}
 
 /// コマンド実行結果の構造体
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct CommandResult {
     pub command: String,
     pub stdout: String,
     pub stderr: String,
     pub success: bool,
     pub exit_code: Option<i32>,
+    /// 実行にかかった時間
+    #[serde(default)]
+    pub duration_ms: u64,
+}
+
+impl CommandResult {
+    /// 終了コードのバッジ（タイムアウトなどで終了コードがなければ「exit ?」）
+    pub fn exit_badge(&self) -> String {
+        match self.exit_code {
+            Some(code) => format!("[exit {}]", code),
+            None => "[exit ?]".to_string(),
+        }
+    }
+
+    /// 「$ cargo test  [exit 0]  3.2s」
+    pub fn header(&self) -> String {
+        format!("$ {}  {}  {:.1}s", self.command, self.exit_badge(), self.duration_ms as f64 / 1000.0)
+    }
+
+    /// エクスポート用。stdout/stderrをラベル付きのコードブロックにする（空のストリームは省く）
+    pub fn markdown(&self) -> String {
+        let mut sections = vec![self.header()];
+        for (label, output) in [("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            if !output.trim().is_empty() {
+                sections.push(format!("```{}\n{}\n```", label, output.trim_end()));
+            }
+        }
+        sections.join("\n")
+    }
+}
+
+/// 子プロセスの出力を最後まで読み取る（読めなかった分は空のまま）
+async fn read_pipe<R: tokio::io::AsyncRead + Unpin>(pipe: Option<R>) -> Vec<u8> {
+    use tokio::io::AsyncReadExt;
+    let mut buffer = Vec::new();
+    if let Some(mut pipe) = pipe {
+        if let Err(e) = pipe.read_to_end(&mut buffer).await {
+            debug_log!("[read_pipe] error: {:?}", e);
+        }
+    }
+    buffer
+}
+
+/// 再送すれば成功しうる一時的なエラーか（通信エラー、429、5xx）
+/// 認証エラーや不正なリクエストなどは再送しても同じ結果になるのでfalse
+pub fn is_transient_error(error: &anyhow::Error) -> bool {
+    if error.downcast_ref::<reqwest::Error>().is_some() {
+        return true;
+    }
+    let message = error.to_string();
+    let Some(body) = message.strip_prefix("Gemini API Error: ") else {
+        return false;
+    };
+    match serde_json::from_str::<serde_json::Value>(body).ok().and_then(|json| json["error"]["code"].as_u64()) {
+        Some(code) => code == 429 || (500..600).contains(&code),
+        None => false,
+    }
+}
+
+/// 許可ディレクトリの外で失敗したときはパスを添え、UIがディレクトリの許可を確認できるようにする
+fn with_outside_path(mut response: serde_json::Value, error: &anyhow::Error) -> serde_json::Value {
+    if let Some(denied) = error.downcast_ref::<AccessDenied>() {
+        if !denied.sensitive {
+            response["outside_path"] = serde_json::json!(denied.path);
+        }
+    }
+    response
+}
+
+/// コマンドの終了コード・stdout・stderrをまとめた詳細表示
+fn command_output_detail(result: &CommandResult) -> String {
+    let mut sections = Vec::new();
+    if let Some(code) = result.exit_code {
+        sections.push(format!("exit code: {}", code));
+    }
+    if !result.stdout.trim().is_empty() {
+        sections.push(format!("stdout:\n{}", result.stdout.trim_end()));
+    }
+    if !result.stderr.trim().is_empty() {
+        sections.push(format!("stderr:\n{}", result.stderr.trim_end()));
+    }
+    sections.join("\n")
 }
 
 #[derive(Debug, Serialize)]
 struct GeminiRequest {
+    #[serde(rename = "systemInstruction", skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<SystemInstruction>,
     contents: Vec<Content>,
     #[serde(rename = "generationConfig")]
     generation_config: GenerationConfig,
@@ -27,6 +178,12 @@ struct GeminiRequest {
     tools: Option<Vec<Tool>>,
 }
 
+/// systemInstructionフィールド（roleを持たない）
+#[derive(Debug, Serialize, Clone)]
+struct SystemInstruction {
+    parts: Vec<Part>,
+}
+
 #[derive(Debug, Serialize, Clone)] // Added Clone
 pub struct Content { // Made public
     pub role: String, // Made public
@@ -39,6 +196,71 @@ pub enum Part { // Made public - changed to enum
     Text { text: String },
     FunctionCall { #[serde(rename = "functionCall")] function_call: FunctionCall },
     FunctionResponse { #[serde(rename = "functionResponse")] function_response: FunctionResponse },
+    /// アプリ内でのみ使う構造化されたツール実行結果（APIへ送る前にテキストへ変換する）
+    ToolOutcome { #[serde(rename = "toolOutcome")] tool_outcome: ToolOutcome },
+}
+
+impl Part {
+    /// APIリクエストに含められる形に変換する
+    pub fn to_request_part(&self) -> Part {
+        match self {
+            Part::ToolOutcome { tool_outcome } => Part::Text {
+                text: serde_json::to_string(&tool_outcome.function_response()).unwrap_or_default(),
+            },
+            other => other.clone(),
+        }
+    }
+}
+
+/// ツール（関数呼び出し）の実行結果。チャットには1行のチップとして表示する
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ToolOutcome {
+    pub tool: String,
+    pub args_summary: String,
+    pub success: bool,
+    /// stdout/stderrなどの詳細（展開表示・エクスポート用）
+    pub detail: String,
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+    /// モデルに返したレスポンス本体
+    #[serde(default)]
+    pub response: serde_json::Value,
+    /// execute_commandの結果（チャットではstdout/stderrを分けて表示する）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command_result: Option<CommandResult>,
+}
+
+impl ToolOutcome {
+    /// 1行表示（例: 🛠 execute_command `cargo test` ✓ 3.2s）
+    pub fn chip(&self) -> String {
+        let mut chip = format!("🛠 {}", self.tool);
+        if !self.args_summary.is_empty() {
+            chip.push_str(&format!(" `{}`", self.args_summary));
+        }
+        chip.push_str(if self.success { " ✓" } else { " ✗" });
+        if let Some(duration_ms) = self.duration_ms {
+            chip.push_str(&format!(" {:.1}s", duration_ms as f64 / 1000.0));
+        }
+        chip
+    }
+
+    /// チップの下に詳細を付けた展開表示（コマンドの結果はstdout/stderrのコードブロック）
+    pub fn expanded(&self) -> String {
+        if let Some(result) = &self.command_result {
+            format!("{}\n{}", self.chip(), result.markdown())
+        } else if self.detail.is_empty() {
+            self.chip()
+        } else {
+            format!("{}\n{}", self.chip(), self.detail)
+        }
+    }
+
+    pub fn function_response(&self) -> FunctionResponse {
+        FunctionResponse {
+            name: self.tool.clone(),
+            response: self.response.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -59,17 +281,117 @@ struct Tool {
 struct FunctionDeclaration {
     name: String,
     description: String,
-    parameters: serde_json::Value,
+    parameters: FunctionParameters,
+}
+
+/// functionDeclarationsのparameters。組み込みの関数は型付きのスキーマ、カスタムツールは設定ファイルのJSONのまま
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum FunctionParameters {
+    Schema(FunctionSchema),
+    Custom(serde_json::Value),
+}
+
+/// 引数の型（JSON Schemaの type）
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ParamType {
+    String,
+    Integer,
+    Boolean,
+    Object,
+}
+
+/// 引数1つの定義
+#[derive(Debug, Clone, Serialize, PartialEq)]
+struct PropertyDef {
+    #[serde(rename = "type")]
+    kind: ParamType,
+    description: String,
+}
+
+/// 関数の引数のスキーマ（{"type": "object", "properties": {...}, "required": [...]}）
+/// 引数はrequired/optionalで追加するので、requiredには必ずpropertiesにある名前だけが入る
+#[derive(Debug, Clone, Serialize, PartialEq)]
+struct FunctionSchema {
+    #[serde(rename = "type")]
+    kind: ParamType,
+    properties: BTreeMap<String, PropertyDef>,
+    required: Vec<String>,
+}
+
+impl FunctionSchema {
+    fn new() -> Self {
+        Self { kind: ParamType::Object, properties: BTreeMap::new(), required: Vec::new() }
+    }
+
+    /// 必須の引数を追加する
+    fn required(mut self, name: &str, kind: ParamType, description: &str) -> Self {
+        self.required.push(name.to_string());
+        self.optional(name, kind, description)
+    }
+
+    /// 省略できる引数を追加する
+    fn optional(mut self, name: &str, kind: ParamType, description: &str) -> Self {
+        self.properties.insert(name.to_string(), PropertyDef { kind, description: description.to_string() });
+        self
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct GeminiResponse {
     candidates: Vec<Candidate>,
+    #[serde(rename = "usageMetadata", default)]
+    usage_metadata: Option<UsageMetadata>,
+}
+
+/// レスポンスに含まれるトークン数
+#[derive(Debug, Deserialize)]
+struct UsageMetadata {
+    #[serde(rename = "promptTokenCount", default)]
+    prompt_token_count: u64,
+    #[serde(rename = "candidatesTokenCount", default)]
+    candidates_token_count: u64,
+}
+
+/// models エンドポイント（モデル一覧）の1件
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct ModelInfo {
+    /// 「models/gemini-2.5-flash」の形
+    pub name: String,
+    #[serde(rename = "supportedGenerationMethods", default)]
+    pub supported_generation_methods: Vec<String>,
+}
+
+/// models エンドポイントのレスポンス（1ページ分）
+#[derive(Debug, Deserialize)]
+struct ModelListPage {
+    #[serde(default)]
+    models: Vec<ModelInfo>,
+    #[serde(rename = "nextPageToken", default)]
+    next_page_token: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 struct Candidate {
     content: ResponseContent,
+    #[serde(rename = "finishReason", default)]
+    finish_reason: Option<String>,
+}
+
+/// maxOutputTokensで応答が途中で切れたときに本文へ付け足す警告
+const TRUNCATION_WARNING: &str = "⚠️ Response truncated (max tokens reached). Try `/set max_tokens 4000`.";
+
+/// countTokensの結果を覚えておく件数の上限（超えたら覚えた分を捨てて数え直す）
+const TOKEN_COUNT_CACHE_LIMIT: usize = 1024;
+
+/// finishReasonがMAX_TOKENSなら本文の末尾に警告を追記する
+fn with_truncation_warning(text: &str, finish_reason: Option<&str>) -> String {
+    if finish_reason == Some("MAX_TOKENS") {
+        format!("{}\n\n{}", text, TRUNCATION_WARNING)
+    } else {
+        text.to_string()
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -89,6 +411,303 @@ pub enum ResponsePart { // Made public
     Text { text: String },
     FunctionCall { #[serde(rename = "functionCall")] function_call: FunctionCall },
     FunctionResponse { #[serde(rename = "functionResponse")] function_response: FunctionResponse },
+    ToolOutcome { #[serde(rename = "toolOutcome")] tool_outcome: ToolOutcome },
+}
+
+impl ResponsePart {
+    /// 画面表示・ログ用のテキスト
+    pub fn display_text(&self) -> String {
+        match self {
+            ResponsePart::Text { text } => text.clone(),
+            ResponsePart::FunctionCall { function_call } => {
+                format!("FunctionCall: {}", serde_json::to_string_pretty(function_call).unwrap_or_default())
+            }
+            ResponsePart::FunctionResponse { function_response } => {
+                format!("FunctionResponse: {}", serde_json::to_string_pretty(function_response).unwrap_or_default())
+            }
+            ResponsePart::ToolOutcome { tool_outcome } => {
+                format!("FunctionResponse: {}", serde_json::to_string_pretty(&tool_outcome.function_response()).unwrap_or_default())
+            }
+        }
+    }
+
+    /// 履歴に保存する形式（関数呼び出し・結果はJSON文字列として保存）
+    pub fn to_history_part(&self) -> Part {
+        match self {
+            ResponsePart::Text { text } => Part::Text { text: text.clone() },
+            ResponsePart::FunctionCall { function_call } => Part::Text { text: serde_json::to_string(function_call).unwrap_or_default() },
+            ResponsePart::FunctionResponse { function_response } => Part::Text { text: serde_json::to_string(function_response).unwrap_or_default() },
+            ResponsePart::ToolOutcome { tool_outcome } => Part::ToolOutcome { tool_outcome: tool_outcome.clone() },
+        }
+    }
+}
+
+/// トークン数推定用のテキスト（関数呼び出し・結果はJSONとして数える）
+fn part_text_for_estimate(part: &Part) -> String {
+    match part {
+        Part::Text { text } => text.clone(),
+        Part::FunctionCall { function_call } => serde_json::to_string(function_call).unwrap_or_default(),
+        Part::FunctionResponse { function_response } => serde_json::to_string(function_response).unwrap_or_default(),
+        Part::ToolOutcome { tool_outcome } => serde_json::to_string(&tool_outcome.function_response()).unwrap_or_default(),
+    }
+}
+
+/// システムプロンプトとツール定義の分を数えるcountTokensのリクエスト
+fn overhead_count_body(model: &str, request: &GeminiRequest) -> serde_json::Value {
+    serde_json::json!({
+        "generateContentRequest": {
+            "model": format!("models/{}", model),
+            "systemInstruction": request.system_instruction,
+            "tools": request.tools,
+            "contents": [{"role": "user", "parts": [{"text": "."}]}],
+        }
+    })
+}
+
+/// システムプロンプトとツール定義の分のトークン数推定用のテキスト
+fn overhead_estimate_text(request: &GeminiRequest) -> String {
+    let instruction = request.system_instruction.iter().flat_map(|i| i.parts.iter()).map(part_text_for_estimate);
+    instruction.chain(std::iter::once(serde_json::to_string(&request.tools).unwrap_or_default())).collect()
+}
+
+/// コンテキストウィンドウに収まらないプロンプト（古いコンテキストを全て落としても超える）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PromptTooLarge {
+    pub tokens: u64,
+    pub limit: u64,
+}
+
+impl std::fmt::Display for PromptTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Prompt is {} tokens, limit {} — remove @file attachments or /compact",
+            group_digits(self.tokens),
+            group_digits(self.limit)
+        )
+    }
+}
+
+fn parse_model_list_page(text: &str) -> Result<ModelListPage> {
+    serde_json::from_str(text).with_context(|| format!("models returned an unexpected response: {}", text))
+}
+
+/// /gemini-version で表示するモデル一覧（「models/」を外した名前と対応するメソッド。今のモデルには * を付ける）
+pub fn format_model_list(models: &[ModelInfo], current_model: &str) -> String {
+    let mut lines = vec![format!("📋 Available models ({}):", models.len())];
+    for model in models {
+        let name = model.name.strip_prefix("models/").unwrap_or(&model.name);
+        let marker = if name == current_model { "*" } else { " " };
+        let methods = if model.supported_generation_methods.is_empty() {
+            "-".to_string()
+        } else {
+            model.supported_generation_methods.join(", ")
+        };
+        lines.push(format!("{} {} — {}", marker, name, methods));
+    }
+    lines.join("\n")
+}
+
+/// 3桁ごとにカンマで区切る（41203 → "41,203"）
+pub fn group_digits(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+/// contentsごとのトークン数から、上限に収まるまで古いコンテキストを何件落とすかを求める
+/// 先頭のpinned_head件（添付ファイル）と最後の1件（今回のメッセージ）は落とさない
+pub fn plan_context_trim(overhead: u64, counts: &[u64], pinned_head: usize, limit: u64) -> std::result::Result<usize, PromptTooLarge> {
+    let total = overhead + counts.iter().sum::<u64>();
+    let mut remaining = total;
+    let mut dropped = 0;
+    let droppable = counts.get(pinned_head..counts.len().saturating_sub(1)).unwrap_or_default();
+    for count in droppable {
+        if remaining <= limit {
+            break;
+        }
+        remaining -= count;
+        dropped += 1;
+    }
+    if remaining <= limit {
+        Ok(dropped)
+    } else {
+        Err(PromptTooLarge { tokens: total, limit })
+    }
+}
+
+/// build_file_context_requestが先頭に置く添付ファイルのcontentか
+fn is_file_contents_block(content: &Content) -> bool {
+    matches!(content.parts.first(), Some(Part::Text { text }) if text.starts_with("=== FILE CONTENTS ==="))
+}
+
+/// 添付ファイルの送り方（chat_with_file_contextで使う）
+#[derive(Debug, Default, PartialEq)]
+pub struct AttachmentPlan {
+    /// 本リクエストに含めるファイル内容
+    pub inline_text: String,
+    /// 本リクエストの前に1つずつ送る分割アップロード
+    pub chunks: Vec<String>,
+    /// 上限を超えたときに、モデルが実際に見た内容の説明（超えなければNone）
+    pub report: Option<String>,
+}
+
+fn attachment_header(reference: &FileReference, encoding: Option<&str>) -> String {
+    let lines = reference.lines.map(|(start, end)| format!(" (lines {}-{})", start, end)).unwrap_or_default();
+    let encoding = encoding.map(|encoding| format!(" (decoded from {})", encoding)).unwrap_or_default();
+    format!("\n--- File: {}{}{} ---\n", reference.path, lines, encoding)
+}
+
+fn attachment_section(reference: &FileReference, content: &Result<FileContent>) -> String {
+    match content {
+        Ok(FileContent::Text { text, encoding }) => {
+            format!("{}{}\n--- End of file ---\n\n", attachment_header(reference, *encoding), text)
+        }
+        // バイナリは中身の代わりに1行の注記だけを送る
+        Ok(FileContent::Binary { size }) => format!("\n--- File: {}: binary file, {} bytes, skipped ---\n\n", reference.path, size),
+        Err(e) => format!("\n--- Error reading file: {} ---\nError: {}\n\n", reference.path, e),
+    }
+}
+
+/// 省略・分割の対象にできるテキストの内容
+fn attachment_text(content: &Result<FileContent>) -> Option<(&str, Option<&'static str>)> {
+    match content {
+        Ok(FileContent::Text { text, encoding }) => Some((text, *encoding)),
+        _ => None,
+    }
+}
+
+/// index以下で最も近い文字境界
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// 先頭と末尾を合わせてbudgetバイト程度残し、間を省略する（できるだけ行単位で切る）
+/// 省略した行数とバイト数も返す
+fn truncate_head_tail(content: &str, budget: usize) -> (String, usize, usize) {
+    let half = budget / 2;
+    let mut head_end = floor_char_boundary(content, half);
+    if let Some(newline) = content[..head_end].rfind('\n') {
+        head_end = newline + 1;
+    }
+    let mut tail_start = floor_char_boundary(content, content.len().saturating_sub(half)).max(head_end);
+    if let Some(newline) = content[tail_start..].find('\n') {
+        tail_start += newline + 1;
+    }
+    let omitted = &content[head_end..tail_start];
+    let omitted_lines = omitted.lines().count();
+    let text = format!(
+        "{}\n... [{} lines, {} bytes omitted] ...\n{}",
+        &content[..head_end],
+        omitted_lines,
+        omitted.len(),
+        &content[tail_start..]
+    );
+    (text, omitted_lines, omitted.len())
+}
+
+/// contentをchunk_sizeバイト以下の断片に分ける
+fn split_chunks(content: &str, chunk_size: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = content;
+    while !rest.is_empty() {
+        // 1文字がchunk_sizeより大きくても必ず前に進む
+        let mut end = floor_char_boundary(rest, chunk_size.max(1));
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (chunk, remaining) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = remaining;
+    }
+    chunks
+}
+
+/// メッセージと添付ファイルの合計がlimitバイトを超えるなら送り方を決める
+/// @file!: の参照は分割アップロードし、それ以外は先頭+末尾に省略して残りの枠に収める
+pub fn plan_attachments(message: &str, attachments: &[(FileReference, Result<FileContent>)], limit: usize) -> AttachmentPlan {
+    let sections: Vec<String> = attachments.iter().map(|(reference, content)| attachment_section(reference, content)).collect();
+    let total = message.len() + sections.iter().map(String::len).sum::<usize>();
+    if total <= limit {
+        return AttachmentPlan { inline_text: sections.concat(), ..Default::default() };
+    }
+
+    let mut plan = AttachmentPlan::default();
+    let mut notes = Vec::new();
+    // 分割アップロードにはコンテキストも一緒に送るので、1回あたりは上限の半分にする
+    let chunk_size = (limit / 2).max(1);
+    for (reference, content) in attachments {
+        let Some((content, encoding)) = attachment_text(content).filter(|_| reference.full) else {
+            continue;
+        };
+        let pieces = split_chunks(content, chunk_size);
+        for (i, piece) in pieces.iter().enumerate() {
+            plan.chunks.push(format!(
+                "Part {}/{} of {}. Do not answer yet; reply only \"OK\".{}{}\n--- End of part ---\n",
+                i + 1,
+                pieces.len(),
+                reference,
+                attachment_header(reference, encoding),
+                piece
+            ));
+        }
+        notes.push(format!("- {}: sent in full as {} chunked uploads before the question", reference, pieces.len()));
+    }
+
+    // 分割しないファイル（バイナリや読み込みエラーを含む）で残りの枠を等分する
+    let uploaded_in_parts = |(reference, content): &(FileReference, Result<FileContent>)| reference.full && attachment_text(content).is_some();
+    let inline: Vec<usize> = (0..attachments.len()).filter(|&i| !uploaded_in_parts(&attachments[i])).collect();
+    let uploaded: String = attachments
+        .iter()
+        .filter(|attachment| uploaded_in_parts(attachment))
+        .map(|(reference, _)| format!("\n--- File: {} was uploaded in parts earlier in this conversation ---\n", reference))
+        .collect();
+    let budget = limit.saturating_sub(message.len() + uploaded.len()) / inline.len().max(1);
+    plan.inline_text.push_str(&uploaded);
+    for i in inline {
+        let (reference, content) = &attachments[i];
+        match attachment_text(content) {
+            Some((text, encoding)) if sections[i].len() > budget => {
+                let overhead = sections[i].len() - text.len();
+                let (truncated, omitted_lines, omitted_bytes) = truncate_head_tail(text, budget.saturating_sub(overhead));
+                plan.inline_text.push_str(&attachment_section(reference, &Ok(FileContent::Text { text: truncated, encoding })));
+                notes.push(format!(
+                    "- {}: truncated to head+tail ({} lines, {} bytes omitted)",
+                    reference, omitted_lines, omitted_bytes
+                ));
+            }
+            _ => plan.inline_text.push_str(&sections[i]),
+        }
+    }
+
+    if notes.is_empty() {
+        notes.push("- attachments were sent unchanged (the message itself is over the limit)".to_string());
+    }
+    plan.report = Some(format!(
+        "📎 The prompt with attachments was {} bytes, over the {}-byte limit (MAX_PROMPT_BYTES). What the model saw:\n{}",
+        total,
+        limit,
+        notes.join("\n")
+    ));
+    plan
+}
+
+/// 応答パートのうち最初のテキスト（テキストのみを期待するリクエスト用）
+fn first_text(parts: &[ResponsePart]) -> Option<&str> {
+    parts.iter().find_map(|part| match part {
+        ResponsePart::Text { text } => Some(text.as_str()),
+        _ => None,
+    })
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)] // Added Serialize and Clone
@@ -103,6 +722,31 @@ pub struct GeminiClient {
     config: LlmConfig,
     file_access: FileAccessManager,
     history_manager: Arc<Mutex<HistoryManager>>, // Change type
+    shell: ShellKind,
+    command_timeout: Option<Duration>,
+    max_prompt_bytes: usize,
+    custom_tools: Vec<CustomTool>,
+    allow_recursive_delete: bool,
+    mock_script: Option<Arc<MockScript>>,
+    language: Language,
+    /// 再送時に元のリクエストと同じ生成パラメータを使うための固定値
+    pinned_settings: Option<EffectiveSettings>,
+    /// 使用量を記録するセッション（Noneなら表示中のセッション）
+    session_id: Option<uuid::Uuid>,
+    /// countTokensの結果（モデルと内容のハッシュ → トークン数）。同じコンテキストは数え直さない
+    token_counts: Arc<Mutex<HashMap<u64, u64>>>,
+    /// 送信前に秘密情報を伏せる（リクエストのログにも伏せた後の内容を書く）
+    redactor: Redactor,
+    /// 送信したリクエストのJSONを追記するログ（Noneなら書かない）
+    request_log: Option<std::path::PathBuf>,
+    /// エージェントループを止める条件
+    termination: TerminationConfig,
+    /// ツールの実行前後と応答の後に実行するフック
+    hooks: Arc<Hooks>,
+    /// まだ通知していないフックの失敗
+    hook_warnings: Arc<Mutex<Vec<String>>>,
+    /// まだ通知していない、古いコンテキストを落として送ったことの注記（履歴には残さない）
+    preflight_notes: Arc<Mutex<Vec<String>>>,
 }
 
 impl GeminiClient {
@@ -112,10 +756,35 @@ impl GeminiClient {
             config,
             file_access: FileAccessManager::new(),
             history_manager,
+            shell: ShellKind::platform_default(),
+            command_timeout: None,
+            max_prompt_bytes: DEFAULT_MAX_PROMPT_BYTES,
+            custom_tools: Vec::new(),
+            allow_recursive_delete: false,
+            mock_script: None,
+            language: Language::default(),
+            pinned_settings: None,
+            session_id: None,
+            token_counts: Arc::new(Mutex::new(HashMap::new())),
+            redactor: Redactor::default(),
+            request_log: Some(std::path::PathBuf::from("contui_llm_request.log")),
+            termination: TerminationConfig::default(),
+            hooks: Arc::default(),
+            hook_warnings: Arc::default(),
+            preflight_notes: Arc::default(),
         }
     }
         
 
+    /// 現在のセッションの上書きを反映した生成パラメータ
+    pub fn effective_settings(&self) -> EffectiveSettings {
+        if let Some(settings) = self.pinned_settings {
+            return settings;
+        }
+        let overrides = self.history_manager.lock().unwrap().get_history().current_overrides();
+        resolve_settings(&self.config, &overrides)
+    }
+
     /// Google APIリクエスト共通化＋429時3秒リトライ
     async fn send_google_request_with_retry(
         &self,
@@ -125,15 +794,11 @@ impl GeminiClient {
         use tokio::time::{sleep, Duration};
         loop {
             // デバッグ: POST送信直前 (contui_debug.log)
+            // URLのクエリにはAPIキーが入るのでログには書かない
             debug_log!("[send_google_request_with_retry] POST to: {}
-", url);
+", url.split('?').next().unwrap_or(url));
             debug_log!("[send_google_request_with_retry] Request Body: {} 
 ", serde_json::to_string_pretty(request).unwrap_or_else(|_| "Failed to serialize request".to_string()));
-            // LLMリクエストJSONをcontui_llm_request.logに出力
-            if let Ok(mut file) = std::fs::OpenOptions::new().create(true).append(true).open("contui_llm_request.log") {
-                let _ = writeln!(file, "{}
-", serde_json::to_string_pretty(request).unwrap_or_else(|_| "Failed to serialize request".to_string()));
-            }
             let resp = self.client
                 .post(url)
                 .json(request)
@@ -163,7 +828,7 @@ impl GeminiClient {
                 Err(e) => {
                     // 通信エラー時も3秒待ってリトライ
                     debug_log!("[send_google_request_with_retry] Err (retrying): {}
-", e);
+", e.without_url());
                     sleep(Duration::from_secs(3)).await;
                     continue;
                 }
@@ -171,87 +836,287 @@ impl GeminiClient {
         }
     }
 
+    pub fn model(&self) -> &str {
+        &self.config.model
+    }
+
+    /// 使用中のモデルのコンテキストウィンドウ（トークン数）。不明ならNone
+    pub fn context_window(&self) -> Option<u64> {
+        self.config.context_windows.get(&self.config.model)
+    }
+
+    /// モデル名だけを差し替えたクライアントを作成
+    pub fn with_model(&self, model: &str) -> Self {
+        let mut client = self.clone();
+        client.config.model = model.to_string();
+        client
+    }
+
+    /// execute_commandで使うシェルを指定する
+    pub fn with_shell(mut self, shell: ShellKind) -> Self {
+        self.shell = shell;
+        self
+    }
+
+    /// 設定ファイルで定義したカスタムツールをモデルに公開する
+    pub fn with_custom_tools(mut self, custom_tools: Vec<CustomTool>) -> Self {
+        self.custom_tools = custom_tools;
+        self
+    }
+
+    /// delete_directoryを有効にする（無効ならモデルに関数を公開しない）
+    pub fn with_recursive_delete(mut self, allow: bool) -> Self {
+        self.allow_recursive_delete = allow;
+        self
+    }
+
+    /// システムプロンプトとエージェントループのメッセージの言語を指定する
+    pub fn with_language(mut self, language: Language) -> Self {
+        self.language = language;
+        self
+    }
+
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// セッションの上書きに関係なく、指定した生成パラメータでリクエストする
+    pub fn with_settings(mut self, settings: EffectiveSettings) -> Self {
+        self.pinned_settings = Some(settings);
+        self
+    }
+
+    /// 使用量をsession_idのセッションに記録する（リクエストの途中で表示中のセッションやタブが変わっても変わらない）
+    /// 通知する注記と警告は、同時に動く別のタブのリクエストと混ざらないようこのクライアントだけで持つ
+    pub fn with_session(mut self, session_id: uuid::Uuid) -> Self {
+        self.session_id = Some(session_id);
+        self.hook_warnings = Arc::default();
+        self.preflight_notes = Arc::default();
+        self
+    }
+
+    /// APIの代わりにモックスクリプトの応答を使う
+    pub fn with_mock_script(mut self, script: MockScript) -> Self {
+        self.mock_script = Some(Arc::new(script));
+        self
+    }
+
+    /// 設定されたコマンドのタイムアウト（COMMAND_TIMEOUT_SECS、未設定なら無制限）
+    pub fn command_timeout(&self) -> Option<Duration> {
+        self.command_timeout
+    }
+
+    /// execute_commandのタイムアウトを指定する（Noneなら無制限）
+    pub fn with_command_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.command_timeout = timeout;
+        self
+    }
+
+    /// 添付ファイルを含むプロンプトの上限バイト数を指定する
+    pub fn with_max_prompt_bytes(mut self, max_prompt_bytes: usize) -> Self {
+        self.max_prompt_bytes = max_prompt_bytes;
+        self
+    }
+
+    /// UTF-8で読めなかった添付ファイルに試すエンコーディングを指定する
+    pub fn with_fallback_encodings(mut self, encodings: Vec<&'static encoding_rs::Encoding>) -> Self {
+        self.file_access.set_fallback_encodings(encodings);
+        self
+    }
+
+    /// ファイル読み取りのキャッシュ（更新時刻が変わるまで読み直さない）を有効にする
+    pub fn with_file_cache(mut self, enabled: bool) -> Self {
+        self.file_access.set_file_cache(enabled);
+        self
+    }
+
+    /// 秘密情報の伏せ字処理を差し替える（REDACT_PATTERNSを加えたもの）
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = redactor;
+        self
+    }
+
+    /// 送信したリクエストのログの書き込み先（Noneなら書かない）
+    pub fn with_request_log(mut self, path: Option<std::path::PathBuf>) -> Self {
+        self.request_log = path;
+        self
+    }
+
+    /// エージェントループを止める条件（AGENT_MAX_STEPSなど）
+    pub fn with_termination(mut self, termination: TerminationConfig) -> Self {
+        self.termination = termination;
+        self
+    }
+
+    pub fn termination(&self) -> TerminationConfig {
+        self.termination
+    }
+
+    /// hooks.tomlの [hooks] を使う
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = Arc::new(hooks);
+        self
+    }
+
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    pub fn shell(&self) -> ShellKind {
+        self.shell
+    }
+
+    /// 前回呼んでからのフックの失敗（通知に使い、空に戻す）
+    pub fn take_hook_warnings(&self) -> Vec<String> {
+        std::mem::take(&mut *self.hook_warnings.lock().unwrap())
+    }
+
+    /// 前回呼んでから古いコンテキストを落として送ったことの注記（通知に使い、空に戻す）
+    pub fn take_preflight_notes(&self) -> Vec<String> {
+        std::mem::take(&mut *self.preflight_notes.lock().unwrap())
+    }
+
+    fn push_hook_warning(&self, event: &str, message: String) {
+        debug_log!("[hook] {} warning: {}", event, message);
+        self.hook_warnings.lock().unwrap().push(format!("⚠ {} hook: {}", event, message));
+    }
+
+    /// pre_toolフックが0以外で終了したら、その出力（ツールを実行しない理由）を返す
+    async fn run_pre_tool_hook(&self, function_call: &FunctionCall) -> Option<String> {
+        let hook = self.hooks.pre_tool.as_ref()?;
+        let input = serde_json::json!({"event": "pre_tool", "tool": function_call.name, "args": function_call.args});
+        match hook.run(self.shell, &input).await {
+            Ok(output) if output.success => None,
+            Ok(output) => Some(output.message().to_string()),
+            Err(e) => {
+                self.push_hook_warning("pre_tool", e.to_string());
+                None
+            }
+        }
+    }
+
+    async fn run_post_tool_hook(&self, function_call: &FunctionCall, outcome: &ToolOutcome) {
+        let Some(hook) = self.hooks.post_tool.as_ref() else {
+            return;
+        };
+        let input = serde_json::json!({
+            "event": "post_tool",
+            "tool": function_call.name,
+            "args": function_call.args,
+            "result": {"success": outcome.success, "detail": outcome.detail, "response": outcome.response},
+        });
+        match hook.run(self.shell, &input).await {
+            Ok(output) if !output.success => self.push_hook_warning("post_tool", format!("exited with an error: {}", output.message())),
+            Ok(_) => {}
+            Err(e) => self.push_hook_warning("post_tool", e.to_string()),
+        }
+    }
+
+    pub fn redactor(&self) -> &Redactor {
+        &self.redactor
+    }
+
     pub fn add_allowed_directory<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<()> {
         self.file_access.add_allowed_directory(path)
     }
 
+    pub fn allow_configured_directory<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<()> {
+        self.file_access.allow_configured_directory(path)
+    }
+
+    pub fn allowed_directories(&self) -> &[std::path::PathBuf] {
+        self.file_access.list_allowed()
+    }
+
+    pub fn denied_directories(&self) -> &[std::path::PathBuf] {
+        self.file_access.list_denied()
+    }
+
+    pub fn remove_allowed_directory(&mut self, directory: &std::path::Path) -> bool {
+        self.file_access.remove_allowed(directory)
+    }
+
+    pub fn check_path_access<P: AsRef<std::path::Path>>(&self, path: P) -> Result<PathAccess> {
+        self.file_access.check_access(path)
+    }
+
     /// Function declarations for Gemini Function Calling
     fn get_function_declarations(&self) -> Vec<Tool> {
+        let declaration = |name: &str, description: &str, schema: FunctionSchema| FunctionDeclaration {
+            name: name.to_string(),
+            description: description.to_string(),
+            parameters: FunctionParameters::Schema(schema),
+        };
         vec![
             Tool {
                 function_declarations: vec![
-                    FunctionDeclaration {
-                        name: "create_file".to_string(),
-                        description: "ファイルを作成します".to_string(),
-                        parameters: serde_json::json!({
-                            "type": "object",
-                            "properties": {
-                                "filename": {
-                                    "type": "string",
-                                    "description": "作成するファイル名"
-                                },
-                                "content": {
-                                    "type": "string",
-                                    "description": "ファイルの内容"
-                                }
-                            },
-                            "required": ["filename", "content"]
-                        }),
-                    },
-                    FunctionDeclaration {
-                        name: "edit_file".to_string(),
-                        description: "ファイルの一部を編集します".to_string(),
-                        parameters: serde_json::json!({
-                            "type": "object",
-                            "properties": {
-                                "filename": {
-                                    "type": "string",
-                                    "description": "編集するファイル名"
-                                },
-                                "start_line": {
-                                    "type": "integer",
-                                    "description": "編集開始行（1始まり）"
-                                },
-                                "end_line": {
-                                    "type": "integer",
-                                    "description": "編集終了行（1始まり）"
-                                },
-                                "content": {
-                                    "type": "string",
-                                    "description": "新しい内容"
-                                }
-                            },
-                            "required": ["filename", "start_line", "end_line", "content"]
-                        }),
-                    },
-                    FunctionDeclaration {
-                        name: "execute_command".to_string(),
-                        description: "シェルコマンドを実行します".to_string(),
-                        parameters: serde_json::json!({
-                            "type": "object",
-                            "properties": {
-                                "command": {
-                                    "type": "string",
-                                    "description": "実行するコマンド"
-                                },
-                                "silent": {
-                                    "type": "boolean",
-                                    "description": "サイレント実行かどうか（デフォルト: false）"
-                                }
-                            },
-                            "required": ["command"]
-                        }),
-                    },
-                ],
+                    declaration(
+                        "create_file",
+                        "ファイルを作成します",
+                        FunctionSchema::new()
+                            .required("filename", ParamType::String, "作成するファイル名")
+                            .required("content", ParamType::String, "ファイルの内容"),
+                    ),
+                    declaration(
+                        "edit_file",
+                        "ファイルの一部を編集します",
+                        FunctionSchema::new()
+                            .required("filename", ParamType::String, "編集するファイル名")
+                            .required("start_line", ParamType::Integer, "編集開始行（1始まり）")
+                            .required("end_line", ParamType::Integer, "編集終了行（1始まり）")
+                            .required("content", ParamType::String, "新しい内容"),
+                    ),
+                    declaration(
+                        "execute_command",
+                        "シェルコマンドを実行します",
+                        FunctionSchema::new()
+                            .required("command", ParamType::String, "実行するコマンド")
+                            .optional("silent", ParamType::Boolean, "サイレント実行かどうか（デフォルト: false）"),
+                    ),
+                    declaration(
+                        "create_directory",
+                        "ディレクトリを作成します（途中のディレクトリも作成）",
+                        FunctionSchema::new().required("path", ParamType::String, "作成するディレクトリのパス"),
+                    ),
+                ]
+                .into_iter()
+                .chain(self.allow_recursive_delete.then(|| {
+                    declaration(
+                        "delete_directory",
+                        "ディレクトリを中身ごと削除します",
+                        FunctionSchema::new().required("path", ParamType::String, "削除するディレクトリのパス"),
+                    )
+                }))
+                .chain(self.custom_tools.iter().map(|tool| FunctionDeclaration {
+                    name: tool.name.clone(),
+                    description: tool.description.clone(),
+                    parameters: FunctionParameters::Custom(tool.parameters.clone()),
+                }))
+                .collect(),
             }
         ]
     }
 
     /// Handle function call from Gemini API
-    async fn handle_function_call(&self, function_call: &FunctionCall) -> Result<ResponsePart> {
+    /// モデルに返すレスポンスと、チャットに表示する要約・詳細をまとめたToolOutcomeを返す
+    async fn handle_function_call(&self, function_call: &FunctionCall) -> Result<ToolOutcome> {
         debug_log!("[handle_function_call] Function: {}, Args: {}", function_call.name, function_call.args);
+        let started = std::time::Instant::now();
+
+        if let Some(reason) = self.run_pre_tool_hook(function_call).await {
+            return Ok(ToolOutcome {
+                tool: function_call.name.clone(),
+                args_summary: serde_json::to_string(&function_call.args).unwrap_or_default(),
+                success: false,
+                detail: format!("Refused by pre_tool hook: {}", reason),
+                duration_ms: Some(started.elapsed().as_millis() as u64),
+                response: serde_json::json!({"status": "error", "message": format!("❌ pre_toolフックが実行を拒否しました: {}", reason)}),
+                command_result: None,
+            });
+        }
 
-        let response_value = match function_call.name.as_str() {
+        let mut command_result = None;
+        let (args_summary, detail, response_value) = match function_call.name.as_str() {
             "create_file" => {
                 let filename = function_call.args["filename"].as_str()
                     .ok_or(anyhow::anyhow!("filename parameter is required"))?;
@@ -259,8 +1124,16 @@ impl GeminiClient {
                     .ok_or(anyhow::anyhow!("content parameter is required"))?;
 
                 match self.create_file_with_unique_name(filename, content) {
-                    Ok(created_path) => serde_json::json!({"status": "success", "message": format!("✅ ファイルを作成しました: {}", created_path)}),
-                    Err(e) => serde_json::json!({"status": "error", "message": format!("❌ ファイル作成に失敗しました: {}", e)}),
+                    Ok(created_path) => (
+                        filename.to_string(),
+                        format!("Created {} ({} bytes)", created_path, content.len()),
+                        serde_json::json!({"status": "success", "message": format!("✅ ファイルを作成しました: {}", created_path), "path": created_path, "bytes": content.len()}),
+                    ),
+                    Err(e) => (
+                        filename.to_string(),
+                        e.to_string(),
+                        with_outside_path(serde_json::json!({"status": "error", "message": format!("❌ ファイル作成に失敗しました: {}", e)}), &e),
+                    ),
                 }
             },
             "edit_file" => {
@@ -273,9 +1146,18 @@ impl GeminiClient {
                 let content = function_call.args["content"].as_str()
                     .ok_or(anyhow::anyhow!("content parameter is required"))?;
 
+                let args_summary = format!("{}:L{}-L{}", filename, start_line, end_line);
                 match self.file_access.edit_file_range(filename, start_line, end_line, content) {
-                    Ok(_) => serde_json::json!({"status": "success", "message": format!("✅ ファイルを編集しました: {}", filename)}),
-                    Err(e) => serde_json::json!({"status": "error", "message": format!("❌ ファイル編集に失敗しました: {}", e)}), 
+                    Ok(_) => (
+                        args_summary,
+                        format!("Replaced lines {}-{} of {} ({} bytes)", start_line, end_line, filename, content.len()),
+                        serde_json::json!({"status": "success", "message": format!("✅ ファイルを編集しました: {}", filename), "path": filename, "bytes": content.len()}),
+                    ),
+                    Err(e) => (
+                        args_summary,
+                        e.to_string(),
+                        with_outside_path(serde_json::json!({"status": "error", "message": format!("❌ ファイル編集に失敗しました: {}", e)}), &e),
+                    ),
                 }
             },
             "execute_command" => {
@@ -283,46 +1165,127 @@ impl GeminiClient {
                     .ok_or(anyhow::anyhow!("command parameter is required"))?;
                 let _silent = function_call.args["silent"].as_bool().unwrap_or(false);
 
-                match self.execute_command(command).await {
+                match self.execute_command(command, self.command_timeout).await {
                     Ok(result) => {
+                        let detail = command_output_detail(&result);
+                        let mut response = serde_json::json!({
+                            "status": if result.success { "success" } else { "error" },
+                            "command": command,
+                            "exit_code": result.exit_code,
+                            "stdout": result.stdout,
+                            "stderr": result.stderr,
+                            "duration_ms": result.duration_ms,
+                        });
                         if result.success {
-                            serde_json::json!({"status": "success", "message": format!("✅ コマンド実行成功: {}
-出力: {}", command, result.stdout)})
-                        } else {
-                            serde_json::json!({"status": "error", "message": format!("❌ コマンド実行失敗: {}
-エラー: {}", command, result.stderr)})
+                            // /changes に記録する（track_command_changes）
+                            response["path"] = serde_json::json!(command);
+                            response["bytes"] = serde_json::json!(result.stdout.len());
                         }
+                        command_result = Some(result);
+                        (command.to_string(), detail, response)
                     },
-                    Err(e) => serde_json::json!({"status": "error", "message": format!("❌ コマンド実行エラー: {}", e)}),
+                    Err(e) => (
+                        command.to_string(),
+                        e.to_string(),
+                        serde_json::json!({"status": "error", "message": format!("❌ コマンド実行エラー: {}", e)}),
+                    ),
                 }
             },
-            _ => serde_json::json!({"status": "error", "message": format!("❌ 未知の関数呼び出し: {}", function_call.name)}),
+            "create_directory" => {
+                let path = function_call.args["path"].as_str()
+                    .ok_or(anyhow::anyhow!("path parameter is required"))?;
+
+                match self.file_access.create_directory(std::path::Path::new(path)) {
+                    Ok(()) => (
+                        path.to_string(),
+                        format!("Created directory {}", path),
+                        serde_json::json!({"status": "success", "message": format!("✅ ディレクトリを作成しました: {}", path), "path": path, "bytes": 0}),
+                    ),
+                    Err(e) => (
+                        path.to_string(),
+                        e.to_string(),
+                        with_outside_path(serde_json::json!({"status": "error", "message": format!("❌ ディレクトリ作成に失敗しました: {}", e)}), &e),
+                    ),
+                }
+            },
+            "delete_directory" => {
+                let path = function_call.args["path"].as_str()
+                    .ok_or(anyhow::anyhow!("path parameter is required"))?;
+
+                let result = if self.allow_recursive_delete {
+                    self.file_access.delete_directory_recursive(std::path::Path::new(path))
+                } else {
+                    Err(anyhow::anyhow!("Recursive delete is disabled (set ALLOW_RECURSIVE_DELETE=true)"))
+                };
+                match result {
+                    Ok(()) => (
+                        path.to_string(),
+                        format!("Deleted directory {}", path),
+                        serde_json::json!({"status": "success", "message": format!("✅ ディレクトリを削除しました: {}", path), "path": path, "bytes": 0}),
+                    ),
+                    Err(e) => (
+                        path.to_string(),
+                        e.to_string(),
+                        with_outside_path(serde_json::json!({"status": "error", "message": format!("❌ ディレクトリ削除に失敗しました: {}", e)}), &e),
+                    ),
+                }
+            },
+            name => match self.custom_tools.iter().find(|tool| tool.name == name) {
+                Some(tool) => self.run_custom_tool(tool, &function_call.args).await,
+                None => (
+                    serde_json::to_string(&function_call.args).unwrap_or_default(),
+                    format!("Unknown function: {}", function_call.name),
+                    serde_json::json!({"status": "error", "message": format!("❌ 未知の関数呼び出し: {}", function_call.name)}),
+                ),
+            },
         };
 
-        Ok(ResponsePart::FunctionResponse {
-            function_response: FunctionResponse {
-                name: function_call.name.clone(),
-                response: response_value,
-            },
-        })
+        let outcome = ToolOutcome {
+            tool: function_call.name.clone(),
+            args_summary,
+            success: response_value["status"].as_str() == Some("success"),
+            detail,
+            duration_ms: Some(started.elapsed().as_millis() as u64),
+            response: response_value,
+            command_result,
+        };
+        self.run_post_tool_hook(function_call, &outcome).await;
+        Ok(outcome)
     }
 
-    // システムプロンプトを作成
-    fn get_system_prompt(&self) -> String {
-        r###"あなたはファイル作成・部分編集・コマンド実行機能を持つAIアシスタントです。
-
-ユーザーのリクエストに応じて、以下の機能を提供できます：
-
-1. **ファイル作成**: 新しいファイルを作成
-2. **ファイル編集**: 既存ファイルの部分編集
-3. **コマンド実行**: シェルコマンドの実行
+    /// カスタムツールを実行する（引数がスキーマに合わなければ実行せずに違反内容を返す）
+    async fn run_custom_tool(&self, tool: &CustomTool, args: &serde_json::Value) -> (String, String, serde_json::Value) {
+        if let Err(violations) = tool.validate_args(args) {
+            let detail = violations.join("\n");
+            let response = serde_json::json!({"status": "error", "message": format!("❌ 引数がスキーマに一致しません: {}", violations.join("; ")), "violations": violations});
+            return (serde_json::to_string(args).unwrap_or_default(), detail, response);
+        }
 
-これらの機能は、Function Calling機能を通じて実行されます。必要に応じて適切な関数を呼び出してください。
+        let command = tool.render_command(args, self.shell);
+        let (detail, response) = match self.execute_command(&command, self.command_timeout).await {
+            Ok(result) => {
+                let detail = command_output_detail(&result);
+                let response = if result.success {
+                    serde_json::json!({"status": "success", "message": format!("✅ {} 実行成功
+出力: {}", tool.name, result.stdout), "path": command, "bytes": result.stdout.len()})
+                } else {
+                    serde_json::json!({"status": "error", "message": format!("❌ {} 実行失敗
+エラー: {}", tool.name, result.stderr)})
+                };
+                (detail, response)
+            }
+            Err(e) => (
+                e.to_string(),
+                serde_json::json!({"status": "error", "message": format!("❌ コマンド実行エラー: {}", e)}),
+            ),
+        };
+        (command, detail, response)
+    }
 
----
-【重要】全ての返答の末尾に, タスクが終了したかを示すフラグである is_finished: true または is_finished: false を必ず明示してください（JSON形式または "is_finished: true" のような形式でOK）。
-また、is_finished:falseの際は、作業を完了させるため適切な関数を呼び出すこと。適切な関数が存在しない、また異常終了しているなどの場合はtrueを返すこと。
-"###.to_string()
+    // システムプロンプトを作成
+    fn get_system_prompt(&self) -> String {
+        let respond_in = tr(self.language, "respond_in").replace("{language}", self.language.name());
+        format!("{}\n\n{}\n", tr(self.language, "system_prompt"), respond_in)
     }
 
     /// レスポンステキストでファイル作成とコマンド実行を処理する共通関数
@@ -339,8 +1302,209 @@ impl GeminiClient {
 
     async fn _send_request_and_parse_response(
         &self,
-        request: GeminiRequest,
-    ) -> Result<ResponsePart> {
+        mut request: GeminiRequest,
+    ) -> Result<Vec<ResponsePart>> {
+        // countTokensにも送るので、トークン数を数える前に伏せる
+        self.redact_request(&mut request);
+        if let Some(note) = self.preflight(&mut request).await? {
+            self.preflight_notes.lock().unwrap().push(note);
+        }
+        self.send_request(request).await
+    }
+
+    /// 送信前にトークン数を数え、モデルのコンテキストウィンドウを超えるなら古いコンテキストから落とす
+    /// 落としたときは通知する注記を返す。今回のメッセージと添付ファイルだけで超えるならエラー
+    async fn preflight(&self, request: &mut GeminiRequest) -> Result<Option<String>> {
+        let Some(limit) = self.config.context_windows.get(&self.config.model).filter(|_| self.config.token_preflight) else {
+            return Ok(None);
+        };
+        let counted = async {
+            let overhead = self.count_overhead_tokens(request).await?;
+            let mut counts = Vec::with_capacity(request.contents.len());
+            for content in &request.contents {
+                counts.push(self.count_content_tokens(content).await?);
+            }
+            anyhow::Ok((overhead, counts))
+        };
+        // 数えられなかったときは確認せずに送る（送信自体のエラーは通常どおり報告される）
+        let (overhead, counts) = match counted.await {
+            Ok(counted) => counted,
+            Err(e) => {
+                debug_log!("[preflight] countTokens failed, sending without preflight: {:?}", e);
+                return Ok(None);
+            }
+        };
+        let pinned_head = usize::from(request.contents.first().is_some_and(is_file_contents_block));
+        let mut dropped = plan_context_trim(overhead, &counts, pinned_head, limit).map_err(|e| anyhow::anyhow!("{}", e))?;
+        if dropped == 0 {
+            return Ok(None);
+        }
+        // 残したコンテキストがモデルの応答から始まらないようにする
+        let next = pinned_head + dropped;
+        if next + 1 < request.contents.len() && request.contents[next].role == "model" {
+            dropped += 1;
+        }
+        request.contents.drain(pinned_head..pinned_head + dropped);
+        let tokens = overhead + counts.iter().sum::<u64>() - counts[pinned_head..pinned_head + dropped].iter().sum::<u64>();
+        debug_log!("[preflight] dropped {} contents, {} / {} tokens", dropped, tokens, limit);
+        Ok(Some(format!(
+            "✂️ Dropped {} older context messages to fit the context window ({} / {} tokens)",
+            dropped,
+            group_digits(tokens),
+            group_digits(limit)
+        )))
+    }
+
+    /// modelsエンドポイントで、APIキーで使えるモデルの一覧を取得する（/gemini-version）
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        if self.mock_script.is_some() {
+            return Err(anyhow::anyhow!("The model list is not available with the mock provider"));
+        }
+        let mut models = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut url = format!(
+                "https://generativelanguage.googleapis.com/v1beta/models?pageSize=1000&key={}",
+                self.config.gemini_api_key
+            );
+            if let Some(token) = &page_token {
+                url.push_str(&format!("&pageToken={}", token));
+            }
+            let response = self.client.get(url).send().await?;
+            let status = response.status();
+            let text = response.text().await?;
+            if !status.is_success() {
+                return Err(anyhow::anyhow!("models failed (HTTP {}): {}", status.as_u16(), text));
+            }
+            let page = parse_model_list_page(&text)?;
+            models.extend(page.models);
+            match page.next_page_token.filter(|token| !token.is_empty()) {
+                Some(token) => page_token = Some(token),
+                None => break,
+            }
+        }
+        Ok(models)
+    }
+
+    /// countTokensエンドポイントでcontentsの正確なトークン数を数える（/tokens。モックでは文字数から推定する）
+    pub async fn count_tokens(&self, contents: &[Content]) -> Result<u32> {
+        let mut body = serde_json::json!({ "contents": contents });
+        self.redactor.redact_json(&mut body);
+        let estimate = || contents.iter().flat_map(|content| content.parts.iter()).map(part_text_for_estimate).collect();
+        let count = self.count_tokens_cached(body, estimate).await?;
+        u32::try_from(count).map_err(|_| anyhow::anyhow!("countTokens returned an out-of-range count: {}", count))
+    }
+
+    /// システムプロンプトとツール定義の分のトークン数
+    async fn count_overhead_tokens(&self, request: &GeminiRequest) -> Result<u64> {
+        self.count_tokens_cached(overhead_count_body(&self.config.model, request), || overhead_estimate_text(request)).await
+    }
+
+    async fn count_content_tokens(&self, content: &Content) -> Result<u64> {
+        let body = serde_json::json!({ "contents": [content] });
+        self.count_tokens_cached(body, || content.parts.iter().map(part_text_for_estimate).collect()).await
+    }
+
+    /// 入力中に表示するゲージ用の、システムプロンプト・ツール定義とcontextのおおよそのトークン数（通信しない）
+    /// countTokensで数えたことのある内容はその結果を使い、なければ文字数から推定する
+    pub fn estimate_request_tokens(&self, context: &[Content]) -> (u64, u64) {
+        let request = self.build_chat_request(".", None);
+        let overhead = self.cached_token_count(&overhead_count_body(&self.config.model, &request)).unwrap_or_else(|| estimate_tokens(&overhead_estimate_text(&request)) as u64);
+        let context = context
+            .iter()
+            .map(|content| {
+                self.cached_token_count(&serde_json::json!({ "contents": [content] }))
+                    .unwrap_or_else(|| content.parts.iter().map(|part| estimate_tokens(&part_text_for_estimate(part)) as u64).sum())
+            })
+            .sum();
+        (overhead, context)
+    }
+
+    fn token_count_key(&self, body: &serde_json::Value) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.config.model.hash(&mut hasher);
+        body.to_string().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn cached_token_count(&self, body: &serde_json::Value) -> Option<u64> {
+        self.token_counts.lock().unwrap().get(&self.token_count_key(body)).copied()
+    }
+
+    /// countTokensで数える（モックでは文字数から推定する）。結果はモデルと内容ごとにTOKEN_COUNT_CACHE_LIMIT件まで覚えておく
+    async fn count_tokens_cached(&self, body: serde_json::Value, estimate: impl FnOnce() -> String) -> Result<u64> {
+        let key = self.token_count_key(&body);
+        if let Some(count) = self.token_counts.lock().unwrap().get(&key) {
+            return Ok(*count);
+        }
+        let count = if self.mock_script.is_some() {
+            estimate_tokens(&estimate()) as u64
+        } else {
+            let url = format!(
+                "https://generativelanguage.googleapis.com/v1beta/models/{}:countTokens?key={}",
+                self.config.model, self.config.gemini_api_key
+            );
+            let response = self.client.post(url).json(&body).send().await?;
+            let status = response.status();
+            let text = response.text().await?;
+            if !status.is_success() {
+                return Err(anyhow::anyhow!("countTokens failed (HTTP {}): {}", status.as_u16(), text));
+            }
+            serde_json::from_str::<serde_json::Value>(&text)?["totalTokens"]
+                .as_u64()
+                .ok_or_else(|| anyhow::anyhow!("countTokens returned no totalTokens: {}", text))?
+        };
+        let mut token_counts = self.token_counts.lock().unwrap();
+        if token_counts.len() >= TOKEN_COUNT_CACHE_LIMIT {
+            token_counts.clear();
+        }
+        token_counts.insert(key, count);
+        Ok(count)
+    }
+
+    /// 送るリクエストのJSONをリクエストのログに追記する（伏せ字にした後の内容）
+    fn log_request(&self, request: &GeminiRequest) {
+        let Some(path) = &self.request_log else {
+            return;
+        };
+        if let Ok(mut file) = std::fs::OpenOptions::new().create(true).append(true).open(path) {
+            let _ = writeln!(file, "{}\n", serde_json::to_string_pretty(request).unwrap_or_else(|_| "Failed to serialize request".to_string()));
+        }
+    }
+
+    /// メッセージ・コンテキスト・添付ファイル・関数呼び出しの引数と結果の秘密情報を伏せる（システムプロンプトとツール定義はそのまま）
+    fn redact_request(&self, request: &mut GeminiRequest) {
+        for part in request.contents.iter_mut().flat_map(|content| content.parts.iter_mut()) {
+            match part {
+                Part::Text { text } => {
+                    if let std::borrow::Cow::Owned(redacted) = self.redactor.redact(text) {
+                        *text = redacted;
+                    }
+                }
+                Part::FunctionCall { function_call } => self.redactor.redact_json(&mut function_call.args),
+                Part::FunctionResponse { function_response } => self.redactor.redact_json(&mut function_response.response),
+                Part::ToolOutcome { .. } => {}
+            }
+        }
+    }
+
+    async fn send_request(&self, request: GeminiRequest) -> Result<Vec<ResponsePart>> {
+        self.log_request(&request);
+        if let Some(script) = &self.mock_script {
+            // 最後のユーザー入力をプロンプトとして照合し、関数呼び出しは通常の応答と同じく実行する
+            let prompt = request
+                .contents
+                .last()
+                .map(|content| content.parts.iter().map(part_text_for_estimate).collect::<String>())
+                .unwrap_or_default();
+            let parts = script.respond(&prompt)?;
+            let gemini_response = GeminiResponse {
+                candidates: vec![Candidate { content: ResponseContent { parts }, finish_reason: None }],
+                usage_metadata: None,
+            };
+            self.record_usage(&request, &gemini_response);
+            return self.process_candidate(&gemini_response.candidates[0]).await;
+        }
         let url = format!(
             "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent?key={}",
             self.config.model, self.config.gemini_api_key
@@ -361,67 +1525,143 @@ impl GeminiClient {
             return Err(anyhow::anyhow!("Gemini API Error: {}", response_text));
         }
         let gemini_response: GeminiResponse = serde_json::from_str(&response_text)?;
-        if let Some(candidate) = gemini_response.candidates.first() {
-            if let Some(part) = candidate.content.parts.first() {
-                match part {
-                    ResponsePart::Text { text } => Ok(ResponsePart::Text { text: text.clone() }),
-                    ResponsePart::FunctionCall { function_call } => {
-                        // Function callの処理
-                        self.handle_function_call(function_call).await
-                    },
-                    ResponsePart::FunctionResponse { function_response } => {
-                        Ok(ResponsePart::FunctionResponse { function_response: function_response.clone() })
-                    }
+        self.record_usage(&request, &gemini_response);
+        match gemini_response.candidates.first() {
+            Some(candidate) => self.process_candidate(candidate).await,
+            None => Err(anyhow::anyhow!("No candidates in Gemini response")),
+        }
+    }
+
+    /// トークン数と料金をリクエストを送ったセッション（with_sessionがなければ現在のセッション）に記録する
+    /// usageMetadataがない場合は文字数から推定し、推定であることを記録する
+    fn record_usage(&self, request: &GeminiRequest, response: &GeminiResponse) {
+        let (input_tokens, output_tokens, estimated) = match &response.usage_metadata {
+            Some(usage) => (usage.prompt_token_count, usage.candidates_token_count, false),
+            None => {
+                let request_text = request
+                    .system_instruction
+                    .iter()
+                    .flat_map(|instruction| instruction.parts.iter())
+                    .chain(request.contents.iter().flat_map(|content| content.parts.iter()))
+                    .map(part_text_for_estimate)
+                    .collect::<String>();
+                let response_text = response
+                    .candidates
+                    .iter()
+                    .flat_map(|candidate| candidate.content.parts.iter())
+                    .map(ResponsePart::display_text)
+                    .collect::<String>();
+                (estimate_tokens(&request_text) as u64, estimate_tokens(&response_text) as u64, true)
+            }
+        };
+        let cost = self
+            .config
+            .pricing
+            .get(&self.config.model)
+            .map(|pricing| pricing.cost(input_tokens, output_tokens));
+        let mut history_guard = self.history_manager.lock().unwrap();
+        let history = history_guard.get_history_mut();
+        let result = match self.session_id {
+            Some(session_id) => history.record_usage_for(session_id, &self.config.model, input_tokens, output_tokens, estimated, cost),
+            None => history.record_usage(&self.config.model, input_tokens, output_tokens, estimated, cost),
+        };
+        if let Err(e) = result {
+            debug_log!("[record_usage] error: {:?}", e);
+        }
+    }
+
+    /// 候補の全パートを処理する
+    /// テキストは順番に連結して先頭に置き、関数呼び出しは全て実行して結果をその後ろに並べる
+    async fn process_candidate(&self, candidate: &Candidate) -> Result<Vec<ResponsePart>> {
+        let mut text = String::new();
+        let mut tool_results = Vec::new();
+        for part in &candidate.content.parts {
+            match part {
+                ResponsePart::Text { text: part_text } => text.push_str(part_text),
+                ResponsePart::FunctionCall { function_call } => {
+                    // 引数の不足などで失敗しても、その呼び出しのエラーとして返して残りの呼び出しを続ける
+                    let tool_outcome = match self.handle_function_call(function_call).await {
+                        Ok(tool_outcome) => tool_outcome,
+                        Err(e) => {
+                            debug_log!("[process_candidate] {} failed: {:?}", function_call.name, e);
+                            ToolOutcome {
+                                tool: function_call.name.clone(),
+                                args_summary: serde_json::to_string(&function_call.args).unwrap_or_default(),
+                                success: false,
+                                detail: e.to_string(),
+                                duration_ms: None,
+                                response: serde_json::json!({"status": "error", "message": format!("❌ ツールの実行に失敗しました: {}", e)}),
+                                command_result: None,
+                            }
+                        }
+                    };
+                    tool_results.push(ResponsePart::ToolOutcome { tool_outcome });
                 }
-            } else {
-                Err(anyhow::anyhow!("No parts in Gemini response"))
+                ResponsePart::FunctionResponse { .. } | ResponsePart::ToolOutcome { .. } => tool_results.push(part.clone()),
             }
-        } else {
-            Err(anyhow::anyhow!("No candidates in Gemini response"))
         }
+
+        let mut parts = Vec::with_capacity(tool_results.len() + 1);
+        if !text.is_empty() {
+            parts.push(ResponsePart::Text {
+                text: with_truncation_warning(&text, candidate.finish_reason.as_deref()),
+            });
+        }
+        parts.extend(tool_results);
+        if parts.is_empty() {
+            return Err(anyhow::anyhow!("No parts in Gemini response"));
+        }
+        Ok(parts)
     }
 
-    // chatとchat_with_file_contextの共通処理をまとめたヘルパー関数
-    async fn send_chat_request_and_process_response(
-        &self,
-        contents: Vec<Content>,
-        _original_message: &str,
-    ) -> Result<ResponsePart> {
-        let request = GeminiRequest {
-            contents,
+    /// システムプロンプト・生成設定・ツール定義を付けてリクエストを組み立てる
+    /// システムプロンプトはsystemInstructionに入れ、contentsは同じroleが連続しないよう正規化する
+    fn build_request(&self, contents: Vec<Content>) -> GeminiRequest {
+        let settings = self.effective_settings();
+        GeminiRequest {
+            system_instruction: Some(SystemInstruction {
+                parts: vec![Part::Text {
+                    text: self.get_system_prompt(),
+                }],
+            }),
+            contents: normalize_contents(contents),
             generation_config: GenerationConfig {
-                temperature: self.config.temperature.unwrap_or(0.7),
-                max_output_tokens: self.config.max_tokens.unwrap_or(1000),
+                temperature: settings.temperature,
+                max_output_tokens: settings.max_tokens,
             },
             tools: Some(self.get_function_declarations()),
-        };
-
-        let response_part = self._send_request_and_parse_response(request).await?;
-
-        // Function Calling移行により、疑似ツール処理は無効化
-        // let (_has_actions, _context_message) =
-        //     self.process_response_actions_sync(&response_text, original_message);
-        
-        // Function Callingで処理されるため、直接レスポンスを返す
-        Ok(response_part)
+        }
     }
 
-    pub async fn chat(&self, message: &str, context: Option<&[Content]>) -> Result<ResponsePart> {
-        debug_log!("[chat] called with message: {}
-", message);
-
+    fn build_chat_request(&self, message: &str, context: Option<&[Content]>) -> GeminiRequest {
         let mut contents: Vec<Content> = Vec::new();
+        if let Some(ctxs) = context {
+            contents.extend_from_slice(ctxs);
+        }
+
         contents.push(Content {
             role: "user".to_string(),
             parts: vec![Part::Text {
-                text: self.get_system_prompt(),
+                text: message.to_string(),
             }],
         });
 
+        self.build_request(contents)
+    }
+
+    fn build_file_context_request(&self, message: &str, file_contents_text: &str, context: Option<&[Content]>) -> GeminiRequest {
+        let mut contents: Vec<Content> = Vec::new();
+        if !file_contents_text.is_empty() {
+            contents.push(Content {
+                role: "user".to_string(),
+                parts: vec![Part::Text {
+                    text: format!("=== FILE CONTENTS ===\n{}\n=== END FILE CONTENTS ===\n\n", file_contents_text),
+                }],
+            });
+        }
+
         if let Some(ctxs) = context {
-            for msg_content in ctxs {
-                contents.push(msg_content.clone());
-            }
+            contents.extend_from_slice(ctxs);
         }
 
         contents.push(Content {
@@ -431,76 +1671,128 @@ impl GeminiClient {
             }],
         });
 
-        self.send_chat_request_and_process_response(contents, message).await
+        self.build_request(contents)
     }
 
-    pub async fn chat_with_file_context(&self, message: &str, file_paths: &[String], context: Option<&[Content]>) -> Result<ResponsePart> {
-        let mut file_contents_text = String::new();
-        for file_path in file_paths {
-            match self.file_access.read_file(file_path) {
-                Ok(content) => {
-                    file_contents_text.push_str(&format!("
---- File: {} ---
-", file_path));
-                    file_contents_text.push_str(&content);
-                    file_contents_text.push_str("
---- End of file ---
+    pub async fn chat(&self, message: &str, context: Option<&[Content]>) -> Result<Vec<ResponsePart>> {
+        debug_log!("[chat] called with message ({} bytes)\n", message.len());
 
-");
-                }
-                Err(e) => {
-                    eprintln!("Failed to read file {}: {}", file_path, e);
-                    file_contents_text.push_str(&format!("
---- Error reading file: {} ---
-", file_path));
-                    file_contents_text.push_str(&format!("Error: {}
+        let request = self.build_chat_request(message, context);
+        // Function Callingで処理されるため、直接レスポンスを返す
+        self._send_request_and_parse_response(request).await
+    }
+
+    /// ツールを渡さずにテキスト応答だけを得る（比較モードで副作用を二重に起こさないため）
+    pub async fn chat_text_only(&self, message: &str, context: Option<&[Content]>) -> Result<String> {
+        let mut request = self.build_chat_request(message, context);
+        request.tools = None;
+        let parts = self._send_request_and_parse_response(request).await?;
+        match first_text(&parts) {
+            Some(text) => Ok(text.to_string()),
+            None => Err(anyhow::anyhow!("Unexpected response parts: {:?}", parts)),
+        }
+    }
 
-", e));
+    /// 古い会話を要約する（ツールなし・短い出力の軽量リクエスト）
+    pub async fn summarize_conversation(&self, previous_summary: Option<&str>, messages: &[ChatMessage]) -> Result<String> {
+        let mut transcript = String::new();
+        if let Some(summary) = previous_summary {
+            transcript.push_str(&format!("[これまでの要約]\n{}\n\n", summary));
+        }
+        for msg in messages.iter().filter(|msg| !msg.is_system) {
+            let prefix = msg.speaker();
+            for part in &msg.parts {
+                match part {
+                    Part::Text { text } => transcript.push_str(&format!("{}: {}\n", prefix, text)),
+                    Part::ToolOutcome { tool_outcome } => transcript.push_str(&format!("{}: {}\n", prefix, tool_outcome.chip())),
+                    _ => {}
                 }
             }
         }
 
-        let mut contents: Vec<Content> = Vec::new();
-        contents.push(Content {
-            role: "user".to_string(),
-            parts: vec![Part::Text {
-                text: self.get_system_prompt(),
-            }],
-        });
-
-        if !file_contents_text.is_empty() {
-            contents.push(Content {
+        let request = GeminiRequest {
+            system_instruction: None,
+            contents: vec![Content {
                 role: "user".to_string(),
                 parts: vec![Part::Text {
-                    text: format!("=== FILE CONTENTS ===
-{}
-=== END FILE CONTENTS ===
-
-", file_contents_text),
+                    text: format!(
+                        "以下の会話を、後の会話で必要になる事実・決定事項・未完了のタスクを残して簡潔に要約してください。要約本文のみを返してください。\n\n{}",
+                        transcript
+                    ),
                 }],
-            });
+            }],
+            generation_config: GenerationConfig {
+                temperature: 0.2,
+                max_output_tokens: 1024,
+            },
+            tools: None,
+        };
+
+        let parts = self._send_request_and_parse_response(request).await?;
+        match first_text(&parts) {
+            Some(text) if !text.trim().is_empty() => Ok(text.trim().to_string()),
+            _ => Err(anyhow::anyhow!("要約の応答が空です")),
         }
+    }
 
-        if let Some(ctxs) = context {
-            for msg_content in ctxs {
-                contents.push(msg_content.clone());
-            }
+    /// ファイル内容を添えて送る
+    /// プロンプトが上限を超える場合は、省略（先頭+末尾）や分割アップロードをした内容を応答の先頭で報告する
+    pub async fn chat_with_file_context(&self, message: &str, file_references: &[FileReference], context: Option<&[Content]>) -> Result<Vec<ResponsePart>> {
+        // 同じファイルを別の書き方で参照していても内容は1回だけ送る。読めないファイルはその理由を送る
+        let prepared = self.file_access.prepare_attachments(file_references);
+        let skipped = prepared.skipped.into_iter().map(|(reference, reason)| (reference, Err(anyhow::anyhow!(reason))));
+        let attachments: Vec<(FileReference, Result<FileContent>)> = prepared
+            .files
+            .into_iter()
+            .map(|(reference, _)| {
+                let content = self.file_access.read_reference_content(&reference);
+                if let Err(e) = &content {
+                    eprintln!("Failed to read file {}: {}", reference.path, e);
+                }
+                (reference, content)
+            })
+            .chain(skipped)
+            .collect();
+        let plan = plan_attachments(message, &attachments, self.max_prompt_bytes);
+
+        // 分割アップロードは1つずつ送り、やり取りを本リクエストのコンテキストに積む
+        let mut context = context.map(<[Content]>::to_vec).unwrap_or_default();
+        for chunk in &plan.chunks {
+            let reply = self.chat_text_only(chunk, Some(&context)).await?;
+            context.push(Content { role: "user".to_string(), parts: vec![Part::Text { text: chunk.clone() }] });
+            context.push(Content { role: "model".to_string(), parts: vec![Part::Text { text: reply }] });
         }
 
-        contents.push(Content {
-            role: "user".to_string(),
-            parts: vec![Part::Text {
-                text: message.to_string(),
-            }],
-        });
+        let request = self.build_file_context_request(message, &plan.inline_text, Some(&context));
+        let mut parts = self._send_request_and_parse_response(request).await?;
+        if let Some(report) = plan.report {
+            parts.insert(0, ResponsePart::Text { text: report });
+        }
+        Ok(parts)
+    }
+
+    pub fn read_file(&self, path: &str) -> Result<String> {
+        self.file_access.read_file(path)
+    }
 
-        self.send_chat_request_and_process_response(contents, message).await
+    /// 添付ファイルのパスを正規化して重複を除き、読めないものを外す
+    pub fn prepare_attachments(&self, references: &[FileReference]) -> PreparedAttachments {
+        self.file_access.prepare_attachments(references)
     }
 
     pub fn list_directory(&self, path: &str) -> Result<Vec<String>> {
         self.file_access.list_directory(path)
     }
 
+    /// pathの現在の内容がcontuiのファイル作成・編集によるものか
+    pub fn is_own_write(&self, path: &std::path::Path) -> bool {
+        self.file_access.is_own_write(path)
+    }
+
+    pub fn list_directory_with_metadata(&self, path: &std::path::Path) -> Result<Vec<crate::file_access::DirectoryEntry>> {
+        self.file_access.list_directory_with_metadata(path)
+    }
+
     /// ファイルを作成（重複チェック付き）- 実際に作成されたファイル名を返す
     pub fn create_file_with_unique_name(&self, path: &str, content: &str) -> Result<String> {
         let created_path = self.file_access.create_file_with_unique_name(path, content)?;
@@ -532,8 +1824,7 @@ impl GeminiClient {
                 // ``` で終わるまで、または最後の行まで内容を収集
                 while i < lines.len() && !lines[i].starts_with("```") {
                     if !content.is_empty() {
-                        content.push_str("
-");
+                        content.push('\n');
                     }
                     content.push_str(lines[i]);
                     i += 1;
@@ -560,20 +1851,52 @@ impl GeminiClient {
     }
 
     /// シェルコマンドを実行
-    pub async fn execute_command(&self, command: &str) -> Result<CommandResult> {
+    /// timeoutを超えたらプロセスをkillし、失敗として結果を返す
+    pub async fn execute_command(&self, command: &str, timeout: Option<Duration>) -> Result<CommandResult> {
+        use std::process::Stdio;
         use tokio::process::Command;
+        let started = std::time::Instant::now();
         
-        // macOS/Linux用のシェルコマンド実行
-        let output = Command::new("sh")
-            .arg("-c")
-            .arg(command)
-            .output()
-            .await?;
+        // 設定されたシェル経由で実行（Windowsの既定はcmd /C）
+        let (program, args) = self.shell.program_and_args(command);
+        let mut child = Command::new(program)
+            .args(&args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let stdout_pipe = child.stdout.take();
+        let stderr_pipe = child.stderr.take();
+        let wait_for_output = async {
+            let (stdout, stderr) = tokio::join!(read_pipe(stdout_pipe), read_pipe(stderr_pipe));
+            let status = child.wait().await?;
+            Ok::<_, std::io::Error>((status, stdout, stderr))
+        };
+        let finished = match timeout {
+            Some(limit) => tokio::time::timeout(limit, wait_for_output).await.ok(),
+            None => Some(wait_for_output.await),
+        };
+        let Some(finished) = finished else {
+            debug_log!("[execute_command] timed out after {:?}: {}", timeout, command);
+            if let Err(e) = child.kill().await {
+                debug_log!("[execute_command] kill error: {:?}", e);
+            }
+            return Ok(CommandResult {
+                command: command.to_string(),
+                stdout: String::new(),
+                stderr: "Command timed out".to_string(),
+                success: false,
+                exit_code: None,
+                duration_ms: started.elapsed().as_millis() as u64,
+            });
+        };
+        let (status, stdout, stderr) = finished?;
 
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        let success = output.status.success();
-        let exit_code = output.status.code();
+        let stdout = String::from_utf8_lossy(&stdout).to_string();
+        let stderr = String::from_utf8_lossy(&stderr).to_string();
+        let success = status.success();
+        let exit_code = status.code();
 
         Ok(CommandResult {
             command: command.to_string(),
@@ -581,6 +1904,7 @@ impl GeminiClient {
             stderr,
             success,
             exit_code,
+            duration_ms: started.elapsed().as_millis() as u64,
         })
     }
 
@@ -602,8 +1926,7 @@ impl GeminiClient {
                 // ``` で終わるまで、または最後の行まで内容を収集
                 while i < lines.len() && !lines[i].starts_with("```") {
                     if !command.is_empty() {
-                        command.push_str("
-");
+                        command.push('\n');
                     }
                     command.push_str(lines[i]);
                     i += 1;
@@ -611,7 +1934,7 @@ impl GeminiClient {
 
                 // コマンドが空でない場合実行
                 if !command.trim().is_empty() {
-                    match self.execute_command(command.trim()).await {
+                    match self.execute_command(command.trim(), self.command_timeout).await {
                         Ok(result) => {
                             command_results.push(result);
                         }
@@ -623,6 +1946,7 @@ impl GeminiClient {
                                 stderr: format!("❌ 実行エラー: {}", e),
                                 success: false,
                                 exit_code: None,
+                                duration_ms: 0,
                             });
                         }
                     }
@@ -673,8 +1997,7 @@ impl GeminiClient {
                 i += 1;
                 while i < lines.len() && !lines[i].starts_with("```") {
                     if !content.is_empty() {
-                        content.push_str("
-");
+                        content.push('\n');
                     }
                     content.push_str(lines[i]);
                     i += 1;
@@ -709,44 +2032,25 @@ impl GeminiClient {
         let mut step = 1;
         loop {
             // 毎回「次に何をすべきか」「追加タスクがあるか」を問うプロンプトを付与
-            let prompt = format!(
-                "{}
-
----
-次に何をすべきか、追加タスクがあるかを必ず明示してください。
-is_finished: true/false のJSONフラグを必ず返してください。",
-                message
-            );
+            let prompt = format!("{}\n\n---\n{}", message, tr(self.language, "agent.suffix_flag"));
             println!("========== LLM Step {} ==========", step);
             let conversation_context = (*self.history_manager.lock().unwrap()).get_conversation_context(10); // Explicit dereference
             
-            // Call chat and get ResponsePart
-            let response_part = self.chat(&prompt, Some(&conversation_context)).await?;
+            // Call chat and get every ResponsePart of the candidate
+            let response_parts = self.chat(&prompt, Some(&conversation_context)).await?;
 
-            let response_text = match &response_part {
-                ResponsePart::Text { text } => text.clone(),
-                ResponsePart::FunctionCall { function_call } => {
-                    // This case should ideally not happen here if handle_function_call is called
-                    // immediately after a FunctionCall is received.
-                    // For now, we'll just represent it as text.
-                    format!("FunctionCall: {}", serde_json::to_string_pretty(&function_call).unwrap_or_default())
-                },
-                ResponsePart::FunctionResponse { function_response } => {
-                    format!("FunctionResponse: {}", serde_json::to_string_pretty(&function_response).unwrap_or_default())
-                },
-            };
+            let response_text = response_parts
+                .iter()
+                .map(ResponsePart::display_text)
+                .collect::<Vec<_>>()
+                .join("\n");
 
             println!("LLM Response:
 {}
 ", response_text);
 
             // Add the response to history
-            // Extract parts from response_part to add to history
-            let parts_to_add_to_history = match response_part {
-                                ResponsePart::Text { text } => vec![Part::Text { text }],
-                ResponsePart::FunctionCall { function_call } => vec![Part::Text { text: serde_json::to_string(&function_call).unwrap_or_default() }],
-                ResponsePart::FunctionResponse { function_response } => vec![Part::Text { text: serde_json::to_string(&function_response).unwrap_or_default() }],
-            };
+            let parts_to_add_to_history: Vec<Part> = response_parts.iter().map(ResponsePart::to_history_part).collect();
 
             (*self.history_manager.lock().unwrap()).get_history_mut().add_message(parts_to_add_to_history, false)?;
 
@@ -765,16 +2069,464 @@ is_finished: true/false のJSONフラグを必ず返してください。",
 
     /// レスポンステキストから is_finished: true/false を抽出
     pub fn extract_is_finished_flag(&self, text: &str) -> Option<bool> {
-        // 例: {"is_finished": true} または is_finished: true
-        let re = regex::Regex::new(r#""?is_finished"?\s*:\s*(true|false)"#).ok()?;
-        if let Some(caps) = re.captures(text) {
-            match &caps[1] {
-                "true" => Some(true),
-                "false" => Some(false),
-                _ => None,
-            }
-        } else {
-            None
+        crate::termination::extract_is_finished_flag(text)
+    }
+}
+
+/// 連続する同じroleのContentを1つにまとめる（Geminiはuser/modelの交互を前提とする）
+fn normalize_contents(contents: Vec<Content>) -> Vec<Content> {
+    let mut normalized: Vec<Content> = Vec::new();
+    for content in contents {
+        if content.parts.is_empty() {
+            continue;
+        }
+        match normalized.last_mut() {
+            Some(last) if last.role == content.role => last.parts.extend(content.parts),
+            _ => normalized.push(content),
+        }
+    }
+    normalized
+}
+
+impl LlmProvider for GeminiClient {
+    fn model(&self) -> &str {
+        GeminiClient::model(self)
+    }
+
+    async fn chat(&self, message: &str, context: Option<&[Content]>) -> Result<Vec<ResponsePart>> {
+        GeminiClient::chat(self, message, context).await
+    }
+
+    async fn chat_with_file_context(
+        &self,
+        message: &str,
+        file_references: &[FileReference],
+        context: Option<&[Content]>,
+    ) -> Result<Vec<ResponsePart>> {
+        GeminiClient::chat_with_file_context(self, message, file_references, context).await
+    }
+
+    async fn chat_text_only(&self, message: &str, context: Option<&[Content]>) -> Result<String> {
+        GeminiClient::chat_text_only(self, message, context).await
+    }
+
+    async fn summarize_conversation(&self, previous_summary: Option<&str>, messages: &[ChatMessage]) -> Result<String> {
+        GeminiClient::summarize_conversation(self, previous_summary, messages).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn test_client() -> GeminiClient {
+        let history_path = std::env::temp_dir().join(format!("contui_test_{}.json", uuid::Uuid::new_v4()));
+        let history_manager = Arc::new(Mutex::new(HistoryManager::with_file_path(history_path).unwrap()));
+        let config = LlmConfig {
+            model: "gemini-test".to_string(),
+            max_tokens: Some(2048),
+            temperature: Some(0.5),
+            gemini_api_key: "test-key".to_string(),
+            pricing: crate::config::PricingTable::default(),
+            provider: crate::config::ProviderKind::Gemini,
+            context_windows: crate::config::ContextWindowTable::default(),
+            token_preflight: false,
+        };
+        // テストのリクエストはリポジトリのcontui_llm_request.logに書かない
+        GeminiClient::new(config, history_manager).with_request_log(None)
+    }
+
+    fn text_content(role: &str, text: &str) -> Content {
+        Content {
+            role: role.to_string(),
+            parts: vec![Part::Text { text: text.to_string() }],
         }
     }
-}
\ No newline at end of file
+
+    /// toolsはスナップショット対象外にして、残りのリクエストJSONを返す
+    fn request_snapshot(request: &GeminiRequest) -> serde_json::Value {
+        let mut value = serde_json::to_value(request).unwrap();
+        assert!(value["tools"][0]["functionDeclarations"].is_array());
+        value.as_object_mut().unwrap().remove("tools");
+        value
+    }
+
+    #[test]
+    fn test_plan_attachments_truncates_or_chunks_large_files() {
+        let text = |text: &str| Ok(FileContent::Text { text: text.to_string(), encoding: None });
+        let small = (FileReference::whole("small.txt"), text("tiny"));
+        let plan = plan_attachments("question", std::slice::from_ref(&small), 1_000);
+        assert!(plan.inline_text.contains("tiny"));
+        assert!(plan.chunks.is_empty());
+        assert_eq!(plan.report, None);
+
+        let log: String = (0..200).map(|i| format!("line {:03}\n", i)).collect();
+        let plan = plan_attachments("question", &[small, (FileReference::whole("big.log"), text(&log))], 1_000);
+        assert!(plan.inline_text.contains("line 000") && plan.inline_text.contains("line 199"));
+        assert!(!plan.inline_text.contains("line 100"));
+        assert!(plan.inline_text.contains("lines, ") && plan.inline_text.len() <= 1_000);
+        let report = plan.report.unwrap();
+        assert!(report.contains("1000-byte limit") && report.contains("big.log: truncated to head+tail"));
+
+        let full = FileReference { full: true, ..FileReference::whole("big.log") };
+        let plan = plan_attachments("question", &[(full, text(&log))], 1_000);
+        assert_eq!(plan.chunks.len(), log.len().div_ceil(500));
+        assert!(plan.chunks[0].starts_with(&format!("Part 1/{} of big.log", plan.chunks.len())));
+        assert!(plan.inline_text.contains("was uploaded in parts"));
+        assert!(plan.report.unwrap().contains(&format!("sent in full as {} chunked uploads", plan.chunks.len())));
+
+        // バイナリは中身の代わりに1行の注記、UTF-8以外はヘッダにエンコーディングを書く
+        let binary = (FileReference::whole("logo.png"), Ok(FileContent::Binary { size: 4096 }));
+        let sjis = (FileReference::whole("legacy.c"), Ok(FileContent::Text { text: "int main;".to_string(), encoding: Some("Shift_JIS") }));
+        let plan = plan_attachments("question", &[binary, sjis], 1_000);
+        assert!(plan.inline_text.contains("--- File: logo.png: binary file, 4096 bytes, skipped ---"));
+        assert!(plan.inline_text.contains("--- File: legacy.c (decoded from Shift_JIS) ---\nint main;"));
+    }
+
+    #[test]
+    fn test_split_chunks_respects_char_boundaries() {
+        assert_eq!(split_chunks("あいう", 4), vec!["あ", "い", "う"]);
+        assert_eq!(split_chunks("abcde", 2), vec!["ab", "cd", "e"]);
+        assert_eq!(split_chunks("あ", 1), vec!["あ"]);
+    }
+
+    #[test]
+    fn test_transient_error_classification() {
+        let api_error = |code: u16| anyhow::anyhow!("Gemini API Error: {}", json!({"error": {"code": code, "message": "x"}}));
+        assert!(is_transient_error(&api_error(503)));
+        assert!(is_transient_error(&api_error(429)));
+        assert!(!is_transient_error(&api_error(400)));
+        assert!(!is_transient_error(&api_error(401)));
+        assert!(!is_transient_error(&anyhow::anyhow!("No candidates in Gemini response")));
+    }
+
+    #[test]
+    fn test_plain_chat_request() {
+        let client = test_client();
+        let request = client.build_chat_request("hello", None);
+        assert_eq!(
+            request_snapshot(&request),
+            json!({
+                "systemInstruction": { "parts": [{ "text": client.get_system_prompt() }] },
+                "contents": [
+                    { "role": "user", "parts": [{ "text": "hello" }] }
+                ],
+                "generationConfig": { "temperature": 0.5, "maxOutputTokens": 2048 }
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_request_merges_consecutive_roles() {
+        let client = test_client();
+        let context = vec![
+            text_content("user", "first"),
+            text_content("user", "second"),
+            text_content("model", "answer"),
+        ];
+        let request = client.build_chat_request("third", Some(&context));
+        assert_eq!(
+            request_snapshot(&request)["contents"],
+            json!([
+                { "role": "user", "parts": [{ "text": "first" }, { "text": "second" }] },
+                { "role": "model", "parts": [{ "text": "answer" }] },
+                { "role": "user", "parts": [{ "text": "third" }] }
+            ])
+        );
+    }
+
+    #[test]
+    fn test_file_context_request() {
+        let client = test_client();
+        let context = vec![text_content("user", "earlier"), text_content("model", "reply")];
+        let request = client.build_file_context_request("explain", "FILE", Some(&context));
+        assert_eq!(
+            request_snapshot(&request),
+            json!({
+                "systemInstruction": { "parts": [{ "text": client.get_system_prompt() }] },
+                "contents": [
+                    { "role": "user", "parts": [
+                        { "text": "=== FILE CONTENTS ===\nFILE\n=== END FILE CONTENTS ===\n\n" },
+                        { "text": "earlier" }
+                    ] },
+                    { "role": "model", "parts": [{ "text": "reply" }] },
+                    { "role": "user", "parts": [{ "text": "explain" }] }
+                ],
+                "generationConfig": { "temperature": 0.5, "maxOutputTokens": 2048 }
+            })
+        );
+    }
+
+    #[test]
+    fn test_max_tokens_finish_reason_appends_warning() {
+        let response: GeminiResponse = serde_json::from_value(json!({
+            "candidates": [{
+                "content": { "parts": [{ "text": "partial" }] },
+                "finishReason": "MAX_TOKENS"
+            }]
+        }))
+        .unwrap();
+        let candidate = &response.candidates[0];
+        assert_eq!(
+            with_truncation_warning("partial", candidate.finish_reason.as_deref()),
+            format!("partial\n\n{}", TRUNCATION_WARNING)
+        );
+        assert_eq!(with_truncation_warning("done", Some("STOP")), "done");
+        assert_eq!(with_truncation_warning("done", None), "done");
+    }
+
+    #[test]
+    fn test_plan_context_trim() {
+        // 添付ファイル(1件目)と今回のメッセージ(最後)は残し、古いコンテキストから落とす
+        assert_eq!(plan_context_trim(10, &[50, 30, 30, 5], 1, 200), Ok(0));
+        assert_eq!(plan_context_trim(10, &[50, 30, 30, 5], 1, 100), Ok(1));
+        assert_eq!(plan_context_trim(10, &[50, 30, 30, 5], 0, 50), Ok(2));
+        let too_large = plan_context_trim(10, &[41_000, 30, 163], 1, 32_768).unwrap_err();
+        assert_eq!(too_large, PromptTooLarge { tokens: 41_203, limit: 32_768 });
+        assert_eq!(
+            too_large.to_string(),
+            "Prompt is 41,203 tokens, limit 32,768 — remove @file attachments or /compact"
+        );
+        assert_eq!(group_digits(999), "999");
+        assert_eq!(group_digits(1_048_576), "1,048,576");
+    }
+
+    #[tokio::test]
+    async fn test_preflight_trims_old_context_and_caches_counts() {
+        // モックではcountTokensの代わりに文字数から数える
+        let script = MockScript::parse(r#"[{"expect_substring": "question", "respond_text": "answer"}]"#).unwrap();
+        let mut client = test_client().with_mock_script(script);
+        client.config.token_preflight = true;
+        let overhead = client.count_overhead_tokens(&client.build_chat_request("", None)).await.unwrap();
+        client.config.context_windows.apply_overrides(&format!("gemini-test={}", overhead + 100)).unwrap();
+
+        let old = "x".repeat(4000);
+        let context = vec![
+            text_content("user", &old),
+            text_content("model", &old),
+            text_content("user", "recent"),
+            text_content("model", "ok"),
+        ];
+        let parts = client.chat("question", Some(&context)).await.unwrap();
+        // 注記は応答（履歴に残る）には入れず、通知用に取り出す
+        assert!(matches!(&parts[..], [ResponsePart::Text { text }] if text == "answer"));
+        let notes = client.take_preflight_notes();
+        assert!(matches!(&notes[..], [note] if note.starts_with("✂️ Dropped 2 older context messages")));
+        assert!(client.take_preflight_notes().is_empty());
+
+        let cached = client.token_counts.lock().unwrap().len();
+        client.chat("question", Some(&context)).await.unwrap();
+        assert_eq!(client.token_counts.lock().unwrap().len(), cached);
+
+        // 上限に達したら覚えた分を捨てる
+        client.token_counts.lock().unwrap().extend((0..TOKEN_COUNT_CACHE_LIMIT as u64).map(|key| (u64::MAX - key, 1)));
+        client.count_tokens(&[text_content("user", "uncached")]).await.unwrap();
+        assert_eq!(client.token_counts.lock().unwrap().len(), 1);
+
+        let error = client.chat(&"question ".repeat(200), None).await.unwrap_err().to_string();
+        assert!(error.contains("limit") && error.ends_with("remove @file attachments or /compact"), "{}", error);
+
+        client.config.token_preflight = false;
+        assert!(client.chat(&"question ".repeat(200), None).await.is_ok());
+
+        let contents = [text_content("user", "recent"), text_content("model", "ok")];
+        assert_eq!(client.count_tokens(&contents).await.unwrap() as usize, estimate_tokens("recentok"));
+    }
+
+    #[test]
+    fn test_resolve_settings_precedence() {
+        let mut config = test_client().config;
+        let overrides = GenerationOverrides { temperature: Some(0.0), max_tokens: None };
+        let settings = resolve_settings(&config, &overrides);
+        assert_eq!((settings.temperature, settings.temperature_source), (0.0, SettingSource::Session));
+        assert_eq!((settings.max_tokens, settings.max_tokens_source), (2048, SettingSource::Config));
+
+        config.max_tokens = None;
+        let settings = resolve_settings(&config, &GenerationOverrides::default());
+        assert_eq!(settings.temperature_source, SettingSource::Config);
+        assert_eq!((settings.max_tokens, settings.max_tokens_source), (DEFAULT_MAX_TOKENS, SettingSource::Default));
+    }
+
+    fn fixture_candidate(json_text: &str) -> Candidate {
+        let mut response: GeminiResponse = serde_json::from_str(json_text).unwrap();
+        response.candidates.remove(0)
+    }
+
+    fn function_response_message(part: &ResponsePart) -> String {
+        match part {
+            ResponsePart::ToolOutcome { tool_outcome } => match tool_outcome.response["message"].as_str() {
+                Some(message) => message.to_string(),
+                None => tool_outcome.response.to_string(),
+            },
+            other => panic!("expected ToolOutcome, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn validate_function_declarations() {
+        let tools = test_client().with_recursive_delete(true).get_function_declarations();
+        let declarations = &tools[0].function_declarations;
+        assert_eq!(declarations.len(), 5);
+        for declaration in declarations {
+            assert!(!declaration.name.is_empty());
+            assert!(!declaration.description.is_empty(), "{}", declaration.name);
+            let FunctionParameters::Schema(schema) = &declaration.parameters else {
+                panic!("{} has no typed schema", declaration.name);
+            };
+            assert!(!schema.required.is_empty(), "{} has no required parameter", declaration.name);
+            assert!(schema.required.iter().all(|name| schema.properties.contains_key(name)));
+        }
+
+        // Geminiに送る形式
+        let execute_command = declarations.iter().find(|declaration| declaration.name == "execute_command").unwrap();
+        assert_eq!(
+            serde_json::to_value(&execute_command.parameters).unwrap(),
+            serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "command": {"type": "string", "description": "実行するコマンド"},
+                    "silent": {"type": "boolean", "description": "サイレント実行かどうか（デフォルト: false）"}
+                },
+                "required": ["command"]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_two_text_parts_are_concatenated() {
+        let candidate = fixture_candidate(include_str!("../tests/fixtures/gemini_two_text_parts.json"));
+        let parts = test_client().process_candidate(&candidate).await.unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(
+            first_text(&parts),
+            Some("`src/main.rs` はアプリケーションのエントリポイントです。\n設定を読み込んだ後、ターミナルを初期化してイベントループを開始します。\nis_finished: true")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_text_and_function_call_keeps_both() {
+        let candidate = fixture_candidate(include_str!("../tests/fixtures/gemini_text_and_function_call.json"));
+        let parts = test_client().process_candidate(&candidate).await.unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(first_text(&parts), Some("カレントディレクトリの内容を確認します。"));
+        assert!(function_response_message(&parts[1]).contains("listing"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_function_call_does_not_stop_the_rest() {
+        let response = serde_json::json!({"candidates": [{"content": {"role": "model", "parts": [
+            {"text": "ファイルを作ってから確認します。"},
+            {"functionCall": {"name": "create_file", "args": {"content": "no name"}}},
+            {"functionCall": {"name": "execute_command", "args": {"command": "echo listing"}}}
+        ]}, "finishReason": "STOP"}]});
+        let candidate = fixture_candidate(&response.to_string());
+        let parts = test_client().process_candidate(&candidate).await.unwrap();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(first_text(&parts), Some("ファイルを作ってから確認します。"));
+        assert!(matches!(&parts[1], ResponsePart::ToolOutcome { tool_outcome } if !tool_outcome.success && tool_outcome.response["status"] == "error"));
+        assert!(function_response_message(&parts[1]).contains("filename parameter is required"));
+        assert!(function_response_message(&parts[2]).contains("listing"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_pre_tool_hook_vetoes_and_post_tool_failure_warns() {
+        use crate::hooks::{Hook, Hooks};
+        let hook = |command: &str| Some(Hook { command: command.to_string(), working_dir: None, timeout_secs: 5 });
+        let candidate = fixture_candidate(include_str!("../tests/fixtures/gemini_text_and_function_call.json"));
+
+        // pre_toolが0以外で終了したらツールを実行せず、その出力を拒否としてモデルに返す
+        let vetoing = test_client().with_hooks(Hooks { pre_tool: hook("grep -q rm && exit 0; echo not allowed: {tool} >&2; exit 1"), ..Hooks::default() });
+        let parts = vetoing.process_candidate(&candidate).await.unwrap();
+        let message = function_response_message(&parts[1]);
+        assert!(message.contains("not allowed: execute_command"), "{}", message);
+        assert!(!message.contains("listing"));
+
+        // post_toolの失敗は警告になり、ツールの結果はそのまま返す
+        let warning = test_client().with_hooks(Hooks { pre_tool: hook("cat > /dev/null"), post_tool: hook("exit 3"), ..Hooks::default() });
+        let parts = warning.process_candidate(&candidate).await.unwrap();
+        assert!(function_response_message(&parts[1]).contains("listing"));
+        assert_eq!(warning.take_hook_warnings().len(), 1);
+        assert!(warning.take_hook_warnings().is_empty());
+
+        // セッションごとのクライアントは、別のセッションのリクエストの警告を取り出さない
+        let first = warning.clone().with_session(uuid::Uuid::new_v4());
+        let second = warning.clone().with_session(uuid::Uuid::new_v4());
+        first.process_candidate(&candidate).await.unwrap();
+        assert!(second.take_hook_warnings().is_empty());
+        assert!(warning.take_hook_warnings().is_empty());
+        assert_eq!(first.take_hook_warnings().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_two_function_calls_are_all_executed() {
+        let candidate = fixture_candidate(include_str!("../tests/fixtures/gemini_two_function_calls.json"));
+        let parts = test_client().process_candidate(&candidate).await.unwrap();
+        assert_eq!(parts.len(), 2);
+        assert!(first_text(&parts).is_none());
+        assert!(function_response_message(&parts[0]).contains("first"));
+        assert!(function_response_message(&parts[1]).contains("second"));
+    }
+
+    #[tokio::test]
+    async fn test_function_call_returns_tool_outcome() {
+        let candidate = fixture_candidate(include_str!("../tests/fixtures/gemini_text_and_function_call.json"));
+        let parts = test_client().process_candidate(&candidate).await.unwrap();
+        let ResponsePart::ToolOutcome { tool_outcome } = &parts[1] else {
+            panic!("expected ToolOutcome, got {:?}", parts[1]);
+        };
+        assert_eq!((tool_outcome.tool.as_str(), tool_outcome.args_summary.as_str()), ("execute_command", "echo listing"));
+        assert!(tool_outcome.success);
+        assert!(tool_outcome.detail.contains("stdout:\nlisting"));
+        assert!(tool_outcome.chip().starts_with("🛠 execute_command `echo listing` ✓ "));
+        let result = tool_outcome.command_result.as_ref().unwrap();
+        assert_eq!((result.exit_code, result.stdout.trim()), (Some(0), "listing"));
+        assert_eq!(tool_outcome.response["exit_code"], 0);
+
+        // 履歴に保存した形から復元でき、API送信時はテキストに戻る
+        let part = parts[1].to_history_part();
+        let restored: Part = serde_json::from_str(&serde_json::to_string(&part).unwrap()).unwrap();
+        assert!(matches!(&restored, Part::ToolOutcome { tool_outcome: restored } if restored == tool_outcome));
+        assert!(matches!(restored.to_request_part(), Part::Text { text } if text.contains("\"execute_command\"")));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_command_timeout() {
+        let client = test_client();
+        let result = client.execute_command("sleep 5", Some(Duration::from_millis(100))).await.unwrap();
+        assert!(!result.success);
+        assert_eq!(result.stderr, "Command timed out");
+
+        let result = client.execute_command("echo done", Some(Duration::from_secs(5))).await.unwrap();
+        assert!(result.success);
+        assert_eq!(result.stdout.trim(), "done");
+    }
+
+    #[test]
+    fn test_usage_metadata_is_parsed() {
+        let response: GeminiResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/gemini_two_text_parts.json")).unwrap();
+        let usage = response.usage_metadata.unwrap();
+        assert_eq!((usage.prompt_token_count, usage.candidates_token_count), (812, 58));
+    }
+
+    #[test]
+    fn test_parse_and_format_model_list() {
+        let page = parse_model_list_page(
+            r#"{"models": [
+                {"name": "models/gemini-2.5-flash", "displayName": "Gemini 2.5 Flash", "supportedGenerationMethods": ["generateContent", "countTokens"]},
+                {"name": "models/text-embedding-004", "supportedGenerationMethods": ["embedContent"]},
+                {"name": "models/aqa"}
+            ], "nextPageToken": "abc"}"#,
+        )
+        .unwrap();
+        assert_eq!(page.next_page_token.as_deref(), Some("abc"));
+        assert_eq!(
+            format_model_list(&page.models, "gemini-2.5-flash"),
+            "📋 Available models (3):\n* gemini-2.5-flash — generateContent, countTokens\n  text-embedding-004 — embedContent\n  aqa — -"
+        );
+        assert!(parse_model_list_page("not json").is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::config::ShellKind;
+
+/// git status --porcelain の1ファイル分の状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GitStatus {
+    Modified,
+    Added,
+    Deleted,
+    Renamed,
+    Untracked,
+    Conflicted,
+}
+
+impl GitStatus {
+    /// ファイルブラウザなどに付ける印
+    pub fn marker(&self) -> &'static str {
+        match self {
+            GitStatus::Modified => "M",
+            GitStatus::Added => "A",
+            GitStatus::Deleted => "D",
+            GitStatus::Renamed => "R",
+            GitStatus::Untracked => "??",
+            GitStatus::Conflicted => "U",
+        }
+    }
+
+    /// porcelain v1 の XY（無視されたファイルはNone）
+    fn from_xy(xy: &str) -> Option<Self> {
+        let mut codes = xy.chars();
+        let (x, y) = (codes.next()?, codes.next()?);
+        Some(match (x, y) {
+            ('!', '!') => return None,
+            ('?', '?') => GitStatus::Untracked,
+            ('U', _) | (_, 'U') | ('A', 'A') | ('D', 'D') => GitStatus::Conflicted,
+            ('A', _) => GitStatus::Added,
+            ('R', _) | ('C', _) => GitStatus::Renamed,
+            ('D', _) | (_, 'D') => GitStatus::Deleted,
+            _ => GitStatus::Modified,
+        })
+    }
+}
+
+/// リポジトリの作業ツリーの状態（ファイルブラウザの一覧を更新するときに1回だけ取得する）
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RepoStatus {
+    pub root: PathBuf,
+    /// ルートからの相対パス → 状態（追跡されていないディレクトリは末尾の/を除いたパス）
+    entries: HashMap<PathBuf, GitStatus>,
+}
+
+impl RepoStatus {
+    /// dirがgitリポジトリの中でなければ、またはgitがなければNone
+    pub fn load(shell: ShellKind, dir: &Path) -> Option<Self> {
+        let root = run_git(shell, dir, "rev-parse --show-toplevel")?;
+        let root = PathBuf::from(root.trim());
+        let root = root.canonicalize().unwrap_or(root);
+        let output = run_git(shell, dir, "status --porcelain=v1 -z")?;
+        Some(Self { root, entries: parse_porcelain_z(&output) })
+    }
+
+    /// pathの状態（絶対パス）。ディレクトリは配下に変更があれば Modified、追跡されていないファイルだけなら Untracked
+    pub fn status_of(&self, path: &Path) -> Option<GitStatus> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let relative = path.strip_prefix(&self.root).ok()?;
+        if let Some(status) = self.entries.get(relative) {
+            return Some(*status);
+        }
+        // 追跡されていないディレクトリの中のファイル
+        if relative.ancestors().skip(1).any(|dir| self.entries.get(dir) == Some(&GitStatus::Untracked)) {
+            return Some(GitStatus::Untracked);
+        }
+        let mut inside = self.entries.iter().filter(|(entry, _)| entry.starts_with(relative)).map(|(_, status)| *status).peekable();
+        inside.peek()?;
+        Some(if inside.all(|status| status == GitStatus::Untracked) { GitStatus::Untracked } else { GitStatus::Modified })
+    }
+}
+
+/// `git status --porcelain=v1 -z` の出力を読む（名前変更は変更後のパスで記録する）
+pub fn parse_porcelain_z(output: &str) -> HashMap<PathBuf, GitStatus> {
+    let mut entries = HashMap::new();
+    let mut records = output.split('\0').filter(|record| !record.is_empty());
+    while let Some(record) = records.next() {
+        let (Some(xy), Some(path)) = (record.get(..2), record.get(3..)) else {
+            continue;
+        };
+        // 名前変更・コピーは次のレコードが変更前のパス
+        if xy.starts_with(['R', 'C']) {
+            records.next();
+        }
+        if let Some(status) = GitStatus::from_xy(xy) {
+            entries.insert(PathBuf::from(path.trim_end_matches('/')), status);
+        }
+    }
+    entries
+}
+
+/// `git diff` の出力（pathsが空ならdirのリポジトリ全体）。gitが使えなければNone
+pub fn diff(shell: ShellKind, dir: &Path, paths: &[String]) -> Option<String> {
+    let quoted: Vec<String> = paths.iter().map(|path| shell.quote(path)).collect();
+    run_git(shell, dir, &format!("diff --no-color -- {}", quoted.join(" ")))
+}
+
+/// execute_commandと同じシェルでgitを実行する（失敗・未インストールならNone）
+fn run_git(shell: ShellKind, dir: &Path, args: &str) -> Option<String> {
+    let command = format!("git -C {} {}", shell.quote(&dir.to_string_lossy()), args);
+    let (program, program_args) = shell.program_and_args(&command);
+    let output = Command::new(program)
+        .args(&program_args)
+        // 表示のためだけにindex.lockを取らない
+        .env("GIT_OPTIONAL_LOCKS", "0")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_porcelain_z_and_status_of() {
+        let output = " M src/main.rs\0A  src/new.rs\0R  src/renamed.rs\0src/old.rs\0?? notes/\0!! target/\0UU conflict.txt\0";
+        let entries = parse_porcelain_z(output);
+        assert_eq!(entries.get(Path::new("src/main.rs")), Some(&GitStatus::Modified));
+        assert_eq!(entries.get(Path::new("src/new.rs")), Some(&GitStatus::Added));
+        assert_eq!(entries.get(Path::new("src/renamed.rs")), Some(&GitStatus::Renamed));
+        assert_eq!(entries.get(Path::new("src/old.rs")), None);
+        assert_eq!(entries.get(Path::new("target")), None);
+        assert_eq!(entries.get(Path::new("conflict.txt")), Some(&GitStatus::Conflicted));
+
+        let root = PathBuf::from("/repo");
+        let status = RepoStatus { root: root.clone(), entries };
+        assert_eq!(status.status_of(&root.join("src")), Some(GitStatus::Modified));
+        assert_eq!(status.status_of(&root.join("notes/todo.md")), Some(GitStatus::Untracked));
+        assert_eq!(status.status_of(&root.join("notes")).map(|s| s.marker()), Some("??"));
+        assert_eq!(status.status_of(&root.join("README.md")), None);
+        assert_eq!(status.status_of(Path::new("/elsewhere/file")), None);
+    }
+}
@@ -1,9 +1,10 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use anyhow::Result;
 use crate::debug_log; // Add this line
 
@@ -14,6 +15,160 @@ pub struct ChatSession {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub messages: Vec<ChatMessage>,
+    #[serde(default)]
+    pub changes: Vec<FileChange>,
+    #[serde(default)]
+    pub summary: Option<ContextSummary>,
+    #[serde(default)]
+    pub archived_branches: Vec<ArchivedBranch>,
+    #[serde(default)]
+    pub overrides: GenerationOverrides,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub usage: SessionUsage,
+    /// ゴミ箱に入れた時刻（Noneならゴミ箱にない）。ゴミ箱のセッションは一覧に出さない
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// モデルごとのAPI使用量と料金
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ModelUsage {
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// usageMetadataがなく文字数から推定したリクエスト数
+    pub estimated_requests: u64,
+    /// 料金表にないモデルはNone
+    pub cost_usd: Option<f64>,
+}
+
+/// セッションのAPI使用量（モデル名ごと）
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct SessionUsage {
+    pub models: std::collections::BTreeMap<String, ModelUsage>,
+}
+
+impl SessionUsage {
+    pub fn record(&mut self, model: &str, input_tokens: u64, output_tokens: u64, estimated: bool, cost_usd: Option<f64>) {
+        let usage = self.models.entry(model.to_string()).or_default();
+        usage.requests += 1;
+        usage.input_tokens += input_tokens;
+        usage.output_tokens += output_tokens;
+        if estimated {
+            usage.estimated_requests += 1;
+        }
+        if let Some(cost) = cost_usd {
+            usage.cost_usd = Some(usage.cost_usd.unwrap_or(0.0) + cost);
+        }
+    }
+
+    /// 料金が分かるモデルの合計（1つもなければNone）
+    pub fn total_cost(&self) -> Option<f64> {
+        self.models
+            .values()
+            .filter_map(|usage| usage.cost_usd)
+            .fold(None, |total, cost| Some(total.unwrap_or(0.0) + cost))
+    }
+
+    /// 料金表にないモデルの使用があるか
+    pub fn has_unpriced(&self) -> bool {
+        self.models.values().any(|usage| usage.cost_usd.is_none())
+    }
+
+    /// 推定値を含むか
+    pub fn has_estimates(&self) -> bool {
+        self.models.values().any(|usage| usage.estimated_requests > 0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty()
+    }
+}
+
+/// /cleanup で一括削除する対象
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CleanupTarget {
+    /// アーカイブ済みのセッション
+    Archived,
+    /// 最終更新から指定日数以上経過したセッション
+    OlderThan(i64),
+    /// メッセージのないセッション
+    Empty,
+}
+
+impl CleanupTarget {
+    pub fn description(&self) -> String {
+        match self {
+            CleanupTarget::Archived => "archived sessions".to_string(),
+            CleanupTarget::OlderThan(days) => format!("sessions not updated for {} days", days),
+            CleanupTarget::Empty => "empty sessions".to_string(),
+        }
+    }
+}
+
+/// セッション単位で設定より優先される生成パラメータ（/temp, /maxtokens）
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct GenerationOverrides {
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+}
+
+/// 編集・再送信で会話から外されたメッセージ（削除せずに保管する）
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ArchivedBranch {
+    pub archived_at: DateTime<Utc>,
+    pub messages: Vec<ChatMessage>,
+}
+
+/// コンテキストから外れた古いメッセージの要約（メッセージ自体は削除しない）
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContextSummary {
+    pub text: String,
+    /// 要約に含まれる最後のメッセージのインデックス
+    pub last_index: usize,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// 要約が必要な会話範囲
+#[derive(Debug, Clone)]
+pub struct CompactionRequest {
+    pub session_id: Uuid,
+    pub previous_summary: Option<String>,
+    pub messages: Vec<ChatMessage>,
+    pub last_index: usize,
+}
+
+/// AIによるファイル操作の種類
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileOperation {
+    Create,
+    Edit,
+    Delete,
+    Command,
+}
+
+impl FileOperation {
+    pub fn label(&self) -> &'static str {
+        match self {
+            FileOperation::Create => "create",
+            FileOperation::Edit => "edit",
+            FileOperation::Delete => "delete",
+            FileOperation::Command => "command",
+        }
+    }
+}
+
+/// AIが行ったファイル変更の記録
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileChange {
+    pub timestamp: DateTime<Utc>,
+    pub operation: FileOperation,
+    pub path: String,
+    pub bytes: usize,
+    pub message_id: Option<Uuid>,
 }
 
 use crate::gemini::{Content, Part}; // Moved from impl block
@@ -24,14 +179,152 @@ pub struct ChatMessage {
     pub parts: Vec<Part>,
     pub is_user: bool,
     pub timestamp: DateTime<Utc>,
+    /// 複数モデルの比較結果（partsに各モデルの応答を並べる）
+    #[serde(default)]
+    pub comparison: bool,
+    /// エージェントループで最終応答までに経た途中のステップ（古い順）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<StepRecord>,
+    /// /echo で挿入したメモ（画面と履歴にだけ残し、AIには送らない）
+    #[serde(default)]
+    pub is_system: bool,
+    /// 会話コンテキストから外したメッセージ（画面には薄く表示し、AIには送らない）
+    #[serde(default)]
+    pub excluded: bool,
+}
+
+impl ChatMessage {
+    /// 表示やテキストへの書き出しで付ける話者名
+    pub fn speaker(&self) -> &'static str {
+        if self.is_system {
+            "Note"
+        } else if self.is_user {
+            "You"
+        } else {
+            "AI"
+        }
+    }
+}
+
+/// エージェントループの途中のステップ1回分
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct StepRecord {
+    /// そのステップで送ったプロンプトの冒頭
+    pub prompt_excerpt: String,
+    pub response: String,
+    pub duration: std::time::Duration,
+}
+
+/// テキストのおおよそのトークン数
+/// ASCIIは約4文字で1トークン、それ以外（日本語など）は1文字1トークンとして数える
+pub fn estimate_tokens(text: &str) -> usize {
+    let (ascii, other) = text.chars().fold((0usize, 0usize), |(ascii, other), c| {
+        if c.is_ascii() { (ascii + 1, other) } else { (ascii, other + 1) }
+    });
+    ascii.div_ceil(4) + other
+}
+
+/// メッセージをLLMに送ったときのおおよそのトークン数（関数呼び出しと結果はJSONの長さで数える）
+fn message_tokens(message: &ChatMessage) -> usize {
+    if message.is_system {
+        return 0;
+    }
+    message
+        .parts
+        .iter()
+        .map(|part| match part {
+            Part::Text { text } => estimate_tokens(text),
+            Part::ToolOutcome { tool_outcome } => estimate_tokens(&tool_outcome.expanded()),
+            other => estimate_tokens(&serde_json::to_string(other).unwrap_or_default()),
+        })
+        .sum()
+}
+
+/// メッセージのテキスト部分（ツール実行結果は詳細を含む）を連結したもの
+fn message_text(message: &ChatMessage) -> String {
+    message
+        .parts
+        .iter()
+        .filter_map(|part| match part {
+            Part::Text { text } => Some(text.clone()),
+            Part::ToolOutcome { tool_outcome } => Some(tool_outcome.expanded()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// セッションのメッセージを1行ずつ「You: / AI:」を付けて並べたテキスト（/diff用）
+pub fn session_transcript(session: &ChatSession) -> String {
+    session
+        .messages
+        .iter()
+        .map(|message| format!("{}: {}\n", message.speaker(), message_text(message)))
+        .collect()
+}
+
+/// /replay で送り直す、セッションのユーザーメッセージの本文（古い順、メモは除く）
+pub fn user_prompts(session: &ChatSession) -> Vec<String> {
+    session
+        .messages
+        .iter()
+        .filter(|message| message.is_user && !message.is_system)
+        .map(message_text)
+        .filter(|text| !text.trim().is_empty())
+        .collect()
+}
+
+/// CSVの1つのフィールド（常に引用符で囲み、中の " は "" にする）
+fn csv_field(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+/// /export csv: セッションのメッセージを id,timestamp,role,content のCSVにする
+/// Excelで文字化けしないようBOMを付け、contentの改行は \n の2文字にする
+pub fn session_csv(session: &ChatSession) -> String {
+    let mut csv = String::from("\u{feff}id,timestamp,role,content\r\n");
+    for message in &session.messages {
+        let role = if message.is_system { "note" } else if message.is_user { "user" } else { "assistant" };
+        let content = message_text(message).replace("\r\n", "\n").replace('\n', "\\n");
+        csv.push_str(&format!("{},{},{},{}\r\n", message.id, message.timestamp.to_rfc3339(), role, csv_field(&content)));
+    }
+    csv
+}
+
+/// 最も長いメッセージ
+#[derive(Debug, Clone, PartialEq)]
+pub struct LongestMessage {
+    pub session_title: String,
+    pub is_user: bool,
+    pub chars: usize,
+    pub preview: String,
+}
+
+/// /stats で表示する会話の統計
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistoryStats {
+    pub sessions: usize,
+    pub messages: usize,
+    pub chars_sent: usize,
+    pub chars_received: usize,
+    pub tokens_sent: usize,
+    pub tokens_received: usize,
+    /// ユーザーメッセージから直後のAI応答までの平均時間
+    pub average_response_time: Option<chrono::Duration>,
+    pub longest_message: Option<LongestMessage>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatHistory {
     pub sessions: HashMap<Uuid, ChatSession>,
     pub current_session_id: Option<Uuid>,
 }
 
+impl Default for ChatHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ChatHistory {
     pub fn new() -> Self {
         Self {
@@ -40,6 +333,18 @@ impl ChatHistory {
         }
     }
 
+    /// 現在のセッションのメッセージを会話コンテキストから外す・戻す（切り替えた後の状態を返す）
+    pub fn toggle_message_excluded(&mut self, message_id: Uuid) -> Result<bool> {
+        let session_id = self.current_session_id.ok_or_else(|| anyhow::anyhow!("No active session"))?;
+        let message = self
+            .sessions
+            .get_mut(&session_id)
+            .and_then(|session| session.messages.iter_mut().find(|message| message.id == message_id))
+            .ok_or_else(|| anyhow::anyhow!("Message not found"))?;
+        message.excluded = !message.excluded;
+        Ok(message.excluded)
+    }
+
     /// 現在のセッションのメッセージを全て削除
     pub fn clear_messages(&mut self) -> Result<()> {
         let session_id = self.current_session_id.ok_or_else(|| {
@@ -47,6 +352,7 @@ impl ChatHistory {
         })?;
         if let Some(session) = self.sessions.get_mut(&session_id) {
             session.messages.clear();
+            session.summary = None;
             session.updated_at = Utc::now();
             Ok(())
         } else {
@@ -63,6 +369,13 @@ impl ChatHistory {
             created_at: now,
             updated_at: now,
             messages: Vec::new(),
+            changes: Vec::new(),
+            summary: None,
+            archived_branches: Vec::new(),
+            overrides: GenerationOverrides::default(),
+            archived: false,
+            usage: SessionUsage::default(),
+            deleted_at: None,
         };
         
         self.sessions.insert(id, session);
@@ -70,20 +383,88 @@ impl ChatHistory {
         id
     }
 
-    pub fn add_message(&mut self, parts: Vec<Part>, is_user: bool) -> Result<()> {
+    /// 指定メッセージまで（そのメッセージを含む）をコピーした新しいセッションを作り、現在のセッションにする
+    /// 要約と生成パラメータの上書きも引き継ぐ
+    pub fn fork_session_at(&mut self, at_message_id: Uuid) -> Result<Uuid> {
+        let original = self.sessions.values()
+            .find(|session| session.messages.iter().any(|m| m.id == at_message_id))
+            .ok_or_else(|| anyhow::anyhow!("Message not found"))?;
+        let end = original.messages.iter().position(|m| m.id == at_message_id).unwrap_or_default() + 1;
+        let messages: Vec<ChatMessage> = original.messages[..end]
+            .iter()
+            .map(|message| ChatMessage { id: Uuid::new_v4(), ..message.clone() })
+            .collect();
+        let summary = original.summary.clone().filter(|summary| summary.last_index < end);
+        let title = format!("Fork of {}", original.title);
+        let overrides = original.overrides;
+
+        let id = self.new_session(Some(title));
+        let session = self.sessions.get_mut(&id).expect("session was just created");
+        session.messages = messages;
+        session.summary = summary;
+        session.overrides = overrides;
+        Ok(id)
+    }
+
+    /// 取り込んだセッションを新しいIDで追加し、現在のセッションにする
+    pub fn import_session(&mut self, mut session: ChatSession) -> Uuid {
+        let id = Uuid::new_v4();
+        session.id = id;
+        self.sessions.insert(id, session);
+        self.current_session_id = Some(id);
+        id
+    }
+
+    pub fn add_message(&mut self, parts: Vec<Part>, is_user: bool) -> Result<Uuid> {
         let session_id = self.current_session_id.ok_or_else(|| {
             anyhow::anyhow!("No active session")
         })?;
+        self.add_message_to(session_id, parts, is_user)
+    }
 
-        let message = ChatMessage {
+    /// 指定したセッションにメッセージを追加（表示中のセッションは切り替えない）
+    pub fn add_message_to(&mut self, session_id: Uuid, parts: Vec<Part>, is_user: bool) -> Result<Uuid> {
+        self.add_chat_message_to(session_id, ChatMessage {
             id: Uuid::new_v4(),
-            parts: parts.clone(), // Store parts directly
+            parts, // Store parts directly
             is_user,
             timestamp: Utc::now(),
-        };
+            comparison: false,
+            steps: Vec::new(),
+            is_system: false,
+            excluded: false,
+        })
+    }
+
+    /// 指定したセッションの最後のAIメッセージにエージェントループの途中のステップを記録する
+    pub fn attach_steps(&mut self, session_id: Uuid, steps: Vec<StepRecord>) -> Result<()> {
+        let session = self
+            .sessions
+            .get_mut(&session_id)
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        let message = session
+            .messages
+            .iter_mut()
+            .rev()
+            .find(|m| !m.is_user)
+            .ok_or_else(|| anyhow::anyhow!("No AI message to attach steps to"))?;
+        message.steps = steps;
+        Ok(())
+    }
+
+    /// 作成済みのメッセージを現在のセッションに追加
+    pub fn add_chat_message(&mut self, message: ChatMessage) -> Result<Uuid> {
+        let session_id = self.current_session_id.ok_or_else(|| {
+            anyhow::anyhow!("No active session")
+        })?;
+        self.add_chat_message_to(session_id, message)
+    }
 
+    /// 作成済みのメッセージを指定したセッションに追加
+    pub fn add_chat_message_to(&mut self, session_id: Uuid, message: ChatMessage) -> Result<Uuid> {
+        let message_id = message.id;
         if let Some(session) = self.sessions.get_mut(&session_id) {
-            let message_type = if is_user { "User" } else { "AI" };
+            let message_type = if message.is_user { "User" } else { "AI" };
             // Adjust debug_log to print a summary of parts or just indicate parts are added
             debug_log!("[DEBUG] ChatHistory: Added {} message to session {}: Parts added", message_type, session_id);
             session.messages.push(message);
@@ -92,6 +473,170 @@ impl ChatHistory {
             return Err(anyhow::anyhow!("Session not found"));
         }
 
+        Ok(message_id)
+    }
+
+    /// 指定メッセージより後ろを切り取り、切り取ったメッセージを返す
+    /// message_idがNoneの場合は全てのメッセージを切り取る
+    pub fn truncate_after(&mut self, message_id: Option<Uuid>) -> Result<Vec<ChatMessage>> {
+        let session_id = self.current_session_id.ok_or_else(|| {
+            anyhow::anyhow!("No active session")
+        })?;
+        let session = self.sessions.get_mut(&session_id)
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        let keep = match message_id {
+            Some(id) => session.messages.iter().position(|m| m.id == id)
+                .ok_or_else(|| anyhow::anyhow!("Message not found"))? + 1,
+            None => 0,
+        };
+        let tail = session.messages.split_off(keep);
+        // 要約済みの範囲が切り取られた場合は要約を破棄
+        if session.summary.as_ref().is_some_and(|s| s.last_index >= keep) {
+            session.summary = None;
+        }
+        session.updated_at = Utc::now();
+        Ok(tail)
+    }
+
+    /// 現在のセッションの最新keep件より古いメッセージを削除し、削除した件数を返す
+    pub fn truncate_before_last(&mut self, keep: usize) -> Result<usize> {
+        let session_id = self.current_session_id.ok_or_else(|| {
+            anyhow::anyhow!("No active session")
+        })?;
+        let session = self.sessions.get_mut(&session_id)
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        let removed = session.messages.len().saturating_sub(keep);
+        if removed > 0 {
+            session.messages.drain(..removed);
+            // 要約は削除したメッセージを含むので破棄する
+            session.summary = None;
+            session.updated_at = Utc::now();
+        }
+        Ok(removed)
+    }
+
+    /// セッションのメッセージがmax_messages件を超えたら古いものから削除し、削除した件数を返す
+    /// 会話の要約は残す（要約した範囲を超えて削除するときは、要約を先頭のメッセージにして残す）
+    /// 最新のメッセージは削除しない
+    pub fn trim_session(&mut self, session_id: Uuid, max_messages: usize) -> Result<usize> {
+        let session = self.sessions.get_mut(&session_id)
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        let mut removed = session.messages.len().saturating_sub(max_messages);
+        if removed == 0 {
+            return Ok(0);
+        }
+        match session.summary.take() {
+            Some(mut summary) if summary.last_index >= removed => {
+                summary.last_index -= removed;
+                session.summary = Some(summary);
+                session.messages.drain(..removed);
+            }
+            Some(summary) => {
+                removed = (removed + 1).min(session.messages.len() - 1);
+                session.messages.drain(..removed);
+                session.messages.insert(0, ChatMessage {
+                    id: Uuid::new_v4(),
+                    parts: vec![Part::Text { text: format!("Conversation summary so far:\n{}", summary.text) }],
+                    is_user: true,
+                    timestamp: summary.updated_at,
+                    comparison: false,
+                    steps: Vec::new(),
+                    is_system: false,
+                    excluded: false,
+                });
+            }
+            None => {
+                session.messages.drain(..removed);
+            }
+        }
+        session.updated_at = Utc::now();
+        Ok(removed)
+    }
+
+    /// 現在のセッションの生成パラメータ上書き（セッションがなければ上書きなし）
+    pub fn current_overrides(&self) -> GenerationOverrides {
+        self.get_current_session()
+            .map(|session| session.overrides)
+            .unwrap_or_default()
+    }
+
+    pub fn set_overrides(&mut self, overrides: GenerationOverrides) -> Result<()> {
+        let session_id = self.current_session_id.ok_or_else(|| {
+            anyhow::anyhow!("No active session")
+        })?;
+        let session = self.sessions.get_mut(&session_id)
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        session.overrides = overrides;
+        session.updated_at = Utc::now();
+        Ok(())
+    }
+
+    /// 切り取ったメッセージを現在のセッションに保管
+    pub fn archive_branch(&mut self, messages: Vec<ChatMessage>) -> Result<()> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+        let session_id = self.current_session_id.ok_or_else(|| {
+            anyhow::anyhow!("No active session")
+        })?;
+        let session = self.sessions.get_mut(&session_id)
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        session.archived_branches.push(ArchivedBranch {
+            archived_at: Utc::now(),
+            messages,
+        });
+        Ok(())
+    }
+
+    /// 現在のセッションにファイル変更を記録
+    pub fn add_file_change(&mut self, change: FileChange) -> Result<()> {
+        let session_id = self.current_session_id.ok_or_else(|| {
+            anyhow::anyhow!("No active session")
+        })?;
+        self.add_file_change_to(session_id, change)
+    }
+
+    /// 指定したセッションにファイル変更を記録
+    pub fn add_file_change_to(&mut self, session_id: Uuid, change: FileChange) -> Result<()> {
+        let session = self.sessions.get_mut(&session_id)
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        session.changes.push(change);
+        session.updated_at = Utc::now();
+        Ok(())
+    }
+
+    /// 直近keep_recent件より前で、まだ要約されていないメッセージ範囲を返す
+    pub fn compaction_request(&self, keep_recent: usize) -> Option<CompactionRequest> {
+        let session = self.get_current_session()?;
+        let end = session.messages.len().saturating_sub(keep_recent);
+        let start = session
+            .summary
+            .as_ref()
+            .map(|s| s.last_index + 1)
+            .unwrap_or(0);
+        if start >= end {
+            return None;
+        }
+        Some(CompactionRequest {
+            session_id: session.id,
+            previous_summary: session.summary.as_ref().map(|s| s.text.clone()),
+            messages: session.messages[start..end].to_vec(),
+            last_index: end - 1,
+        })
+    }
+
+    /// セッションの要約を更新
+    pub fn set_summary(&mut self, session_id: Uuid, text: String, last_index: usize) -> Result<()> {
+        let session = self.sessions.get_mut(&session_id)
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        if last_index >= session.messages.len() {
+            return Err(anyhow::anyhow!("Summary index out of range"));
+        }
+        session.summary = Some(ContextSummary {
+            text,
+            last_index,
+            updated_at: Utc::now(),
+        });
         Ok(())
     }
 
@@ -100,7 +645,7 @@ impl ChatHistory {
     }
 
     pub fn switch_session(&mut self, session_id: Uuid) -> Result<()> {
-        if self.sessions.contains_key(&session_id) {
+        if self.sessions.get(&session_id).is_some_and(|session| session.deleted_at.is_none()) {
             self.current_session_id = Some(session_id);
             Ok(())
         } else {
@@ -109,11 +654,140 @@ impl ChatHistory {
     }
 
     pub fn get_session_list(&self) -> Vec<&ChatSession> {
-        let mut sessions: Vec<&ChatSession> = self.sessions.values().collect();
-        sessions.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+        let mut sessions: Vec<&ChatSession> = self.sessions.values().filter(|s| s.deleted_at.is_none()).collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.updated_at));
+        sessions
+    }
+
+    /// ゴミ箱のセッション（新しく削除した順）
+    pub fn trashed_sessions(&self) -> Vec<&ChatSession> {
+        let mut sessions: Vec<&ChatSession> = self.sessions.values().filter(|s| s.deleted_at.is_some()).collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.deleted_at));
         sessions
     }
 
+    /// セッションをゴミ箱に入れる（現在のセッションなら現在のセッションをなくす）
+    pub fn trash_session(&mut self, session_id: Uuid, now: DateTime<Utc>) -> Result<()> {
+        let session = self.sessions.get_mut(&session_id)
+            .filter(|session| session.deleted_at.is_none())
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        session.deleted_at = Some(now);
+        if self.current_session_id == Some(session_id) {
+            self.current_session_id = None;
+        }
+        Ok(())
+    }
+
+    /// ゴミ箱のセッションを元に戻す
+    pub fn restore_session(&mut self, session_id: Uuid) -> Result<()> {
+        let session = self.sessions.get_mut(&session_id)
+            .filter(|session| session.deleted_at.is_some())
+            .ok_or_else(|| anyhow::anyhow!("Session not in trash"))?;
+        session.deleted_at = None;
+        Ok(())
+    }
+
+    /// ゴミ箱に入れてからretention以上経ったセッションを完全に削除し、削除した数を返す
+    pub fn purge_trash(&mut self, retention: chrono::Duration, now: DateTime<Utc>) -> usize {
+        let before = self.sessions.len();
+        self.sessions.retain(|_, session| session.deleted_at.is_none_or(|deleted_at| now - deleted_at < retention));
+        before - self.sessions.len()
+    }
+
+    /// 現在のセッションにAPI使用量を記録する
+    pub fn record_usage(&mut self, model: &str, input_tokens: u64, output_tokens: u64, estimated: bool, cost_usd: Option<f64>) -> Result<()> {
+        let session_id = self.current_session_id.ok_or_else(|| {
+            anyhow::anyhow!("No active session")
+        })?;
+        self.record_usage_for(session_id, model, input_tokens, output_tokens, estimated, cost_usd)
+    }
+
+    /// 指定したセッションにAPI使用量を記録する（リクエストを送ったセッション。表示中のセッションは関係ない）
+    pub fn record_usage_for(&mut self, session_id: Uuid, model: &str, input_tokens: u64, output_tokens: u64, estimated: bool, cost_usd: Option<f64>) -> Result<()> {
+        let session = self.sessions.get_mut(&session_id)
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        session.usage.record(model, input_tokens, output_tokens, estimated, cost_usd);
+        Ok(())
+    }
+
+    /// 全セッションから統計を集計する
+    pub fn stats(&self) -> HistoryStats {
+        let mut stats = HistoryStats {
+            sessions: self.get_session_list().len(),
+            ..HistoryStats::default()
+        };
+        let mut response_times = Vec::new();
+        for session in self.get_session_list() {
+            stats.messages += session.messages.len();
+            for (i, message) in session.messages.iter().enumerate() {
+                let text = message_text(message);
+                let chars = text.chars().count();
+                if message.is_user {
+                    stats.chars_sent += chars;
+                    stats.tokens_sent += estimate_tokens(&text);
+                    if let Some(reply) = session.messages.get(i + 1).filter(|m| !m.is_user) {
+                        response_times.push(reply.timestamp - message.timestamp);
+                    }
+                } else {
+                    stats.chars_received += chars;
+                    stats.tokens_received += estimate_tokens(&text);
+                }
+                if stats.longest_message.as_ref().is_none_or(|longest| chars > longest.chars) {
+                    stats.longest_message = Some(LongestMessage {
+                        session_title: session.title.clone(),
+                        is_user: message.is_user,
+                        chars,
+                        preview: text.chars().take(60).collect(),
+                    });
+                }
+            }
+        }
+        if !response_times.is_empty() {
+            let total: chrono::Duration = response_times.iter().sum();
+            stats.average_response_time = Some(total / response_times.len() as i32);
+        }
+        stats
+    }
+
+    /// 一覧に表示するセッション（アーカイブ済みは include_archived のときだけ含める）
+    pub fn get_visible_session_list(&self, include_archived: bool) -> Vec<&ChatSession> {
+        self.get_session_list()
+            .into_iter()
+            .filter(|s| include_archived || !s.archived)
+            .collect()
+    }
+
+    /// アーカイブ状態を切り替える（並び順を変えないようupdated_atは更新しない）
+    pub fn toggle_archived(&mut self, session_id: Uuid) -> Result<bool> {
+        let session = self.sessions.get_mut(&session_id)
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        session.archived = !session.archived;
+        Ok(session.archived)
+    }
+
+    /// 一括削除の対象となるセッションID（現在のセッションは含めない）
+    pub fn cleanup_candidates(&self, target: CleanupTarget, now: DateTime<Utc>) -> Vec<Uuid> {
+        self.get_session_list()
+            .into_iter()
+            .filter(|s| Some(s.id) != self.current_session_id)
+            .filter(|s| match target {
+                CleanupTarget::Archived => s.archived,
+                CleanupTarget::OlderThan(days) => now - s.updated_at >= chrono::Duration::days(days),
+                CleanupTarget::Empty => s.messages.is_empty(),
+            })
+            .map(|s| s.id)
+            .collect()
+    }
+
+    /// 複数のセッションを削除し、削除した数を返す（現在のセッションは削除しない）
+    pub fn delete_sessions(&mut self, session_ids: &[Uuid]) -> usize {
+        session_ids
+            .iter()
+            .filter(|id| Some(**id) != self.current_session_id)
+            .filter(|id| self.sessions.remove(id).is_some())
+            .count()
+    }
+
     pub fn delete_session(&mut self, session_id: Uuid) -> Result<()> {
         if self.sessions.remove(&session_id).is_some() {
             if self.current_session_id == Some(session_id) {
@@ -129,16 +803,51 @@ impl ChatHistory {
 pub struct HistoryManager {
     history: ChatHistory,
     file_path: PathBuf,
+    /// 履歴ファイルに書けているメッセージ
+    saved_ids: HashSet<Uuid>,
+    /// 保存に失敗して、まだ履歴ファイルに書けていないメッセージ
+    pending_writes: Vec<PendingWrite>,
+    /// 次に保存を試みる時刻（失敗が続くほど間を空ける）
+    retry_at: Option<Instant>,
+}
+
+/// 保存に失敗したメッセージと、保存を試みた回数
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingWrite {
+    pub session_id: Uuid,
+    pub message_id: Uuid,
+    pub attempts: u32,
+    pub last_error: String,
+}
+
+/// 保存に失敗したときに次に試みるまでの間隔（失敗するたびに倍にする）
+const SAVE_RETRY_BASE: Duration = Duration::from_secs(1);
+/// 保存を試みる間隔の上限
+const SAVE_RETRY_MAX: Duration = Duration::from_secs(60);
+/// 終了時にも保存できなかったメッセージの書き出し先
+pub const DEAD_LETTER_FILE: &str = "unsaved_messages.json";
+
+/// attempts回失敗した後、次に保存を試みるまでの間隔
+fn save_retry_delay(attempts: u32) -> Duration {
+    SAVE_RETRY_BASE.saturating_mul(1 << attempts.saturating_sub(1).min(6)).min(SAVE_RETRY_MAX)
+}
+
+/// 履歴ファイルを保存するディレクトリ（<data_dir>/contui）
+pub fn history_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("contui"))
 }
 
 impl HistoryManager {
     pub fn new() -> Result<Self> {
-        let mut file_path = dirs::data_dir()
+        let mut file_path = history_dir()
             .ok_or_else(|| anyhow::anyhow!("Cannot find data directory"))?;
-        file_path.push("contui");
         fs::create_dir_all(&file_path)?;
         file_path.push("chat_history.json");
+        Self::with_file_path(file_path)
+    }
 
+    /// 指定した履歴ファイルを使うHistoryManagerを作成
+    pub fn with_file_path(file_path: PathBuf) -> Result<Self> {
         let history = if file_path.exists() {
             let content = fs::read_to_string(&file_path)?;
             serde_json::from_str(&content).unwrap_or_else(|_| ChatHistory::new())
@@ -146,9 +855,13 @@ impl HistoryManager {
             ChatHistory::new()
         };
 
+        let saved_ids = history.sessions.values().flat_map(|session| session.messages.iter().map(|message| message.id)).collect();
         Ok(Self {
             history,
             file_path,
+            saved_ids,
+            pending_writes: Vec::new(),
+            retry_at: None,
         })
     }
 
@@ -159,10 +872,122 @@ impl HistoryManager {
         Ok(())
     }
 
-    pub fn save(&self) -> Result<()> {
-        let content = serde_json::to_string_pretty(&self.history)?;
-        fs::write(&self.file_path, content)?;
-        Ok(())
+    /// 履歴ファイルに保存する。前回失敗して次に試みる時刻より前なら、書かずに未保存のメッセージに加える
+    pub fn save(&mut self) -> Result<()> {
+        if let Some(pending) = self.pending_writes.first().filter(|_| self.retry_at.is_some_and(|at| Instant::now() < at)) {
+            let error = pending.last_error.clone();
+            self.queue_unsaved(&error, false);
+            return Err(anyhow::anyhow!("History is not saved yet (waiting to retry after a disk error): {}", error));
+        }
+        self.flush()
+    }
+
+    /// 待たずに保存を試みる（/flush）。失敗したら未保存のメッセージとして覚えておく
+    pub fn flush(&mut self) -> Result<()> {
+        let result = serde_json::to_string_pretty(&self.history)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| fs::write(&self.file_path, content).map_err(anyhow::Error::from));
+        match result {
+            Ok(()) => {
+                self.saved_ids = self.message_ids().map(|(_, id)| id).collect();
+                self.pending_writes.clear();
+                self.retry_at = None;
+                Ok(())
+            }
+            Err(e) => {
+                self.queue_unsaved(&format!("{:#}", e), true);
+                let attempts = self.pending_writes.iter().map(|pending| pending.attempts).max().unwrap_or(1);
+                self.retry_at = Some(Instant::now() + save_retry_delay(attempts));
+                Err(e)
+            }
+        }
+    }
+
+    fn message_ids(&self) -> impl Iterator<Item = (Uuid, Uuid)> + '_ {
+        self.history.sessions.values().flat_map(|session| session.messages.iter().map(|message| (session.id, message.id)))
+    }
+
+    /// 履歴ファイルに書けていないメッセージを未保存のメッセージに加える（attemptなら試みた回数も数える）
+    fn queue_unsaved(&mut self, error: &str, attempt: bool) {
+        let unsaved: Vec<(Uuid, Uuid)> = self.message_ids().filter(|(_, id)| !self.saved_ids.contains(id)).collect();
+        self.pending_writes.retain(|pending| unsaved.iter().any(|(_, id)| *id == pending.message_id));
+        for (session_id, message_id) in unsaved {
+            match self.pending_writes.iter_mut().find(|pending| pending.message_id == message_id) {
+                Some(pending) => {
+                    pending.attempts += u32::from(attempt);
+                    pending.last_error = error.to_string();
+                }
+                None => self.pending_writes.push(PendingWrite { session_id, message_id, attempts: 1, last_error: error.to_string() }),
+            }
+        }
+    }
+
+    pub fn pending_writes(&self) -> &[PendingWrite] {
+        &self.pending_writes
+    }
+
+    /// 未保存のメッセージがあり、次に保存を試みる時刻になった
+    pub fn retry_due(&self, now: Instant) -> bool {
+        !self.pending_writes.is_empty() && self.retry_at.is_none_or(|at| now >= at)
+    }
+
+    /// 未保存のメッセージがある間表示する警告
+    pub fn pending_write_warning(&self) -> Option<String> {
+        let pending = self.pending_writes.last()?;
+        let count = self.pending_writes.len();
+        let noun = if count == 1 { "message" } else { "messages" };
+        Some(format!("⚠️ {} {} not yet saved — history disk error: {} (/flush to retry)", count, noun, pending.last_error))
+    }
+
+    /// 終了時に呼ぶ。未保存のメッセージがあればもう一度保存を試み、それでも失敗したら
+    /// 履歴ファイルの隣（書けなければ一時ディレクトリ）のunsaved_messages.jsonに追記して、その場所を返す
+    pub fn flush_on_exit(&mut self) -> Result<Option<PathBuf>> {
+        if self.pending_writes.is_empty() || self.flush().is_ok() {
+            return Ok(None);
+        }
+        let entries: Vec<serde_json::Value> = self
+            .pending_writes
+            .iter()
+            .filter_map(|pending| {
+                let session = self.history.sessions.get(&pending.session_id)?;
+                let message = session.messages.iter().find(|message| message.id == pending.message_id)?;
+                Some(serde_json::json!({
+                    "session_id": pending.session_id,
+                    "session_title": session.title,
+                    "attempts": pending.attempts,
+                    "last_error": pending.last_error,
+                    "message": message,
+                }))
+            })
+            .collect();
+        let mut last_error = None;
+        for path in [self.file_path.with_file_name(DEAD_LETTER_FILE), std::env::temp_dir().join(DEAD_LETTER_FILE)] {
+            // 前の実行で書き出したものは残して追記する
+            let mut all: Vec<serde_json::Value> = fs::read_to_string(&path).ok().and_then(|content| serde_json::from_str(&content).ok()).unwrap_or_default();
+            all.extend(entries.iter().cloned());
+            match fs::write(&path, serde_json::to_string_pretty(&all)?) {
+                Ok(()) => return Ok(Some(path)),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(anyhow::anyhow!("Failed to write {}: {:?}", DEAD_LETTER_FILE, last_error))
+    }
+
+    /// 全セッションを保存形式と同じJSONで書き出す（セッション数とメッセージ数を返す）
+    /// with_stepsでなければエージェントループの途中のステップは含めない
+    pub fn export_all(&self, path: &Path, with_steps: bool) -> Result<(usize, usize)> {
+        let content = if with_steps {
+            serde_json::to_string_pretty(&self.history)?
+        } else {
+            let mut history = self.history.clone();
+            for message in history.sessions.values_mut().flat_map(|s| s.messages.iter_mut()) {
+                message.steps.clear();
+            }
+            serde_json::to_string_pretty(&history)?
+        };
+        fs::write(path, content)?;
+        let message_count = self.history.sessions.values().map(|s| s.messages.len()).sum();
+        Ok((self.history.sessions.len(), message_count))
     }
 
     pub fn get_history(&self) -> &ChatHistory {
@@ -175,7 +1000,7 @@ impl HistoryManager {
 
     pub fn ensure_active_session(&mut self) -> Uuid {
         if let Some(session_id) = self.history.current_session_id {
-            if self.history.sessions.contains_key(&session_id) {
+            if self.history.sessions.get(&session_id).is_some_and(|session| session.deleted_at.is_none()) {
                 return session_id;
             }
         }
@@ -184,32 +1009,427 @@ impl HistoryManager {
     }
 
     pub fn get_conversation_context(&self, max_messages: usize) -> Vec<Content> {
-        if let Some(session) = self.history.get_current_session() {
-            let start_index = session.messages.len().saturating_sub(max_messages);
-            
-            session.messages[start_index..].iter().map(|msg| {
-                let actual_role = if msg.is_user {
-                    "user".to_string()
-                } else {
-                    let has_function_call = msg.parts.iter().any(|p| matches!(p, crate::gemini::Part::FunctionCall { .. }));
-                    let has_function_response = msg.parts.iter().any(|p| matches!(p, crate::gemini::Part::FunctionResponse { .. }));
-
-                    if has_function_call {
-                        "model".to_string()
-                    } else if has_function_response {
-                        "function".to_string()
-                    } else {
-                        "model".to_string()
-                    }
-                };
+        match self.history.current_session_id {
+            Some(session_id) => self.get_conversation_context_for(session_id, max_messages),
+            None => Vec::new(),
+        }
+    }
 
-                Content {
-                    role: actual_role,
-                    parts: msg.parts.clone(),
-                }
-            }).collect()
+    /// 指定したセッションの会話コンテキスト（応答待ちの間に表示中のセッションが変わっても送信元の会話を使う）
+    pub fn get_conversation_context_for(&self, session_id: Uuid, max_messages: usize) -> Vec<Content> {
+        match self.history.sessions.get(&session_id) {
+            Some(session) => conversation_context(session, session.messages.len().saturating_sub(max_messages)),
+            None => Vec::new(),
+        }
+    }
+
+    /// 現在のセッションの会話コンテキスト（見積もりトークン数の合計がmax_tokensに収まるところまで）
+    pub fn get_conversation_context_with_budget(&self, max_tokens: usize) -> Vec<Content> {
+        match self.history.current_session_id {
+            Some(session_id) => self.get_conversation_context_with_budget_for(session_id, max_tokens),
+            None => Vec::new(),
+        }
+    }
+
+    /// 新しいメッセージから順に見積もりトークン数を足し、max_tokensを超えるメッセージの手前で止める（要約も予算に含める）
+    pub fn get_conversation_context_with_budget_for(&self, session_id: Uuid, max_tokens: usize) -> Vec<Content> {
+        let Some(session) = self.history.sessions.get(&session_id) else {
+            return Vec::new();
+        };
+        let first = session.summary.as_ref().map_or(0, |summary| summary.last_index + 1).min(session.messages.len());
+        let mut remaining = max_tokens.saturating_sub(session.summary.as_ref().map_or(0, |summary| estimate_tokens(&summary.text)));
+        let mut start_index = session.messages.len();
+        while start_index > first {
+            let message = &session.messages[start_index - 1];
+            let tokens = if message.excluded { 0 } else { message_tokens(message) };
+            if tokens > remaining {
+                break;
+            }
+            remaining -= tokens;
+            start_index -= 1;
+        }
+        conversation_context(session, start_index)
+    }
+}
+
+/// start_index以降のメッセージの会話コンテキスト（要約済みのメッセージは要約に置き換えて先頭に付ける）
+fn conversation_context(session: &ChatSession, mut start_index: usize) -> Vec<Content> {
+    let mut contents = Vec::new();
+    if let Some(summary) = &session.summary {
+        start_index = start_index.max(summary.last_index + 1).min(session.messages.len());
+        contents.push(Content {
+            role: "user".to_string(),
+            parts: vec![crate::gemini::Part::Text {
+                text: format!("Conversation summary so far:\n{}", summary.text),
+            }],
+        });
+    }
+
+    contents.extend(session.messages[start_index..].iter().filter(|msg| !msg.is_system && !msg.excluded).map(|msg| {
+        let actual_role = if msg.is_user {
+            "user".to_string()
         } else {
-            Vec::new()
+            let has_function_call = msg.parts.iter().any(|p| matches!(p, crate::gemini::Part::FunctionCall { .. }));
+            let has_function_response = msg.parts.iter().any(|p| matches!(p, crate::gemini::Part::FunctionResponse { .. }));
+
+            if has_function_call {
+                "model".to_string()
+            } else if has_function_response {
+                "function".to_string()
+            } else {
+                "model".to_string()
+            }
+        };
+
+        Content {
+            role: actual_role,
+            parts: msg.parts.iter().map(crate::gemini::Part::to_request_part).collect(),
+        }
+    }));
+    contents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history_with_messages(count: usize) -> ChatHistory {
+        let mut history = ChatHistory::new();
+        history.new_session(None);
+        for i in 0..count {
+            history.add_message(vec![Part::Text { text: format!("msg {}", i) }], i % 2 == 0).unwrap();
+        }
+        history
+    }
+
+    #[test]
+    fn test_compaction_request_is_incremental() {
+        let mut history = history_with_messages(15);
+        assert!(history.compaction_request(20).is_none());
+
+        let request = history.compaction_request(10).unwrap();
+        assert_eq!(request.messages.len(), 5);
+        assert_eq!(request.last_index, 4);
+        assert!(request.previous_summary.is_none());
+
+        history.set_summary(request.session_id, "summary".to_string(), request.last_index).unwrap();
+        assert!(history.compaction_request(10).is_none());
+
+        history.add_message(vec![Part::Text { text: "new".to_string() }], true).unwrap();
+        let request = history.compaction_request(10).unwrap();
+        assert_eq!(request.messages.len(), 1);
+        assert_eq!(request.last_index, 5);
+        assert_eq!(request.previous_summary.as_deref(), Some("summary"));
+    }
+
+    #[test]
+    fn test_truncate_after_returns_tail_and_resets_summary() {
+        let mut history = history_with_messages(6);
+        let session_id = history.current_session_id.unwrap();
+        history.set_summary(session_id, "summary".to_string(), 3).unwrap();
+        let keep_id = history.get_current_session().unwrap().messages[1].id;
+
+        let tail = history.truncate_after(Some(keep_id)).unwrap();
+        assert_eq!(tail.len(), 4);
+        assert_eq!(history.get_current_session().unwrap().messages.len(), 2);
+        assert!(history.get_current_session().unwrap().summary.is_none());
+
+        history.archive_branch(tail).unwrap();
+        assert_eq!(history.get_current_session().unwrap().archived_branches[0].messages.len(), 4);
+
+        let tail = history.truncate_after(None).unwrap();
+        assert_eq!(tail.len(), 2);
+        assert!(history.get_current_session().unwrap().messages.is_empty());
+    }
+
+    #[test]
+    fn test_context_prepends_summary_and_skips_summarized_messages() {
+        let mut history = history_with_messages(6);
+        let session_id = history.current_session_id.unwrap();
+        history.set_summary(session_id, "earlier".to_string(), 3).unwrap();
+        let manager = HistoryManager {
+            history,
+            file_path: PathBuf::new(),
+            saved_ids: HashSet::new(),
+            pending_writes: Vec::new(),
+            retry_at: None,
+        };
+
+        let context = manager.get_conversation_context(10);
+        assert_eq!(context.len(), 3);
+        match &context[0].parts[0] {
+            Part::Text { text } => assert_eq!(text, "Conversation summary so far:\nearlier"),
+            _ => panic!("summary should be a text part"),
+        }
+        match &context[1].parts[0] {
+            Part::Text { text } => assert_eq!(text, "msg 4"),
+            _ => panic!("expected text part"),
+        }
+    }
+
+    #[test]
+    fn test_context_with_token_budget() {
+        let mut history = history_with_messages(4);
+        let session_id = history.current_session_id.unwrap();
+        history.add_message(vec![Part::Text { text: "x".repeat(400) }], true).unwrap();
+        history.add_message(vec![Part::Text { text: "short".to_string() }], false).unwrap();
+        let manager = HistoryManager {
+            history,
+            file_path: PathBuf::new(),
+            saved_ids: HashSet::new(),
+            pending_writes: Vec::new(),
+            retry_at: None,
+        };
+
+        // "short"は2トークン、400文字のメッセージは100トークン
+        assert_eq!(manager.get_conversation_context_with_budget(101).len(), 1);
+        assert_eq!(manager.get_conversation_context_with_budget(102).len(), 2);
+        // 古いメッセージ（"msg N"は各2トークン）は予算が残っている分だけ含める
+        assert_eq!(manager.get_conversation_context_with_budget(106).len(), 4);
+        assert_eq!(manager.get_conversation_context_with_budget_for(session_id, 10_000).len(), 6);
+        assert!(manager.get_conversation_context_with_budget(1).is_empty());
+    }
+
+    #[test]
+    fn test_trim_session_keeps_summary() {
+        let mut history = history_with_messages(10);
+        let session_id = history.current_session_id.unwrap();
+        assert_eq!(history.trim_session(session_id, 10).unwrap(), 0);
+
+        // 要約した範囲の中で削除するなら、要約のインデックスをずらす
+        history.set_summary(session_id, "earlier work".to_string(), 5).unwrap();
+        assert_eq!(history.trim_session(session_id, 8).unwrap(), 2);
+        let session = &history.sessions[&session_id];
+        assert_eq!(session.summary.as_ref().unwrap().last_index, 3);
+        assert!(matches!(&session.messages[0].parts[..], [Part::Text { text }] if text == "msg 2"));
+
+        // 要約した範囲を超えるなら、要約を先頭のメッセージにして残す
+        assert_eq!(history.trim_session(session_id, 3).unwrap(), 6);
+        let session = &history.sessions[&session_id];
+        assert!(session.summary.is_none());
+        assert_eq!(session.messages.len(), 3);
+        assert!(matches!(&session.messages[0].parts[..], [Part::Text { text }] if text == "Conversation summary so far:\nearlier work"));
+        assert!(matches!(&session.messages[1].parts[..], [Part::Text { text }] if text == "msg 8"));
+
+        // 上限が1件でも最新のメッセージは残す
+        history.set_summary(session_id, "more work".to_string(), 1).unwrap();
+        assert_eq!(history.trim_session(session_id, 1).unwrap(), 2);
+        let session = &history.sessions[&session_id];
+        assert_eq!(session.messages.len(), 2);
+        assert!(matches!(&session.messages[0].parts[..], [Part::Text { text }] if text == "Conversation summary so far:\nmore work"));
+        assert!(matches!(&session.messages[1].parts[..], [Part::Text { text }] if text == "msg 9"));
+    }
+
+    #[test]
+    fn test_fork_session_at() {
+        let mut history = history_with_messages(4);
+        let original_id = history.current_session_id.unwrap();
+        let at = history.get_current_session().unwrap().messages[1].id;
+
+        let fork_id = history.fork_session_at(at).unwrap();
+        assert_eq!(history.current_session_id, Some(fork_id));
+        let fork = history.get_current_session().unwrap();
+        assert_eq!(fork.title, format!("Fork of {}", history.sessions[&original_id].title));
+        assert_eq!(fork.messages.len(), 2);
+        assert!(fork.messages.iter().all(|m| m.id != at));
+        match &fork.messages[1].parts[0] {
+            Part::Text { text } => assert_eq!(text, "msg 1"),
+            _ => panic!("expected text part"),
+        }
+        // 元のセッションはそのまま残る
+        assert_eq!(history.sessions[&original_id].messages.len(), 4);
+        assert!(history.fork_session_at(Uuid::new_v4()).is_err());
+    }
+
+    #[test]
+    fn test_truncate_before_last() {
+        let mut history = history_with_messages(5);
+        assert_eq!(history.truncate_before_last(10).unwrap(), 0);
+        assert_eq!(history.truncate_before_last(2).unwrap(), 3);
+        let session = history.get_current_session().unwrap();
+        assert_eq!(session.messages.len(), 2);
+        match &session.messages[0].parts[0] {
+            Part::Text { text } => assert_eq!(text, "msg 3"),
+            _ => panic!("expected text part"),
+        }
+        assert_eq!(history.truncate_before_last(0).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_export_all_round_trips() {
+        let dir = std::env::temp_dir();
+        let mut manager = HistoryManager::with_file_path(dir.join(format!("contui_test_{}.json", Uuid::new_v4()))).unwrap();
+        manager.history = history_with_messages(3);
+        let second = manager.history.new_session(Some("second".to_string()));
+        manager.history.add_message(vec![Part::Text { text: "hi".to_string() }], true).unwrap();
+        manager.history.add_message(vec![Part::Text { text: "done".to_string() }], false).unwrap();
+        let steps = vec![StepRecord {
+            prompt_excerpt: "hi".to_string(),
+            response: "looking".to_string(),
+            duration: std::time::Duration::from_millis(1200),
+        }];
+        manager.history.attach_steps(second, steps.clone()).unwrap();
+        let last_steps = |history: &ChatHistory| history.get_current_session().unwrap().messages.last().unwrap().steps.clone();
+
+        let archive_path = dir.join(format!("contui_archive_test_{}.json", Uuid::new_v4()));
+        assert_eq!(manager.export_all(&archive_path, false).unwrap(), (2, 5));
+
+        let restored: ChatHistory = serde_json::from_str(&fs::read_to_string(&archive_path).unwrap()).unwrap();
+        assert_eq!(restored.sessions.len(), 2);
+        assert_eq!(restored.current_session_id, manager.history.current_session_id);
+        assert!(last_steps(&restored).is_empty());
+
+        manager.export_all(&archive_path, true).unwrap();
+        let restored: ChatHistory = serde_json::from_str(&fs::read_to_string(&archive_path).unwrap()).unwrap();
+        fs::remove_file(&archive_path).unwrap();
+        assert_eq!(last_steps(&restored), steps);
+    }
+
+    #[test]
+    fn test_session_csv_escapes_content() {
+        let mut history = ChatHistory::new();
+        history.new_session(None);
+        history.add_message(vec![Part::Text { text: "say \"hi\",\nthen stop".to_string() }], true).unwrap();
+        history.add_message(vec![Part::Text { text: "ok".to_string() }], false).unwrap();
+        let session = history.get_current_session().unwrap();
+        let csv = session_csv(session);
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(lines[0], "\u{feff}id,timestamp,role,content");
+        let first = &session.messages[0];
+        assert_eq!(lines[1], format!("{},{},user,\"say \"\"hi\"\",\\nthen stop\"", first.id, first.timestamp.to_rfc3339()));
+        assert!(lines[2].ends_with(",assistant,\"ok\""));
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn test_user_prompts_in_order() {
+        let mut history = ChatHistory::new();
+        history.new_session(None);
+        history.add_message(vec![Part::Text { text: "first".to_string() }], true).unwrap();
+        history.add_message(vec![Part::Text { text: "answer".to_string() }], false).unwrap();
+        history.add_message(vec![Part::Text { text: "second".to_string() }], true).unwrap();
+        history.add_message(vec![Part::Text { text: " ".to_string() }], true).unwrap();
+        assert_eq!(user_prompts(history.get_current_session().unwrap()), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn test_failed_saves_are_queued_retried_and_dead_lettered() {
+        // rootでも書き込めないよう、ディレクトリの代わりにファイルを置いて保存を失敗させる
+        let dir = std::env::temp_dir().join(format!("contui_pending_{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let blocked = dir.join("history");
+        fs::write(&blocked, "").unwrap();
+        let mut manager = HistoryManager::with_file_path(blocked.join("chat_history.json")).unwrap();
+        manager.history = history_with_messages(3);
+        assert!(manager.save().is_err());
+        assert_eq!(manager.pending_writes().len(), 3);
+        assert!(manager.pending_write_warning().unwrap().starts_with("⚠️ 3 messages not yet saved — history disk error: "));
+        assert!(!manager.retry_due(Instant::now()));
+        assert!(manager.retry_due(Instant::now() + save_retry_delay(1)));
+
+        // 待っている間の保存は書かずに未保存に加える。flushはすぐに試みて回数を数える
+        manager.history.add_message(vec![Part::Text { text: "more".to_string() }], true).unwrap();
+        assert!(manager.save().is_err());
+        assert_eq!(manager.pending_writes().len(), 4);
+        assert_eq!(manager.pending_writes()[0].attempts, 1);
+        assert!(manager.flush().is_err());
+        assert_eq!((manager.pending_writes()[0].attempts, manager.pending_writes()[3].attempts), (2, 2));
+        assert_eq!(save_retry_delay(2), Duration::from_secs(2));
+        assert_eq!(save_retry_delay(30), SAVE_RETRY_MAX);
+
+        // 終了時にも書けなければunsaved_messages.jsonに書き出す
+        let dead_letter = manager.flush_on_exit().unwrap().unwrap();
+        let entries: Vec<serde_json::Value> = serde_json::from_str(&fs::read_to_string(&dead_letter).unwrap()).unwrap();
+        assert!(entries.iter().any(|entry| entry["message"]["parts"][0]["text"] == "more"));
+        fs::remove_file(&dead_letter).unwrap();
+
+        // 書けるようになったら未保存のメッセージはなくなる
+        fs::remove_file(&blocked).unwrap();
+        fs::create_dir_all(&blocked).unwrap();
+        manager.flush().unwrap();
+        assert!(manager.pending_writes().is_empty());
+        assert_eq!(manager.pending_write_warning(), None);
+        assert_eq!(manager.flush_on_exit().unwrap(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_trashed_session_survives_restart_until_purged() {
+        let path = std::env::temp_dir().join(format!("contui_test_{}.json", Uuid::new_v4()));
+        let mut manager = HistoryManager::with_file_path(path.clone()).unwrap();
+        manager.history = history_with_messages(2);
+        let trashed = manager.history.current_session_id.unwrap();
+        let deleted_at = Utc::now();
+        manager.history.trash_session(trashed, deleted_at).unwrap();
+        manager.history.new_session(None);
+        manager.save().unwrap();
+
+        let mut restarted = HistoryManager::with_file_path(path.clone()).unwrap();
+        assert_eq!(restarted.history.get_session_list().len(), 1);
+        assert_eq!(restarted.history.trashed_sessions()[0].id, trashed);
+        assert!(restarted.history.switch_session(trashed).is_err());
+        let retention = chrono::Duration::days(7);
+        assert_eq!(restarted.history.purge_trash(retention, deleted_at + chrono::Duration::days(6)), 0);
+        assert_eq!(restarted.history.purge_trash(retention, deleted_at + chrono::Duration::days(8)), 1);
+        assert!(restarted.history.trashed_sessions().is_empty());
+        assert_eq!(restarted.history.sessions.len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_cleanup_never_deletes_current_session() {
+        let mut history = ChatHistory::new();
+        let empty_id = history.new_session(Some("empty".to_string()));
+        let old_id = history.new_session(Some("old".to_string()));
+        history.add_message(vec![Part::Text { text: "hi".to_string() }], true).unwrap();
+        history.sessions.get_mut(&old_id).unwrap().updated_at = Utc::now() - chrono::Duration::days(40);
+        history.toggle_archived(old_id).unwrap();
+        let current_id = history.new_session(Some("current".to_string()));
+        history.toggle_archived(current_id).unwrap();
+
+        let now = Utc::now();
+        assert_eq!(history.cleanup_candidates(CleanupTarget::Archived, now), vec![old_id]);
+        assert_eq!(history.cleanup_candidates(CleanupTarget::OlderThan(30), now), vec![old_id]);
+        assert_eq!(history.cleanup_candidates(CleanupTarget::Empty, now), vec![empty_id]);
+        assert_eq!(history.get_visible_session_list(false).len(), 1);
+
+        assert_eq!(history.delete_sessions(&[empty_id, current_id]), 1);
+        assert!(history.sessions.contains_key(&current_id));
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut history = history_with_messages(4);
+        let session_id = history.current_session_id.unwrap();
+        let start = Utc::now();
+        for (i, message) in history.sessions.get_mut(&session_id).unwrap().messages.iter_mut().enumerate() {
+            message.timestamp = start + chrono::Duration::seconds(i as i64 * 3);
         }
+        history.add_message(vec![Part::Text { text: "こんにちは".to_string() }], false).unwrap();
+
+        let stats = history.stats();
+        assert_eq!(stats.sessions, 1);
+        assert_eq!(stats.messages, 5);
+        assert_eq!(stats.chars_sent, 10);
+        assert_eq!(stats.chars_received, 15);
+        assert_eq!(stats.tokens_received, 2 + 2 + 5);
+        assert_eq!(stats.average_response_time, Some(chrono::Duration::seconds(3)));
+        assert_eq!(stats.longest_message.unwrap().preview, "msg 0");
+        assert_eq!(estimate_tokens("abcdefgh"), 2);
+    }
+
+    #[test]
+    fn test_session_usage_cost() {
+        let mut usage = SessionUsage::default();
+        assert_eq!(usage.total_cost(), None);
+
+        usage.record("priced", 1000, 500, false, Some(0.0123456));
+        usage.record("priced", 10, 5, true, Some(0.0000001));
+        usage.record("unknown", 100, 100, false, None);
+        assert!((usage.total_cost().unwrap() - 0.0123457).abs() < 1e-12);
+        assert!(usage.has_unpriced());
+        assert!(usage.has_estimates());
+        assert_eq!(usage.models["priced"].requests, 2);
+        assert_eq!(usage.models["unknown"].cost_usd, None);
     }
 }
@@ -0,0 +1,172 @@
+//! ツールの実行前後と応答の後に、設定したシェルコマンド（フック）を実行する
+//! フックにはJSONを標準入力で渡す。フックの失敗は警告にして、アプリは止めない
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use crate::config::ShellKind;
+use crate::debug_log;
+
+/// フックのタイムアウトの既定値（秒）
+const DEFAULT_HOOK_TIMEOUT_SECS: u64 = 30;
+
+/// /hooks で表示する、フックが標準入力で受け取るJSON
+pub const HOOK_SCHEMAS: &[(&str, &str)] = &[
+    ("pre_tool", r#"{"event": "pre_tool", "tool": "<name>", "args": {...}}"#),
+    ("post_tool", r#"{"event": "post_tool", "tool": "<name>", "args": {...}, "result": {"success": bool, "detail": "...", "response": {...}}}"#),
+    ("post_response", r#"{"event": "post_response", "session_id": "<uuid>", "text": "<response text>"}"#),
+];
+
+/// 1つのフック。commandの {tool} はツール名をシェル用にエスケープして置き換える
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Hook {
+    pub command: String,
+    /// 実行するディレクトリ（なければcontuiを起動したディレクトリ）
+    #[serde(default)]
+    pub working_dir: Option<PathBuf>,
+    #[serde(default = "default_hook_timeout")]
+    pub timeout_secs: u64,
+}
+
+/// hooks.tomlの [hooks]
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct Hooks {
+    /// ツールの実行前（0以外で終了したらツールを実行せず、モデルに拒否を返す）
+    #[serde(default)]
+    pub pre_tool: Option<Hook>,
+    /// ツールの実行後
+    #[serde(default)]
+    pub post_tool: Option<Hook>,
+    /// エージェントループの応答の後（非同期に実行する）
+    #[serde(default)]
+    pub post_response: Option<Hook>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HooksFile {
+    #[serde(default)]
+    hooks: Hooks,
+}
+
+fn default_hook_timeout() -> u64 {
+    DEFAULT_HOOK_TIMEOUT_SECS
+}
+
+/// フックの実行結果
+#[derive(Debug, Clone)]
+pub struct HookOutput {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl HookOutput {
+    /// 拒否や警告に添える出力（stderrがなければstdout）
+    pub fn message(&self) -> &str {
+        let stderr = self.stderr.trim();
+        if stderr.is_empty() { self.stdout.trim() } else { stderr }
+    }
+}
+
+/// フック定義ファイルの既定の場所（<config_dir>/contui/hooks.toml）
+pub fn default_hooks_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("contui").join("hooks.toml"))
+}
+
+/// hooks.tomlの [hooks] を読み込む（ファイルがなければフックなし）
+pub fn load_hooks(path: &Path) -> Result<Hooks> {
+    if !path.exists() {
+        return Ok(Hooks::default());
+    }
+    let content = fs::read_to_string(path)?;
+    let file: HooksFile = toml::from_str(&content).with_context(|| format!("Invalid hooks file: {}", path.display()))?;
+    Ok(file.hooks)
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.entries().all(|(_, hook)| hook.is_none())
+    }
+
+    /// (イベント名, フック) の一覧
+    pub fn entries(&self) -> impl Iterator<Item = (&'static str, Option<&Hook>)> {
+        [("pre_tool", self.pre_tool.as_ref()), ("post_tool", self.post_tool.as_ref()), ("post_response", self.post_response.as_ref())].into_iter()
+    }
+}
+
+impl Hook {
+    /// inputをJSONで標準入力に渡してフックを実行する。標準出力はデバッグログに書く
+    /// 起動できない・タイムアウトしたときはErr（0以外の終了はOkでsuccess=false）
+    pub async fn run(&self, shell: ShellKind, input: &Value) -> Result<HookOutput> {
+        use tokio::io::AsyncWriteExt;
+        use tokio::process::Command;
+
+        let tool = input["tool"].as_str().unwrap_or_default();
+        let command = self.command.replace("{tool}", &shell.quote(tool));
+        let (program, args) = shell.program_and_args(&command);
+        let mut process = Command::new(program);
+        process.args(&args).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).kill_on_drop(true);
+        if let Some(dir) = &self.working_dir {
+            process.current_dir(dir);
+        }
+        let mut child = process.spawn().with_context(|| format!("failed to start hook: {}", command))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // 入力を読まないフックでも止まらないよう、書き込みは別タスクで行いタイムアウトの対象にする
+            // （タイムアウトでプロセスが終わればパイプが閉じてタスクも終わる。書き込みの失敗は無視する）
+            let input = input.to_string();
+            tokio::spawn(async move {
+                let _ = stdin.write_all(input.as_bytes()).await;
+            });
+        }
+        let output = tokio::time::timeout(Duration::from_secs(self.timeout_secs), child.wait_with_output())
+            .await
+            .map_err(|_| anyhow::anyhow!("hook timed out after {}s: {}", self.timeout_secs, command))??;
+
+        let output = HookOutput {
+            success: output.status.success(),
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        };
+        debug_log!("[hook] {} ({}) exit={}: {}", input["event"], command, output.success, output.stdout);
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_hooks_section() {
+        let dir = crate::test_common::TempDir::new("hooks");
+        let path = dir.join("hooks.toml");
+        assert!(load_hooks(&path).unwrap().is_empty());
+
+        std::fs::write(&path, "[hooks]\npost_tool = { command = \"cargo fmt\", working_dir = \"/tmp\" }\npre_tool = { command = \"./check {tool}\", timeout_secs = 5 }\n").unwrap();
+        let hooks = load_hooks(&path).unwrap();
+        assert_eq!(hooks.pre_tool.as_ref().map(|hook| hook.timeout_secs), Some(5));
+        assert_eq!(hooks.post_tool.as_ref().map(|hook| hook.timeout_secs), Some(DEFAULT_HOOK_TIMEOUT_SECS));
+        assert_eq!(hooks.post_tool.unwrap().working_dir, Some(PathBuf::from("/tmp")));
+        assert!(hooks.post_response.is_none());
+
+        std::fs::write(&path, "[hooks]\npre_tool = \"not a table\"\n").unwrap();
+        assert!(load_hooks(&path).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_hook_that_ignores_stdin_still_times_out() {
+        let hook = Hook { command: "sleep 30".to_string(), working_dir: None, timeout_secs: 1 };
+        // パイプのバッファに収まらない大きさの入力
+        let input = serde_json::json!({"event": "pre_tool", "tool": "create_file", "args": {"content": "x".repeat(1 << 20)}});
+        let started = std::time::Instant::now();
+        let result = hook.run(ShellKind::Sh, &input).await;
+        assert!(result.unwrap_err().to_string().contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,527 @@
+use std::fmt;
+
+/// 応答・表示に使う言語
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Language {
+    #[default]
+    En,
+    Ja,
+}
+
+impl Language {
+    /// "en" / "ja" を解釈する（"auto" や未設定はロケールに従う）
+    pub fn from_setting(value: Option<&str>) -> Option<Self> {
+        match value.map(|value| value.trim().to_ascii_lowercase()).as_deref() {
+            None | Some("") | Some("auto") => Some(Self::from_environment()),
+            Some("en") => Some(Language::En),
+            Some("ja") => Some(Language::Ja),
+            _ => None,
+        }
+    }
+
+    /// LC_ALL → LC_MESSAGES → LANG の順にロケールを見る
+    pub fn from_environment() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .map(|locale| Self::from_locale(&locale))
+            .unwrap_or_default()
+    }
+
+    /// ja_JP.UTF-8 などのロケール名から言語を決める（日本語以外は英語）
+    pub fn from_locale(locale: &str) -> Self {
+        if locale.to_ascii_lowercase().starts_with("ja") {
+            Language::Ja
+        } else {
+            Language::En
+        }
+    }
+
+    /// システムプロンプトで指示する言語名
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::En => "English",
+            Language::Ja => "Japanese",
+        }
+    }
+
+    fn table(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Language::En => EN,
+            Language::Ja => JA,
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Language::En => write!(f, "en"),
+            Language::Ja => write!(f, "ja"),
+        }
+    }
+}
+
+/// キーに対応する文字列（翻訳がなければ英語、英語にもなければキーそのもの）
+pub fn tr(language: Language, key: &'static str) -> &'static str {
+    let lookup = |table: &'static [(&'static str, &'static str)]| {
+        table.iter().find(|(entry, _)| *entry == key).map(|(_, text)| *text)
+    };
+    lookup(language.table()).or_else(|| lookup(EN)).unwrap_or(key)
+}
+
+const EN: &[(&str, &str)] = &[
+    ("system_prompt", r#"You are an AI assistant that can create files, edit parts of files and run commands.
+
+Depending on the user's request, you can:
+
+1. **Create files**: create new files
+2. **Edit files**: edit parts of existing files
+3. **Run commands**: run shell commands
+4. **Manage directories**: create directories (and delete them when allowed)
+
+These are performed through Function Calling. Call the appropriate functions as needed.
+
+---
+[IMPORTANT] Always end every reply with the flag is_finished: true or is_finished: false to show whether the task is finished (JSON or a form like "is_finished: true" is fine).
+When is_finished is false, call the appropriate functions to complete the work. If no suitable function exists or something failed, return true."#),
+    ("respond_in", "Respond in {language}."),
+    ("agent.suffix", r#"Always state clearly what to do next and whether there are additional tasks.
+If the work is done or nothing needs to be done, say so explicitly."#),
+    ("agent.suffix_flag", r#"Always state clearly what to do next and whether there are additional tasks.
+Always return the JSON flag is_finished: true/false."#),
+    ("agent.progress", "🤖 Step {step}: asking the LLM..."),
+    ("agent.step_result", "🤖 Step {step}: response received [is_finished={flag}]"),
+    ("agent.finished", "✅ The LLM reported the task as finished."),
+    ("agent.step_limit", "⚠️ Stopped automatically after {steps} steps because the LLM did not report the task as finished."),
+    ("agent.repeating", "⚠️ Stopped because the LLM is repeating itself ({percent}% the same as the previous response)."),
+    ("agent.missing_flag", "⚠️ Stopped because the LLM did not report is_finished even when asked for it."),
+    ("agent.nudge", "Reply with only the flag is_finished: true or is_finished: false for the task above. Do not repeat your previous answer."),
+    ("agent.timeout", "❌ The LLM request timed out"),
+    ("agent.empty_response", "❌ The LLM returned an empty response. Please try again."),
+    ("agent.request_failed", "❌ Failed to communicate with the LLM: {error}"),
+    ("help.title", " Help (Press Ctrl+H to close) "),
+    ("help.normal", r#"=== Normal Mode ===
+
+Movement:
+  h/j/k/l or ←/↓/↑/→  - Move cursor
+  0                   - Move to beginning of line
+  $                   - Move to end of line
+  W/B/E               - Next/previous/end of WORD (whitespace-delimited)
+  J/K or ]/[          - Select next/previous chat message
+  * / #               - Select next/previous message containing the word under the cursor
+
+Editing:
+  i                   - Insert mode
+  a                   - Append (insert after cursor)
+  A                   - Append at end of line
+  I                   - Insert at beginning of line
+  o                   - Open new line below
+  O                   - Open new line above
+  ci" / ca"           - Change inside/around quotes (also ' ( [)
+  v                   - Visual mode
+  V                   - Visual mode (linewise)
+
+Actions:
+  Enter               - Send message (empty input: expand tool result / open a collapsed long message)
+  y                   - Insert selected message into input
+  F                   - Fork a new session up to the selected message
+  x                   - Exclude the selected message from the context / include it again (empty input)
+  Tab                 - Expand/collapse the agent steps of the selected answer
+  e                   - Edit selected (or last) prompt and resend
+  Esc                 - Cancel message edit
+  r                   - Retry a request that failed with a transient error
+  R                   - Re-attach files that changed on disk since they were attached (/refresh)
+  Ctrl+K              - Discard the input and its saved draft
+  Ctrl+P              - Split pane: show / focus (j/k, Tab, Enter, Esc) / hide
+
+Session:
+  n                   - New session
+  s                   - Save history
+  S                   - Session list
+  f                   - File browser
+  q                   - Quit (asks first if a request or unsent input is pending)
+  Q                   - Quit without asking
+
+Tabs:
+  Ctrl+T              - New tab (in the session list: open the selected session in a tab)
+  Ctrl+W              - Close the tab (asks first if a request is in progress)
+  Ctrl+Tab / Ctrl+1..9 - Next tab / go to tab N (also /tab)
+
+Help:
+  Ctrl+H              - Toggle this help window"#),
+    ("help.insert", r#"=== Insert Mode ===
+
+Text Input:
+  Type normally to enter text
+  Shift+Enter         - New line (multi-line input)
+  Enter               - Send message
+  Esc                 - Return to Normal mode
+  Ctrl+A / Ctrl+E     - Start / end of the current line
+  Ctrl+G              - Emoji picker (arrows to move, Enter to insert)
+  Ctrl+K              - Discard the input and its saved draft
+
+File References:
+  @file:path          - Reference a file in your message
+  Example: @file:./config.json
+
+Commands:
+  /help               - List slash commands
+
+AI Features:
+  Ask AI to create files:
+    'Create a file called test.txt with hello world'
+  Ask AI to run commands:
+    'List files in current directory'
+    'Show git status'
+
+History:
+  ↑/↓                 - Navigate input history
+
+Help:
+  Ctrl+H              - Toggle this help window"#),
+    ("help.visual", r#"=== Visual Mode ===
+
+Selection:
+  h/j/k/l or ←/↓/↑/→  - Extend selection
+  w                   - Move forward by word
+  b                   - Move backward by word
+  e                   - Move to end of word
+  W/B/E               - Same as w/b/e for WORDs (whitespace-delimited)
+
+Actions:
+  d                   - Delete selected text (whole lines in linewise mode)
+  y                   - Yank (copy) the selection to the clipboard
+  I                   - Block mode: insert text at the left edge of every line (applied on Esc)
+
+Mode:
+  V                   - Switch to linewise selection (whole lines)
+  v                   - Switch to characterwise selection
+  Ctrl+V              - Switch to block (rectangular) selection
+
+Exit:
+  v / V               - Exit when already in that mode
+  Esc                 - Exit Visual mode
+
+Help:
+  Ctrl+H              - Toggle this help window"#),
+    ("help.session_list", r#"=== Session List ===
+
+Navigation:
+  j/k or ↓/↑          - Navigate sessions
+  PgUp/PgDn           - Previous/next page
+
+Actions:
+  Enter               - Select session
+  d                   - Delete session
+  n                   - Create new session
+
+Exit:
+  q or Esc            - Return to chat
+
+Help:
+  Ctrl+H              - Toggle this help window"#),
+    ("help.file_browser", r#"=== File Browser ===
+
+Navigation:
+  j/k or ↓/↑          - Navigate files
+  u                   - Go to parent directory
+  r                   - Refresh directory
+
+Actions:
+  Enter               - Add file path to input
+  Space               - Toggle file selection
+  i                   - Edit selected file
+
+Exit:
+  q                   - Return to chat
+
+Help:
+  Ctrl+H              - Toggle this help window"#),
+    ("help.diff_view", r#"=== Diff View ===
+
+Navigation:
+  j/k or ↓/↑          - Scroll
+
+Colors:
+  green               - Lines only in the second session
+  red                 - Lines only in the first session
+
+Exit:
+  q or Esc            - Return to chat
+
+Help:
+  Ctrl+H              - Toggle this help window"#),
+    ("help.message_view", r#"=== Message View ===
+
+Shows the full text of a long message that is collapsed in the chat.
+
+Navigation:
+  j/k or ↓/↑          - Scroll
+  PageUp/PageDown     - Scroll by a page
+  g/G                 - Jump to top/bottom
+
+Exit:
+  q or Esc            - Return to chat (the chat keeps its scroll position)
+
+Help:
+  Ctrl+H              - Toggle this help window"#),
+    ("help.permissions", r#"=== Permissions ===
+
+Lists the directories the AI can access and the sensitive directories that are always denied.
+
+Navigation:
+  j/k or ↓/↑          - Select a directory
+
+Edit:
+  a                   - Add a directory (Tab: complete, Enter: add, Esc: cancel)
+  d or Delete         - Remove the selected directory (asks first for the working directory)
+  Changes are saved to the allowed_directories file
+  (a removed working directory is saved as "!path" and stays removed on restart)
+
+Exit:
+  q or Esc            - Return to chat
+
+Help:
+  Ctrl+H              - Toggle this help window"#),
+];
+
+const JA: &[(&str, &str)] = &[
+    ("system_prompt", r#"あなたはファイル作成・部分編集・コマンド実行機能を持つAIアシスタントです。
+
+ユーザーのリクエストに応じて、以下の機能を提供できます：
+
+1. **ファイル作成**: 新しいファイルを作成
+2. **ファイル編集**: 既存ファイルの部分編集
+3. **コマンド実行**: シェルコマンドの実行
+4. **ディレクトリ操作**: ディレクトリの作成（許可されている場合は削除）
+
+これらの機能は、Function Calling機能を通じて実行されます。必要に応じて適切な関数を呼び出してください。
+
+---
+【重要】全ての返答の末尾に, タスクが終了したかを示すフラグである is_finished: true または is_finished: false を必ず明示してください（JSON形式または "is_finished: true" のような形式でOK）。
+また、is_finished:falseの際は、作業を完了させるため適切な関数を呼び出すこと。適切な関数が存在しない、また異常終了しているなどの場合はtrueを返すこと。"#),
+    ("respond_in", "日本語で回答してください。"),
+    ("agent.suffix", r#"次に何をすべきか、追加タスクがあるかを必ず明示してください。
+「完了」「終了」「何もする必要がない」などの場合は、その旨を明確に書いてください。"#),
+    ("agent.suffix_flag", r#"次に何をすべきか、追加タスクがあるかを必ず明示してください。
+is_finished: true/false のJSONフラグを必ず返してください。"#),
+    ("agent.progress", "🤖 Step {step}: LLMに問い合わせ中..."),
+    ("agent.step_result", "🤖 Step {step}: 応答を受信 [is_finished={flag}]"),
+    ("agent.finished", "✅ LLMが終了を指示したためループを終了します。"),
+    ("agent.step_limit", "⚠️ {steps}ステップの間にLLMが終了を指示しなかったため自動終了しました。"),
+    ("agent.repeating", "⚠️ LLMが同じ応答を繰り返しているため終了しました（直前の応答と{percent}%同じ）。"),
+    ("agent.missing_flag", "⚠️ 尋ねてもLLMがis_finishedを返さなかったため終了しました。"),
+    ("agent.nudge", "上のタスクについて、is_finished: true または is_finished: false のフラグだけを答えてください。前の回答は繰り返さないでください。"),
+    ("agent.timeout", "❌ LLMリクエストがタイムアウトしました"),
+    ("agent.empty_response", "❌ LLMからの応答が空です。再試行してください。"),
+    ("agent.request_failed", "❌ LLMとの通信に失敗しました: {error}"),
+    ("help.title", " ヘルプ（Ctrl+Hで閉じる） "),
+    ("help.normal", r#"=== ノーマルモード ===
+
+移動:
+  h/j/k/l or ←/↓/↑/→  - カーソル移動
+  0                   - 行頭へ移動
+  $                   - 行末へ移動
+  W/B/E               - 次/前/末尾のWORD（空白区切り）へ移動
+  J/K or ]/[          - 次/前のチャットメッセージを選択
+  * / #               - カーソル位置の単語を含む次/前のメッセージを選択
+
+編集:
+  i                   - インサートモード
+  a                   - カーソルの後ろから入力
+  A                   - 行末から入力
+  I                   - 行頭から入力
+  o                   - 下に新しい行を開く
+  O                   - 上に新しい行を開く
+  ci" / ca"           - 引用符の内側/引用符ごとを変更（' ( [ も同様）
+  v                   - ビジュアルモード
+  V                   - ビジュアルモード（行単位）
+
+操作:
+  Enter               - メッセージを送信（入力が空ならツール結果を展開・折りたたまれた長いメッセージを全文表示）
+  y                   - 選択中のメッセージを入力欄に挿入
+  F                   - 選択中のメッセージまでで新しいセッションに分岐
+  x                   - 選択中のメッセージを会話コンテキストから外す / 戻す（入力が空のとき）
+  Tab                 - 選択中の回答のエージェントステップを展開・折りたたみ
+  e                   - 選択中（または最後）のプロンプトを編集して再送信
+  Esc                 - メッセージ編集をキャンセル
+  r                   - 一時的なエラーで失敗したリクエストを再送
+  R                   - 添付後にディスク上で変更されたファイルを添付し直す（/refresh）
+  Ctrl+K              - 入力と保存された下書きを破棄
+  Ctrl+P              - 分割表示: 表示 / フォーカス（j/k, Tab, Enter, Esc）/ 閉じる
+
+セッション:
+  n                   - 新しいセッション
+  s                   - 履歴を保存
+  S                   - セッション一覧
+  f                   - ファイルブラウザ
+  q                   - 終了（応答待ちや未送信の入力があれば確認）
+  Q                   - 確認せずに終了
+
+タブ:
+  Ctrl+T              - 新しいタブ（セッション一覧では選択中のセッションをタブで開く）
+  Ctrl+W              - タブを閉じる（応答待ちなら確認）
+  Ctrl+Tab / Ctrl+1..9 - 次のタブ / N番目のタブ（/tab でも操作できる）
+
+ヘルプ:
+  Ctrl+H              - このヘルプを表示/非表示"#),
+    ("help.insert", r#"=== インサートモード ===
+
+テキスト入力:
+  そのまま入力するとテキストを入力できます
+  Shift+Enter         - 改行（複数行入力）
+  Enter               - メッセージを送信
+  Esc                 - ノーマルモードに戻る
+  Ctrl+A / Ctrl+E     - 現在の行の先頭／末尾に移動
+  Ctrl+G              - 絵文字ピッカー（矢印キーで移動、Enterで挿入）
+  Ctrl+K              - 入力と保存された下書きを破棄
+
+ファイル参照:
+  @file:path          - メッセージでファイルを参照
+  例: @file:./config.json
+
+コマンド:
+  /help               - スラッシュコマンドの一覧
+
+AI機能:
+  AIにファイルを作成させる:
+    'hello worldと書いたtest.txtを作成して'
+  AIにコマンドを実行させる:
+    '現在のディレクトリのファイル一覧を表示して'
+    'git statusを表示して'
+
+履歴:
+  ↑/↓                 - 入力履歴をたどる
+
+ヘルプ:
+  Ctrl+H              - このヘルプを表示/非表示"#),
+    ("help.visual", r#"=== ビジュアルモード ===
+
+選択:
+  h/j/k/l or ←/↓/↑/→  - 選択範囲を広げる
+  w                   - 次の単語へ移動
+  b                   - 前の単語へ移動
+  e                   - 単語の末尾へ移動
+  W/B/E               - WORD（空白区切り）単位のw/b/e
+
+操作:
+  d                   - 選択したテキストを削除（行単位モードでは行ごと）
+  y                   - 選択範囲をクリップボードにコピー
+  I                   - 矩形選択: 各行の矩形の左端に入力（Escで反映）
+
+モード:
+  V                   - 行単位の選択に切り替え
+  v                   - 文字単位の選択に切り替え
+  Ctrl+V              - 矩形選択に切り替え
+
+終了:
+  v / V               - 同じモードで押すと終了
+  Esc                 - ビジュアルモードを終了
+
+ヘルプ:
+  Ctrl+H              - このヘルプを表示/非表示"#),
+    ("help.session_list", r#"=== セッション一覧 ===
+
+移動:
+  j/k or ↓/↑          - セッションを選択
+  PgUp/PgDn           - 前/次のページ
+
+操作:
+  Enter               - セッションを開く
+  d                   - セッションを削除
+  n                   - 新しいセッションを作成
+
+終了:
+  q or Esc            - チャットに戻る
+
+ヘルプ:
+  Ctrl+H              - このヘルプを表示/非表示"#),
+    ("help.file_browser", r#"=== ファイルブラウザ ===
+
+移動:
+  j/k or ↓/↑          - ファイルを選択
+  u                   - 親ディレクトリへ移動
+  r                   - ディレクトリを再読み込み
+
+操作:
+  Enter               - ファイルパスを入力欄に追加
+  Space               - ファイルの選択を切り替え
+  i                   - 選択したファイルを編集
+
+終了:
+  q                   - チャットに戻る
+
+ヘルプ:
+  Ctrl+H              - このヘルプを表示/非表示"#),
+    ("help.diff_view", r#"=== 差分表示 ===
+
+移動:
+  j/k or ↓/↑          - スクロール
+
+色:
+  緑                  - 2つ目のセッションにだけある行
+  赤                  - 1つ目のセッションにだけある行
+
+終了:
+  q or Esc            - チャットに戻る
+
+ヘルプ:
+  Ctrl+H              - このヘルプを表示/非表示"#),
+    ("help.message_view", r#"=== 全文表示 ===
+
+チャットで折りたたまれた長いメッセージの全文を表示します。
+
+移動:
+  j/k or ↓/↑          - スクロール
+  PageUp/PageDown     - 1ページ分スクロール
+  g/G                 - 先頭/末尾へ移動
+
+終了:
+  q or Esc            - チャットに戻る（スクロール位置はそのまま）
+
+ヘルプ:
+  Ctrl+H              - このヘルプを表示/非表示"#),
+    ("help.permissions", r#"=== アクセス許可 ===
+
+AIがアクセスできるディレクトリと、常に拒否する機密ディレクトリを表示します。
+
+移動:
+  j/k or ↓/↑          - ディレクトリを選択
+
+編集:
+  a                   - ディレクトリを追加（Tab: 補完、Enter: 追加、Esc: 取り消し）
+  d or Delete         - 選択中のディレクトリを外す（作業ディレクトリは確認してから）
+  変更はallowed_directoriesファイルに保存されます
+  （外した作業ディレクトリは「!パス」として保存され、次の起動でも許可しません）
+
+終了:
+  q or Esc            - チャットに戻る
+
+ヘルプ:
+  Ctrl+H              - このヘルプを表示/非表示"#),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_falls_back_to_english() {
+        assert_eq!(tr(Language::Ja, "agent.timeout"), "❌ LLMリクエストがタイムアウトしました");
+        assert_eq!(tr(Language::En, "agent.timeout"), "❌ The LLM request timed out");
+        assert_eq!(tr(Language::Ja, "missing.key"), "missing.key");
+        // 全てのキーに英語がある
+        assert!(JA.iter().all(|(key, _)| EN.iter().any(|(en_key, _)| en_key == key)));
+    }
+
+    #[test]
+    fn test_language_setting() {
+        assert_eq!(Language::from_setting(Some("ja")), Some(Language::Ja));
+        assert_eq!(Language::from_setting(Some("EN")), Some(Language::En));
+        assert_eq!(Language::from_setting(Some("fr")), None);
+        assert_eq!(Language::from_locale("ja_JP.UTF-8"), Language::Ja);
+        assert_eq!(Language::from_locale("C.UTF-8"), Language::En);
+    }
+}
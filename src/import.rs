@@ -0,0 +1,269 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use serde_json::Value;
+use std::fmt;
+use uuid::Uuid;
+
+use crate::gemini::Part;
+use crate::history::{ChatHistory, ChatMessage, ChatSession, GenerationOverrides, SessionUsage};
+
+/// /import が読み込める形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// contuiのセッション（またはexport-allのアーカイブ）
+    Contui,
+    /// [{role, content}] の配列
+    RoleContent,
+    /// ChatGPTのエクスポート（conversations.json）
+    ChatGpt,
+}
+
+impl fmt::Display for ImportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportFormat::Contui => write!(f, "contui export"),
+            ImportFormat::RoleContent => write!(f, "role/content array"),
+            ImportFormat::ChatGpt => write!(f, "ChatGPT conversations.json"),
+        }
+    }
+}
+
+/// 読み込んだセッション（IDは取り込むときに振り直す）
+#[derive(Debug, Clone)]
+pub struct ImportedSession {
+    pub format: ImportFormat,
+    pub session: ChatSession,
+    /// 複数の会話を含むファイルから1つだけ取り込んだときの会話数
+    pub conversations: usize,
+}
+
+const SUPPORTED_FORMATS: &str = "Supported formats:\n\
+    - contui export: a session {id, title, messages: [...]} or a /session export-all archive (its current session)\n\
+    - role/content array: [{\"role\": \"user\" | \"assistant\", \"content\": \"...\", \"timestamp\": optional}]\n\
+    - ChatGPT conversations.json: [{\"title\", \"mapping\": {...}, \"current_node\"}] (the most recently updated conversation)";
+
+/// 形式を判定してセッションに変換する
+pub fn parse_import(content: &str) -> Result<ImportedSession> {
+    let value: Value = serde_json::from_str(content).map_err(|e| anyhow!("Not a JSON file ({}).\n{}", e, SUPPORTED_FORMATS))?;
+    let imported = if let Some(session) = parse_contui(&value) {
+        ImportedSession { format: ImportFormat::Contui, session, conversations: 1 }
+    } else if let Some(conversations) = chatgpt_conversations(&value) {
+        let count = conversations.len();
+        let latest = conversations
+            .into_iter()
+            .max_by(|a, b| number(&a["update_time"]).partial_cmp(&number(&b["update_time"])).unwrap_or(std::cmp::Ordering::Equal))
+            .ok_or_else(|| anyhow!("The ChatGPT export has no conversations"))?;
+        ImportedSession { format: ImportFormat::ChatGpt, session: parse_chatgpt(latest)?, conversations: count }
+    } else if let Some(messages) = parse_role_content(&value) {
+        ImportedSession { format: ImportFormat::RoleContent, session: new_session(None, messages), conversations: 1 }
+    } else {
+        return Err(anyhow!("Unrecognized import format.\n{}", SUPPORTED_FORMATS));
+    };
+    if imported.session.messages.is_empty() {
+        return Err(anyhow!("The {} contains no messages", imported.format));
+    }
+    Ok(imported)
+}
+
+fn parse_contui(value: &Value) -> Option<ChatSession> {
+    if let Ok(session) = serde_json::from_value::<ChatSession>(value.clone()) {
+        return Some(session);
+    }
+    let history = serde_json::from_value::<ChatHistory>(value.clone()).ok()?;
+    let session = history.get_current_session().or_else(|| history.get_session_list().into_iter().next())?;
+    Some(session.clone())
+}
+
+/// 取り込むメッセージ1件（本文, ユーザーか, 時刻）
+type Entry = (String, bool, Option<DateTime<Utc>>);
+
+fn parse_role_content(value: &Value) -> Option<Vec<Entry>> {
+    let entries = value.as_array().filter(|entries| !entries.is_empty())?;
+    entries
+        .iter()
+        .filter(|entry| entry["role"].as_str() != Some("system"))
+        .map(|entry| {
+            let is_user = matches!(entry["role"].as_str()?, "user" | "human");
+            let text = entry["content"].as_str()?;
+            let timestamp = ["timestamp", "created_at", "create_time"].iter().find_map(|key| timestamp(&entry[key]));
+            Some((text.to_string(), is_user, timestamp))
+        })
+        .collect()
+}
+
+/// ChatGPTのエクスポート（会話の配列、または会話1つ）なら会話の一覧を返す
+fn chatgpt_conversations(value: &Value) -> Option<Vec<&Value>> {
+    let is_conversation = |value: &Value| value["mapping"].is_object();
+    match value {
+        Value::Array(items) if !items.is_empty() && items.iter().all(is_conversation) => Some(items.iter().collect()),
+        Value::Object(_) if is_conversation(value) => Some(vec![value]),
+        _ => None,
+    }
+}
+
+/// current_nodeから親をたどって、表示されていた分岐のメッセージを古い順に並べる
+fn parse_chatgpt(conversation: &Value) -> Result<ChatSession> {
+    let mapping = &conversation["mapping"];
+    let mut node_id = conversation["current_node"].as_str().map(str::to_string);
+    let node_count = mapping.as_object().map_or(0, |nodes| nodes.len());
+    let mut messages = Vec::new();
+    let mut visited = 0;
+    while let Some(id) = node_id {
+        // 循環していても止まるようにノード数で打ち切る
+        visited += 1;
+        if visited > node_count {
+            return Err(anyhow!("ChatGPT export has a cycle in its message tree"));
+        }
+        let node = &mapping[&id];
+        if node.is_null() {
+            return Err(anyhow!("ChatGPT export refers to a missing node: {}", id));
+        }
+        let entry = &node["message"];
+        let role = entry["author"]["role"].as_str().unwrap_or_default();
+        let text = entry["content"]["parts"]
+            .as_array()
+            .map(|parts| parts.iter().filter_map(Value::as_str).collect::<Vec<_>>().join("\n"))
+            .unwrap_or_default();
+        if matches!(role, "user" | "assistant") && !text.trim().is_empty() {
+            messages.push((text, role == "user", timestamp(&entry["create_time"])));
+        }
+        node_id = node["parent"].as_str().map(str::to_string);
+    }
+    messages.reverse();
+    Ok(new_session(conversation["title"].as_str().map(str::to_string), messages))
+}
+
+fn number(value: &Value) -> f64 {
+    value.as_f64().unwrap_or(0.0)
+}
+
+/// RFC 3339の文字列またはUNIX時刻（秒）
+fn timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::String(text) => DateTime::parse_from_rfc3339(text).ok().map(|time| time.with_timezone(&Utc)),
+        Value::Number(seconds) => {
+            let seconds = seconds.as_f64()?;
+            Utc.timestamp_opt(seconds.trunc() as i64, (seconds.fract() * 1e9) as u32).single()
+        }
+        _ => None,
+    }
+}
+
+/// 時刻のないメッセージには直前のメッセージの時刻（先頭なら取り込んだ時刻）を使う
+fn new_session(title: Option<String>, entries: Vec<Entry>) -> ChatSession {
+    let now = Utc::now();
+    let mut previous = None;
+    let messages: Vec<ChatMessage> = entries
+        .into_iter()
+        .map(|(text, is_user, timestamp)| {
+            let timestamp = timestamp.or(previous).unwrap_or(now);
+            previous = Some(timestamp);
+            ChatMessage {
+                id: Uuid::new_v4(),
+                parts: vec![Part::Text { text }],
+                is_user,
+                timestamp,
+                comparison: false,
+                steps: Vec::new(),
+                is_system: false,
+                excluded: false,
+            }
+        })
+        .collect();
+    let created_at = messages.first().map_or(now, |m| m.timestamp);
+    let updated_at = messages.last().map_or(now, |m| m.timestamp);
+    ChatSession {
+        id: Uuid::new_v4(),
+        title: title.unwrap_or_else(|| format!("Imported {}", now.format("%Y-%m-%d %H:%M"))),
+        created_at,
+        updated_at,
+        messages,
+        changes: Vec::new(),
+        summary: None,
+        archived_branches: Vec::new(),
+        overrides: GenerationOverrides::default(),
+        archived: false,
+        usage: SessionUsage::default(),
+        deleted_at: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::HistoryManager;
+
+    fn texts(session: &ChatSession) -> Vec<(bool, String)> {
+        session
+            .messages
+            .iter()
+            .map(|m| match &m.parts[..] {
+                [Part::Text { text }] => (m.is_user, text.clone()),
+                parts => panic!("unexpected parts: {:?}", parts),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_contui_export_round_trips() {
+        let dir = std::env::temp_dir();
+        let mut manager = HistoryManager::with_file_path(dir.join(format!("contui_test_{}.json", Uuid::new_v4()))).unwrap();
+        manager.get_history_mut().new_session(Some("other".to_string()));
+        manager.get_history_mut().new_session(Some("exported".to_string()));
+        for (i, is_user) in [true, false, true].into_iter().enumerate() {
+            manager.get_history_mut().add_message(vec![Part::Text { text: format!("msg {}", i) }], is_user).unwrap();
+        }
+        let original = manager.get_history().get_current_session().unwrap().clone();
+
+        let archive_path = dir.join(format!("contui_archive_test_{}.json", Uuid::new_v4()));
+        manager.export_all(&archive_path, false).unwrap();
+        let imported = parse_import(&std::fs::read_to_string(&archive_path).unwrap()).unwrap();
+        std::fs::remove_file(&archive_path).unwrap();
+        assert_eq!(imported.format, ImportFormat::Contui);
+        assert_eq!(imported.session.title, "exported");
+        assert_eq!(texts(&imported.session), texts(&original));
+        assert_eq!(imported.session.messages[0].timestamp, original.messages[0].timestamp);
+
+        let single = parse_import(&serde_json::to_string(&original).unwrap()).unwrap();
+        assert_eq!(texts(&single.session), texts(&original));
+    }
+
+    #[test]
+    fn test_role_content_and_chatgpt_formats() {
+        let imported = parse_import(
+            r#"[{"role": "system", "content": "be nice"},
+                {"role": "user", "content": "hi", "timestamp": "2024-05-01T10:00:00Z"},
+                {"role": "assistant", "content": "hello"}]"#,
+        )
+        .unwrap();
+        assert_eq!(imported.format, ImportFormat::RoleContent);
+        assert_eq!(texts(&imported.session), vec![(true, "hi".to_string()), (false, "hello".to_string())]);
+        // 時刻のないメッセージは直前の時刻を引き継ぐ
+        assert_eq!(imported.session.messages[1].timestamp, imported.session.messages[0].timestamp);
+
+        let chatgpt = r#"[{"title": "old", "update_time": 1.0, "current_node": "a", "mapping": {"a": {"message": {"author": {"role": "user"}, "content": {"parts": ["old"]}}}}},
+            {"title": "Trip plan", "update_time": 2.0, "current_node": "c", "mapping": {
+                "root": {"message": null, "parent": null},
+                "s": {"message": {"author": {"role": "system"}, "content": {"parts": [""]}}, "parent": "root"},
+                "a": {"message": {"author": {"role": "user"}, "content": {"parts": ["where to?"]}, "create_time": 1714557600.5}, "parent": "s"},
+                "b": {"message": {"author": {"role": "assistant"}, "content": {"parts": ["Kyoto"]}, "create_time": 1714557601}, "parent": "a"},
+                "c": {"message": {"author": {"role": "user"}, "content": {"parts": ["why?"]}}, "parent": "b"}}}]"#;
+        let imported = parse_import(chatgpt).unwrap();
+        assert_eq!(imported.format, ImportFormat::ChatGpt);
+        assert_eq!(imported.conversations, 2);
+        assert_eq!(imported.session.title, "Trip plan");
+        assert_eq!(
+            texts(&imported.session),
+            vec![(true, "where to?".to_string()), (false, "Kyoto".to_string()), (true, "why?".to_string())]
+        );
+        assert_eq!(imported.session.created_at.timestamp(), 1714557600);
+    }
+
+    #[test]
+    fn test_unknown_format_lists_supported_shapes() {
+        let error = parse_import(r#"{"foo": 1}"#).unwrap_err().to_string();
+        assert!(error.contains("Unrecognized import format") && error.contains("ChatGPT conversations.json"));
+        assert!(parse_import("not json").unwrap_err().to_string().contains("Supported formats"));
+        assert!(parse_import("[]").is_err());
+    }
+}
@@ -0,0 +1,32 @@
+//! contuiのコア（設定・Geminiクライアント・履歴・ファイルアクセスなど）
+//! TUI（app/）は "tui" フィーチャー（既定で有効）でのみビルドする
+
+pub mod bookmarks;
+pub mod config;
+pub mod custom_tools;
+pub mod diagnostics;
+pub mod drafts;
+pub mod emoji;
+pub mod file_access;
+pub mod gemini;
+pub mod hooks;
+pub mod git;
+pub mod history;
+pub mod i18n;
+pub mod import;
+pub mod llm;
+pub mod logger;
+pub mod markdown;
+pub mod mock_llm;
+pub mod redact;
+pub mod templates;
+pub mod termination;
+
+#[cfg(feature = "tui")]
+pub mod app;
+
+#[cfg(test)]
+mod test_function_calling;
+#[cfg(test)]
+#[path = "../tests/common/mod.rs"]
+pub(crate) mod test_common;
@@ -0,0 +1,42 @@
+use anyhow::Result;
+use std::future::Future;
+
+use crate::file_access::FileReference;
+use crate::gemini::{Content, ResponsePart};
+use crate::history::ChatMessage;
+
+/// エージェントループが使うLLMの操作
+/// GeminiClientが実装し、テストや他のツールからは差し替えて使える
+pub trait LlmProvider: Send + Sync {
+    /// 使用中のモデル名
+    fn model(&self) -> &str;
+
+    /// メッセージを送り、テキストと関数呼び出しの結果を受け取る
+    fn chat(
+        &self,
+        message: &str,
+        context: Option<&[Content]>,
+    ) -> impl Future<Output = Result<Vec<ResponsePart>>> + Send;
+
+    /// ファイル内容を添えてメッセージを送る
+    fn chat_with_file_context(
+        &self,
+        message: &str,
+        file_references: &[FileReference],
+        context: Option<&[Content]>,
+    ) -> impl Future<Output = Result<Vec<ResponsePart>>> + Send;
+
+    /// ツールを渡さずにテキスト応答だけを得る
+    fn chat_text_only(
+        &self,
+        message: &str,
+        context: Option<&[Content]>,
+    ) -> impl Future<Output = Result<String>> + Send;
+
+    /// 古い会話を要約する
+    fn summarize_conversation(
+        &self,
+        previous_summary: Option<&str>,
+        messages: &[ChatMessage],
+    ) -> impl Future<Output = Result<String>> + Send;
+}
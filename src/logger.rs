@@ -6,6 +6,7 @@ use once_cell::sync::OnceCell;
 use chrono::Local;
 
 static LOGGER: OnceCell<Arc<Mutex<File>>> = OnceCell::new();
+static ERROR_LOGGER: OnceCell<Arc<Mutex<File>>> = OnceCell::new();
 
 pub fn reset_log_file(path: &str) -> anyhow::Result<()> {
     OpenOptions::new()
@@ -26,6 +27,17 @@ pub fn init_logger(path: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// エラーログを初期化（起動時にリセットせず追記し続ける）
+pub fn init_error_logger(path: &str) -> anyhow::Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    ERROR_LOGGER.set(Arc::new(Mutex::new(file)))
+        .map_err(|_| anyhow::anyhow!("Error logger already initialized"))?;
+    Ok(())
+}
+
 pub fn log_debug(message: &str) {
     if let Some(logger) = LOGGER.get() {
         let mut file = logger.lock();
@@ -34,6 +46,14 @@ pub fn log_debug(message: &str) {
     }
 }
 
+pub fn log_error(message: &str) {
+    if let Some(logger) = ERROR_LOGGER.get() {
+        let mut file = logger.lock();
+        let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
+        let _ = writeln!(file, "[{}] ERROR: {}", timestamp, message);
+    }
+}
+
 #[macro_export]
 macro_rules! debug_log {
     ($($arg:tt)*) => {
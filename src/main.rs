@@ -1,12 +1,3 @@
-mod config;
-mod gemini;
-pub mod app;
-mod history;
-mod file_access;
-mod markdown;
-mod logger; // Add this line
-mod test_function_calling; // Add test module
-
 use crossterm::{
     event::{self, Event},
 };
@@ -17,16 +8,29 @@ use std::{
     time::Duration,
 };
 use anyhow::Result;
-use app::ChatApp;
-use config::Config;
-use gemini::GeminiClient;
-use history::HistoryManager;
-use app::terminal_util::{setup_terminal, cleanup_terminal};
+use contui::app::ChatApp;
+use contui::config::{default_config_path, Config, MissingApiKey, ProviderKind};
+use contui::mock_llm::MockScript;
+use contui::gemini::GeminiClient;
+use contui::history::HistoryManager;
+use contui::termination::TerminationConfig;
+use contui::{diagnostics, logger};
+use contui::app::terminal_util::{setup_terminal, cleanup_terminal, set_terminal_title};
+use contui::app::setup_wizard::run_setup_wizard;
+use std::io::IsTerminal;
 use std::sync::{Arc, Mutex};
 
 #[tokio::main]
 
 async fn main() -> Result<()> {
+    // contui --doctor: 診断だけ実行して終了（失敗があれば終了コード1）
+    if std::env::args().skip(1).any(|arg| arg == "--doctor") {
+        let keyboard_enhancement = crossterm::terminal::supports_keyboard_enhancement().unwrap_or(false);
+        let report = diagnostics::run_diagnostics(keyboard_enhancement).await;
+        println!("{}", report);
+        std::process::exit(if report.all_passed() { 0 } else { 1 });
+    }
+
     // プログラム開始時にデバッグログを初期化
     // プログラム開始時にログファイルをリセット
     println!("Resetting log files...");
@@ -47,12 +51,43 @@ async fn main() -> Result<()> {
     } else {
         logger::log_debug("Debug logger initialized.");
     }
+
+    // エラーログはリセットせず、セッションをまたいで追記する
+    if let Err(e) = logger::init_error_logger("contui_error.log") {
+        eprintln!("Failed to initialize error logger: {}", e);
+    }
     
     println!("Starting contui application...");
     
     // 設定を読み込む
     println!("Loading configuration...");
-    let config = Config::load()?;
+    // --mock <script.json>: APIの代わりにモックスクリプトの応答を使う
+    let args: Vec<String> = std::env::args().collect();
+    let provider = match args.iter().position(|arg| arg == "--mock") {
+        Some(i) => {
+            let script = args.get(i + 1).ok_or_else(|| anyhow::anyhow!("--mock requires a script path"))?;
+            Some(ProviderKind::Mock { script: script.into() })
+        }
+        None => None,
+    };
+    let config = match Config::load_with_provider(provider.clone()) {
+        // 初回起動: 設定がなければセットアップで作る（--no-wizard ならこれまでどおりエラー）
+        Err(e) if e.is::<MissingApiKey>() && !args.iter().any(|arg| arg == "--no-wizard") && std::io::stdout().is_terminal() => {
+            let Some(config_path) = default_config_path() else {
+                return Err(e);
+            };
+            let mut terminal = setup_terminal()?;
+            let saved = run_setup_wizard(&mut terminal, &config_path).await;
+            cleanup_terminal(&mut terminal)?;
+            if !saved? {
+                println!("Setup cancelled; no configuration was written.");
+                return Ok(());
+            }
+            println!("Configuration saved to {}", config_path.display());
+            Config::load_with_provider(provider)?
+        }
+        result => result?,
+    };
     println!("Configuration loaded successfully");
     
     // 履歴管理を初期化
@@ -62,7 +97,24 @@ async fn main() -> Result<()> {
     
     // Geminiクライアントを作成
     println!("Creating Gemini client...");
-    let gemini_client = GeminiClient::new(config.llm, history_manager.clone());
+    let gemini_client = GeminiClient::new(config.llm.clone(), history_manager.clone()).with_shell(config.app.shell)
+        .with_command_timeout(config.app.command_timeout_secs.map(std::time::Duration::from_secs))
+        .with_max_prompt_bytes(config.app.max_prompt_bytes)
+        .with_fallback_encodings(config.app.fallback_encodings.clone())
+        .with_file_cache(config.app.file_cache)
+        .with_custom_tools(config.app.custom_tools.clone())
+        .with_hooks(config.app.hooks.clone())
+        .with_recursive_delete(config.app.allow_recursive_delete)
+        .with_language(config.app.language)
+        .with_termination(TerminationConfig {
+            max_steps: config.app.agent_max_steps,
+            repeat_similarity: config.app.agent_repeat_similarity,
+            nudge_after_missing_flags: config.app.agent_nudge_after,
+        });
+    let gemini_client = match &config.llm.provider {
+        ProviderKind::Mock { script } => gemini_client.with_mock_script(MockScript::load(script)?),
+        ProviderKind::Gemini => gemini_client,
+    };
     println!("Gemini client created");
 
     // ターミナルをセットアップ
@@ -73,11 +125,15 @@ async fn main() -> Result<()> {
     
     // アプリケーションを作成
     println!("Creating chat application...");
-    let mut app = ChatApp::new(gemini_client, history_manager.clone());
+    let mut app = ChatApp::new(gemini_client, history_manager.clone(), config.app);
+    if let Some(path) = contui::drafts::default_draft_path() {
+        app.enable_drafts(path);
+    }
     println!("Chat application created");
     
 
     let result = run_app(&mut app, &mut terminal).await;
+    app.flush_draft();
 
     // ターミナルをクリーンアップ
     cleanup_terminal(&mut terminal)?;
@@ -86,6 +142,13 @@ async fn main() -> Result<()> {
         eprintln!("Error: {}", err);
     }
 
+    // 保存できていないメッセージがあれば最後にもう一度保存し、だめなら別のファイルに書き出す
+    match history_manager.lock().unwrap().flush_on_exit() {
+        Ok(None) => {}
+        Ok(Some(path)) => eprintln!("Warning: some messages could not be saved to the history; they were written to {}", path.display()),
+        Err(err) => eprintln!("Error: some messages could not be saved: {}", err),
+    }
+
     Ok(())
 }
 
@@ -93,31 +156,30 @@ async fn run_app(
     app: &mut ChatApp,
     terminal: &mut Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
 ) -> Result<()> {
+    let mut title_unsaved = None;
     loop {
         terminal.draw(|f| {
             app.render(f);
         })?;
 
+        // 未保存状態が変わったときだけターミナルのタイトルを更新
+        let unsaved = app.has_unsaved_changes();
+        if title_unsaved != Some(unsaved) {
+            if let Err(e) = set_terminal_title(unsaved) {
+                logger::log_error(&format!("Failed to set terminal title: {}", e));
+            }
+            title_unsaved = Some(unsaved);
+        }
+
         // イベントを非ブロッキングで処理
         if event::poll(Duration::from_millis(100))? {
             match event::read()? {
-                Event::Key(key) => {
-                    if app.handle_key_event(key, terminal).await? {
-                        // 「q」などの終了要求時のみabort
-                        if let Some(handle) = app.llm_task_handle.take() {
-                            handle.abort();
-                            app.is_loading = false;
-                            app.ui.input_mode = app::InputMode::Normal;
-                            // abort時に必ずエラーイベント送信
-                            let _ = app.event_sender.send(app::ChatEvent::Error("LLMタスクがabortされました".to_string()));
-                        }
-                        return Ok(());
-                    }
-                    // 通常の入力時はabortしない
-                }
-                Event::Resize(_, _) => {
-                    // リサイズイベントを処理
+                // 終了が確定した（後始末はprepare_quitで済んでいる）
+                Event::Key(key) if app.handle_key_event(key, terminal).await? => {
+                    return Ok(());
                 }
+                Event::Mouse(mouse) => app.handle_mouse_event(mouse),
+                Event::Resize(_, _) => app.handle_resize(),
                 _ => {}
             }
         }
@@ -126,5 +188,15 @@ async fn run_app(
         while let Ok(chat_event) = app.event_receiver.try_recv() {
             app.handle_chat_event(chat_event);
         }
+
+        // 入力が止まってしばらくしたら下書きを保存
+        let now = std::time::Instant::now();
+        app.tick_draft(now);
+        // 入力が止まってしばらくしたらコンテキストウィンドウの使用量を数え直す
+        app.tick_context_gauge(now);
+        // 表示してからしばらく経った通知を消す
+        app.tick_notification(now);
+        // 保存に失敗したメッセージがあれば保存し直す
+        app.tick_pending_writes(now);
     }
 }
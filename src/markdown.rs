@@ -1,6 +1,76 @@
-use unicode_width::UnicodeWidthStr;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-/// テキストを指定した幅で自動改行する
+const ESC: char = '\u{1b}';
+
+/// textをANSIエスケープシーケンス（true）とそれ以外の文字列（false）に分ける
+/// CSI（ESC [ … 終端文字）とOSC（ESC ] … BELまたはESC \）を1つのシーケンスとして扱う
+fn ansi_segments(text: &str) -> Vec<(&str, bool)> {
+    let mut segments = Vec::new();
+    let mut plain_start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, ch)) = chars.next() {
+        if ch != ESC {
+            continue;
+        }
+        let mut end = start + ch.len_utf8();
+        match chars.peek().copied() {
+            Some((i, '[')) => {
+                chars.next();
+                end = i + 1;
+                for (i, ch) in chars.by_ref() {
+                    end = i + ch.len_utf8();
+                    if ('\u{40}'..='\u{7e}').contains(&ch) {
+                        break;
+                    }
+                }
+            }
+            Some((i, ']')) => {
+                chars.next();
+                end = i + 1;
+                while let Some((i, ch)) = chars.next() {
+                    end = i + ch.len_utf8();
+                    if ch == '\u{7}' {
+                        break;
+                    }
+                    if ch == ESC && chars.peek().is_some_and(|&(_, next)| next == '\\') {
+                        end = chars.next().map_or(end, |(i, _)| i + 1);
+                        break;
+                    }
+                }
+            }
+            Some((i, next)) => {
+                chars.next();
+                end = i + next.len_utf8();
+            }
+            None => {}
+        }
+        if plain_start < start {
+            segments.push((&text[plain_start..start], false));
+        }
+        segments.push((&text[start..end], true));
+        plain_start = end;
+    }
+    if plain_start < text.len() {
+        segments.push((&text[plain_start..], false));
+    }
+    segments
+}
+
+/// ANSIエスケープシーケンスを取り除く
+pub fn ansi_strip(text: &str) -> String {
+    ansi_segments(text).into_iter().filter(|(_, escape)| !escape).map(|(segment, _)| segment).collect()
+}
+
+/// ANSIエスケープシーケンスを除いた表示幅
+fn display_width(text: &str) -> usize {
+    if text.contains(ESC) {
+        UnicodeWidthStr::width(ansi_strip(text).as_str())
+    } else {
+        UnicodeWidthStr::width(text)
+    }
+}
+
+/// テキストを指定した幅で自動改行する（ANSIエスケープシーケンスは幅に数えず、そのまま残す）
 pub fn wrap_text(text: &str, max_width: usize) -> String {
     if max_width == 0 {
         return text.to_string();
@@ -10,7 +80,7 @@ pub fn wrap_text(text: &str, max_width: usize) -> String {
     let lines = text.lines();
 
     for line in lines {
-        if UnicodeWidthStr::width(line) <= max_width {
+        if display_width(line) <= max_width {
             wrapped_lines.push(line.to_string());
         } else {
             // 行を単語単位で分割
@@ -18,7 +88,7 @@ pub fn wrap_text(text: &str, max_width: usize) -> String {
             let mut current_line = String::new();
 
             for word in words {
-                let word_width = UnicodeWidthStr::width(word);
+                let word_width = display_width(word);
                 
                 // 単語が最大幅を超える場合、文字単位で強制分割
                 if word_width > max_width {
@@ -28,21 +98,29 @@ pub fn wrap_text(text: &str, max_width: usize) -> String {
                         current_line.clear();
                     }
                     
-                    // 長い単語を文字単位で分割
-                    let mut chars = word.chars();
+                    // 長い単語を文字単位で分割（エスケープシーケンスは次の文字と同じ行に置く）
                     let mut char_line = String::new();
+                    let mut char_line_width = 0;
+                    let mut pending_escapes = String::new();
                     
-                    while let Some(ch) = chars.next() {
-                        let char_width = UnicodeWidthStr::width(ch.to_string().as_str());
-                        
-                        if UnicodeWidthStr::width(char_line.as_str()) + char_width > max_width {
-                            if !char_line.is_empty() {
-                                wrapped_lines.push(char_line.clone());
-                                char_line.clear();
+                    for (segment, escape) in ansi_segments(word) {
+                        if escape {
+                            pending_escapes.push_str(segment);
+                            continue;
+                        }
+                        for ch in segment.chars() {
+                            let char_width = ch.width().unwrap_or(0);
+                            
+                            if char_line_width + char_width > max_width && !char_line.is_empty() {
+                                wrapped_lines.push(std::mem::take(&mut char_line));
+                                char_line_width = 0;
                             }
+                            char_line.push_str(&std::mem::take(&mut pending_escapes));
+                            char_line.push(ch);
+                            char_line_width += char_width;
                         }
-                        char_line.push(ch);
                     }
+                    char_line.push_str(&pending_escapes);
                     
                     if !char_line.is_empty() {
                         current_line = char_line;
@@ -50,7 +128,7 @@ pub fn wrap_text(text: &str, max_width: usize) -> String {
                     continue;
                 }
 
-                let current_width = UnicodeWidthStr::width(current_line.as_str());
+                let current_width = display_width(&current_line);
                 let space_width = if current_line.is_empty() { 0 } else { 1 };
 
                 // 単語を追加しても幅を超えない場合
@@ -81,4 +159,108 @@ pub fn wrap_text(text: &str, max_width: usize) -> String {
     }
 
     wrapped_lines.join("\n")
-}
\ No newline at end of file
+}
+/// 単語単位の折り返しをせず、空白やインデントを保ったまま幅で強制的に改行する
+pub fn hard_wrap(text: &str, max_width: usize) -> String {
+    if max_width == 0 {
+        return text.to_string();
+    }
+    let mut wrapped_lines = Vec::new();
+    for line in text.split('\n') {
+        let mut current_line = String::new();
+        let mut current_width = 0;
+        for ch in line.chars() {
+            let char_width = ch.width().unwrap_or(0);
+            if current_width + char_width > max_width && !current_line.is_empty() {
+                wrapped_lines.push(std::mem::take(&mut current_line));
+                current_width = 0;
+            }
+            current_line.push(ch);
+            current_width += char_width;
+        }
+        wrapped_lines.push(current_line);
+    }
+    wrapped_lines.join("\n")
+}
+
+/// JSONとして読めるテキスト、または ```json コードブロックの中身を整形する
+/// JSONが見つからなければNone
+pub fn pretty_print_json(text: &str) -> Option<String> {
+    if let Some(pretty) = pretty_json_value(text) {
+        return Some(pretty);
+    }
+
+    let mut output = Vec::new();
+    let mut block: Option<Vec<&str>> = None;
+    let mut changed = false;
+    for line in text.lines() {
+        match block.as_mut() {
+            None if line.trim_start().starts_with("```json") => {
+                output.push(line.to_string());
+                block = Some(Vec::new());
+            }
+            None => output.push(line.to_string()),
+            Some(lines) if line.trim_start().starts_with("```") => {
+                let body = lines.join("\n");
+                match pretty_json_value(&body) {
+                    Some(pretty) => {
+                        output.push(pretty);
+                        changed = true;
+                    }
+                    None => output.push(body),
+                }
+                output.push(line.to_string());
+                block = None;
+            }
+            Some(lines) => lines.push(line),
+        }
+    }
+    if let Some(lines) = block {
+        output.extend(lines.iter().map(|line| line.to_string()));
+    }
+    changed.then(|| output.join("\n"))
+}
+
+fn pretty_json_value(text: &str) -> Option<String> {
+    let trimmed = text.trim();
+    if !(trimmed.starts_with('{') || trimmed.starts_with('[')) {
+        return None;
+    }
+    let value: serde_json::Value = serde_json::from_str(trimmed).ok()?;
+    serde_json::to_string_pretty(&value).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_text_ignores_ansi_escapes() {
+        let red = "\u{1b}[31m";
+        let reset = "\u{1b}[0m";
+        // 見た目は10文字なので幅10に収まる
+        let line = format!("{}error{} done", red, reset);
+        assert_eq!(wrap_text(&line, 10), line);
+        assert_eq!(wrap_text(&format!("{}abc def{} ghi", red, reset), 7), format!("{}abc def{}\nghi", red, reset));
+        // 長い単語の途中で折り返すときは、シーケンスを次の文字と同じ行に置く
+        assert_eq!(wrap_text(&format!("abc{}def{}", red, reset), 3), format!("abc\n{}def{}", red, reset));
+        assert_eq!(ansi_strip(&format!("{}ok{} \u{1b}]8;;https://example.com\u{7}link\u{1b}]8;;\u{1b}\\", red, reset)), "ok link");
+    }
+
+    #[test]
+    fn test_hard_wrap_keeps_indentation() {
+        assert_eq!(hard_wrap("  abcdef\n    x", 4), "  ab\ncdef\n    \nx");
+        assert_eq!(hard_wrap("日本語", 4), "日本\n語");
+    }
+
+    #[test]
+    fn test_pretty_print_json() {
+        assert_eq!(pretty_print_json(r#"{"a":[1,2]}"#).unwrap(), "{\n  \"a\": [\n    1,\n    2\n  ]\n}");
+        assert_eq!(
+            pretty_print_json("result:\n```json\n{\"ok\":true}\n```\ndone").unwrap(),
+            "result:\n```json\n{\n  \"ok\": true\n}\n```\ndone"
+        );
+        assert!(pretty_print_json("plain text").is_none());
+        assert!(pretty_print_json("{not json}").is_none());
+    }
+}
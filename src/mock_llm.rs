@@ -0,0 +1,82 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use crate::gemini::{FunctionCall, ResponsePart};
+
+/// モックLLMのスクリプト1件
+/// プロンプトにexpect_substringが含まれていれば、respond_textとfunction_callsを返す
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockEntry {
+    pub expect_substring: String,
+    #[serde(default)]
+    pub respond_text: Option<String>,
+    #[serde(default)]
+    pub function_calls: Vec<FunctionCall>,
+}
+
+/// オフライン開発・テスト用にAPIの代わりに応答を返すスクリプト（--mock / LLM_PROVIDER=mock）
+/// 関数呼び出しはGeminiの応答と同じ経路で実行される
+#[derive(Debug, Clone)]
+pub struct MockScript {
+    entries: Vec<MockEntry>,
+}
+
+impl MockScript {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read mock script: {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("Invalid mock script: {}", path.display()))
+    }
+
+    /// JSONの配列 [{expect_substring, respond_text, function_calls}] を読み込む
+    pub fn parse(content: &str) -> Result<Self> {
+        let entries: Vec<MockEntry> = serde_json::from_str(content)?;
+        if entries.is_empty() {
+            return Err(anyhow!("Mock script has no entries"));
+        }
+        Ok(Self { entries })
+    }
+
+    /// プロンプトに一致する最初のエントリの応答（一致しなければエラー）
+    pub fn respond(&self, prompt: &str) -> Result<Vec<ResponsePart>> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| prompt.contains(&entry.expect_substring))
+            .ok_or_else(|| anyhow!("Mock LLM: no script entry matches the prompt:\n{}", prompt))?;
+        let text = entry.respond_text.iter().map(|text| ResponsePart::Text { text: text.clone() });
+        let calls = entry
+            .function_calls
+            .iter()
+            .map(|call| ResponsePart::FunctionCall { function_call: call.clone() });
+        Ok(text.chain(calls).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_script_matches_first_entry() {
+        let script = MockScript::parse(
+            r#"[
+                {"expect_substring": "hello", "respond_text": "hi! is_finished: true"},
+                {"expect_substring": "file", "function_calls": [{"name": "create_file", "args": {"filename": "a.txt", "content": "x"}}]},
+                {"expect_substring": "hello file", "respond_text": "never used"}
+            ]"#,
+        )
+        .unwrap();
+
+        let parts = script.respond("say hello file").unwrap();
+        assert!(matches!(&parts[..], [ResponsePart::Text { text }] if text == "hi! is_finished: true"));
+        let parts = script.respond("make a file").unwrap();
+        assert!(matches!(&parts[..], [ResponsePart::FunctionCall { function_call }] if function_call.name == "create_file"));
+
+        let error = script.respond("unknown prompt").unwrap_err().to_string();
+        assert!(error.contains("no script entry matches"));
+        assert!(MockScript::parse("[]").is_err());
+    }
+}
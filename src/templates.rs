@@ -0,0 +1,70 @@
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// プロンプトテンプレートの保存先（<config_dir>/contui/templates）
+pub fn default_templates_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("contui").join("templates"))
+}
+
+/// テンプレート名はファイル名にそのまま使うので、英数字と - _ . のみ許可する
+fn template_path(dir: &Path, name: &str) -> Result<PathBuf> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(anyhow!("Invalid template name '{}' (use letters, digits, '-', '_' or '.')", name));
+    }
+    Ok(dir.join(format!("{}.txt", name)))
+}
+
+pub fn save_template(dir: &Path, name: &str, text: &str) -> Result<PathBuf> {
+    let path = template_path(dir, name)?;
+    fs::create_dir_all(dir)?;
+    fs::write(&path, text)?;
+    Ok(path)
+}
+
+pub fn load_template(dir: &Path, name: &str) -> Result<String> {
+    let path = template_path(dir, name)?;
+    if !path.exists() {
+        return Err(anyhow!("Template '{}' not found", name));
+    }
+    Ok(fs::read_to_string(path)?)
+}
+
+/// 保存済みのテンプレート名（名前順）
+pub fn list_templates(dir: &Path) -> Result<Vec<String>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut names: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+        .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_load_and_list_templates() {
+        let dir = std::env::temp_dir().join(format!("contui_templates_{}", uuid::Uuid::new_v4()));
+        assert!(list_templates(&dir).unwrap().is_empty());
+
+        save_template(&dir, "review", "Review this @file:src/main.rs").unwrap();
+        save_template(&dir, "explain", "Explain\nstep by step").unwrap();
+        assert_eq!(load_template(&dir, "review").unwrap(), "Review this @file:src/main.rs");
+        assert_eq!(list_templates(&dir).unwrap(), vec!["explain", "review"]);
+
+        assert!(load_template(&dir, "missing").is_err());
+        assert!(save_template(&dir, "../escape", "x").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
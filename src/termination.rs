@@ -0,0 +1,134 @@
+//! エージェントループの終了判定（is_finishedフラグの抽出と、同じ応答の繰り返しの検出）
+
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// is_finished: true / "is_finished": "true" / **is_finished** = True / "finished": true など
+static FLAG_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?i)\b(?:is_)?finished\b["'*`]*\s*[:=]\s*["'*`]*(true|false)\b"#).expect("valid is_finished regex")
+});
+
+/// エージェントループを止める条件
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerminationConfig {
+    /// 最大のステップ数
+    pub max_steps: usize,
+    /// 直前の応答との類似度がこれ以上なら繰り返しとみなして止める（Noneなら判定しない）
+    pub repeat_similarity: Option<f64>,
+    /// is_finishedのない応答がこの回数続いたら、フラグだけを答えるよう促す（Noneなら促さない）
+    pub nudge_after_missing_flags: Option<usize>,
+}
+
+impl Default for TerminationConfig {
+    fn default() -> Self {
+        Self { max_steps: 10, repeat_similarity: Some(0.9), nudge_after_missing_flags: Some(2) }
+    }
+}
+
+/// エージェントループを終えた理由（最後の進捗メッセージをそれぞれ変える）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TerminationReason {
+    /// LLMが is_finished: true を返した
+    Finished,
+    /// 直前とほぼ同じ応答を返した（類似度）
+    Repeating(f64),
+    /// フラグだけを答えるよう促してもis_finishedを返さなかった
+    MissingFlag,
+    /// 最大のステップ数に達した
+    StepLimit,
+}
+
+impl TerminationReason {
+    /// 最後の進捗メッセージのi18nキー
+    pub fn message_key(&self) -> &'static str {
+        match self {
+            Self::Finished => "agent.finished",
+            Self::Repeating(_) => "agent.repeating",
+            Self::MissingFlag => "agent.missing_flag",
+            Self::StepLimit => "agent.step_limit",
+        }
+    }
+}
+
+/// 応答からis_finishedフラグを取り出す（複数あれば最後のもの）
+/// フラグがなく、最後の行が DONE だけなら終了とみなす
+pub fn extract_is_finished_flag(text: &str) -> Option<bool> {
+    if let Some(caps) = FLAG_RE.captures_iter(text).last() {
+        return Some(caps[1].eq_ignore_ascii_case("true"));
+    }
+    let last_line = text.lines().map(str::trim).rfind(|line| !line.is_empty() && !line.starts_with("```"))?;
+    (last_line.trim_matches(|c: char| matches!(c, '*' | '`' | '.' | '!')) == "DONE").then_some(true)
+}
+
+/// 比較用に、フラグを除いて小文字にし、空白をまとめる
+fn normalize_response(text: &str) -> Vec<char> {
+    let without_flag = FLAG_RE.replace_all(text, "");
+    without_flag.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase().chars().collect()
+}
+
+/// 2つの応答の類似度（0.0〜1.0、文字のbigramのDice係数）
+pub fn response_similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (normalize_response(a), normalize_response(b));
+    if a == b {
+        return 1.0;
+    }
+    if a.len() < 2 || b.len() < 2 {
+        return 0.0;
+    }
+    let mut bigrams: HashMap<(char, char), usize> = HashMap::new();
+    for pair in a.windows(2) {
+        *bigrams.entry((pair[0], pair[1])).or_default() += 1;
+    }
+    let mut shared = 0;
+    for pair in b.windows(2) {
+        if let Some(count) = bigrams.get_mut(&(pair[0], pair[1])).filter(|count| **count > 0) {
+            *count -= 1;
+            shared += 1;
+        }
+    }
+    2.0 * shared as f64 / (a.len() + b.len() - 2) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_is_finished_flag() {
+        let cases: &[(&str, Option<bool>)] = &[
+            ("Done.\nis_finished: true", Some(true)),
+            ("{\"is_finished\": false}", Some(false)),
+            ("```json\n{\n  \"is_finished\": true\n}\n```", Some(true)),
+            ("**is_finished**: True", Some(true)),
+            ("`is_finished: false`", Some(false)),
+            ("is_finished = true", Some(true)),
+            ("{'is_finished': 'false'}", Some(false)),
+            ("{\"status\": \"ok\", \"finished\": true}", Some(true)),
+            ("IS_FINISHED: TRUE", Some(true)),
+            // 途中で触れたフラグではなく最後のものを使う
+            ("I'll keep is_finished: false until the tests pass.\nThey pass now.\nis_finished: true", Some(true)),
+            ("All files are updated.\n\nDONE", Some(true)),
+            ("All files are updated.\n**DONE**\n```", Some(true)),
+            ("I am done with step 1.", None),
+            ("The task is not finished yet.", None),
+            ("unfinished: true", None),
+            ("is_finished: maybe", None),
+            ("", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(extract_is_finished_flag(text), *expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_response_similarity() {
+        let first = "I will now read src/main.rs to check the setup.\nis_finished: false";
+        let again = "I will now read  src/main.rs to check the setup!\n\"is_finished\": false";
+        assert!(response_similarity(first, again) > 0.9);
+        assert_eq!(response_similarity(first, first), 1.0);
+        assert!(response_similarity(first, "Tests pass, the bug in the parser is fixed.\nis_finished: true") < 0.5);
+        assert_eq!(response_similarity("a", "b"), 0.0);
+    }
+}
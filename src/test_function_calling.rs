@@ -1,8 +1,6 @@
-// use serde_json::json; // Unused import
-
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use serde_json::json;
 
     #[test]
     fn test_function_calling_json_structure() {
@@ -0,0 +1,35 @@
+//! 単体テスト（crate::test_common）と結合テストで共有するヘルパー
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// テスト用の一時ディレクトリ（Dropで中身ごと削除するので、テストが失敗しても残らない）
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("contui_{}_{}", name, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
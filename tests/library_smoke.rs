@@ -0,0 +1,76 @@
+//! 端末なしでライブラリの型を組み立てられることを確認するスモークテスト
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use contui::config::{ContextWindowTable, LlmConfig, PricingTable, ProviderKind};
+use contui::file_access::{FileAccessManager, FileReference};
+use contui::gemini::{GeminiClient, Part};
+use contui::history::HistoryManager;
+use contui::llm::LlmProvider;
+
+use common::TempDir;
+
+fn model_name(provider: &impl LlmProvider) -> String {
+    provider.model().to_string()
+}
+
+#[test]
+fn history_round_trips_through_file() {
+    let dir = TempDir::new("history");
+    let path = dir.join("chat_history.json");
+
+    let mut manager = HistoryManager::with_file_path(path.clone()).unwrap();
+    let session_id = manager.get_history_mut().new_session(Some("smoke".to_string()));
+    manager
+        .get_history_mut()
+        .add_message(vec![Part::Text { text: "hello".to_string() }], true)
+        .unwrap();
+    manager.save().unwrap();
+
+    let reloaded = HistoryManager::with_file_path(path).unwrap();
+    let session = reloaded.get_history().get_current_session().unwrap();
+    assert_eq!(session.id, session_id);
+    assert_eq!(session.title, "smoke");
+    assert_eq!(session.messages.len(), 1);
+}
+
+#[test]
+fn file_access_reads_only_allowed_directories() {
+    let dir = TempDir::new("file_access");
+    let file = dir.join("notes.txt");
+    std::fs::write(&file, "one\ntwo\nthree\n").unwrap();
+
+    let mut manager = FileAccessManager::new();
+    assert!(manager.read_file(&file).is_err());
+    manager.add_allowed_directory(&dir).unwrap();
+
+    let reference = FileReference::parse(&format!("{}:L2-L3", file.display()));
+    assert_eq!(manager.read_reference(&reference).unwrap().lines().collect::<Vec<_>>(), vec!["two", "three"]);
+}
+
+#[test]
+fn gemini_client_is_an_llm_provider() {
+    let dir = TempDir::new("client");
+    let history = Arc::new(Mutex::new(HistoryManager::with_file_path(dir.join("chat_history.json")).unwrap()));
+    let config = LlmConfig {
+        model: "gemini-2.5-flash".to_string(),
+        max_tokens: None,
+        temperature: None,
+        gemini_api_key: "test-key".to_string(),
+        pricing: PricingTable::default(),
+        provider: ProviderKind::Gemini,
+        context_windows: ContextWindowTable::default(),
+        token_preflight: false,
+    };
+    let client = GeminiClient::new(config, history);
+    assert_eq!(model_name(&client), "gemini-2.5-flash");
+    assert_eq!(model_name(&client.with_model("gemini-2.5-pro")), "gemini-2.5-pro");
+}
+
+#[test]
+fn markdown_wraps_without_a_terminal() {
+    let wrapped = contui::markdown::hard_wrap("abcdefgh", 4);
+    assert_eq!(wrapped, "abcd\nefgh");
+}